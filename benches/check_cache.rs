@@ -0,0 +1,52 @@
+//! Compares `check_permission` served from the check cache with the same
+//! check answered by SQLite.
+
+use std::fs;
+
+use chrono::Duration;
+use criterion::{criterion_group, criterion_main, Criterion};
+use linux_permission_manager::{Config, PermissionManager};
+use tempfile::TempDir;
+use tokio::runtime::Runtime;
+
+const COMMAND: &str = "/usr/bin/true";
+
+/// A manager in a scratch directory holding one grant, with or without the
+/// check cache
+fn manager(runtime: &Runtime, cache: bool) -> (PermissionManager, TempDir) {
+    let dir = TempDir::new().unwrap();
+    let mut yaml = format!(
+        "sudoers_path: {dir}/sudoers\ndb_path: {dir}/permctl.db\nlog_path: {dir}/permctl.log\n\
+         allowed_commands:\n  {COMMAND}:\n    description: bench\n    max_duration: 60\n    required_groups: []\n",
+        dir = dir.path().display(),
+    );
+    if cache {
+        yaml.push_str("check_cache:\n  ttl_secs: 3600\n");
+    }
+    let config_path = dir.path().join("config.yaml");
+    fs::write(&config_path, yaml).unwrap();
+
+    let manager = runtime.block_on(async {
+        let manager = PermissionManager::new(Config::load_from(&config_path).unwrap()).await.unwrap();
+        manager.grant_permission("root", COMMAND, Duration::minutes(60), "bench").await.unwrap();
+        manager
+    });
+    (manager, dir)
+}
+
+fn check_permission(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("check_permission");
+
+    for (name, cache) in [("database", false), ("cache_hit", true)] {
+        let (manager, _dir) = manager(&runtime, cache);
+        group.bench_function(name, |b| {
+            b.iter(|| runtime.block_on(manager.check_permission("root", COMMAND)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, check_permission);
+criterion_main!(benches);
@@ -15,15 +15,42 @@ _permctl() {
                 cmd="permctl"
                 ;;
             
+            approve)
+                cmd+="__approve"
+                ;;
+            check)
+                cmd+="__check"
+                ;;
+            config-check)
+                cmd+="__config__check"
+                ;;
+            config-diff)
+                cmd+="__config__diff"
+                ;;
             cleanup)
                 cmd+="__cleanup"
                 ;;
+            audit)
+                cmd+="__audit"
+                ;;
             commands)
                 cmd+="__commands"
                 ;;
+            doctor)
+                cmd+="__doctor"
+                ;;
+            dump)
+                cmd+="__dump"
+                ;;
             grant)
                 cmd+="__grant"
                 ;;
+            quota)
+                cmd+="__quota"
+                ;;
+            status)
+                cmd+="__status"
+                ;;
             help)
                 cmd+="__help"
                 ;;
@@ -36,6 +63,27 @@ _permctl() {
             revoke)
                 cmd+="__revoke"
                 ;;
+            revoke-by-granter)
+                cmd+="__revoke__by__granter"
+                ;;
+            drop)
+                cmd+="__drop"
+                ;;
+            serve)
+                cmd+="__serve"
+                ;;
+            http)
+                cmd+="__http"
+                ;;
+            sync)
+                cmd+="__sync"
+                ;;
+            compact)
+                cmd+="__compact"
+                ;;
+            maintenance)
+                cmd+="__maintenance"
+                ;;
             verify)
                 cmd+="__verify"
                 ;;
@@ -46,7 +94,7 @@ _permctl() {
 
     case "${cmd}" in
         permctl)
-            opts="grant revoke list commands cleanup init verify help"
+            opts="grant approve revoke revoke-by-granter drop list commands quota status cleanup sync compact maintenance dump audit init verify doctor check config-check config-diff serve http help"
             COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
             return 0
             ;;
@@ -58,7 +106,11 @@ _permctl() {
                     return 0
                     ;;
                 -d|--duration)
-                    COMPREPLY=( $(compgen -W "30 60 120 240 480" -- ${cur}) )
+                    COMPREPLY=( $(compgen -W "30m 1h 2h 4h 8h" -- ${cur}) )
+                    return 0
+                    ;;
+                --on-behalf-of)
+                    COMPREPLY=( $(compgen -u -- ${cur}) )
                     return 0
                     ;;
                 *)
@@ -68,7 +120,7 @@ _permctl() {
                             COMPREPLY=( $(compgen -W "$(permctl commands | grep -v Allowed | tr -d ' ')" -- ${cur}) )
                         fi
                     else
-                        opts="-d --duration"
+                        opts="-d --duration --from --host --confirm --require-password -r --reason --on-behalf-of --notify-user --check -t --template --var"
                         COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
                     fi
                     return 0
@@ -88,29 +140,210 @@ _permctl() {
                         if [ -f /etc/permctl/config.yaml ]; then
                             COMPREPLY=( $(compgen -W "$(permctl commands | grep -v Allowed | tr -d ' ')" -- ${cur}) )
                         fi
+                    else
+                        opts="--glob -r --reason"
+                        COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
                     fi
                     return 0
                     ;;
             esac
             ;;
             
+        permctl__revoke__by__granter)
+            case "${prev}" in
+                revoke-by-granter)
+                    COMPREPLY=( $(compgen -u -- ${cur}) )
+                    ;;
+                *)
+                    opts="-r --reason"
+                    COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
+                    ;;
+            esac
+            return 0
+            ;;
+
+        permctl__drop)
+            case "${prev}" in
+                drop)
+                    if [ -f /etc/permctl/config.yaml ]; then
+                        COMPREPLY=( $(compgen -W "--all $(permctl commands | grep -v Allowed | tr -d ' ')" -- ${cur}) )
+                    fi
+                    ;;
+            esac
+            return 0
+            ;;
+
+        permctl__check)
+            case "${prev}" in
+                check)
+                    COMPREPLY=( $(compgen -u -- ${cur}) )
+                    return 0
+                    ;;
+                *)
+                    if [[ ${COMP_CWORD} -eq 3 ]]; then
+                        # Complete with allowed commands from config
+                        if [ -f /etc/permctl/config.yaml ]; then
+                            COMPREPLY=( $(compgen -W "$(permctl commands | grep -v Allowed | tr -d ' ')" -- ${cur}) )
+                        fi
+                    else
+                        opts="-r --record -v --verbose"
+                        COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
+                    fi
+                    return 0
+                    ;;
+            esac
+            ;;
+
+        permctl__serve)
+            case "${prev}" in
+                -s|--socket)
+                    COMPREPLY=( $(compgen -f -- ${cur}) )
+                    ;;
+                *)
+                    opts="-s --socket"
+                    COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
+                    ;;
+            esac
+            return 0
+            ;;
+        permctl__http)
+            case "${prev}" in
+                --addr)
+                    COMPREPLY=()
+                    ;;
+                *)
+                    opts="--addr --allow-remote"
+                    COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
+                    ;;
+            esac
+            return 0
+            ;;
+
+        permctl__config__check)
+            COMPREPLY=( $(compgen -f -- ${cur}) )
+            return 0
+            ;;
+
+        permctl__config__diff)
+            case "${prev}" in
+                --output)
+                    COMPREPLY=( $(compgen -W "text json table" -- ${cur}) )
+                    ;;
+                *)
+                    COMPREPLY=( $(compgen -f -W "--output" -- ${cur}) )
+                    ;;
+            esac
+            return 0
+            ;;
+
         permctl__list)
-            opts="-a --all -u --user"
-            COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
+            case "${prev}" in
+                --output)
+                    COMPREPLY=( $(compgen -W "text json table" -- ${cur}) )
+                    ;;
+                --sort)
+                    COMPREPLY=( $(compgen -W "user command granted expires last-used" -- ${cur}) )
+                    ;;
+                --watch)
+                    COMPREPLY=()
+                    ;;
+                *)
+                    opts="-a --all -u --user -e --expiring -l --limit -o --offset -p --pending --active-during --sort --desc --watch --output"
+                    COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
+                    ;;
+            esac
             return 0
             ;;
             
+        permctl__doctor)
+            case "${prev}" in
+                --output)
+                    COMPREPLY=( $(compgen -W "text json table" -- ${cur}) )
+                    ;;
+                *)
+                    COMPREPLY=( $(compgen -W "--output --recover --fix" -- ${cur}) )
+                    ;;
+            esac
+            return 0
+            ;;
+
+        permctl__dump)
+            case "${prev}" in
+                --format)
+                    COMPREPLY=( $(compgen -W "ansible json yaml" -- ${cur}) )
+                    ;;
+                *)
+                    COMPREPLY=( $(compgen -W "--format" -- ${cur}) )
+                    ;;
+            esac
+            return 0
+            ;;
+
+        permctl__audit)
+            case "${prev}" in
+                --output)
+                    COMPREPLY=( $(compgen -W "text json table" -- ${cur}) )
+                    ;;
+                -a|--action)
+                    COMPREPLY=( $(compgen -W "grant revoke request approve confirm_request orphan_cleanup" -- ${cur}) )
+                    ;;
+                -u|--user|--command|-l|--limit)
+                    COMPREPLY=()
+                    ;;
+                *)
+                    COMPREPLY=( $(compgen -W "-u --user --command -a --action -l --limit --output" -- ${cur}) )
+                    ;;
+            esac
+            return 0
+            ;;
+
+        permctl__maintenance)
+            COMPREPLY=( $(compgen -W "on off" -- ${cur}) )
+            return 0
+            ;;
+
+        permctl__status)
+            opts="--exit-code"
+            COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
+            return 0
+            ;;
+
+        permctl__quota)
+            case "${prev}" in
+                --output)
+                    COMPREPLY=( $(compgen -W "text json table" -- ${cur}) )
+                    ;;
+                *)
+                    COMPREPLY=( $(compgen -W "--output" -- ${cur}) )
+                    ;;
+            esac
+            return 0
+            ;;
+
         permctl__commands)
-            opts="-v --verbose"
+            opts="-v --verbose -t --tag"
             COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
             return 0
             ;;
             
-        permctl__init)
-            opts="-f --force"
+        permctl__cleanup)
+            opts="--dry-run --orphans --dedupe --revalidate-groups"
             COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
             return 0
             ;;
+
+        permctl__init)
+            case "${prev}" in
+                --with)
+                    COMPREPLY=( $(compgen -W "docker kubernetes database web" -- ${cur}) )
+                    ;;
+                *)
+                    opts="-f --force -i --interactive --with"
+                    COMPREPLY=( $(compgen -W "${opts}" -- ${cur}) )
+                    ;;
+            esac
+            return 0
+            ;;
     esac
 }
 
@@ -0,0 +1,58 @@
+//! Embeds the permission manager in another service. Grants are recorded in
+//! a scratch database without touching the sudoers file, and group
+//! membership comes from the service's own directory rather than the host.
+//!
+//! Run with `cargo run --example embedded`.
+
+use chrono::Duration;
+use linux_permission_manager::{CommandConfig, Config, IdentityProvider, PermissionManager, Result};
+use tempfile::TempDir;
+
+/// Accounts known to the embedding service
+struct ServiceDirectory;
+
+impl IdentityProvider for ServiceDirectory {
+    fn user_exists(&self, username: &str) -> Result<bool> {
+        Ok(username == "alice")
+    }
+
+    fn user_groups(&self, username: &str) -> Result<Vec<String>> {
+        Ok(match username {
+            "alice" => vec!["oncall".to_string()],
+            _ => Vec::new(),
+        })
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let dir = TempDir::new().expect("create scratch directory");
+    let restart: CommandConfig = serde_yaml::from_str(
+        "{description: Restart services, max_duration: 30, required_groups: [oncall]}",
+    )
+    .expect("valid command config");
+
+    let mut config = Config {
+        db_path: dir.path().join("permctl.db"),
+        log_path: dir.path().join("permctl.log"),
+        ..Config::default()
+    };
+    config.allowed_commands.insert("/usr/bin/systemctl".to_string(), restart);
+
+    let manager = PermissionManager::builder()
+        .config(config)
+        .manage_sudoers(false)
+        .identity_provider(ServiceDirectory)
+        .build()
+        .await?;
+
+    manager
+        .grant_permission("alice", "/usr/bin/systemctl", Duration::minutes(15), "incident-bot")
+        .await?;
+    for username in ["alice", "mallory"] {
+        let allowed = manager.check_permission(username, "/usr/bin/systemctl").await?;
+        println!("{} may run systemctl: {}", username, allowed);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,67 @@
+-- Permission grants table
+CREATE TABLE IF NOT EXISTS permission_grants (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    command TEXT NOT NULL,
+    granted_at DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL,
+    granted_by TEXT NOT NULL,
+    last_used DATETIME,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE,
+    revoked_at DATETIME,
+    revoked_by TEXT,
+    active_from DATETIME,
+    revoke_reason TEXT,
+    host TEXT,
+    use_count INTEGER NOT NULL DEFAULT 0,
+    max_uses INTEGER,
+    UNIQUE(username, command) ON CONFLICT REPLACE
+);
+
+-- Indices for efficient querying
+CREATE INDEX IF NOT EXISTS idx_permissions_user
+    ON permission_grants(username);
+CREATE INDEX IF NOT EXISTS idx_permissions_expires
+    ON permission_grants(expires_at);
+CREATE INDEX IF NOT EXISTS idx_permissions_active
+    ON permission_grants(username, command, expires_at)
+    WHERE NOT revoked;
+
+-- Audit log table
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    timestamp DATETIME NOT NULL,
+    username TEXT NOT NULL,
+    command TEXT NOT NULL,
+    action TEXT NOT NULL,
+    details TEXT
+);
+
+-- Index for audit log queries
+CREATE INDEX IF NOT EXISTS idx_audit_log_timestamp
+    ON audit_log(timestamp);
+
+-- Grants waiting for approval
+CREATE TABLE IF NOT EXISTS grant_requests (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    command TEXT NOT NULL,
+    duration_minutes INTEGER NOT NULL,
+    active_from DATETIME,
+    host TEXT,
+    requested_by TEXT NOT NULL,
+    requested_at DATETIME NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    decided_by TEXT,
+    decided_at DATETIME,
+    grant_id INTEGER
+);
+CREATE INDEX IF NOT EXISTS idx_grant_requests_status
+    ON grant_requests(status);
+
+-- Latest wall clock time observed by any mutation
+CREATE TABLE IF NOT EXISTS clock_state (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    last_seen DATETIME NOT NULL
+);
+
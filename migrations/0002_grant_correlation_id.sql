@@ -0,0 +1,2 @@
+-- Identifier shared by every log line and audit entry about a grant
+ALTER TABLE permission_grants ADD COLUMN correlation_id TEXT;
@@ -0,0 +1,15 @@
+-- One-time tokens confirming grants of commands with require_confirmation
+CREATE TABLE IF NOT EXISTS grant_confirmations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    token TEXT NOT NULL,
+    username TEXT NOT NULL,
+    command TEXT NOT NULL,
+    host TEXT,
+    active_from DATETIME,
+    duration_minutes INTEGER NOT NULL,
+    granted_by TEXT NOT NULL,
+    created_at DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_grant_confirmations_expires
+    ON grant_confirmations(expires_at);
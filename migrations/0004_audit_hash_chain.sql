@@ -0,0 +1,4 @@
+-- Hash chain making changes to the audit log detectable: each entry's hash
+-- covers its fields and the previous entry's hash
+ALTER TABLE audit_log ADD COLUMN prev_hash TEXT;
+ALTER TABLE audit_log ADD COLUMN entry_hash TEXT;
@@ -0,0 +1,6 @@
+-- Grants that make sudo ask for the user's own password even when the
+-- command's configuration does not, carried through requests and
+-- confirmations so the grant they end in keeps it
+ALTER TABLE permission_grants ADD COLUMN require_password BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE grant_requests ADD COLUMN require_password BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE grant_confirmations ADD COLUMN require_password BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,8 @@
+-- Switches that apply to the whole installation rather than one grant.
+-- A `maintenance` row stops every grant from being honored until removed.
+CREATE TABLE IF NOT EXISTS settings (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL,
+    updated_at DATETIME NOT NULL,
+    updated_by TEXT NOT NULL
+);
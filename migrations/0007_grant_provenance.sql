@@ -0,0 +1,4 @@
+-- Host and permctl version a grant was created on, so grants made by an
+-- outdated binary can be traced; NULL for grants created before 0007
+ALTER TABLE permission_grants ADD COLUMN created_host TEXT;
+ALTER TABLE permission_grants ADD COLUMN created_version TEXT;
@@ -0,0 +1,4 @@
+-- User a grant was requested for by the automation or admin that made it,
+-- carried through requests so the grant an approval creates keeps it
+ALTER TABLE permission_grants ADD COLUMN on_behalf_of TEXT;
+ALTER TABLE grant_requests ADD COLUMN on_behalf_of TEXT;
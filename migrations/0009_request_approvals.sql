@@ -0,0 +1,9 @@
+-- Each distinct operator who approved a grant request, so a command can
+-- require several approvals before the grant is made. Approvals go with
+-- their request if it is ever deleted.
+CREATE TABLE IF NOT EXISTS approvals (
+    request_id INTEGER NOT NULL REFERENCES grant_requests(id) ON DELETE CASCADE,
+    approver TEXT NOT NULL,
+    approved_at DATETIME NOT NULL,
+    PRIMARY KEY (request_id, approver)
+);
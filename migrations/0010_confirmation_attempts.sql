@@ -0,0 +1,3 @@
+-- Wrong tokens tried against a confirmation, so it can be thrown away
+-- before its six digits are guessed
+ALTER TABLE grant_confirmations ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0;
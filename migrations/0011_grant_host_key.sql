@@ -0,0 +1,50 @@
+-- Key grants by host as well as user and command, so a grant scoped to one
+-- host no longer replaces the user's grant of the same command on another.
+-- SQLite cannot drop a table constraint, so the table is rebuilt. The key
+-- is an index on the host with NULL, meaning every host, folded to '' so
+-- that all-host grants stay unique too; writers use INSERT OR REPLACE.
+CREATE TABLE permission_grants_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    command TEXT NOT NULL,
+    granted_at DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL,
+    granted_by TEXT NOT NULL,
+    last_used DATETIME,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE,
+    revoked_at DATETIME,
+    revoked_by TEXT,
+    active_from DATETIME,
+    revoke_reason TEXT,
+    host TEXT,
+    use_count INTEGER NOT NULL DEFAULT 0,
+    max_uses INTEGER,
+    correlation_id TEXT,
+    require_password BOOLEAN NOT NULL DEFAULT FALSE,
+    created_host TEXT,
+    created_version TEXT,
+    on_behalf_of TEXT
+);
+
+INSERT INTO permission_grants_new
+    (id, username, command, granted_at, expires_at, granted_by, last_used, revoked, revoked_at, revoked_by,
+     active_from, revoke_reason, host, use_count, max_uses, correlation_id, require_password, created_host,
+     created_version, on_behalf_of)
+SELECT
+    id, username, command, granted_at, expires_at, granted_by, last_used, revoked, revoked_at, revoked_by,
+    active_from, revoke_reason, host, use_count, max_uses, correlation_id, require_password, created_host,
+    created_version, on_behalf_of
+FROM permission_grants;
+
+DROP TABLE permission_grants;
+ALTER TABLE permission_grants_new RENAME TO permission_grants;
+
+CREATE UNIQUE INDEX idx_permissions_key
+    ON permission_grants(username, command, IFNULL(host, ''));
+CREATE INDEX IF NOT EXISTS idx_permissions_user
+    ON permission_grants(username);
+CREATE INDEX IF NOT EXISTS idx_permissions_expires
+    ON permission_grants(expires_at);
+CREATE INDEX IF NOT EXISTS idx_permissions_active
+    ON permission_grants(username, command, expires_at)
+    WHERE NOT revoked;
@@ -0,0 +1,3 @@
+-- Reason given with a grant request, carried into the grant and its audit
+-- entry when the request is approved
+ALTER TABLE grant_requests ADD COLUMN reason TEXT;
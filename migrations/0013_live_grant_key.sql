@@ -0,0 +1,7 @@
+-- Only live grants need a unique key. A replaced grant is now revoked rather
+-- than deleted, so the time it was granted for still counts towards
+-- max_total_duration.
+DROP INDEX idx_permissions_key;
+CREATE UNIQUE INDEX idx_permissions_key
+    ON permission_grants(username, command, IFNULL(host, ''))
+    WHERE NOT revoked;
@@ -0,0 +1,33 @@
+-- A counter bumped by every change that can take access away, so a process
+-- caching permission checks can tell when another process (such as a CLI
+-- revoke) has made its cached answers stale.
+INSERT OR IGNORE INTO settings (key, value, updated_at, updated_by)
+    VALUES ('grant_generation', '0', strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'permctl');
+
+CREATE TRIGGER IF NOT EXISTS grant_generation_on_update
+    AFTER UPDATE OF revoked, expires_at, active_from, host, max_uses ON permission_grants
+BEGIN
+    UPDATE settings
+    SET value = CAST(value AS INTEGER) + 1,
+        updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+    WHERE key = 'grant_generation';
+END;
+
+CREATE TRIGGER IF NOT EXISTS grant_generation_on_delete
+    AFTER DELETE ON permission_grants
+BEGIN
+    UPDATE settings
+    SET value = CAST(value AS INTEGER) + 1,
+        updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+    WHERE key = 'grant_generation';
+END;
+
+CREATE TRIGGER IF NOT EXISTS grant_generation_on_maintenance
+    AFTER INSERT ON settings
+    WHEN NEW.key = 'maintenance'
+BEGIN
+    UPDATE settings
+    SET value = CAST(value AS INTEGER) + 1,
+        updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+    WHERE key = 'grant_generation';
+END;
@@ -0,0 +1,119 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{timeout_at, Instant};
+use tracing::warn;
+
+use crate::db::Database;
+use crate::error::{PermissionError, Result};
+
+/// Longest an entry waits in the buffer before its batch is written
+const BATCH_INTERVAL: Duration = Duration::from_millis(200);
+/// Most entries written in one transaction
+const BATCH_SIZE: usize = 100;
+
+/// An audit entry waiting to be written
+#[derive(Debug)]
+pub struct QueuedEntry {
+    pub timestamp: DateTime<Utc>,
+    pub username: String,
+    pub command: String,
+    pub action: String,
+    pub details: Option<String>,
+}
+
+enum Message {
+    Entry(QueuedEntry),
+    /// Write everything queued so far, then acknowledge
+    Flush(oneshot::Sender<()>),
+}
+
+/// Writer that collects audit entries and stores them in batches from a
+/// background task, so the operation that logged them does not wait for
+/// the insert. Entries still in the buffer are lost if the process dies
+/// before they are written.
+pub struct AuditBuffer {
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+impl AuditBuffer {
+    /// Start the background task writing batches to `pool`. Must be called
+    /// from within a Tokio runtime.
+    pub fn spawn(pool: SqlitePool) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write_batches(pool, receiver));
+        Self { sender }
+    }
+
+    /// Queue `entry` to be written with the next batch, handing it back if
+    /// the background task has stopped
+    pub fn push(&self, entry: QueuedEntry) -> std::result::Result<(), QueuedEntry> {
+        self.sender.send(Message::Entry(entry)).map_err(|e| match e.0 {
+            Message::Entry(entry) => entry,
+            Message::Flush(_) => unreachable!("an entry was sent"),
+        })
+    }
+
+    /// Wait until every entry queued so far has been written, or its write
+    /// has failed
+    pub async fn flush(&self) {
+        let (done, written) = oneshot::channel();
+        if self.sender.send(Message::Flush(done)).is_ok() {
+            let _ = written.await;
+        }
+    }
+}
+
+async fn write_batches(pool: SqlitePool, mut receiver: mpsc::UnboundedReceiver<Message>) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut flushed = Vec::new();
+
+    // Every sender is gone once the database is dropped
+    while let Some(first) = receiver.recv().await {
+        let deadline = Instant::now() + BATCH_INTERVAL;
+        let mut next = Some(first);
+        while let Some(message) = next.take() {
+            match message {
+                Message::Entry(entry) => batch.push(entry),
+                Message::Flush(done) => {
+                    flushed.push(done);
+                    break;
+                }
+            }
+            if batch.len() >= BATCH_SIZE {
+                break;
+            }
+            next = timeout_at(deadline, receiver.recv()).await.ok().flatten();
+        }
+
+        if !batch.is_empty() {
+            if let Err(e) = write_batch(&pool, &batch).await {
+                warn!("Failed to write {} buffered audit entries: {}", batch.len(), e);
+            }
+            batch.clear();
+        }
+        for done in flushed.drain(..) {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Write `batch` in a single transaction, chaining each entry to the last
+async fn write_batch(pool: &SqlitePool, batch: &[QueuedEntry]) -> Result<()> {
+    let mut tx = pool
+        .begin_with("BEGIN IMMEDIATE")
+        .await
+        .map_err(PermissionError::Database)?;
+    for entry in batch {
+        Database::insert_audit_log(
+            &mut tx,
+            entry.timestamp,
+            &entry.username,
+            &entry.command,
+            &entry.action,
+            entry.details.as_deref(),
+        ).await?;
+    }
+    tx.commit().await.map_err(PermissionError::Database)
+}
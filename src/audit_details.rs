@@ -0,0 +1,167 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::AuditDetail;
+
+/// Structured contents of an audit log entry's `details` column, stored as
+/// JSON. Fields that do not apply to the action are left out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuditDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub granted_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approved_by: Option<String>,
+    /// User a grant or request was made for by its granter or requester
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_behalf_of: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_by: Option<String>,
+    /// Grant request the entry is about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<i64>,
+    /// Scheduled start of the grant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_from: Option<DateTime<Utc>>,
+    /// Expiry of the grant, or of the confirmation token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Expiry of the held grant that a re-grant extended
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extended_from: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_minutes: Option<i64>,
+    /// Whether the grant requires the user's own password
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require_password: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Host a grant was created on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_host: Option<String>,
+    /// permctl version that created a grant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_version: Option<String>,
+}
+
+impl AuditDetails {
+    /// Serialise the details as stored, keeping only what `level` records
+    pub fn to_json(&self, level: AuditDetail) -> String {
+        let details = match level {
+            AuditDetail::Full => self.clone(),
+            AuditDetail::Minimal => Self { duration_minutes: None, reason: None, ..self.clone() },
+        };
+        serde_json::to_string(&details).unwrap_or_default()
+    }
+
+    /// Structured details of an entry, or `None` for the free-form text
+    /// written before details were stored as JSON
+    pub fn parse(details: &str) -> Option<Self> {
+        serde_json::from_str(details).ok()
+    }
+
+    /// Human-readable rendering of the details of an `action` entry
+    pub fn describe(&self, action: &str) -> String {
+        let mut text = match action {
+            "request" => format!("Request {} by {}", self.request_id.unwrap_or_default(), actor(&self.requested_by)),
+            "approve" => format!("Request {} approved by {}", self.request_id.unwrap_or_default(), actor(&self.approved_by)),
+            "request_expired" => format!("Request {} by {} expired unapproved", self.request_id.unwrap_or_default(), actor(&self.requested_by)),
+            "use" => "Used".to_string(),
+            "confirm_request" => format!("Confirmation requested by {}", actor(&self.granted_by)),
+            "revoke" | "orphan_cleanup" | "duplicate_cleanup" | "group_revalidation" | "idle_timeout" | "self_revoke" => format!("Revoked by {}", actor(&self.revoked_by)),
+            _ => match &self.granted_by {
+                Some(by) => format!("Granted by {}", by),
+                None => action.to_string(),
+            },
+        };
+
+        if let Some(user) = &self.on_behalf_of {
+            text.push_str(&format!(" on behalf of {}", user));
+        }
+        if let Some(from) = self.active_from {
+            text.push_str(&format!(" from {}", from));
+        }
+        if let Some(until) = self.expires_at {
+            text.push_str(&format!(" until {}", until));
+        }
+        if let Some(minutes) = self.duration_minutes {
+            text.push_str(&format!(" for {} minutes", minutes));
+        }
+        if self.require_password {
+            text.push_str(", requiring the user's password");
+        }
+        if let Some(held_until) = self.extended_from {
+            text.push_str(&format!(", extending the grant held until {}", held_until));
+        }
+        match (&self.created_host, &self.created_version) {
+            (Some(host), Some(version)) => text.push_str(&format!(" on {} (permctl {})", host, version)),
+            (Some(host), None) => text.push_str(&format!(" on {}", host)),
+            (None, Some(version)) => text.push_str(&format!(" (permctl {})", version)),
+            (None, None) => {}
+        }
+        if let Some(reason) = &self.reason {
+            text.push_str(&format!(": {}", reason));
+        }
+        match &self.correlation_id {
+            Some(id) => format!("[correlation_id={}] {}", id, text),
+            None => text,
+        }
+    }
+
+    /// Human-readable rendering of a stored `details` value, which is shown
+    /// as is if it predates structured details
+    pub fn render(action: &str, details: &str) -> String {
+        match Self::parse(details) {
+            Some(parsed) => parsed.describe(action),
+            None => details.to_string(),
+        }
+    }
+}
+
+fn actor(name: &Option<String>) -> &str {
+    name.as_deref().unwrap_or("unknown")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detail_levels_and_rendering() {
+        let details = AuditDetails {
+            revoked_by: Some("admin".to_string()),
+            reason: Some("ticket 4711".to_string()),
+            correlation_id: Some("abc".to_string()),
+            ..Default::default()
+        };
+
+        let full = details.to_json(AuditDetail::Full);
+        assert_eq!(AuditDetails::parse(&full), Some(details.clone()));
+        assert_eq!(AuditDetails::render("revoke", &full), "[correlation_id=abc] Revoked by admin: ticket 4711");
+
+        let minimal = details.to_json(AuditDetail::Minimal);
+        assert!(!minimal.contains("ticket"));
+        assert_eq!(AuditDetails::render("revoke", &minimal), "[correlation_id=abc] Revoked by admin");
+
+        // Entries written before details were structured are shown as is
+        assert_eq!(AuditDetails::render("grant", "Granted by root until then"), "Granted by root until then");
+
+        let granted = AuditDetails {
+            granted_by: Some("root".to_string()),
+            created_host: Some("web1".to_string()),
+            created_version: Some("1.2.0".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(granted.describe("grant"), "Granted by root on web1 (permctl 1.2.0)");
+
+        let delegated = AuditDetails {
+            granted_by: Some("deploy-bot".to_string()),
+            on_behalf_of: Some("alice".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(delegated.describe("grant"), "Granted by deploy-bot on behalf of alice");
+    }
+}
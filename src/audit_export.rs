@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use chrono::{DateTime, Utc};
+use syslog::{Facility, Formatter3164, Formatter5424, Logger, LoggerBackend};
+use crate::audit_details::AuditDetails;
+use crate::config::{SyslogConfig, SyslogFormat};
+use crate::log_dedup::WarningDeduplicator;
+
+/// Structured data ID used for RFC 5424 audit events
+const SD_ID: &str = "permctl@32473";
+/// Process name reported to syslog
+const PROCESS_NAME: &str = "permctl";
+/// Mode of the audit log file, readable by the group for log shippers
+const AUDIT_FILE_MODE: u32 = 0o640;
+
+/// A single audit event as recorded in the audit log
+#[derive(Debug, Clone)]
+pub struct AuditEvent<'a> {
+    pub timestamp: DateTime<Utc>,
+    pub username: &'a str,
+    pub command: &'a str,
+    pub action: &'a str,
+    pub details: Option<&'a str>,
+}
+
+enum SyslogLogger {
+    Rfc5424(Logger<LoggerBackend, Formatter5424>),
+    Cef(Logger<LoggerBackend, Formatter3164>),
+}
+
+/// Best-effort exporter that mirrors audit events to the local syslog
+pub struct SyslogExporter {
+    format: SyslogFormat,
+    logger: Mutex<Option<SyslogLogger>>,
+    /// Limits the reconnect and send failures logged during a syslog outage
+    warnings: Arc<WarningDeduplicator>,
+}
+
+impl SyslogExporter {
+    /// Create an exporter; the syslog connection is opened on first use
+    pub fn new(config: &SyslogConfig, warnings: Arc<WarningDeduplicator>) -> Self {
+        Self {
+            format: config.format,
+            logger: Mutex::new(None),
+            warnings,
+        }
+    }
+
+    /// Send an event to syslog, logging and discarding any failure so the
+    /// database audit trail is never blocked by a syslog outage
+    pub fn emit(&self, event: &AuditEvent<'_>) {
+        let mut guard = match self.logger.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if guard.is_none() {
+            match self.connect() {
+                Ok(logger) => *guard = Some(logger),
+                Err(e) => {
+                    self.warnings.warn(&format!("Failed to connect to syslog: {}", e));
+                    return;
+                }
+            }
+        }
+
+        let result = match guard.as_mut() {
+            Some(SyslogLogger::Rfc5424(logger)) => logger.notice((
+                0,
+                rfc5424_data(event),
+                format!("{} {} {}", event.action, event.username, event.command),
+            )),
+            Some(SyslogLogger::Cef(logger)) => logger.notice(format_cef(event)),
+            None => return,
+        };
+
+        if let Err(e) = result {
+            self.warnings.warn(&format!("Failed to send audit event to syslog: {}", e));
+            // Drop the connection so the next event reconnects
+            *guard = None;
+        }
+    }
+
+    fn connect(&self) -> syslog::Result<SyslogLogger> {
+        let pid = std::process::id();
+        match self.format {
+            SyslogFormat::Rfc5424 => syslog::unix(Formatter5424 {
+                facility: Facility::LOG_AUTHPRIV,
+                hostname: None,
+                process: PROCESS_NAME.to_string(),
+                pid,
+            })
+            .map(SyslogLogger::Rfc5424),
+            SyslogFormat::Cef => syslog::unix(Formatter3164 {
+                facility: Facility::LOG_AUTHPRIV,
+                hostname: None,
+                process: PROCESS_NAME.to_string(),
+                pid,
+            })
+            .map(SyslogLogger::Cef),
+        }
+    }
+}
+
+/// Best-effort writer appending every audit event as a JSON line to a
+/// plain file, a second trail that survives loss of the database and can
+/// be shipped off the host with standard log tooling. The file is rotated
+/// once it reaches `max_bytes`, and rotated files older than the retention
+/// period are deleted.
+pub struct AuditFileWriter {
+    path: PathBuf,
+    /// Size at which the file is rotated; 0 never rotates
+    max_bytes: u64,
+    /// Age after which rotated files are deleted; `None` keeps them
+    retention: Option<Duration>,
+    /// Serialises writes and rotation within this process
+    lock: Mutex<()>,
+    /// Limits the write failures logged while the file is unwritable
+    warnings: Arc<WarningDeduplicator>,
+}
+
+impl AuditFileWriter {
+    /// Create a writer for `path`; the file is created on first use.
+    /// `retention_days` of 0 keeps rotated files forever.
+    pub fn new(path: &Path, max_bytes: u64, retention_days: u32, warnings: Arc<WarningDeduplicator>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            retention: (retention_days > 0).then(|| Duration::from_secs(u64::from(retention_days) * 86_400)),
+            lock: Mutex::new(()),
+            warnings,
+        }
+    }
+
+    /// Append an event, logging and discarding any failure so the database
+    /// audit trail is never blocked by an unwritable file
+    pub fn emit(&self, event: &AuditEvent<'_>) {
+        if let Err(e) = self.append(event) {
+            self.warnings.warn(&format!("Failed to write audit event to {}: {}", self.path.display(), e));
+        }
+    }
+
+    fn append(&self, event: &AuditEvent<'_>) -> io::Result<()> {
+        let _guard = match self.lock.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if self.max_bytes > 0 {
+            match fs::metadata(&self.path) {
+                Ok(metadata) if metadata.len() >= self.max_bytes => self.rotate()?,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // The mode only applies when the file is created, and then through
+        // the umask, so it is checked every time
+        let mut file = OpenOptions::new().create(true).append(true).mode(AUDIT_FILE_MODE).open(&self.path)?;
+        if file.metadata()?.permissions().mode() & 0o777 != AUDIT_FILE_MODE {
+            file.set_permissions(Permissions::from_mode(AUDIT_FILE_MODE))?;
+        }
+
+        let mut line = format_audit_line(event);
+        line.push('\n');
+        file.write_all(line.as_bytes())
+    }
+
+    /// Move the current file aside under a timestamped name and delete
+    /// rotated files past the retention period
+    fn rotate(&self) -> io::Result<()> {
+        let stamp = Utc::now().format("%Y%m%dT%H%M%S%.3fZ");
+        fs::rename(&self.path, format!("{}.{}", self.path.display(), stamp))?;
+
+        let Some(retention) = self.retention else {
+            return Ok(());
+        };
+        let (Some(dir), Some(name)) = (self.path.parent(), self.path.file_name()) else {
+            return Ok(());
+        };
+        let prefix = format!("{}.", name.to_string_lossy());
+        let cutoff = SystemTime::now() - retention;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if !entry.file_name().to_string_lossy().starts_with(&prefix) {
+                continue;
+            }
+            if entry.metadata()?.modified()? < cutoff {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Format an event as one JSON line for the audit file. Who acted and why
+/// are pulled out of the details so they can be filtered on directly.
+pub fn format_audit_line(event: &AuditEvent<'_>) -> String {
+    let parsed = event.details.and_then(AuditDetails::parse);
+    let by = parsed.as_ref().and_then(|details| {
+        details.granted_by.as_ref()
+            .or(details.revoked_by.as_ref())
+            .or(details.approved_by.as_ref())
+            .or(details.requested_by.as_ref())
+    });
+    let details = match (&parsed, event.details) {
+        (Some(parsed), _) => serde_json::to_value(parsed).unwrap_or_default(),
+        (None, details) => serde_json::Value::from(details),
+    };
+
+    serde_json::json!({
+        "timestamp": event.timestamp.to_rfc3339(),
+        "action": event.action,
+        "user": event.username,
+        "command": event.command,
+        "by": by,
+        "reason": parsed.as_ref().and_then(|details| details.reason.as_ref()),
+        "details": details,
+    })
+    .to_string()
+}
+
+/// Build the RFC 5424 structured data for an event
+fn rfc5424_data(event: &AuditEvent<'_>) -> HashMap<String, HashMap<String, String>> {
+    let mut params = HashMap::new();
+    params.insert("timestamp".to_string(), escape_sd_value(&event.timestamp.to_rfc3339()));
+    params.insert("action".to_string(), escape_sd_value(event.action));
+    params.insert("username".to_string(), escape_sd_value(event.username));
+    params.insert("command".to_string(), escape_sd_value(event.command));
+    if let Some(details) = event.details {
+        params.insert("details".to_string(), escape_sd_value(details));
+    }
+
+    let mut data = HashMap::new();
+    data.insert(SD_ID.to_string(), params);
+    data
+}
+
+/// Format an event as a CEF record
+pub fn format_cef(event: &AuditEvent<'_>) -> String {
+    let mut record = format!(
+        "CEF:0|permctl|{}|{}|{}|Permission {}|5|rt={} duser={} cs1Label=command cs1={}",
+        escape_cef_header(env!("CARGO_PKG_NAME")),
+        escape_cef_header(env!("CARGO_PKG_VERSION")),
+        escape_cef_header(event.action),
+        escape_cef_header(event.action),
+        event.timestamp.timestamp_millis(),
+        escape_cef_extension(event.username),
+        escape_cef_extension(event.command),
+    );
+    if let Some(details) = event.details {
+        record.push_str(" msg=");
+        record.push_str(&escape_cef_extension(details));
+    }
+    record
+}
+
+fn escape_sd_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+fn escape_cef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+fn escape_cef_extension(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_cef() {
+        let event = AuditEvent {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            username: "alice",
+            command: "/usr/bin/docker",
+            action: "grant",
+            details: Some("Granted by root until a=b"),
+        };
+
+        let record = format_cef(&event);
+        assert!(record.starts_with("CEF:0|permctl|linux-permission-manager|"));
+        assert!(record.contains("|grant|Permission grant|5|"));
+        assert!(record.contains("rt=1700000000000 duser=alice cs1Label=command cs1=/usr/bin/docker"));
+        assert!(record.ends_with("msg=Granted by root until a\\=b"));
+    }
+
+    #[test]
+    fn test_audit_file_rotation() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("access.log");
+        let writer = AuditFileWriter::new(&path, 200, 30, Arc::new(WarningDeduplicator::new(Duration::ZERO)));
+        let details = r#"{"revoked_by":"admin","reason":"done"}"#;
+        let event = AuditEvent {
+            timestamp: Utc::now(),
+            username: "alice",
+            command: "/usr/bin/docker",
+            action: "revoke",
+            details: Some(details),
+        };
+
+        writer.append(&event).unwrap();
+        let line: serde_json::Value = serde_json::from_str(fs::read_to_string(&path).unwrap().trim()).unwrap();
+        assert_eq!((line["user"].as_str(), line["by"].as_str(), line["reason"].as_str()), (Some("alice"), Some("admin"), Some("done")));
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, AUDIT_FILE_MODE);
+
+        // A stale rotated file is removed at the next rotation
+        let stale = dir.path().join("access.log.20000101T000000.000Z");
+        fs::write(&stale, "old").unwrap();
+        let month_ago = SystemTime::now() - Duration::from_secs(31 * 86_400);
+        fs::File::options().write(true).open(&stale).unwrap().set_modified(month_ago).unwrap();
+
+        writer.append(&event).unwrap();
+        writer.append(&event).unwrap();
+        let rotated: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("access.log."))
+            .collect();
+        assert_eq!(rotated.len(), 1, "{:?}", rotated);
+        assert!(!stale.exists());
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_rfc5424_data_escaping() {
+        let event = AuditEvent {
+            timestamp: Utc::now(),
+            username: "alice",
+            command: "/usr/bin/docker",
+            action: "revoke",
+            details: Some("reason \"done\" [ok]"),
+        };
+
+        let data = rfc5424_data(&event);
+        let params = &data[SD_ID];
+        assert_eq!(params["action"], "revoke");
+        assert_eq!(params["details"], "reason \\\"done\\\" [ok\\]");
+    }
+}
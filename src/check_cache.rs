@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+/// A cached active grant
+struct Entry {
+    expires_at: DateTime<Utc>,
+    cached_at: Instant,
+}
+
+struct Entries {
+    grants: HashMap<(String, String), Entry>,
+    /// Bumped by every invalidation, so a lookup that raced with a revoke
+    /// cannot cache the grant it read before the revoke
+    generation: u64,
+    /// The database's grant generation the entries were cached under
+    stored_generation: Option<i64>,
+}
+
+/// In-memory record of recent positive `check_permission` results for
+/// long-running processes such as the socket server. Entries lapse when
+/// the grant expires or after the TTL, whichever comes first, and are
+/// evicted as soon as the grant changes through the same manager. Changes
+/// made by other processes bump a generation kept in the database, which
+/// is passed to `sync` before each lookup and clears the cache when it
+/// moves.
+pub struct CheckCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl CheckCache {
+    /// Create a cache holding up to `capacity` grants, each trusted for at
+    /// most `ttl`
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            entries: Mutex::new(Entries { grants: HashMap::new(), generation: 0, stored_generation: None }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        match self.entries.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Forget every cached grant if the database's grant generation is not
+    /// `stored_generation` any more, i.e. another process revoked something
+    /// or turned maintenance mode on
+    pub fn sync(&self, stored_generation: i64) {
+        let mut entries = self.lock();
+        if entries.stored_generation != Some(stored_generation) {
+            entries.stored_generation = Some(stored_generation);
+            entries.generation += 1;
+            entries.grants.clear();
+        }
+    }
+
+    /// Whether `username` is known to hold an active grant for `command`.
+    /// `false` means the database has to be asked.
+    pub fn contains(&self, username: &str, command: &str) -> bool {
+        self.contains_at(username, command, Utc::now(), Instant::now())
+    }
+
+    fn contains_at(&self, username: &str, command: &str, now: DateTime<Utc>, instant: Instant) -> bool {
+        let mut entries = self.lock();
+        let key = (username.to_string(), command.to_string());
+        match entries.grants.get(&key) {
+            Some(entry) if entry.expires_at > now && instant.duration_since(entry.cached_at) < self.ttl => true,
+            Some(_) => {
+                entries.grants.remove(&key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Current invalidation generation, to be read before querying the
+    /// database and passed to `insert` with the result
+    pub fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    /// Remember that `username` holds `command` until `expires_at`, unless
+    /// anything was invalidated since `generation` was read
+    pub fn insert(&self, username: &str, command: &str, expires_at: DateTime<Utc>, generation: u64) {
+        self.insert_at(username, command, expires_at, generation, Utc::now(), Instant::now());
+    }
+
+    fn insert_at(
+        &self,
+        username: &str,
+        command: &str,
+        expires_at: DateTime<Utc>,
+        generation: u64,
+        now: DateTime<Utc>,
+        instant: Instant,
+    ) {
+        let mut entries = self.lock();
+        if entries.generation != generation || self.capacity == 0 {
+            return;
+        }
+
+        if entries.grants.len() >= self.capacity {
+            let ttl = self.ttl;
+            entries.grants.retain(|_, entry| entry.expires_at > now && instant.duration_since(entry.cached_at) < ttl);
+        }
+        if entries.grants.len() >= self.capacity {
+            let oldest = entries.grants.iter().min_by_key(|(_, entry)| entry.cached_at).map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.grants.remove(&oldest);
+            }
+        }
+
+        entries.grants.insert((username.to_string(), command.to_string()), Entry { expires_at, cached_at: instant });
+    }
+
+    /// Forget every cached grant
+    pub fn clear(&self) {
+        let mut entries = self.lock();
+        entries.generation += 1;
+        entries.grants.clear();
+    }
+
+    /// Forget the grant of `command` to `username`
+    pub fn invalidate(&self, username: &str, command: &str) {
+        let mut entries = self.lock();
+        entries.generation += 1;
+        entries.grants.remove(&(username.to_string(), command.to_string()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_lapse_on_expiry_and_ttl() {
+        let cache = CheckCache::new(Duration::from_secs(5), 16);
+        let now = Utc::now();
+        let start = Instant::now();
+
+        cache.insert_at("alice", "/usr/bin/docker", now + chrono::Duration::hours(1), 0, now, start);
+        cache.insert_at("bob", "/usr/bin/docker", now + chrono::Duration::seconds(2), 0, now, start);
+        assert!(cache.contains_at("alice", "/usr/bin/docker", now, start + Duration::from_secs(4)));
+        assert!(!cache.contains_at("alice", "/usr/bin/other", now, start));
+
+        assert!(!cache.contains_at("bob", "/usr/bin/docker", now + chrono::Duration::seconds(3), start));
+        assert!(!cache.contains_at("alice", "/usr/bin/docker", now, start + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_invalidation_evicts_and_blocks_stale_inserts() {
+        let cache = CheckCache::new(Duration::from_secs(60), 16);
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+
+        cache.insert("alice", "/usr/bin/docker", expires_at, cache.generation());
+        assert!(cache.contains("alice", "/usr/bin/docker"));
+
+        // A lookup that read the database before a revoke must not re-add it
+        let before_revoke = cache.generation();
+        cache.invalidate("alice", "/usr/bin/docker");
+        assert!(!cache.contains("alice", "/usr/bin/docker"));
+        cache.insert("alice", "/usr/bin/docker", expires_at, before_revoke);
+        assert!(!cache.contains("alice", "/usr/bin/docker"));
+    }
+
+    #[test]
+    fn test_sync_clears_on_a_new_stored_generation() {
+        let cache = CheckCache::new(Duration::from_secs(60), 16);
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+
+        cache.sync(7);
+        cache.insert("alice", "/usr/bin/docker", expires_at, cache.generation());
+        cache.sync(7);
+        assert!(cache.contains("alice", "/usr/bin/docker"));
+
+        let before_revoke = cache.generation();
+        cache.sync(8);
+        assert!(!cache.contains("alice", "/usr/bin/docker"));
+        cache.insert("alice", "/usr/bin/docker", expires_at, before_revoke);
+        assert!(!cache.contains("alice", "/usr/bin/docker"));
+    }
+
+    #[test]
+    fn test_capacity_evicts_oldest() {
+        let cache = CheckCache::new(Duration::from_secs(60), 2);
+        let now = Utc::now();
+        let expires_at = now + chrono::Duration::hours(1);
+        let start = Instant::now();
+
+        cache.insert_at("alice", "/a", expires_at, 0, now, start);
+        cache.insert_at("bob", "/a", expires_at, 0, now, start + Duration::from_secs(1));
+        cache.insert_at("carol", "/a", expires_at, 0, now, start + Duration::from_secs(2));
+
+        let later = start + Duration::from_secs(3);
+        assert!(!cache.contains_at("alice", "/a", now, later));
+        assert!(cache.contains_at("bob", "/a", now, later));
+        assert!(cache.contains_at("carol", "/a", now, later));
+    }
+}
@@ -0,0 +1,130 @@
+use std::fs::{self, Metadata};
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::error::{PermissionError, Result};
+
+/// Check that no user other than root can change what `command` runs.
+///
+/// The command's path is the whole command if it names a file, or else its
+/// first whitespace-separated word, as for a command rendered from a
+/// template. Symlinks are resolved, then the target file must be owned by
+/// root and not writable by any group but root's or by others, and so must
+/// every directory above it and above the path as given, up to `/`. A user
+/// able to edit the target, or to replace it or a link to it, could run
+/// anything once granted the command. A command that does not exist is
+/// judged by the nearest directory that does, since whoever can create the
+/// file decides what it runs.
+pub fn check_command_target(command: &str) -> Result<()> {
+    let unsafe_target = |reason: String| PermissionError::UnsafeCommandTarget {
+        command: command.to_string(),
+        reason,
+    };
+
+    let path = command_path(command);
+    match fs::canonicalize(path) {
+        Ok(target) => {
+            let metadata = fs::metadata(&target).map_err(|e| PermissionError::io_error(e, target.clone()))?;
+            if let Some(problem) = writable_by_non_root(&metadata, false) {
+                return Err(unsafe_target(format!("{} is {}", target.display(), problem)));
+            }
+            check_directories(path, false).map_err(unsafe_target)?;
+            if target != path {
+                check_directories(&target, false).map_err(unsafe_target)?;
+            }
+            Ok(())
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => check_directories(path, true).map_err(unsafe_target),
+        Err(e) => Err(unsafe_target(format!("{} cannot be resolved: {}", path.display(), e))),
+    }
+}
+
+/// The file `command` runs
+fn command_path(command: &str) -> &Path {
+    let whole = Path::new(command);
+    if fs::symlink_metadata(whole).is_ok() {
+        return whole;
+    }
+    Path::new(command.split_whitespace().next().unwrap_or(command))
+}
+
+/// Check each existing directory above `path` up to `/`. With `missing`,
+/// `path` itself does not exist, so the nearest directory must not let
+/// anyone else create entries even if it is sticky.
+fn check_directories(path: &Path, mut missing: bool) -> std::result::Result<(), String> {
+    for dir in path.ancestors().skip(1).filter(|dir| !dir.as_os_str().is_empty()) {
+        let metadata = match fs::metadata(dir) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                missing = true;
+                continue;
+            }
+            Err(e) => return Err(format!("its directory {} cannot be read: {}", dir.display(), e)),
+        };
+        if let Some(problem) = writable_by_non_root(&metadata, !missing) {
+            return Err(format!("its directory {} is {}", dir.display(), problem));
+        }
+        missing = false;
+    }
+    Ok(())
+}
+
+/// How a file or directory can be written by someone other than root. In a
+/// sticky directory only an entry's owner may replace it, so with
+/// `sticky_protects` such a directory is safe when the entry is root's.
+fn writable_by_non_root(metadata: &Metadata, sticky_protects: bool) -> Option<String> {
+    let mode = metadata.mode();
+    if metadata.uid() != 0 {
+        Some(format!("owned by uid {}", metadata.uid()))
+    } else if sticky_protects && metadata.is_dir() && mode & 0o1000 != 0 {
+        None
+    } else if mode & 0o002 != 0 {
+        Some("writable by everyone".to_string())
+    } else if mode & 0o020 != 0 && metadata.gid() != 0 {
+        Some(format!("writable by group {}", metadata.gid()))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use tempfile::TempDir;
+
+    // Files the test creates belong to whoever runs it, so each unsafe case
+    // is unsafe both for root (a writable mode) and for anyone else (owner)
+    #[test]
+    fn test_writable_command_targets() {
+        assert!(check_command_target("/bin/sh").is_ok());
+        assert!(check_command_target("/bin/sh -c true").is_ok());
+        // Only root can create anything under /
+        assert!(check_command_target("/no/such/command").is_ok());
+
+        let temp_dir = TempDir::new().unwrap();
+        fs::set_permissions(temp_dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+        let script = temp_dir.path().join("deploy.sh");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o777)).unwrap();
+        let err = check_command_target(script.to_str().unwrap()).unwrap_err();
+        assert!(matches!(err, PermissionError::UnsafeCommandTarget { .. }));
+        let with_args = format!("{} --force", script.display());
+        assert!(check_command_target(&with_args).is_err());
+
+        // Links are judged by what they point to and by where they are
+        let unsafe_link = temp_dir.path().join("deploy");
+        symlink(&script, &unsafe_link).unwrap();
+        assert!(check_command_target(unsafe_link.to_str().unwrap()).is_err());
+        let replaceable_link = temp_dir.path().join("sh");
+        symlink("/bin/sh", &replaceable_link).unwrap();
+        let err = check_command_target(replaceable_link.to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("directory"), "{}", err);
+
+        // A missing command in a directory others control can be created
+        let missing = temp_dir.path().join("bin").join("missing");
+        let err = check_command_target(missing.to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("directory"), "{}", err);
+    }
+}
@@ -1,17 +1,43 @@
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::fs;
+use std::os::unix::fs::PermissionsExt;
 use serde::{Deserialize, Serialize};
 use directories::ProjectDirs;
-use chrono::Duration;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use rand::Rng;
+use regex::Regex;
+use tracing::warn;
 
+use crate::db::DatabaseTuning;
 use crate::error::{PermissionError, Result};
+use crate::notify::is_valid_webhook_url;
+
+/// Placeholder in a command template, e.g. `{service}`
+const TEMPLATE_PLACEHOLDER: &str = r"\{([A-Za-z_][A-Za-z0-9_]*)\}";
 
 /// Default configuration values
 const DEFAULT_CONFIG_FILENAME: &str = "config.yaml";
 const DEFAULT_SUDOERS_PATH: &str = "/etc/sudoers.d/permctl";
 const DEFAULT_DB_PATH: &str = "/var/lib/permctl/permissions.db";
 const DEFAULT_LOG_PATH: &str = "/var/log/permctl/access.log";
+const DEFAULT_RUNAS: &str = "ALL";
+
+/// Environment variables that override configuration file values
+const ENV_DB_PATH: &str = "PERMCTL_DB_PATH";
+const ENV_SUDOERS_PATH: &str = "PERMCTL_SUDOERS_PATH";
+const ENV_LOG_PATH: &str = "PERMCTL_LOG_PATH";
+const ENV_DEBUG: &str = "PERMCTL_DEBUG";
+/// Length of the rolling window `max_total_duration` is enforced over unless
+/// `total_duration_window_hours` is set
+pub const DEFAULT_TOTAL_DURATION_WINDOW_HOURS: u32 = 24;
+
+/// Longest duration accepted on the command line or as an idle timeout (ten
+/// years); per-command `max_duration` limits still apply on top of this
+pub const MAX_DURATION_MINUTES: i64 = 10 * 365 * 24 * 60;
+
+/// POSIX portable username pattern used unless `username_pattern` is set
+pub const DEFAULT_USERNAME_PATTERN: &str = "[a-z_][a-z0-9_-]*";
 
 /// Configuration for a specific command
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +46,9 @@ pub struct CommandConfig {
     pub description: String,
     /// Maximum duration in minutes that this command can be granted for
     pub max_duration: i64,
+    /// Minimum duration in minutes that this command can be granted for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_duration: Option<i64>,
     /// Groups that a user must be a member of to be granted this command
     pub required_groups: Vec<String>,
     /// Whether to audit all uses of this command
@@ -28,27 +57,272 @@ pub struct CommandConfig {
     /// Maximum concurrent users allowed for this command
     #[serde(default = "default_max_users")]
     pub max_concurrent_users: usize,
+    /// Runas spec users are allowed to run the command as (defaults to ALL)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runas: Option<String>,
+    /// Whether sudo should prompt for the user's password
+    #[serde(default)]
+    pub require_password: bool,
+    /// Maximum cumulative minutes that can be granted within the rolling
+    /// total duration window, across re-grants
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_duration: Option<i64>,
+    /// Free-form labels used to group and filter commands; these do not
+    /// affect authorization
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Whether grants must be approved by another operator before they
+    /// take effect
+    #[serde(default)]
+    pub requires_approval: bool,
+    /// Number of distinct operators who must approve a request before the
+    /// grant is made, when `requires_approval` is set
+    #[serde(default = "default_required_approvals")]
+    pub required_approvals: u32,
+    /// Number of recorded uses after which a grant is exhausted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<u32>,
+    /// Commands that may not be held by the same user at the same time as
+    /// this one, for separation of duties
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicts_with: Vec<String>,
+    /// Spread expirations by moving each grant's expiry up to this many
+    /// minutes earlier or later, so bulk grants do not all lapse at once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiry_jitter_minutes: Option<i64>,
+    /// Round the expiry of each grant to a whole minute or hour, so grants
+    /// end at tidy times
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiry_round_to: Option<ExpiryRounding>,
+    /// Require the granting admin to repeat the grant with a one-time token
+    /// before it takes effect, as a guard against accidental or scripted
+    /// grants
+    #[serde(default)]
+    pub require_confirmation: bool,
+    /// Webhook notified of grants, revocations and expiries of this command
+    /// instead of the global `notify_webhook`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify_webhook: Option<String>,
+    /// Regular expression the reason given with a grant must match, such as
+    /// `^(JIRA|INC)-\d+` to require a ticket reference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_pattern: Option<ReasonPattern>,
+    /// Revoke a grant once it has gone this many minutes without a recorded
+    /// use, counting from its start if it was never used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_minutes: Option<i64>,
 }
 
 impl CommandConfig {
     /// Validate the command configuration
     pub fn validate(&self) -> Result<()> {
+        match self.validation_errors().into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Collect every problem with the command configuration
+    pub fn validation_errors(&self) -> Vec<PermissionError> {
+        let mut errors = Vec::new();
+
         if self.max_duration <= 0 {
-            return Err(PermissionError::Config(
+            errors.push(PermissionError::Config(
                 format!("max_duration must be positive, got {}", self.max_duration)
             ));
         }
+        if let Some(min) = self.min_duration {
+            if min <= 0 {
+                errors.push(PermissionError::Config(
+                    format!("min_duration must be positive, got {}", min)
+                ));
+            } else if min > self.max_duration {
+                errors.push(PermissionError::Config(
+                    format!("min_duration ({}) must not exceed max_duration ({})", min, self.max_duration)
+                ));
+            }
+        }
         if self.max_concurrent_users == 0 {
-            return Err(PermissionError::Config(
+            errors.push(PermissionError::Config(
                 "max_concurrent_users must be at least 1".to_string()
             ));
         }
-        Ok(())
+        if let Some(total) = self.max_total_duration {
+            if total <= 0 {
+                errors.push(PermissionError::Config(
+                    format!("max_total_duration must be positive, got {}", total)
+                ));
+            }
+        }
+        if let Some(runas) = &self.runas {
+            let valid = !runas.trim().is_empty()
+                && runas.chars().all(|c| {
+                    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '%' | '#' | ':' | ',' | '!' | ' ')
+                });
+            if !valid {
+                errors.push(PermissionError::Config(
+                    format!("Invalid runas spec: {:?}", runas)
+                ));
+            }
+        }
+
+        if self.required_approvals == 0 {
+            errors.push(PermissionError::Config(
+                "required_approvals must be at least 1".to_string()
+            ));
+        }
+        if self.max_uses == Some(0) {
+            errors.push(PermissionError::Config(
+                "max_uses must be at least 1".to_string()
+            ));
+        }
+        if let Some(jitter) = self.expiry_jitter_minutes {
+            if jitter <= 0 {
+                errors.push(PermissionError::Config(
+                    format!("expiry_jitter_minutes must be positive, got {}", jitter)
+                ));
+            }
+        }
+        if let Some(idle) = self.idle_timeout_minutes {
+            if idle <= 0 {
+                errors.push(PermissionError::Config(
+                    format!("idle_timeout_minutes must be positive, got {}", idle)
+                ));
+            } else if idle > MAX_DURATION_MINUTES {
+                errors.push(PermissionError::Config(
+                    format!("idle_timeout_minutes must not exceed {}, got {}", MAX_DURATION_MINUTES, idle)
+                ));
+            }
+        }
+        if let Some(url) = &self.notify_webhook {
+            if !is_valid_webhook_url(url) {
+                errors.push(PermissionError::Config(
+                    format!("notify_webhook must be an http or https URL, got {:?}", url)
+                ));
+            }
+        }
+        if self.tags.iter().any(|tag| tag.trim().is_empty()) {
+            errors.push(PermissionError::Config(
+                "tags must be non-empty strings".to_string()
+            ));
+        }
+
+        errors
+    }
+
+    /// Whether the command carries the given tag
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// How long a grant may go unused before it is revoked, if ever
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_minutes.and_then(Duration::try_minutes)
+    }
+
+    /// Build the runas and tag part of a sudoers rule, e.g. `(ALL) NOPASSWD:`
+    pub fn sudoers_spec(&self) -> String {
+        self.grant_sudoers_spec(false)
+    }
+
+    /// Like `sudoers_spec`, for a grant that may require the user's
+    /// password even though the command does not
+    pub fn grant_sudoers_spec(&self, require_password: bool) -> String {
+        let runas = self.runas.as_deref().unwrap_or(DEFAULT_RUNAS);
+        if self.require_password || require_password {
+            format!("({})", runas)
+        } else {
+            format!("({}) NOPASSWD:", runas)
+        }
     }
 
     /// Convert max_duration to chrono::Duration
     pub fn max_duration_as_duration(&self) -> Duration {
-        Duration::minutes(self.max_duration)
+        Duration::try_minutes(self.max_duration).unwrap_or(Duration::MAX)
+    }
+
+    /// Check the reason given with a grant against `reason_pattern`. A
+    /// missing reason fails any pattern.
+    pub fn check_reason(&self, reason: Option<&str>) -> Result<()> {
+        let Some(pattern) = &self.reason_pattern else {
+            return Ok(());
+        };
+        if reason.is_some_and(|reason| pattern.is_match(reason)) {
+            return Ok(());
+        }
+        Err(PermissionError::Config(format!(
+            "reason must match pattern {}",
+            pattern
+        )))
+    }
+
+    /// Apply `expiry_round_to` to the expiry of a grant requested for
+    /// `requested` and starting at `start`. The nearest boundary is used if
+    /// the grant then still lasts at least `min_duration` (or a minute) and
+    /// at most `max_duration` and `requested` plus one unit, otherwise the
+    /// boundary on the other side, and otherwise `expires_at` unchanged.
+    pub fn rounded_expiry(&self, start: DateTime<Utc>, expires_at: DateTime<Utc>, requested: Duration) -> DateTime<Utc> {
+        let Some(unit) = self.expiry_round_to.map(ExpiryRounding::unit) else {
+            return expires_at;
+        };
+        let Ok(down) = expires_at.duration_trunc(unit) else {
+            return expires_at;
+        };
+        if down == expires_at {
+            return expires_at;
+        }
+        let up = down + unit;
+
+        let shortest = Duration::minutes(self.min_duration.unwrap_or(1).max(1));
+        let longest = self.max_duration_as_duration().min(requested.checked_add(&unit).unwrap_or(Duration::MAX));
+        let candidates = if expires_at - down < up - expires_at { [down, up] } else { [up, down] };
+        candidates
+            .into_iter()
+            .find(|candidate| (shortest..=longest).contains(&(*candidate - start)))
+            .unwrap_or(expires_at)
+    }
+
+    /// Apply `expiry_jitter_minutes` to a requested grant duration. The
+    /// result never exceeds `max_duration` and never drops below a minute or
+    /// `min_duration` (or the requested duration, if that is shorter).
+    pub fn jittered_duration<R: Rng + ?Sized>(&self, duration: Duration, rng: &mut R) -> Duration {
+        let Some(jitter) = self.expiry_jitter_minutes.filter(|j| *j > 0) else {
+            return duration;
+        };
+
+        let jitter_secs = jitter * 60;
+        let offset = Duration::seconds(rng.gen_range(-jitter_secs..=jitter_secs));
+        let floor = duration.min(Duration::minutes(self.min_duration.unwrap_or(1).max(1)));
+        (duration + offset).clamp(floor, self.max_duration_as_duration().max(duration))
+    }
+}
+
+/// An hour-long grant with no further restrictions, and the defaults
+/// deserialization applies to every optional field
+impl Default for CommandConfig {
+    fn default() -> Self {
+        Self {
+            description: String::new(),
+            max_duration: 60,
+            min_duration: None,
+            required_groups: Vec::new(),
+            audit_usage: false,
+            max_concurrent_users: default_max_users(),
+            runas: None,
+            require_password: false,
+            max_total_duration: None,
+            tags: Vec::new(),
+            requires_approval: false,
+            required_approvals: default_required_approvals(),
+            max_uses: None,
+            conflicts_with: Vec::new(),
+            expiry_jitter_minutes: None,
+            expiry_round_to: None,
+            require_confirmation: false,
+            notify_webhook: None,
+            reason_pattern: None,
+            idle_timeout_minutes: None,
+        }
     }
 }
 
@@ -56,31 +330,554 @@ fn default_max_users() -> usize {
     10
 }
 
+fn default_required_approvals() -> u32 {
+    1
+}
+
+/// Settings shared by every command and template, each applied wherever
+/// the command does not set it itself
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandConfigDefaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_duration: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_duration: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub required_groups: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_usage: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_users: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runas: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub require_password: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_duration: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requires_approval: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub required_approvals: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiry_jitter_minutes: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiry_round_to: Option<ExpiryRounding>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub require_confirmation: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_pattern: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_minutes: Option<i64>,
+}
+
+impl CommandConfigDefaults {
+    /// Whether no default is set
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Copy each value in `command_defaults` into every command and template
+/// that lacks that key, before the configuration is deserialized. This has
+/// to happen on the raw YAML, since a deserialized `CommandConfig` cannot
+/// tell an omitted `audit_usage` from an explicit `false`.
+fn apply_command_defaults(value: &mut serde_yaml::Value) -> Result<()> {
+    let Some(defaults) = value.get("command_defaults") else {
+        return Ok(());
+    };
+    let defaults: CommandConfigDefaults = serde_yaml::from_value(defaults.clone())
+        .map_err(|e| PermissionError::Config(format!("Invalid command_defaults: {}", e)))?;
+    let serde_yaml::Value::Mapping(defaults) = serde_yaml::to_value(&defaults)
+        .map_err(|e| PermissionError::Config(format!("Invalid command_defaults: {}", e)))?
+    else {
+        return Ok(());
+    };
+
+    for section in ["allowed_commands", "templates"] {
+        let Some(serde_yaml::Value::Mapping(entries)) = value.get_mut(section) else {
+            continue;
+        };
+        for (_, entry) in entries.iter_mut() {
+            let serde_yaml::Value::Mapping(entry) = entry else {
+                continue;
+            };
+            for (key, default) in &defaults {
+                if !entry.contains_key(key) {
+                    entry.insert(key.clone(), default.clone());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Wire format for audit events sent to syslog
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFormat {
+    /// RFC 5424 message with the event as structured data
+    #[default]
+    Rfc5424,
+    /// ArcSight Common Event Format
+    Cef,
+}
+
+/// How a grant is made for a command the user already holds, while that
+/// grant is neither revoked nor expired. A revoked or expired grant is
+/// always replaced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegrantPolicy {
+    /// Overwrite the held grant with the new one. Anyone allowed to grant
+    /// can shorten or lengthen existing access, and the earlier grant's
+    /// start time and correlation ID are lost from the grants table.
+    #[default]
+    Replace,
+    /// Keep the held grant, moving its expiry later if the new one lasts
+    /// longer. Access can only grow, so a mistaken short re-grant cannot cut
+    /// someone off, but revoking is the only way to shorten it.
+    Extend,
+    /// Refuse the grant with `GrantExists`. Held access cannot be changed
+    /// by re-granting at all; it must be revoked first.
+    Reject,
+}
+
+/// A command's `reason_pattern`, compiled when the configuration is loaded
+/// so an invalid pattern is a load error and grants do not recompile it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ReasonPattern(Regex);
+
+impl ReasonPattern {
+    /// Compile `pattern`
+    pub fn new(pattern: &str) -> Result<Self> {
+        Regex::new(pattern)
+            .map(Self)
+            .map_err(|e| PermissionError::Config(format!("Invalid reason_pattern {:?}: {}", pattern, e)))
+    }
+
+    /// Whether `reason` matches the pattern
+    pub fn is_match(&self, reason: &str) -> bool {
+        self.0.is_match(reason)
+    }
+}
+
+impl TryFrom<String> for ReasonPattern {
+    type Error = PermissionError;
+
+    fn try_from(pattern: String) -> Result<Self> {
+        Self::new(&pattern)
+    }
+}
+
+impl From<ReasonPattern> for String {
+    fn from(pattern: ReasonPattern) -> Self {
+        pattern.0.as_str().to_string()
+    }
+}
+
+impl PartialEq for ReasonPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl std::fmt::Display for ReasonPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0.as_str())
+    }
+}
+
+/// Boundary that grant expiries are rounded to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpiryRounding {
+    Minute,
+    Hour,
+}
+
+impl ExpiryRounding {
+    /// Length of the interval between two boundaries
+    pub fn unit(self) -> Duration {
+        match self {
+            ExpiryRounding::Minute => Duration::minutes(1),
+            ExpiryRounding::Hour => Duration::hours(1),
+        }
+    }
+}
+
+/// How much is recorded in the structured details of each audit log entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditDetail {
+    /// Who acted, the grant window and the identifiers linking entries
+    /// together. Free-text reasons, which may name people or tickets, and
+    /// derived values such as durations are left out.
+    Minimal,
+    /// Everything known about the event
+    #[default]
+    Full,
+}
+
+/// A parameterised command such as `/usr/bin/systemctl restart {service}`.
+/// Each placeholder is replaced by one of its allowed values when granting,
+/// and the resulting command is what ends up in sudoers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandTemplate {
+    /// Absolute command with `{name}` placeholders
+    pub command: String,
+    /// Values each placeholder may take
+    #[serde(default)]
+    pub variables: HashMap<String, Vec<String>>,
+    /// Policy for every command rendered from the template
+    #[serde(flatten)]
+    pub policy: CommandConfig,
+}
+
+impl CommandTemplate {
+    /// Names of the placeholders in the command, in order of appearance
+    pub fn placeholders(&self) -> Vec<String> {
+        placeholder_regex()
+            .captures_iter(&self.command)
+            .map(|caps| caps[1].to_string())
+            .collect()
+    }
+
+    /// Substitute `values` into the command. Every placeholder needs a value
+    /// from its allowed list, and no other variables may be given.
+    pub fn render(&self, values: &HashMap<String, String>) -> Result<String> {
+        let placeholders = self.placeholders();
+        let mut names: Vec<&String> = values.keys().collect();
+        names.sort();
+        if let Some(unknown) = names.into_iter().find(|name| !placeholders.contains(name)) {
+            return Err(PermissionError::CommandNotAllowed(
+                format!("{} has no variable {}", self.command, unknown)
+            ));
+        }
+
+        for name in &placeholders {
+            let value = values.get(name).ok_or_else(|| PermissionError::CommandNotAllowed(
+                format!("{} needs a value for {}", self.command, name)
+            ))?;
+            if !self.variables.get(name).is_some_and(|allowed| allowed.contains(value)) {
+                return Err(PermissionError::CommandNotAllowed(
+                    format!("{} is not an allowed value for {} in {}", value, name, self.command)
+                ));
+            }
+        }
+
+        Ok(placeholder_regex()
+            .replace_all(&self.command, |caps: &regex::Captures| values[&caps[1]].clone())
+            .into_owned())
+    }
+
+    /// Whether `command` is this template rendered with allowed values
+    pub fn matches(&self, command: &str) -> bool {
+        let mut pattern = String::from("^");
+        let mut last = 0;
+        for caps in placeholder_regex().captures_iter(&self.command) {
+            let whole = caps.get(0).expect("capture group 0 always matches");
+            pattern.push_str(&regex::escape(&self.command[last..whole.start()]));
+            let values: Vec<String> = self.variables
+                .get(&caps[1])
+                .map(|allowed| allowed.iter().map(|v| regex::escape(v)).collect())
+                .unwrap_or_default();
+            if values.is_empty() {
+                return false;
+            }
+            pattern.push_str(&format!("(?:{})", values.join("|")));
+            last = whole.end();
+        }
+        pattern.push_str(&regex::escape(&self.command[last..]));
+        pattern.push('$');
+
+        Regex::new(&pattern).is_ok_and(|re| re.is_match(command))
+    }
+
+    /// Collect every problem with the template and its policy
+    pub fn validation_errors(&self) -> Vec<PermissionError> {
+        let mut errors = self.policy.validation_errors();
+
+        if !self.command.starts_with('/') {
+            errors.push(PermissionError::Config(
+                format!("Command path must be absolute: {}", self.command)
+            ));
+        }
+        if let Some(c) = unsafe_sudoers_char(&self.command) {
+            errors.push(PermissionError::Config(
+                format!("command contains {:?}, which cannot be written to sudoers safely", c)
+            ));
+        }
+
+        let placeholders = self.placeholders();
+        if placeholders.is_empty() {
+            errors.push(PermissionError::Config(
+                "command has no {placeholders}; list it under allowed_commands instead".to_string()
+            ));
+        }
+        for name in &placeholders {
+            match self.variables.get(name) {
+                Some(values) if !values.is_empty() => {}
+                _ => errors.push(PermissionError::Config(
+                    format!("variable {} needs at least one allowed value", name)
+                )),
+            }
+        }
+
+        let mut names: Vec<_> = self.variables.iter().collect();
+        names.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, values) in names {
+            if !placeholders.contains(name) {
+                errors.push(PermissionError::Config(
+                    format!("variable {} does not appear in the command", name)
+                ));
+            }
+            // Values end up verbatim in a sudoers rule
+            for value in values {
+                let valid = !value.is_empty()
+                    && value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '@' | '/'));
+                if !valid {
+                    errors.push(PermissionError::Config(
+                        format!("variable {} has invalid value {:?}", name, value)
+                    ));
+                }
+            }
+        }
+
+        errors
+    }
+}
+
+fn placeholder_regex() -> Regex {
+    Regex::new(TEMPLATE_PLACEHOLDER).expect("placeholder pattern is valid")
+}
+
+/// Settings for exporting audit events to syslog
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyslogConfig {
+    /// Format of the emitted events
+    #[serde(default)]
+    pub format: SyslogFormat,
+}
+
+/// Settings for caching positive permission checks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CheckCacheConfig {
+    /// Seconds a cached grant is trusted before the database is asked
+    /// again. Revocations made by other processes take up to this long to
+    /// be seen.
+    #[serde(default = "default_check_cache_ttl_secs")]
+    pub ttl_secs: u64,
+    /// Most user and command pairs kept at once
+    #[serde(default = "default_check_cache_capacity")]
+    pub capacity: usize,
+}
+
+impl Default for CheckCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: default_check_cache_ttl_secs(),
+            capacity: default_check_cache_capacity(),
+        }
+    }
+}
+
 /// Main configuration structure
+///
+/// Settings are resolved with increasing precedence from built-in defaults,
+/// the configuration file, and finally `PERMCTL_*` environment variables
+/// (see [`Config::apply_env_overrides`]).
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     /// Map of command paths to their configurations
     pub allowed_commands: HashMap<String, CommandConfig>,
+
+    /// Named command templates, granted with `grant --template`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub templates: HashMap<String, CommandTemplate>,
+
+    /// Settings applied to every command and template that does not set
+    /// them; already merged in once the configuration is loaded
+    #[serde(default, skip_serializing_if = "CommandConfigDefaults::is_empty")]
+    pub command_defaults: CommandConfigDefaults,
     
     /// Path to the sudoers.d file for this application
     #[serde(default = "default_sudoers_path")]
     pub sudoers_path: PathBuf,
+
+    /// Whether to write the sudoers file at all. Turning this off records
+    /// grants in the database only, for dry testing and CI.
+    #[serde(default = "default_true")]
+    pub sudoers_enabled: bool,
+
+    /// Comment block written at the top of the sudoers file in place of the
+    /// default notice. Every line must be a `#` comment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sudoers_header: Option<String>,
     
     /// Path to the SQLite database
     #[serde(default = "default_db_path")]
     pub db_path: PathBuf,
+
+    /// Milliseconds to wait for a database lock held by another permctl
+    /// process before giving up
+    #[serde(default = "default_db_busy_timeout_ms")]
+    pub db_busy_timeout_ms: u32,
+
+    /// Pages the SQLite write-ahead log may reach before it is checkpointed
+    /// into the database; 0 turns automatic checkpoints off
+    #[serde(default = "default_db_wal_autocheckpoint")]
+    pub db_wal_autocheckpoint: u32,
+
+    /// File holding the SQLCipher passphrase the database is encrypted
+    /// with. Requires a SQLite library built with SQLCipher; the file must
+    /// only be readable by its owner, the user running permctl.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub db_encryption_key_path: Option<PathBuf>,
     
-    /// Path to the log file
+    /// Path to the audit log file, which mirrors every audit event as a
+    /// JSON line
     #[serde(default = "default_log_path")]
     pub log_path: PathBuf,
+
+    /// Size in bytes at which the audit log file is rotated; 0 never rotates
+    #[serde(default = "default_log_max_bytes")]
+    pub log_max_bytes: u64,
     
     /// Whether to enable debug logging
     #[serde(default)]
     pub debug: bool,
     
-    /// Number of days to keep audit logs
+    /// Number of days to keep rotated audit log files; 0 keeps them forever
     #[serde(default = "default_log_retention")]
     pub log_retention_days: u32,
+
+    /// Seconds during which a repeated identical warning, such as a failing
+    /// sudoers refresh in the daemon, is logged only once; 0 logs every one
+    #[serde(default = "default_log_dedup_secs")]
+    pub log_dedup_secs: u64,
+
+    /// Seconds a grant confirmation token stays valid for commands with
+    /// `require_confirmation`
+    #[serde(default = "default_confirmation_timeout_secs")]
+    pub confirmation_timeout_secs: u64,
+
+    /// Hours in the rolling window each command's `max_total_duration` is
+    /// enforced over
+    #[serde(default = "default_total_duration_window_hours")]
+    pub total_duration_window_hours: u32,
+
+    /// Export audit events to the local syslog (AUTHPRIV facility)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub syslog: Option<SyslogConfig>,
+
+    /// Cache positive permission checks in long-running processes such as
+    /// `permctl serve`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check_cache: Option<CheckCacheConfig>,
+
+    /// Delete revoked grants from the database this many days after they
+    /// were revoked. Audit log entries are kept regardless.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hard_delete_after_days: Option<u32>,
+
+    /// Expire grant requests still pending this many minutes after they
+    /// were made, so stale requests cannot be approved long after the fact
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval_request_ttl_minutes: Option<u32>,
+
+    /// Refuse new grants while the system clock is behind the last time
+    /// recorded in the database
+    #[serde(default)]
+    pub strict_clock: bool,
+
+    /// Refuse to grant commands whose target file or directory users other
+    /// than root can write to, instead of only warning
+    #[serde(default)]
+    pub reject_writable_commands: bool,
+
+    /// Whether a reason must be given when revoking permissions
+    #[serde(default)]
+    pub require_reason: bool,
+
+    /// Group whose members may use the local socket API in addition to root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_group: Option<String>,
+
+    /// File holding the bearer token clients of the HTTP API (`permctl
+    /// http`) must present; the HTTP API refuses to start without one.
+    /// Anyone holding the token can grant and revoke, so the file must only
+    /// be readable by its owner, the user running permctl.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_token_path: Option<PathBuf>,
+
+    /// Groups whose members may grant, approve, revoke, clean up and
+    /// re-initialize. When empty, anyone able to run permctl may.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub admin_groups: Vec<String>,
+
+    /// Whether root may run those operations without belonging to one of
+    /// `admin_groups`
+    #[serde(default = "default_true")]
+    pub admin_root_bypass: bool,
+
+    /// Only let admins grant or approve commands they hold themselves or
+    /// that `admin_commands` lists for them. Root is exempt.
+    #[serde(default)]
+    pub delegation_mode: bool,
+
+    /// Commands each admin, by username, may grant under `delegation_mode`
+    /// without holding them
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub admin_commands: HashMap<String, Vec<String>>,
+
+    /// Let `grant --notify-user` tell the recipient about the grant on their
+    /// terminals with write(1)
+    #[serde(default)]
+    pub user_notifications: bool,
+
+    /// Re-run commands that change grants under `sudo` when started without
+    /// root, instead of refusing them
+    #[serde(default)]
+    pub auto_sudo: bool,
+
+    /// Periodically re-check the `required_groups` of every active grant
+    /// and revoke grants whose holder has left a required group. Off by
+    /// default since it looks up the groups of every grant holder.
+    #[serde(default)]
+    pub revalidate_groups: bool,
+
+    /// Webhook receiving a JSON POST for every grant, revocation and expiry,
+    /// unless the command sets its own `notify_webhook`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify_webhook: Option<String>,
+
+    /// What granting a command the user already holds does
+    #[serde(default)]
+    pub regrant_policy: RegrantPolicy,
+
+    /// How much each audit log entry records about the event
+    #[serde(default)]
+    pub audit_detail: AuditDetail,
+
+    /// Write audit entries for uses and revocations from a background task
+    /// in batches instead of inline with the operation. This speeds up
+    /// `permctl check --record`, but entries still queued when the process
+    /// crashes or is killed are lost; grants and other changes are always
+    /// audited in the same transaction and are unaffected.
+    #[serde(default)]
+    pub async_audit: bool,
+
+    /// Regular expression usernames must match in full before they are
+    /// granted anything; defaults to the POSIX portable username pattern
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username_pattern: Option<String>,
 }
 
 fn default_sudoers_path() -> PathBuf {
@@ -91,14 +888,84 @@ fn default_db_path() -> PathBuf {
     PathBuf::from(DEFAULT_DB_PATH)
 }
 
+fn default_db_busy_timeout_ms() -> u32 {
+    DatabaseTuning::default().busy_timeout_ms
+}
+
+fn default_db_wal_autocheckpoint() -> u32 {
+    DatabaseTuning::default().wal_autocheckpoint
+}
+
 fn default_log_path() -> PathBuf {
     PathBuf::from(DEFAULT_LOG_PATH)
 }
 
+fn default_log_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
 fn default_log_retention() -> u32 {
     30
 }
 
+fn default_log_dedup_secs() -> u64 {
+    300
+}
+
+fn default_confirmation_timeout_secs() -> u64 {
+    120
+}
+
+fn default_total_duration_window_hours() -> u32 {
+    DEFAULT_TOTAL_DURATION_WINDOW_HOURS
+}
+
+fn default_check_cache_ttl_secs() -> u64 {
+    5
+}
+
+fn default_check_cache_capacity() -> usize {
+    1024
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Whether sudoers would treat the line purely as a comment. `#include` and
+/// `#includedir` are directives, and `#` followed by digits is a user ID, so
+/// neither is allowed even though both start with `#`.
+fn is_sudoers_comment(line: &str) -> bool {
+    let Some(rest) = line.trim_start().strip_prefix('#') else {
+        return false;
+    };
+    !rest.starts_with(|c: char| c.is_ascii_digit()) && !rest.starts_with("include")
+}
+
+/// Characters sudoers treats as syntax inside a command path or argument;
+/// each is taken literally when preceded by a backslash
+const SUDOERS_ESCAPED: &[char] = &[',', ':', '=', ' ', '\t', '#'];
+
+/// Escape one word of a command (the path or a single argument) so sudoers
+/// reads it literally
+pub fn sudoers_escape(word: &str) -> String {
+    let mut escaped = String::with_capacity(word.len());
+    for c in word.chars() {
+        if SUDOERS_ESCAPED.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// First character of a command path that cannot be written to sudoers
+/// safely: backslashes cannot be escaped in a path, control characters
+/// could start a new rule, and glob characters would match other commands
+fn unsafe_sudoers_char(path: &str) -> Option<char> {
+    path.chars().find(|&c| c == '\\' || c.is_control() || matches!(c, '*' | '?' | '[' | ']'))
+}
+
 impl Config {
     /// Load configuration from the default location
     pub fn load() -> Result<Self> {
@@ -106,51 +973,384 @@ impl Config {
         Self::load_from(config_path)
     }
 
-    /// Load configuration from a specific path
+    /// Load configuration from a specific path, applying environment overrides
     pub fn load_from<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::load_with_overrides(path, |key| std::env::var(key).ok())
+    }
+
+    /// Load configuration from `path`, taking overrides from `lookup`
+    /// instead of the environment
+    fn load_with_overrides<P, F>(path: P, lookup: F) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut config = Self::parse_from(path)?;
+        config.apply_overrides(lookup)?;
+        config.validate()?;
+        for warning in config.validation_warnings() {
+            warn!("{}", warning);
+        }
+        Ok(config)
+    }
+
+    /// Parse a configuration file without validating it
+    pub fn parse_from<P: AsRef<Path>>(path: P) -> Result<Self> {
         let content = fs::read_to_string(&path).map_err(|e| {
             PermissionError::io_error(e, path.as_ref().to_path_buf())
         })?;
 
-        let config: Config = serde_yaml::from_str(&content)
-            .map_err(|e| PermissionError::Config(format!("Invalid config format: {}", e)))?;
+        Self::parse_yaml(&content)
+    }
+
+    /// Parse configuration YAML, resolving `<<` merge keys and applying
+    /// `command_defaults`
+    pub(crate) fn parse_yaml(content: &str) -> Result<Self> {
+        let invalid = |e: serde_yaml::Error| PermissionError::Config(format!("Invalid config format: {}", e));
 
-        config.validate()?;
+        let mut value: serde_yaml::Value = serde_yaml::from_str(content).map_err(invalid)?;
+        value.apply_merge().map_err(invalid)?;
+        apply_command_defaults(&mut value)?;
+        let mut config: Self = serde_yaml::from_value(value).map_err(invalid)?;
+        config.normalize_commands()?;
         Ok(config)
     }
 
-    /// Get the default configuration path
-    pub fn default_config_path() -> Result<PathBuf> {
-        let proj_dirs = ProjectDirs::from("com", "yourorg", "permctl")
-            .ok_or_else(|| PermissionError::Config(
-                "Could not determine config directory".to_string()
-            ))?;
+    /// Key `allowed_commands` and their `conflicts_with` lists by the
+    /// normalized command, so they compare equal to normalized requests
+    fn normalize_commands(&mut self) -> Result<()> {
+        let mut normalized = HashMap::with_capacity(self.allowed_commands.len());
+        for (command, mut config) in self.allowed_commands.drain() {
+            for conflict in &mut config.conflicts_with {
+                *conflict = normalize_command(conflict);
+            }
+            let key = normalize_command(&command);
+            if normalized.insert(key.clone(), config).is_some() {
+                return Err(PermissionError::Config(format!(
+                    "allowed_commands lists {} more than once after normalization",
+                    key
+                )));
+            }
+        }
+        self.allowed_commands = normalized;
+        Ok(())
+    }
 
-        Ok(proj_dirs.config_dir().join(DEFAULT_CONFIG_FILENAME))
+    /// Override settings from `PERMCTL_DB_PATH`, `PERMCTL_SUDOERS_PATH`,
+    /// `PERMCTL_LOG_PATH` and `PERMCTL_DEBUG` when they are set
+    pub fn apply_env_overrides(&mut self) -> Result<()> {
+        self.apply_overrides(|key| std::env::var(key).ok())
+    }
+
+    /// Apply overrides from an arbitrary variable lookup
+    fn apply_overrides<F>(&mut self, lookup: F) -> Result<()>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if let Some(path) = lookup(ENV_DB_PATH) {
+            self.db_path = PathBuf::from(path);
+        }
+        if let Some(path) = lookup(ENV_SUDOERS_PATH) {
+            self.sudoers_path = PathBuf::from(path);
+        }
+        if let Some(path) = lookup(ENV_LOG_PATH) {
+            self.log_path = PathBuf::from(path);
+        }
+        if let Some(value) = lookup(ENV_DEBUG) {
+            self.debug = match value.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => true,
+                "0" | "false" | "no" | "off" | "" => false,
+                _ => {
+                    return Err(PermissionError::Config(
+                        format!("Invalid boolean in {}: {:?}", ENV_DEBUG, value)
+                    ));
+                }
+            };
+        }
+        Ok(())
+    }
+
+    /// Directories that must exist for the configured paths. The sudoers
+    /// directory is only included when sudoers management is enabled.
+    pub fn required_directories(&self) -> Vec<&Path> {
+        let mut dirs = vec![self.db_path.parent(), self.log_path.parent()];
+        if self.sudoers_enabled {
+            dirs.push(self.sudoers_path.parent());
+        }
+        dirs.into_iter().flatten().collect()
+    }
+
+    /// Get the default configuration path
+    pub fn default_config_path() -> Result<PathBuf> {
+        let proj_dirs = ProjectDirs::from("com", "yourorg", "permctl")
+            .ok_or_else(|| PermissionError::Config(
+                "Could not determine config directory".to_string()
+            ))?;
+
+        Ok(proj_dirs.config_dir().join(DEFAULT_CONFIG_FILENAME))
     }
 
     /// Validate the entire configuration
     pub fn validate(&self) -> Result<()> {
-        // Validate command configurations
-        for (cmd, config) in &self.allowed_commands {
+        match self.validation_errors().into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Collect every problem with the configuration rather than stopping at
+    /// the first one
+    pub fn validation_errors(&self) -> Vec<PermissionError> {
+        let mut errors = Vec::new();
+
+        // Validate command configurations in a stable order
+        let mut commands: Vec<_> = self.allowed_commands.iter().collect();
+        commands.sort_by(|a, b| a.0.cmp(b.0));
+
+        for (cmd, config) in commands {
             if !cmd.starts_with('/') {
-                return Err(PermissionError::Config(
+                errors.push(PermissionError::Config(
                     format!("Command path must be absolute: {}", cmd)
                 ));
             }
-            config.validate()?;
+            if let Some(c) = unsafe_sudoers_char(cmd) {
+                errors.push(PermissionError::Config(
+                    format!("Command path {:?} contains {:?}, which cannot be written to sudoers safely", cmd, c)
+                ));
+            }
+            errors.extend(config.validation_errors().into_iter().map(|e| match e {
+                PermissionError::Config(msg) => PermissionError::Config(format!("{}: {}", cmd, msg)),
+                other => other,
+            }));
+            for conflict in &config.conflicts_with {
+                if conflict == cmd {
+                    errors.push(PermissionError::Config(
+                        format!("{}: conflicts_with cannot list the command itself", cmd)
+                    ));
+                } else if !self.allowed_commands.contains_key(conflict) {
+                    errors.push(PermissionError::Config(
+                        format!("{}: conflicts_with references unknown command {}", cmd, conflict)
+                    ));
+                }
+            }
+        }
+
+        let mut templates: Vec<_> = self.templates.iter().collect();
+        templates.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, template) in templates {
+            errors.extend(template.validation_errors().into_iter().map(|e| match e {
+                PermissionError::Config(msg) => PermissionError::Config(format!("template {}: {}", name, msg)),
+                other => other,
+            }));
         }
 
         // Validate paths
-        for path in &[&self.sudoers_path, &self.db_path, &self.log_path] {
+        for path in [&self.sudoers_path, &self.db_path, &self.log_path]
+            .into_iter()
+            .chain(&self.db_encryption_key_path)
+            .chain(&self.api_token_path)
+        {
             if !path.is_absolute() {
-                return Err(PermissionError::Config(
+                errors.push(PermissionError::Config(
                     format!("Path must be absolute: {:?}", path)
                 ));
             }
         }
 
-        Ok(())
+        if let Err(e) = self.username_regex() {
+            errors.push(e);
+        }
+
+        if let Some(url) = &self.notify_webhook {
+            if !is_valid_webhook_url(url) {
+                errors.push(PermissionError::Config(
+                    format!("notify_webhook must be an http or https URL, got {:?}", url)
+                ));
+            }
+        }
+
+        if self.admin_groups.iter().any(|group| group.trim().is_empty()) {
+            errors.push(PermissionError::Config(
+                "admin_groups must be non-empty group names".to_string()
+            ));
+        }
+
+        if self.approval_request_ttl_minutes == Some(0) {
+            errors.push(PermissionError::Config(
+                "approval_request_ttl_minutes must be at least 1".to_string()
+            ));
+        }
+
+        if self.confirmation_timeout_secs == 0 {
+            errors.push(PermissionError::Config(
+                "confirmation_timeout_secs must be at least 1".to_string()
+            ));
+        }
+
+        if self.total_duration_window_hours == 0 {
+            errors.push(PermissionError::Config(
+                "total_duration_window_hours must be at least 1".to_string()
+            ));
+        }
+
+        // SQLite reads both settings as a signed 32-bit integer
+        for (name, value) in [
+            ("db_busy_timeout_ms", self.db_busy_timeout_ms),
+            ("db_wal_autocheckpoint", self.db_wal_autocheckpoint),
+        ] {
+            if value > i32::MAX as u32 {
+                errors.push(PermissionError::Config(
+                    format!("{} must be at most {}, got {}", name, i32::MAX, value)
+                ));
+            }
+        }
+
+        if let Some(cache) = &self.check_cache {
+            if cache.ttl_secs == 0 {
+                errors.push(PermissionError::Config(
+                    "check_cache.ttl_secs must be at least 1".to_string()
+                ));
+            }
+            if cache.capacity == 0 {
+                errors.push(PermissionError::Config(
+                    "check_cache.capacity must be at least 1".to_string()
+                ));
+            }
+        }
+
+        if let Some(header) = &self.sudoers_header {
+            for line in header.lines().filter(|line| !line.trim().is_empty()) {
+                if !is_sudoers_comment(line) {
+                    errors.push(PermissionError::Config(
+                        format!("sudoers_header line is not a plain comment: {:?}", line)
+                    ));
+                }
+            }
+        }
+
+        errors
+    }
+
+    /// Rolling window over which each command's `max_total_duration` is
+    /// enforced
+    pub fn total_duration_window(&self) -> Duration {
+        Duration::hours(self.total_duration_window_hours.into())
+    }
+
+    /// Compile `username_pattern`, anchored so it must match the whole name
+    pub fn username_regex(&self) -> Result<Regex> {
+        let pattern = self.username_pattern.as_deref().unwrap_or(DEFAULT_USERNAME_PATTERN);
+        Regex::new(&format!("^(?:{})$", pattern))
+            .map_err(|e| PermissionError::Config(format!("Invalid username_pattern {:?}: {}", pattern, e)))
+    }
+
+    /// Collect problems that do not make the configuration unusable but
+    /// probably are not what was intended
+    pub fn validation_warnings(&self) -> Vec<String> {
+        let mut commands: Vec<_> = self.allowed_commands.iter().collect();
+        commands.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut warnings = Vec::new();
+        for (cmd, config) in commands {
+            for conflict in &config.conflicts_with {
+                let symmetric = self.allowed_commands.get(conflict)
+                    .is_none_or(|other| other.conflicts_with.contains(cmd));
+                if !symmetric {
+                    warnings.push(format!(
+                        "{} conflicts with {} but not the other way around; the conflict is enforced both ways",
+                        cmd, conflict
+                    ));
+                }
+            }
+        }
+
+        let mut admins: Vec<_> = self.admin_commands.iter().collect();
+        admins.sort_by(|a, b| a.0.cmp(b.0));
+        for (admin, commands) in admins {
+            for command in commands {
+                if self.command_config(command).is_none() {
+                    warnings.push(format!(
+                        "admin_commands lists {} for {}, but it is not an allowed command",
+                        command, admin
+                    ));
+                }
+            }
+        }
+        if !self.admin_commands.is_empty() && !self.delegation_mode {
+            warnings.push("admin_commands has no effect unless delegation_mode is enabled".to_string());
+        }
+        warnings
+    }
+
+    /// Whether `admin_commands` lets `admin` grant `command`
+    pub fn admin_may_delegate(&self, admin: &str, command: &str) -> bool {
+        self.admin_commands.get(admin).is_some_and(|commands| commands.iter().any(|c| c == command))
+    }
+
+    /// SQLite connection settings for the configured database
+    pub fn db_tuning(&self) -> DatabaseTuning {
+        DatabaseTuning {
+            busy_timeout_ms: self.db_busy_timeout_ms,
+            wal_autocheckpoint: self.db_wal_autocheckpoint,
+            encryption_key_path: self.db_encryption_key_path.clone(),
+        }
+    }
+
+    /// Webhook to notify about changes to grants of `command`: the
+    /// command's own `notify_webhook`, falling back to the global one
+    pub fn notify_webhook_for(&self, command: &str) -> Option<&str> {
+        self.command_config(command)
+            .and_then(|config| config.notify_webhook.as_deref())
+            .or(self.notify_webhook.as_deref())
+    }
+
+    /// Policy for `command`: its `allowed_commands` entry, or that of the
+    /// template it was rendered from
+    pub fn command_config(&self, command: &str) -> Option<&CommandConfig> {
+        let command = normalize_command(command);
+        self.allowed_commands.get(&command).or_else(|| {
+            self.templates
+                .values()
+                .find(|template| template.matches(&command))
+                .map(|template| &template.policy)
+        })
+    }
+
+    /// Bearer token for the HTTP API, read from `api_token_path`, or
+    /// `None` if no token file is configured
+    pub fn api_token(&self) -> Result<Option<String>> {
+        self.api_token_path
+            .as_deref()
+            .map(|path| read_secret_file(path, "api_token_path", PermissionError::Config))
+            .transpose()
+    }
+
+    /// `command` as written in a sudoers rule. An allowed command is a single
+    /// path, spaces included; a command rendered from a template is a path
+    /// followed by arguments, each escaped on its own.
+    pub fn sudoers_command(&self, command: &str) -> String {
+        if self.allowed_commands.contains_key(command) {
+            return sudoers_escape(command);
+        }
+        command.split_whitespace().map(sudoers_escape).collect::<Vec<_>>().join(" ")
+    }
+
+    /// Render the named template with `values`, rejecting unknown templates,
+    /// unknown variables and values outside the allowed lists
+    pub fn render_template(&self, name: &str, values: &HashMap<String, String>) -> Result<String> {
+        self.templates
+            .get(name)
+            .ok_or_else(|| PermissionError::CommandNotAllowed(format!("unknown template {}", name)))?
+            .render(values)
+    }
+
+    /// Whether `a` and `b` may not be held together. Either command listing
+    /// the other is enough.
+    pub fn commands_conflict(&self, a: &str, b: &str) -> bool {
+        let lists = |from: &str, to: &str| {
+            self.command_config(from).is_some_and(|c| c.conflicts_with.iter().any(|x| x == to))
+        };
+        lists(a, b) || lists(b, a)
     }
 
     /// Save configuration to a file
@@ -160,28 +1360,258 @@ impl Config {
 
         fs::write(&path, content).map_err(|e| PermissionError::io_error(e, path.as_ref().to_path_buf()))
     }
+}
 
-    /// Create a default configuration
-    pub fn default() -> Self {
-        let mut allowed_commands = HashMap::new();
-        allowed_commands.insert(
-            "/usr/bin/docker".to_string(),
-            CommandConfig {
-                description: "Docker command access".to_string(),
-                max_duration: 480, // 8 hours
-                required_groups: vec!["docker".to_string()],
-                audit_usage: true,
-                max_concurrent_users: 5,
-            },
-        );
+/// Commands the default configuration offers when they are installed:
+/// binary name, description, max duration in minutes, and candidate groups
+/// of which the first one present on the host is required
+const PROBED_COMMANDS: &[(&str, &str, i64, &[&str])] = &[
+    ("docker", "Docker command access", 480, &["docker"]),
+    ("podman", "Podman container management", 480, &["wheel", "sudo"]),
+    ("systemctl", "Service management", 60, &["wheel", "sudo"]),
+];
+
+/// Allowed command entry shown commented out in a new configuration when
+/// none of the probed commands are installed
+pub const EXAMPLE_COMMAND_COMMENT: &str = "\
+# No common commands were found on this host. Add entries under
+# allowed_commands, for example:
+#
+# allowed_commands:
+#   /usr/bin/docker:
+#     description: Docker command access
+#     max_duration: 480
+#     required_groups:
+#     - docker
+";
+
+/// Build allowed command entries for the probed commands that `find`
+/// locates, requiring the first candidate group that `group_exists`
+fn detected_commands<F, G>(find: F, group_exists: G) -> HashMap<String, CommandConfig>
+where
+    F: Fn(&str) -> Option<PathBuf>,
+    G: Fn(&str) -> bool,
+{
+    PROBED_COMMANDS
+        .iter()
+        .filter_map(|(name, description, max_duration, groups)| {
+            let path = find(name)?;
+            let group = groups.iter().find(|g| group_exists(g)).unwrap_or(&groups[0]);
+            Some((path.to_string_lossy().into_owned(), starter_command(description, *max_duration, group)))
+        })
+        .collect()
+}
+
+/// `command` as allowed commands are compared: surrounding whitespace is
+/// trimmed and runs of `/` in the path are collapsed. Case is kept, since
+/// Linux paths are case-sensitive.
+pub fn normalize_command(command: &str) -> String {
+    let command = command.trim();
+    let (path, args) = command.split_at(command.find(char::is_whitespace).unwrap_or(command.len()));
+
+    let mut normalized = String::with_capacity(command.len());
+    for c in path.chars() {
+        if !(c == '/' && normalized.ends_with('/')) {
+            normalized.push(c);
+        }
+    }
+    normalized.push_str(args);
+    normalized
+}
+
+/// Read a secret such as a passphrase or token from `path`, which must be
+/// owned by the current user and inaccessible to anyone else. A trailing
+/// newline is not part of the secret. Problems with the file are reported
+/// through `error`, naming it as `kind`.
+pub(crate) fn read_secret_file(path: &Path, kind: &str, error: fn(String) -> PermissionError) -> Result<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(path).map_err(|e| PermissionError::io_error(e, path.to_path_buf()))?;
+    if metadata.uid() != nix::unistd::geteuid().as_raw() {
+        return Err(error(format!("{} {:?} must be owned by the user running permctl", kind, path)));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        return Err(error(format!(
+            "{} {:?} is accessible to other users (mode {:o}); restrict it to 0400",
+            kind,
+            path,
+            metadata.mode() & 0o777
+        )));
+    }
+
+    let secret = fs::read_to_string(path).map_err(|e| PermissionError::io_error(e, path.to_path_buf()))?;
+    let secret = secret.trim_end_matches(['\r', '\n']);
+    if secret.is_empty() || secret.contains('\0') {
+        return Err(error(format!("{} {:?} must hold a secret without NUL bytes", kind, path)));
+    }
+    Ok(secret.to_string())
+}
+
+/// Allowed command entry with the defaults a new configuration uses
+fn starter_command(description: &str, max_duration: i64, group: &str) -> CommandConfig {
+    CommandConfig {
+        description: description.to_string(),
+        max_duration,
+        required_groups: vec![group.to_string()],
+        audit_usage: true,
+        max_concurrent_users: 5,
+        ..Default::default()
+    }
+}
+
+/// Curated set of allowed commands for one use case, added to a new
+/// configuration by `permctl init --with`
+#[derive(Debug)]
+pub struct Preset {
+    pub name: &'static str,
+    /// Command path, description, max duration in minutes and required group
+    pub commands: &'static [(&'static str, &'static str, i64, &'static str)],
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "docker",
+        commands: &[
+            ("/usr/bin/docker", "Docker command access", 480, "docker"),
+            ("/usr/bin/docker-compose", "Docker Compose stacks", 240, "docker"),
+        ],
+    },
+    Preset {
+        name: "kubernetes",
+        commands: &[
+            ("/usr/bin/kubectl", "Kubernetes cluster administration", 60, "wheel"),
+            ("/usr/bin/crictl", "Container runtime inspection", 30, "wheel"),
+            ("/usr/bin/kubeadm", "Cluster node management", 60, "wheel"),
+        ],
+    },
+    Preset {
+        name: "database",
+        commands: &[
+            ("/usr/bin/psql", "PostgreSQL shell", 60, "postgres"),
+            ("/usr/bin/pg_dump", "PostgreSQL backups", 120, "postgres"),
+            ("/usr/bin/mysql", "MySQL shell", 60, "mysql"),
+            ("/usr/bin/redis-cli", "Redis shell", 30, "redis"),
+        ],
+    },
+    Preset {
+        name: "web",
+        commands: &[
+            ("/usr/sbin/nginx", "nginx configuration tests and reloads", 30, "www-data"),
+            ("/usr/sbin/apachectl", "Apache configuration tests and reloads", 30, "www-data"),
+            ("/usr/bin/certbot", "TLS certificate renewal", 30, "www-data"),
+        ],
+    },
+];
+
+/// Allowed command entries of the named presets, rejecting unknown names
+/// and commands that more than one of them would configure
+fn preset_commands(presets: &[Preset], names: &[String]) -> Result<HashMap<String, CommandConfig>> {
+    let mut commands = HashMap::new();
+    let mut sources: HashMap<&str, &str> = HashMap::new();
+    let mut seen = Vec::new();
+
+    for name in names {
+        if seen.contains(&name.as_str()) {
+            continue;
+        }
+        seen.push(name.as_str());
+
+        let preset = presets.iter().find(|preset| preset.name == name).ok_or_else(|| {
+            let known: Vec<_> = presets.iter().map(|preset| preset.name).collect();
+            PermissionError::Config(format!("unknown preset '{}'; available presets: {}", name, known.join(", ")))
+        })?;
+        for (command, description, max_duration, group) in preset.commands {
+            if let Some(other) = sources.insert(command, preset.name) {
+                return Err(PermissionError::Config(format!(
+                    "presets {} and {} both configure {}",
+                    other, preset.name, command
+                )));
+            }
+            commands.insert(command.to_string(), starter_command(description, *max_duration, group));
+        }
+    }
 
+    Ok(commands)
+}
+
+/// Locate an executable the way `which` does, searching `PATH`
+fn find_executable(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| {
+            fs::metadata(candidate)
+                .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        })
+}
+
+impl Config {
+    /// Allow the commands of each named preset from `PRESETS`, replacing
+    /// any entry already configured for the same command
+    pub fn apply_presets(&mut self, names: &[String]) -> Result<()> {
+        self.allowed_commands.extend(preset_commands(PRESETS, names)?);
+        Ok(())
+    }
+}
+
+impl Config {
+    /// The default configuration, allowing whichever common commands are
+    /// installed on this host
+    pub fn detect() -> Self {
         Config {
-            allowed_commands,
+            allowed_commands: detected_commands(find_executable, |group| {
+                matches!(nix::unistd::Group::from_name(group), Ok(Some(_)))
+            }),
+            ..Config::default()
+        }
+    }
+}
+
+impl Default for Config {
+    /// Create a default configuration allowing no commands. See
+    /// `Config::detect` for one that allows the common commands installed
+    /// on this host.
+    fn default() -> Self {
+        Config {
+            allowed_commands: HashMap::new(),
+            templates: HashMap::new(),
+            admin_groups: Vec::new(),
+            admin_root_bypass: true,
+            delegation_mode: false,
+            admin_commands: HashMap::new(),
+            auto_sudo: false,
+            user_notifications: false,
+            revalidate_groups: false,
+            command_defaults: CommandConfigDefaults::default(),
+            regrant_policy: RegrantPolicy::default(),
+            audit_detail: AuditDetail::default(),
+            async_audit: false,
+            notify_webhook: None,
             sudoers_path: default_sudoers_path(),
             db_path: default_db_path(),
+            db_busy_timeout_ms: default_db_busy_timeout_ms(),
+            db_wal_autocheckpoint: default_db_wal_autocheckpoint(),
+            db_encryption_key_path: None,
             log_path: default_log_path(),
+            log_max_bytes: default_log_max_bytes(),
             debug: false,
             log_retention_days: default_log_retention(),
+            syslog: None,
+            check_cache: None,
+            hard_delete_after_days: None,
+            approval_request_ttl_minutes: None,
+            require_reason: false,
+            strict_clock: false,
+            reject_writable_commands: false,
+            api_group: None,
+            api_token_path: None,
+            sudoers_enabled: true,
+            sudoers_header: None,
+            username_pattern: None,
+            log_dedup_secs: default_log_dedup_secs(),
+            confirmation_timeout_secs: default_confirmation_timeout_secs(),
+            total_duration_window_hours: default_total_duration_window_hours(),
         }
     }
 }
@@ -191,6 +1621,149 @@ mod tests {
     use super::*;
     use tempfile::TempDir;
 
+    fn sample_command() -> CommandConfig {
+        CommandConfig {
+            description: "Docker command access".to_string(),
+            max_duration: 480,
+            required_groups: vec!["docker".to_string()],
+            audit_usage: true,
+            max_concurrent_users: 5,
+            ..Default::default()
+        }
+    }
+
+    fn restart_template() -> CommandTemplate {
+        CommandTemplate {
+            command: "/usr/bin/systemctl restart {service}".to_string(),
+            variables: HashMap::from([("service".to_string(), vec!["nginx".to_string(), "postgresql".to_string()])]),
+            policy: sample_command(),
+        }
+    }
+
+    #[test]
+    fn test_database_tuning() {
+        let config = Config::parse_yaml("allowed_commands: {}\n").unwrap();
+        assert_eq!(config.db_tuning(), DatabaseTuning::default());
+
+        let config = Config::parse_yaml("db_busy_timeout_ms: 30000\ndb_wal_autocheckpoint: 0\nallowed_commands: {}\n").unwrap();
+        assert_eq!(config.db_tuning(), DatabaseTuning { busy_timeout_ms: 30000, wal_autocheckpoint: 0, encryption_key_path: None });
+        assert!(config.validation_errors().is_empty());
+
+        assert!(Config::parse_yaml("db_busy_timeout_ms: -1\nallowed_commands: {}\n").is_err());
+        let config = Config::parse_yaml("db_wal_autocheckpoint: 4000000000\nallowed_commands: {}\n").unwrap();
+        assert!(config.validation_errors().iter().any(|e| e.to_string().contains("db_wal_autocheckpoint")));
+    }
+
+    #[test]
+    fn test_regrant_policy_parsing() {
+        let config = Config::parse_yaml("allowed_commands: {}\n").unwrap();
+        assert_eq!(config.regrant_policy, RegrantPolicy::Replace);
+
+        let config = Config::parse_yaml("regrant_policy: extend\nallowed_commands: {}\n").unwrap();
+        assert_eq!(config.regrant_policy, RegrantPolicy::Extend);
+
+        assert!(Config::parse_yaml("regrant_policy: merge\nallowed_commands: {}\n").is_err());
+    }
+
+    #[test]
+    fn test_audit_detail_parsing() {
+        let config = Config::parse_yaml("allowed_commands: {}\n").unwrap();
+        assert_eq!(config.audit_detail, AuditDetail::Full);
+
+        let config = Config::parse_yaml("audit_detail: minimal\nallowed_commands: {}\n").unwrap();
+        assert_eq!(config.audit_detail, AuditDetail::Minimal);
+    }
+
+    #[test]
+    fn test_command_templates() {
+        let template = restart_template();
+        let vars = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+
+        assert_eq!(template.render(&vars(&[("service", "nginx")])).unwrap(), "/usr/bin/systemctl restart nginx");
+        for bad in [vars(&[("service", "sshd")]), vars(&[]), vars(&[("service", "nginx"), ("unit", "x")])] {
+            assert!(matches!(template.render(&bad), Err(PermissionError::CommandNotAllowed(_))));
+        }
+
+        assert!(template.matches("/usr/bin/systemctl restart postgresql"));
+        assert!(!template.matches("/usr/bin/systemctl restart sshd"));
+        assert!(!template.matches("/usr/bin/systemctl restart nginx; id"));
+
+        let mut config = Config::default();
+        config.templates.insert("restart".to_string(), template);
+        assert!(config.validate().is_ok());
+        assert_eq!(config.command_config("/usr/bin/systemctl restart nginx").unwrap().max_duration, 480);
+        assert!(matches!(config.render_template("stop", &vars(&[])), Err(PermissionError::CommandNotAllowed(_))));
+
+        let broken = config.templates.get_mut("restart").unwrap();
+        broken.variables.insert("unit".to_string(), vec!["nginx service".to_string()]);
+        let errors = config.validation_errors();
+        assert!(errors.iter().any(|e| e.to_string().contains("template restart: variable unit does not appear")));
+        assert!(errors.iter().any(|e| e.to_string().contains("invalid value")));
+    }
+
+    #[test]
+    fn test_default_commands_are_detected() {
+        let found = |name: &str| match name {
+            "docker" => Some(PathBuf::from("/usr/bin/docker")),
+            "systemctl" => Some(PathBuf::from("/bin/systemctl")),
+            _ => None,
+        };
+        let commands = detected_commands(found, |group| group == "sudo");
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands["/usr/bin/docker"].required_groups, vec!["docker"]);
+        assert_eq!(commands["/bin/systemctl"].required_groups, vec!["sudo"]);
+        assert!(commands.values().all(|c| c.validate().is_ok()));
+
+        assert!(detected_commands(|_| None, |_| true).is_empty());
+        assert!(EXAMPLE_COMMAND_COMMENT.lines().all(|line| line.starts_with('#')));
+    }
+
+    #[test]
+    fn test_commands_are_normalized() {
+        assert_eq!(normalize_command(" /usr/bin/docker \t"), "/usr/bin/docker");
+        assert_eq!(normalize_command("//usr//bin///docker"), "/usr/bin/docker");
+        assert_eq!(normalize_command("/usr/bin/Docker"), "/usr/bin/Docker");
+        // Only the path is touched, not the arguments after it
+        assert_eq!(normalize_command("/usr//bin/curl http://host"), "/usr/bin/curl http://host");
+
+        let config = Config::parse_yaml(
+            "allowed_commands:\n  /usr//bin/docker:\n    description: Docker\n    max_duration: 60\n    required_groups: []\n    conflicts_with: ['/usr/bin//podman']\n  /usr/bin/podman:\n    description: Podman\n    max_duration: 60\n    required_groups: []\n",
+        ).unwrap();
+        assert!(config.allowed_commands.contains_key("/usr/bin/docker"));
+        assert!(config.command_config("/usr/bin/docker ").is_some());
+        assert!(config.commands_conflict("/usr/bin/docker", "/usr/bin/podman"));
+
+        let duplicated = "allowed_commands:\n  /usr//bin/docker:\n    description: a\n    max_duration: 60\n    required_groups: []\n  /usr/bin/docker:\n    description: b\n    max_duration: 60\n    required_groups: []\n";
+        assert!(Config::parse_yaml(duplicated).unwrap_err().to_string().contains("more than once"));
+    }
+
+    #[test]
+    fn test_presets() {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+
+        let mut config = Config::default();
+        config.apply_presets(&names(&["docker", "database", "docker"])).unwrap();
+        assert_eq!(config.allowed_commands["/usr/bin/psql"].required_groups, vec!["postgres"]);
+        assert_eq!(config.allowed_commands["/usr/bin/docker"].max_duration, 480);
+        config.validate().unwrap();
+
+        // Every preset can be combined with every other
+        let all: Vec<String> = PRESETS.iter().map(|preset| preset.name.to_string()).collect();
+        assert!(preset_commands(PRESETS, &all).unwrap().values().all(|c| c.validate().is_ok()));
+
+        let err = preset_commands(PRESETS, &names(&["mainframe"])).unwrap_err();
+        assert!(err.to_string().contains("available presets: docker"));
+
+        let overlapping = [
+            Preset { name: "a", commands: &[("/usr/bin/tool", "Tool", 30, "wheel")] },
+            Preset { name: "b", commands: &[("/usr/bin/tool", "Tool", 60, "wheel")] },
+        ];
+        let err = preset_commands(&overlapping, &names(&["a", "b"])).unwrap_err();
+        assert!(err.to_string().contains("presets a and b both configure /usr/bin/tool"));
+    }
+
     #[test]
     fn test_command_config_validation() {
         let valid_config = CommandConfig {
@@ -199,6 +1772,7 @@ mod tests {
             required_groups: vec!["test".to_string()],
             audit_usage: true,
             max_concurrent_users: 5,
+            ..Default::default()
         };
         assert!(valid_config.validate().is_ok());
 
@@ -207,6 +1781,89 @@ mod tests {
             ..valid_config.clone()
         };
         assert!(invalid_duration.validate().is_err());
+
+        let invalid_runas = CommandConfig {
+            runas: Some("postgres) ALL=(ALL".to_string()),
+            ..valid_config.clone()
+        };
+        assert!(invalid_runas.validate().is_err());
+
+        let empty_tag = CommandConfig {
+            tags: vec!["database".to_string(), " ".to_string()],
+            ..valid_config.clone()
+        };
+        assert!(empty_tag.validate().is_err());
+
+        assert!(ReasonPattern::new("^(JIRA|INC-\\d+").is_err());
+        let command = "{description: test, max_duration: 60, required_groups: [], max_concurrent_users: 5, reason_pattern: '^(JIRA|INC)-\\d+'}";
+        assert!(serde_yaml::from_str::<CommandConfig>(command).is_ok());
+        let invalid_reason_pattern = command.replace(")-", "-");
+        assert!(serde_yaml::from_str::<CommandConfig>(&invalid_reason_pattern).is_err());
+
+        let within_range = CommandConfig {
+            min_duration: Some(60),
+            ..valid_config.clone()
+        };
+        assert!(within_range.validate().is_ok());
+
+        let above_max = CommandConfig {
+            min_duration: Some(61),
+            ..valid_config.clone()
+        };
+        assert!(above_max.validate().is_err());
+
+        let non_positive = CommandConfig {
+            min_duration: Some(0),
+            ..valid_config.clone()
+        };
+        assert!(non_positive.validate().is_err());
+
+        for (idle, valid) in [(0, false), (1, true), (MAX_DURATION_MINUTES, true), (MAX_DURATION_MINUTES + 1, false), (i64::MAX, false)] {
+            let idle_timeout = CommandConfig {
+                idle_timeout_minutes: Some(idle),
+                ..valid_config.clone()
+            };
+            assert_eq!(idle_timeout.validate().is_ok(), valid, "{}", idle);
+        }
+    }
+
+    #[test]
+    fn test_notify_webhook_routing() {
+        let mut config = Config {
+            allowed_commands: HashMap::new(),
+            notify_webhook: Some("https://hooks.example.com/all".to_string()),
+            ..Config::default()
+        };
+        config.allowed_commands.insert("/usr/bin/docker".to_string(), sample_command());
+        config.allowed_commands.insert("/usr/bin/psql".to_string(), CommandConfig {
+            notify_webhook: Some("https://hooks.example.com/dba".to_string()),
+            ..sample_command()
+        });
+        assert!(config.validate().is_ok());
+
+        assert_eq!(config.notify_webhook_for("/usr/bin/psql"), Some("https://hooks.example.com/dba"));
+        assert_eq!(config.notify_webhook_for("/usr/bin/docker"), Some("https://hooks.example.com/all"));
+
+        config.notify_webhook = None;
+        assert_eq!(config.notify_webhook_for("/usr/bin/docker"), None);
+
+        config.allowed_commands.get_mut("/usr/bin/psql").unwrap().notify_webhook = Some("dba-channel".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_sudoers_spec() {
+        let mut config = sample_command();
+        assert_eq!(config.sudoers_spec(), "(ALL) NOPASSWD:");
+
+        config.runas = Some("postgres".to_string());
+        assert!(config.validate().is_ok());
+        assert_eq!(config.sudoers_spec(), "(postgres) NOPASSWD:");
+        assert_eq!(config.grant_sudoers_spec(true), "(postgres)");
+
+        config.require_password = true;
+        assert_eq!(config.sudoers_spec(), "(postgres)");
+        assert_eq!(config.grant_sudoers_spec(false), "(postgres)");
     }
 
     #[test]
@@ -234,11 +1891,283 @@ mod tests {
             CommandConfig {
                 description: "test".to_string(),
                 max_duration: 60,
-                required_groups: vec![],
-                audit_usage: false,
                 max_concurrent_users: 1,
+                ..Default::default()
             },
         );
         assert!(config.validate().is_err());
     }
+
+    #[test]
+    fn test_sudoers_escaping() {
+        assert_eq!(sudoers_escape("/usr/bin/docker"), "/usr/bin/docker");
+        assert_eq!(sudoers_escape("/opt/my app/bin/tool"), r"/opt/my\ app/bin/tool");
+        assert_eq!(sudoers_escape("/opt/a,b:c=d#e"), r"/opt/a\,b\:c\=d\#e");
+
+        let mut config = Config::default();
+        config.allowed_commands.insert("/opt/my app/bin/tool".to_string(), sample_command());
+        assert!(config.validate().is_ok());
+        assert_eq!(config.sudoers_command("/opt/my app/bin/tool"), r"/opt/my\ app/bin/tool");
+        assert_eq!(config.sudoers_command("/usr/bin/systemctl restart --unit=nginx"), r"/usr/bin/systemctl restart --unit\=nginx");
+
+        for path in ["/opt/tool\nalice ALL=(ALL) ALL", "/usr/bin/*", "/opt/back\\slash", "/opt/tool[1]"] {
+            let mut config = Config::default();
+            config.allowed_commands.insert(path.to_string(), sample_command());
+            assert!(matches!(config.validate(), Err(PermissionError::Config(_))), "{:?} should be rejected", path);
+        }
+    }
+
+    #[test]
+    fn test_sudoers_header_validation() {
+        let config = Config {
+            sudoers_header: Some("# ACME Corp\n#\n# Questions: #security-team\n".to_string()),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+
+        for header in [
+            "# ok\nalice ALL=(ALL) NOPASSWD: ALL",
+            "#include /etc/sudoers.d/extra",
+            "#1000 ALL=(ALL) NOPASSWD: ALL",
+        ] {
+            let config = Config {
+                sudoers_header: Some(header.to_string()),
+                ..Config::default()
+            };
+            assert!(config.validate().is_err(), "{:?} should be rejected", header);
+        }
+    }
+
+    #[test]
+    fn test_sudoers_enabled_by_default() {
+        let config: Config = serde_yaml::from_str("allowed_commands: {}").unwrap();
+        assert!(config.sudoers_enabled);
+        assert!(config.required_directories().contains(&Path::new(DEFAULT_SUDOERS_PATH).parent().unwrap()));
+
+        let config = Config { sudoers_enabled: false, ..config };
+        assert!(!config.required_directories().contains(&Path::new(DEFAULT_SUDOERS_PATH).parent().unwrap()));
+    }
+
+    #[test]
+    fn test_command_defaults_are_inherited() {
+        let config = Config::parse_yaml(
+            r#"
+command_defaults:
+  max_duration: 120
+  required_groups: [ops]
+  audit_usage: true
+allowed_commands:
+  /usr/bin/docker:
+    description: Docker
+  /usr/bin/journalctl:
+    description: Logs
+    max_duration: 30
+    audit_usage: false
+templates:
+  restart:
+    command: /usr/bin/systemctl restart {unit}
+    variables:
+      unit: [nginx]
+    description: Restart a unit
+"#,
+        ).unwrap();
+        assert!(config.validate().is_ok());
+
+        let docker = &config.allowed_commands["/usr/bin/docker"];
+        assert_eq!(docker.max_duration, 120);
+        assert_eq!(docker.required_groups, vec!["ops"]);
+        assert!(docker.audit_usage);
+
+        let journalctl = &config.allowed_commands["/usr/bin/journalctl"];
+        assert_eq!(journalctl.max_duration, 30);
+        assert_eq!(journalctl.required_groups, vec!["ops"]);
+        assert!(!journalctl.audit_usage);
+
+        assert_eq!(config.templates["restart"].policy.max_duration, 120);
+    }
+
+    #[test]
+    fn test_command_defaults_reject_unknown_keys() {
+        let err = Config::parse_yaml("command_defaults:\n  audit: true\nallowed_commands: {}\n").unwrap_err();
+        assert!(err.to_string().contains("command_defaults"));
+    }
+
+    #[test]
+    fn test_yaml_merge_keys() {
+        let config = Config::parse_yaml(
+            r#"
+x-shared: &shared
+  max_duration: 60
+  required_groups: [docker]
+allowed_commands:
+  /usr/bin/docker:
+    <<: *shared
+    description: Docker
+"#,
+        ).unwrap();
+        assert_eq!(config.allowed_commands["/usr/bin/docker"].required_groups, vec!["docker"]);
+    }
+
+    #[test]
+    fn test_conflicts_with_validation() {
+        let mut config = Config::default();
+        let base = sample_command();
+        config.allowed_commands.insert("/usr/bin/docker".to_string(), base.clone());
+        config.allowed_commands.insert("/usr/bin/deploy-staging".to_string(), CommandConfig {
+            conflicts_with: vec!["/usr/bin/deploy-prod".to_string()],
+            ..base.clone()
+        });
+        config.allowed_commands.insert("/usr/bin/deploy-prod".to_string(), base.clone());
+
+        // One-sided conflicts are valid, enforced both ways, and warned about
+        assert!(config.validate().is_ok());
+        assert!(config.commands_conflict("/usr/bin/deploy-prod", "/usr/bin/deploy-staging"));
+        assert!(!config.commands_conflict("/usr/bin/docker", "/usr/bin/deploy-prod"));
+        assert_eq!(config.validation_warnings().len(), 1);
+
+        config.allowed_commands.get_mut("/usr/bin/deploy-prod").unwrap().conflicts_with =
+            vec!["/usr/bin/deploy-staging".to_string()];
+        assert!(config.validation_warnings().is_empty());
+
+        config.allowed_commands.get_mut("/usr/bin/docker").unwrap().conflicts_with =
+            vec!["/usr/bin/missing".to_string(), "/usr/bin/docker".to_string()];
+        assert_eq!(config.validation_errors().len(), 2);
+    }
+
+    #[test]
+    fn test_api_token_must_be_private() {
+        let temp_dir = TempDir::new().unwrap();
+        let token_path = temp_dir.path().join("api.token");
+        fs::write(&token_path, "s3cret\n").unwrap();
+        let config = Config { api_token_path: Some(token_path.clone()), ..Config::default() };
+
+        fs::set_permissions(&token_path, fs::Permissions::from_mode(0o644)).unwrap();
+        let err = config.api_token().unwrap_err();
+        assert!(matches!(err, PermissionError::Config(ref msg) if msg.contains("0400")), "{}", err);
+
+        fs::set_permissions(&token_path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(config.api_token().unwrap().as_deref(), Some("s3cret"));
+        assert_eq!(Config::default().api_token().unwrap(), None);
+    }
+
+    #[test]
+    fn test_jittered_duration() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut config = sample_command();
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(config.jittered_duration(Duration::minutes(30), &mut rng), Duration::minutes(30));
+
+        config.expiry_jitter_minutes = Some(10);
+        let durations: Vec<Duration> = (0..50)
+            .map(|_| config.jittered_duration(Duration::minutes(30), &mut rng))
+            .collect();
+        assert!(durations.iter().all(|d| *d >= Duration::minutes(20) && *d <= Duration::minutes(40)));
+        assert!(durations.iter().any(|d| *d != durations[0]));
+
+        // Jitter never pushes a grant past the command's maximum
+        let max = config.max_duration_as_duration();
+        assert!((0..50).all(|_| config.jittered_duration(max, &mut rng) <= max));
+
+        config.expiry_jitter_minutes = Some(0);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_rounded_expiry() {
+        let mut config = sample_command();
+        let start: DateTime<Utc> = "2026-03-02T09:12:40Z".parse().unwrap();
+        let at = |time: &str| -> DateTime<Utc> { format!("2026-03-02T{}Z", time).parse().unwrap() };
+        let requested = Duration::minutes(30);
+
+        assert_eq!(config.rounded_expiry(start, at("09:42:40"), requested), at("09:42:40"));
+
+        config.expiry_round_to = Some(ExpiryRounding::Minute);
+        assert_eq!(config.rounded_expiry(start, at("09:42:40"), requested), at("09:43:00"));
+        assert_eq!(config.rounded_expiry(start, at("09:42:10"), requested), at("09:42:00"));
+
+        config.expiry_round_to = Some(ExpiryRounding::Hour);
+        let requested = Duration::minutes(450);
+        assert_eq!(config.rounded_expiry(start, at("16:42:40"), requested), at("17:00:00"));
+        assert_eq!(config.rounded_expiry(start, at("10:12:40"), requested), at("10:00:00"));
+
+        // Rounding up would pass max_duration, so the expiry is rounded down
+        config.max_duration = 465;
+        assert_eq!(config.rounded_expiry(start, at("16:45:00"), requested), at("16:00:00"));
+
+        // Rounding up may add at most an hour to the requested ten minutes
+        assert_eq!(config.rounded_expiry(start, at("10:35:00"), Duration::minutes(10)), at("10:00:00"));
+
+        // Neither boundary gives a duration within bounds
+        config.max_duration = 30;
+        assert_eq!(config.rounded_expiry(start, at("09:42:40"), requested), at("09:42:40"));
+    }
+
+    #[test]
+    fn test_username_pattern() {
+        let mut config = Config::default();
+        let regex = config.username_regex().unwrap();
+        assert!(regex.is_match("deploy_bot-2"));
+        assert!(!regex.is_match("root\nattacker"));
+        assert!(!regex.is_match("x; rm -rf /"));
+
+        config.username_pattern = Some("[a-z]+|[A-Z]+".to_string());
+        let regex = config.username_regex().unwrap();
+        assert!(!regex.is_match("abcDEF"), "alternatives must still match the whole name");
+
+        config.username_pattern = Some("(".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let mut config = Config::default();
+        let vars: HashMap<&str, &str> = [
+            ("PERMCTL_DB_PATH", "/tmp/permctl/test.db"),
+            ("PERMCTL_DEBUG", "yes"),
+        ].into_iter().collect();
+
+        config.apply_overrides(|key| vars.get(key).map(|v| v.to_string())).unwrap();
+        assert_eq!(config.db_path, PathBuf::from("/tmp/permctl/test.db"));
+        assert_eq!(config.sudoers_path, PathBuf::from(DEFAULT_SUDOERS_PATH));
+        assert!(config.debug);
+
+        // Relative paths from the environment fail validation like file values
+        config.apply_overrides(|key| (key == "PERMCTL_LOG_PATH").then(|| "access.log".to_string())).unwrap();
+        assert!(config.validate().is_err());
+
+        assert!(config.apply_overrides(|key| (key == "PERMCTL_DEBUG").then(|| "maybe".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_env_overrides_take_precedence_over_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.yaml");
+        let env_sudoers = temp_dir.path().join("env-sudoers");
+        Config::default().save_to(&config_path).unwrap();
+
+        let loaded = Config::load_with_overrides(&config_path, |key| {
+            (key == "PERMCTL_SUDOERS_PATH").then(|| env_sudoers.display().to_string())
+        });
+        assert_eq!(loaded.unwrap().sudoers_path, env_sudoers);
+    }
+
+    #[test]
+    fn test_validation_errors_accumulate() {
+        let mut config = Config {
+            db_path: PathBuf::from("relative.db"),
+            ..Config::default()
+        };
+        config.allowed_commands.insert(
+            "relative-cmd".to_string(),
+            CommandConfig {
+                max_duration: 0,
+                max_concurrent_users: 0,
+                ..sample_command()
+            },
+        );
+
+        let errors = config.validation_errors();
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::Config;
+
+/// Fields holding credentials, such as webhook URLs with a token in their
+/// path. A change to one is reported without either value.
+const SECRET_FIELDS: &[&str] = &["notify_webhook"];
+
+/// Stands in for the value of a changed secret field
+const REDACTED: &str = "<changed>";
+
+/// A field whose value differs between two configurations
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    /// Value in the old configuration; `None` if it is not set there
+    pub old: Option<Value>,
+    /// Value in the new configuration; `None` if it is not set there
+    pub new: Option<Value>,
+}
+
+/// Differences between two configurations, as found by `Config::diff`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ConfigDiff {
+    /// Commands only the new configuration allows
+    pub added_commands: Vec<String>,
+    /// Commands only the old configuration allows
+    pub removed_commands: Vec<String>,
+    /// Commands allowed by both, with every field that changed
+    pub modified_commands: BTreeMap<String, Vec<FieldChange>>,
+    /// Changed fields outside `allowed_commands`
+    pub settings: Vec<FieldChange>,
+}
+
+impl ConfigDiff {
+    /// Whether the configurations are equivalent
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Config {
+    /// What changes going from this configuration to `other`, compared
+    /// field by field as the values would be written out
+    pub fn diff(&self, other: &Config) -> ConfigDiff {
+        let mut diff = ConfigDiff::default();
+
+        let old_commands: BTreeSet<&String> = self.allowed_commands.keys().collect();
+        let new_commands: BTreeSet<&String> = other.allowed_commands.keys().collect();
+        diff.added_commands = new_commands.difference(&old_commands).map(|c| c.to_string()).collect();
+        diff.removed_commands = old_commands.difference(&new_commands).map(|c| c.to_string()).collect();
+        for command in old_commands.intersection(&new_commands) {
+            let changes = field_changes(
+                to_value(&self.allowed_commands[*command]),
+                to_value(&other.allowed_commands[*command]),
+            );
+            if !changes.is_empty() {
+                diff.modified_commands.insert(command.to_string(), changes);
+            }
+        }
+
+        diff.settings = field_changes(settings_value(self), settings_value(other));
+        diff
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+/// Every field of `config` except `allowed_commands`
+fn settings_value(config: &Config) -> Value {
+    let mut value = to_value(config);
+    if let Value::Object(fields) = &mut value {
+        fields.remove("allowed_commands");
+    }
+    value
+}
+
+/// The top-level fields that differ between two serialized structs.
+/// Nested values such as group lists are compared and reported whole, and
+/// the values of `SECRET_FIELDS` are redacted.
+fn field_changes(old: Value, new: Value) -> Vec<FieldChange> {
+    let (Value::Object(old), Value::Object(new)) = (old, new) else {
+        return Vec::new();
+    };
+
+    let fields: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let (old, new) = (old.get(field).filter(|v| !v.is_null()), new.get(field).filter(|v| !v.is_null()));
+            (old != new).then(|| {
+                let shown = |value: Option<&Value>| match value {
+                    Some(_) if SECRET_FIELDS.contains(&field.as_str()) => Some(Value::from(REDACTED)),
+                    value => value.cloned(),
+                };
+                FieldChange { field: field.clone(), old: shown(old), new: shown(new) }
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_reports_commands_and_settings() {
+        let old = Config::parse_yaml(r#"
+regrant_policy: extend
+allowed_commands:
+  /usr/bin/docker:
+    description: Docker
+    max_duration: 60
+    required_groups: [docker]
+  /usr/bin/journalctl:
+    description: Logs
+    max_duration: 30
+    required_groups: []
+"#).unwrap();
+        let new = Config::parse_yaml(r#"
+regrant_policy: reject
+allowed_commands:
+  /usr/bin/docker:
+    description: Docker
+    max_duration: 120
+    required_groups: [docker, ops]
+    idle_timeout_minutes: 15
+  /usr/bin/systemctl:
+    description: Services
+    max_duration: 30
+    required_groups: []
+"#).unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added_commands, vec!["/usr/bin/systemctl"]);
+        assert_eq!(diff.removed_commands, vec!["/usr/bin/journalctl"]);
+
+        let docker = &diff.modified_commands["/usr/bin/docker"];
+        let fields: Vec<&str> = docker.iter().map(|c| c.field.as_str()).collect();
+        assert_eq!(fields, ["idle_timeout_minutes", "max_duration", "required_groups"]);
+        assert_eq!(docker[0].old, None);
+        assert_eq!(docker[1].new, Some(json!(120)));
+
+        assert_eq!(diff.settings.len(), 1);
+        assert_eq!(diff.settings[0].field, "regrant_policy");
+
+        assert!(new.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn test_diff_redacts_webhooks() {
+        let old = Config::parse_yaml(r#"
+notify_webhook: https://hooks.example.com/old-secret
+allowed_commands:
+  /usr/bin/docker:
+    description: Docker
+    max_duration: 60
+    required_groups: []
+"#).unwrap();
+        let new = Config::parse_yaml(r#"
+notify_webhook: https://hooks.example.com/new-secret
+allowed_commands:
+  /usr/bin/docker:
+    description: Docker
+    max_duration: 60
+    required_groups: []
+    notify_webhook: https://hooks.example.com/docker-secret
+"#).unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.settings, vec![FieldChange {
+            field: "notify_webhook".to_string(),
+            old: Some(json!("<changed>")),
+            new: Some(json!("<changed>")),
+        }]);
+        let docker = &diff.modified_commands["/usr/bin/docker"];
+        assert_eq!(docker[0].old, None);
+        assert_eq!(docker[0].new, Some(json!("<changed>")));
+        assert!(!serde_json::to_string(&diff).unwrap().contains("secret"));
+    }
+}
@@ -1,15 +1,26 @@
-use std::path::Path;
-use sqlx::{sqlite::{SqlitePool, SqlitePoolOptions}, Row};
-use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use sqlx::{migrate::Migrator, sqlite::{Sqlite, SqliteConnection, SqlitePool, SqlitePoolOptions, SqliteRow}, Executor, QueryBuilder, Row, Transaction};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use sha2::{Digest, Sha256};
 use serde::{Serialize, Deserialize};
-use tracing::{info, warn, error};
+use rand::Rng;
+use tracing::{debug, info, warn};
 use std::os::unix::fs::PermissionsExt;
 use std::fs;
+use std::io::Read;
 
+use crate::audit_export::{AuditEvent, AuditFileWriter, SyslogExporter};
+use crate::audit_buffer::{AuditBuffer, QueuedEntry};
+use crate::audit_details::AuditDetails;
+use crate::config::{AuditDetail, SyslogConfig};
+use crate::log_dedup::WarningDeduplicator;
+use crate::config::read_secret_file;
 use crate::error::{Result, PermissionError};
 
 /// Represents a permission grant in the database
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PermissionGrant {
     pub id: i64,
     pub username: String,
@@ -21,30 +32,544 @@ pub struct PermissionGrant {
     pub revoked: bool,
     pub revoked_at: Option<DateTime<Utc>>,
     pub revoked_by: Option<String>,
+    /// Justification given when the grant was revoked
+    pub revoke_reason: Option<String>,
+    /// Time the grant becomes usable; `None` means immediately
+    pub active_from: Option<DateTime<Utc>>,
+    /// Host the grant applies to; `None` means every host sharing the database
+    pub host: Option<String>,
+    /// Number of recorded uses of the grant
+    pub use_count: i64,
+    /// Number of uses after which the grant is exhausted; `None` means unlimited
+    pub max_uses: Option<i64>,
+    /// Identifier attached to every log line and audit entry about the
+    /// grant; `None` for grants created before it was recorded
+    pub correlation_id: Option<String>,
+    /// Whether sudo asks for the user's own password, even if the command's
+    /// configuration does not
+    #[serde(default)]
+    pub require_password: bool,
+    /// Host the grant was created on; `None` for grants created before it
+    /// was recorded
+    #[serde(default)]
+    pub created_host: Option<String>,
+    /// permctl version that created the grant
+    #[serde(default)]
+    pub created_version: Option<String>,
+    /// User the grant was made for by `granted_by`, such as the person whose
+    /// request an automation account acted on
+    #[serde(default)]
+    pub on_behalf_of: Option<String>,
+}
+
+impl PermissionGrant {
+    /// Build a grant from a `permission_grants` row
+    fn from_row(row: &SqliteRow) -> Self {
+        Self {
+            id: row.get("id"),
+            username: row.get("username"),
+            command: row.get("command"),
+            granted_at: row.get("granted_at"),
+            expires_at: row.get("expires_at"),
+            granted_by: row.get("granted_by"),
+            last_used: row.get("last_used"),
+            revoked: row.get("revoked"),
+            revoked_at: row.get("revoked_at"),
+            revoked_by: row.get("revoked_by"),
+            revoke_reason: row.get("revoke_reason"),
+            active_from: row.get("active_from"),
+            host: row.get("host"),
+            use_count: row.get("use_count"),
+            max_uses: row.get("max_uses"),
+            correlation_id: row.get("correlation_id"),
+            require_password: row.get("require_password"),
+            created_host: row.get("created_host"),
+            created_version: row.get("created_version"),
+            on_behalf_of: row.get("on_behalf_of"),
+        }
+    }
+
+    /// Uses left before the grant is exhausted, or `None` if unlimited
+    pub fn remaining_uses(&self) -> Option<i64> {
+        self.max_uses.map(|max| (max - self.use_count).max(0))
+    }
+
+    /// Whether the grant has used up its usage quota
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining_uses() == Some(0)
+    }
+
+    /// Whether the grant's start time has been reached
+    pub fn is_active_now(&self) -> bool {
+        self.active_from.is_none_or(|from| from <= Utc::now())
+    }
+
+    /// When the grant started to apply: its scheduled start, if any, or the
+    /// time it was granted
+    pub fn effective_start(&self) -> DateTime<Utc> {
+        self.active_from.unwrap_or(self.granted_at)
+    }
+
+    /// Last recorded use of the grant, or its start if it was never used,
+    /// which is what an idle timeout counts from
+    pub fn idle_since(&self) -> DateTime<Utc> {
+        self.last_used.unwrap_or_else(|| self.effective_start())
+    }
+
+    /// When the grant stopped (or will stop) applying: its expiry, or the
+    /// time it was revoked if that came first
+    pub fn effective_end(&self) -> DateTime<Utc> {
+        match self.revoked_at {
+            Some(revoked_at) if self.revoked => revoked_at.min(self.expires_at),
+            _ => self.expires_at,
+        }
+    }
+
+    /// Time left until the grant expires, or zero if it already has
+    pub fn time_remaining(&self) -> Duration {
+        (self.expires_at - Utc::now()).max(Duration::zero())
+    }
+}
+
+/// State of a grant request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestStatus {
+    /// Waiting for an approver
+    Pending,
+    /// Approved and turned into a grant
+    Approved,
+    /// Left pending past `approval_request_ttl_minutes`
+    ExpiredUnapproved,
+}
+
+impl RequestStatus {
+    /// Name stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Approved => "approved",
+            Self::ExpiredUnapproved => "expired_unapproved",
+        }
+    }
+}
+
+impl std::str::FromStr for RequestStatus {
+    type Err = PermissionError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "approved" => Ok(Self::Approved),
+            "expired_unapproved" => Ok(Self::ExpiredUnapproved),
+            other => Err(PermissionError::Config(format!("Unknown request status: {}", other))),
+        }
+    }
+}
+
+/// A request for a command that requires approval before it is granted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrantRequest {
+    pub id: i64,
+    pub username: String,
+    pub command: String,
+    /// Requested grant length in minutes, counted from approval or `active_from`
+    pub duration_minutes: i64,
+    /// Requested start time; `None` means on approval
+    pub active_from: Option<DateTime<Utc>>,
+    /// Host the grant will apply to; `None` means every host
+    pub host: Option<String>,
+    pub requested_by: String,
+    pub requested_at: DateTime<Utc>,
+    pub status: RequestStatus,
+    pub decided_by: Option<String>,
+    pub decided_at: Option<DateTime<Utc>>,
+    /// Grant created when the request was approved
+    pub grant_id: Option<i64>,
+    /// Whether the grant will require the user's own password
+    #[serde(default)]
+    pub require_password: bool,
+    /// User `requested_by` made the request for
+    #[serde(default)]
+    pub on_behalf_of: Option<String>,
+    /// Why the grant was requested, recorded with the grant on approval
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Distinct approvals recorded so far
+    #[serde(default)]
+    pub approvals: u32,
+}
+
+impl GrantRequest {
+    /// Build a request from a `grant_requests` row
+    fn from_row(row: &SqliteRow) -> Result<Self> {
+        Ok(Self {
+            id: row.get("id"),
+            username: row.get("username"),
+            command: row.get("command"),
+            duration_minutes: row.get("duration_minutes"),
+            active_from: row.get("active_from"),
+            host: row.get("host"),
+            requested_by: row.get("requested_by"),
+            requested_at: row.get("requested_at"),
+            status: row.get::<String, _>("status").parse()?,
+            decided_by: row.get("decided_by"),
+            decided_at: row.get("decided_at"),
+            grant_id: row.get("grant_id"),
+            require_password: row.get("require_password"),
+            on_behalf_of: row.get("on_behalf_of"),
+            reason: row.get("reason"),
+            approvals: row.try_get::<i64, _>("approvals").unwrap_or(0) as u32,
+        })
+    }
+
+    /// Requested grant length
+    pub fn duration(&self) -> Duration {
+        Duration::minutes(self.duration_minutes)
+    }
+}
+
+/// Result of approving a grant request
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum ApprovalOutcome {
+    /// The approval completed the quorum and the grant was made
+    Granted(PermissionGrant),
+    /// The approval was recorded, but the request stays pending until
+    /// `required` distinct operators have approved it
+    Recorded { request: GrantRequest, required: u32 },
+}
+
+impl ApprovalOutcome {
+    /// The grant, if the approval completed the quorum
+    pub fn granted(self) -> Option<PermissionGrant> {
+        match self {
+            Self::Granted(grant) => Some(grant),
+            Self::Recorded { .. } => None,
+        }
+    }
+}
+
+/// A one-time token that must be presented to complete a grant of a command
+/// with `require_confirmation`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrantConfirmation {
+    /// Six-digit token to pass back with the repeated grant
+    pub token: String,
+    pub username: String,
+    pub command: String,
+    /// Time after which the token is no longer accepted
+    pub expires_at: DateTime<Utc>,
+}
+
+/// One command of a multi-command grant made by `Database::grant_permissions`
+#[derive(Debug, Clone)]
+pub struct BatchGrant<'a> {
+    pub command: &'a str,
+    pub expires_at: DateTime<Utc>,
+    pub max_concurrent_users: Option<usize>,
+    pub max_uses: Option<u32>,
+    pub require_password: bool,
+    /// Held grant to extend instead of replacing, see `held_grant`
+    pub extends: Option<i64>,
+}
+
+/// Filters for listing permission grants. The default lists only grants
+/// that are currently active.
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    /// Only list grants for this user
+    pub username: Option<String>,
+    /// Include grants that have been revoked
+    pub include_revoked: bool,
+    /// Include grants whose expiry time has passed
+    pub include_expired: bool,
+    /// Include scheduled grants that have not started yet
+    pub include_scheduled: bool,
+    /// Only list grants that apply on this host, including host-less ones
+    pub host: Option<String>,
+    /// Maximum number of grants to return
+    pub limit: Option<i64>,
+    /// Number of grants to skip before the first one returned
+    pub offset: i64,
+    /// Field to order by; by user then command when unset
+    pub sort: Option<SortField>,
+    /// Order by `sort` from highest to lowest
+    pub descending: bool,
+}
+
+/// Grant fields a listing can be ordered by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    User,
+    Command,
+    Granted,
+    Expires,
+    LastUsed,
+}
+
+impl SortField {
+    /// Column the field is stored in. Only these fixed names ever reach the
+    /// ORDER BY clause.
+    fn column(self) -> &'static str {
+        match self {
+            Self::User => "username",
+            Self::Command => "command",
+            Self::Granted => "granted_at",
+            Self::Expires => "expires_at",
+            Self::LastUsed => "last_used",
+        }
+    }
+}
+
+impl ListOptions {
+    /// Options that list every grant regardless of state
+    pub fn all() -> Self {
+        Self {
+            username: None,
+            include_revoked: true,
+            include_expired: true,
+            include_scheduled: true,
+            host: None,
+            limit: None,
+            offset: 0,
+            sort: None,
+            descending: false,
+        }
+    }
+}
+
+/// One page of a grant listing
+#[derive(Debug, Clone, Serialize)]
+pub struct GrantPage {
+    /// Grants on this page
+    pub grants: Vec<PermissionGrant>,
+    /// Number of grants matching the filters across all pages
+    pub total_count: i64,
+    /// Offset of the first grant on this page
+    pub offset: i64,
+}
+
+/// Grants that have expired but are not yet revoked, bound to the current time.
+/// Shared by `cleanup_expired` and `count_expired` so a preview matches reality.
+const EXPIRED_PREDICATE: &str = "NOT revoked AND expires_at <= ?";
+/// Revoked grants past retention, bound to the cutoff time. Shared by
+/// `purge_revoked` and `count_purgeable`.
+const PURGEABLE_PREDICATE: &str = "revoked AND revoked_at < ?";
+
+/// Schema migrations, embedded from the `migrations` directory
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// `prev_hash` of the first entry in the audit log hash chain
+const AUDIT_CHAIN_GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Magic string at the start of every SQLite database file
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// SQLite primary result codes for a damaged database file
+const SQLITE_CORRUPT: i32 = 11;
+const SQLITE_NOTADB: i32 = 26;
+
+/// Tables that must exist for the database schema to be usable
+const REQUIRED_TABLES: &[&str] = &[
+    "permission_grants",
+    "audit_log",
+    "grant_requests",
+    "clock_state",
+    "grant_confirmations",
+    "settings",
+    "approvals",
+];
+
+/// Column counting the distinct approvals of each `grant_requests` row
+const REQUEST_APPROVALS: &str =
+    "(SELECT COUNT(*) FROM approvals WHERE approvals.request_id = grant_requests.id) AS approvals";
+
+/// Key of the `settings` row present while maintenance mode is on
+const MAINTENANCE_KEY: &str = "maintenance";
+
+/// Key of the `settings` row counting changes that can take access away,
+/// bumped by triggers on `permission_grants` and maintenance mode
+const GRANT_GENERATION_KEY: &str = "grant_generation";
+
+/// Wrong tokens tried against a pending confirmation before it is discarded
+pub const MAX_CONFIRMATION_ATTEMPTS: i64 = 3;
+
+/// Maintenance mode, during which no grant is honored
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Maintenance {
+    pub since: DateTime<Utc>,
+    pub enabled_by: String,
+}
+
+/// Space taken by the database file and its write-ahead log before and
+/// after compaction, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Compaction {
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+/// Result of a database health check
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseHealth {
+    /// Whether a trivial query succeeded on the connection
+    pub connected: bool,
+    /// Required tables that are missing from the schema
+    pub missing_tables: Vec<String>,
+}
+
+impl DatabaseHealth {
+    /// Whether every required table is present. Only meaningful when connected.
+    pub fn schema_ok(&self) -> bool {
+        self.missing_tables.is_empty()
+    }
+
+    /// Whether the database is fully usable
+    pub fn is_healthy(&self) -> bool {
+        self.connected && self.schema_ok()
+    }
+}
+
+/// Rows copied from one table of a damaged database
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoveredTable {
+    pub name: String,
+    /// Rows written to the recovered database
+    pub copied: u64,
+    /// Rows that could not be read from the damaged database
+    pub lost: u64,
+}
+
+/// Outcome of rebuilding a damaged database
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoveryReport {
+    /// Where the damaged database was moved to
+    pub backup_path: PathBuf,
+    pub tables: Vec<RecoveredTable>,
+}
+
+/// An audit log entry
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub username: String,
+    pub command: String,
+    pub action: String,
+    /// Structured JSON details, or free-form text for entries written
+    /// before details were structured
+    pub details: Option<String>,
+}
+
+impl AuditEntry {
+    /// Human-readable rendering of the details
+    pub fn summary(&self) -> Option<String> {
+        self.details.as_deref().map(|details| AuditDetails::render(&self.action, details))
+    }
+}
+
+/// Filters for reading the audit log. Entries are returned newest first.
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    /// Only entries about this user
+    pub username: Option<String>,
+    /// Only entries about this command
+    pub command: Option<String>,
+    /// Only entries with this action, such as `grant` or `revoke`
+    pub action: Option<String>,
+    /// Maximum number of entries to return
+    pub limit: Option<i64>,
+}
+
+/// Result of checking the audit log hash chain
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditChainReport {
+    /// Entries whose hash was recomputed and matched
+    pub verified: u64,
+    /// Entries written before the hash chain was introduced, which cannot
+    /// be checked
+    pub unchained: u64,
+    /// ID of the first entry that does not follow from the one before it
+    pub broken_at: Option<i64>,
+}
+
+impl AuditChainReport {
+    /// Whether no entry was found altered, removed or inserted
+    pub fn is_intact(&self) -> bool {
+        self.broken_at.is_none()
+    }
 }
 
 /// Database manager for permission storage
 pub struct Database {
     pool: SqlitePool,
+    syslog: Option<SyslogExporter>,
+    audit_file: Option<AuditFileWriter>,
+    /// How much audit entries record
+    audit_detail: AuditDetail,
+    /// Writer for audit entries not tied to another change, when they are
+    /// stored asynchronously
+    audit_buffer: Option<AuditBuffer>,
+}
+
+/// SQLite settings applied to every pooled connection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseTuning {
+    /// Milliseconds a connection waits for a lock held by another process
+    /// before failing with "database is locked"
+    pub busy_timeout_ms: u32,
+    /// Pages the write-ahead log may grow to before SQLite checkpoints it
+    /// back into the database; 0 turns automatic checkpoints off
+    pub wal_autocheckpoint: u32,
+    /// File holding the SQLCipher passphrase; `None` leaves the database
+    /// unencrypted
+    pub encryption_key_path: Option<PathBuf>,
+}
+
+impl Default for DatabaseTuning {
+    fn default() -> Self {
+        Self {
+            busy_timeout_ms: 10_000,
+            wal_autocheckpoint: 1000,
+            encryption_key_path: None,
+        }
+    }
 }
 
 impl Database {
     /// Create a new database connection with proper initialization
     pub async fn new(db_path: impl AsRef<Path>) -> Result<Self> {
+        Self::new_with_tuning(db_path, DatabaseTuning::default()).await
+    }
+
+    /// Create a new database connection whose connections use `tuning`
+    pub async fn new_with_tuning(db_path: impl AsRef<Path>, tuning: DatabaseTuning) -> Result<Self> {
         // Ensure the parent directory exists with proper permissions
         if let Some(parent) = db_path.as_ref().parent() {
             fs::create_dir_all(parent)
                 .map_err(|e| PermissionError::io_error(e, parent.to_path_buf()))?;
             
-            let mut perms = fs::metadata(parent)?.permissions();
+            let mut perms = fs::metadata(parent)
+                .map_err(|e| PermissionError::io_error(e, parent.to_path_buf()))?
+                .permissions();
             perms.set_mode(0o755);
             fs::set_permissions(parent, perms)
                 .map_err(|e| PermissionError::io_error(e, parent.to_path_buf()))?;
         }
 
+        // A file SQLite does not recognise would otherwise only surface as a
+        // pool timeout. Encrypted files have no plain header to check.
+        let key = tuning.encryption_key_path.as_deref().map(read_encryption_key).transpose()?;
+        if key.is_none() {
+            check_database_header(db_path.as_ref())?;
+        }
+        let encrypted = key.is_some();
+        let DatabaseTuning { busy_timeout_ms, wal_autocheckpoint, .. } = tuning;
+
         // Create a robust connection string with proper settings
         let connection_string = format!(
-            "sqlite:{}?mode=rwc&cache=shared&timeout=60",
+            "sqlite:{}?mode=rwc",
             db_path.as_ref().display()
         );
 
@@ -53,29 +578,55 @@ impl Database {
             .max_connections(5)
             .min_connections(1)
             .acquire_timeout(std::time::Duration::from_secs(30))
-            .after_connect(|conn, _| Box::pin(async move {
-                // Enable WAL mode for better concurrency
-                sqlx::query("PRAGMA journal_mode=WAL")
-                    .execute(conn)
-                    .await?;
-                
-                // Set busy timeout for concurrent access
-                sqlx::query("PRAGMA busy_timeout=10000")
-                    .execute(conn)
-                    .await?;
-                
-                Ok(())
-            }))
+            .after_connect(move |conn, _| {
+                let key = key.clone();
+                Box::pin(async move {
+                    // The key has to be set before anything reads the file
+                    if let Some(key) = &key {
+                        sqlx::query(&key_pragma(key))
+                            .execute(&mut *conn)
+                            .await?;
+                    }
+
+                    // Enable WAL mode for better concurrency
+                    sqlx::query("PRAGMA journal_mode=WAL")
+                        .execute(&mut *conn)
+                        .await?;
+
+                    // Set busy timeout for concurrent access
+                    sqlx::query(&format!("PRAGMA busy_timeout={}", busy_timeout_ms))
+                        .execute(&mut *conn)
+                        .await?;
+
+                    sqlx::query(&format!("PRAGMA wal_autocheckpoint={}", wal_autocheckpoint))
+                        .execute(&mut *conn)
+                        .await?;
+
+                    Ok(())
+                })
+            })
             .connect(&connection_string)
             .await
-            .map_err(PermissionError::Database)?;
+            .map_err(|e| open_error(e, encrypted))?;
 
-        let db = Self { pool };
+        let db = Self {
+            pool,
+            syslog: None,
+            audit_file: None,
+            audit_detail: AuditDetail::default(),
+            audit_buffer: None,
+        };
+        if encrypted {
+            db.check_cipher_support().await?;
+        }
+        db.quick_check().await?;
         db.initialize().await?;
 
         // Set appropriate permissions on the database file
         if db_path.as_ref().exists() {
-            let mut perms = fs::metadata(db_path.as_ref())?.permissions();
+            let mut perms = fs::metadata(db_path.as_ref())
+                .map_err(|e| PermissionError::io_error(e, db_path.as_ref().to_path_buf()))?
+                .permissions();
             perms.set_mode(0o644);
             fs::set_permissions(db_path.as_ref(), perms)
                 .map_err(|e| PermissionError::io_error(e, db_path.as_ref().to_path_buf()))?;
@@ -84,384 +635,3902 @@ impl Database {
         Ok(db)
     }
 
-    /// Get a reference to the connection pool
-    pub fn get_pool(&self) -> &SqlitePool {
-        &self.pool
+    /// Open an existing database for reading only. Nothing on disk is
+    /// created, migrated or re-permissioned, so this works without root as
+    /// long as the file is readable.
+    pub async fn open_readonly(db_path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_readonly_with_tuning(db_path, DatabaseTuning::default()).await
     }
 
-    /// Initialize the database schema with proper indices
-    async fn initialize(&self) -> Result<()> {
-        sqlx::query(
-            r#"
-            -- Permission grants table
-            CREATE TABLE IF NOT EXISTS permission_grants (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                username TEXT NOT NULL,
-                command TEXT NOT NULL,
-                granted_at DATETIME NOT NULL,
-                expires_at DATETIME NOT NULL,
-                granted_by TEXT NOT NULL,
-                last_used DATETIME,
-                revoked BOOLEAN NOT NULL DEFAULT FALSE,
-                revoked_at DATETIME,
-                revoked_by TEXT,
-                UNIQUE(username, command) ON CONFLICT REPLACE
-            );
+    /// Open an existing database for reading only, waiting up to the
+    /// tuning's busy timeout for locks
+    pub async fn open_readonly_with_tuning(db_path: impl AsRef<Path>, tuning: DatabaseTuning) -> Result<Self> {
+        let db_path = db_path.as_ref();
+        if !db_path.exists() {
+            return Err(PermissionError::io_error(
+                std::io::Error::from(std::io::ErrorKind::NotFound),
+                db_path.to_path_buf(),
+            ));
+        }
+        let key = tuning.encryption_key_path.as_deref().map(read_encryption_key).transpose()?;
+        if key.is_none() {
+            check_database_header(db_path)?;
+        }
+        let encrypted = key.is_some();
+        let busy_timeout_ms = tuning.busy_timeout_ms;
 
-            -- Indices for efficient querying
-            CREATE INDEX IF NOT EXISTS idx_permissions_user 
-                ON permission_grants(username);
-            CREATE INDEX IF NOT EXISTS idx_permissions_expires 
-                ON permission_grants(expires_at);
-            CREATE INDEX IF NOT EXISTS idx_permissions_active 
-                ON permission_grants(username, command, expires_at) 
-                WHERE NOT revoked;
-
-            -- Audit log table
-            CREATE TABLE IF NOT EXISTS audit_log (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                timestamp DATETIME NOT NULL,
-                username TEXT NOT NULL,
-                command TEXT NOT NULL,
-                action TEXT NOT NULL,
-                details TEXT
-            );
+        let pool = SqlitePoolOptions::new()
+            .max_connections(5)
+            .acquire_timeout(std::time::Duration::from_secs(30))
+            .after_connect(move |conn, _| {
+                let key = key.clone();
+                Box::pin(async move {
+                    if let Some(key) = &key {
+                        sqlx::query(&key_pragma(key))
+                            .execute(&mut *conn)
+                            .await?;
+                    }
+                    sqlx::query(&format!("PRAGMA busy_timeout={}", busy_timeout_ms))
+                        .execute(&mut *conn)
+                        .await?;
+                    Ok(())
+                })
+            })
+            .connect(&format!("sqlite:{}?mode=ro", db_path.display()))
+            .await
+            .map_err(|e| open_error(e, encrypted))?;
 
-            -- Index for audit log queries
-            CREATE INDEX IF NOT EXISTS idx_audit_log_timestamp 
-                ON audit_log(timestamp);
-            "#,
-        )
-        .execute(&self.pool)
-        .await
-        .map_err(PermissionError::Database)?;
+        let db = Self {
+            pool,
+            syslog: None,
+            audit_file: None,
+            audit_detail: AuditDetail::default(),
+            audit_buffer: None,
+        };
+        if encrypted {
+            db.check_cipher_support().await?;
+        }
+        Ok(db)
+    }
 
+    /// Fail unless the SQLite library understands `PRAGMA key`. Without
+    /// SQLCipher the key is silently ignored and the file would be written
+    /// in plain text.
+    async fn check_cipher_support(&self) -> Result<()> {
+        let version: Option<String> = sqlx::query_scalar("PRAGMA cipher_version")
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| open_error(e, true))?;
+        if version.is_none() {
+            return Err(PermissionError::DatabaseEncryption(
+                "db_encryption_key_path is set, but the SQLite library was built without SQLCipher".to_string(),
+            ));
+        }
         Ok(())
     }
 
-    /// Grant a new permission with proper logging
-    pub async fn grant_permission(
-        &self,
-        username: &str,
-        command: &str,
-        expires_at: DateTime<Utc>,
-        granted_by: &str,
-    ) -> Result<i64> {
-        let now = Utc::now();
-        
-        let result = sqlx::query(
-            r#"
-            INSERT INTO permission_grants 
-                (username, command, granted_at, expires_at, granted_by)
-            VALUES (?, ?, ?, ?, ?)
-            RETURNING id
-            "#,
-        )
-        .bind(username)
-        .bind(command)
-        .bind(now)
-        .bind(expires_at)
-        .bind(granted_by)
-        .fetch_one(&self.pool)
-        .await
-        .map_err(PermissionError::Database)?;
+    /// Mirror audit log entries to syslog in addition to the database,
+    /// reporting syslog failures through `warnings`
+    pub fn with_syslog(mut self, config: &SyslogConfig, warnings: Arc<WarningDeduplicator>) -> Self {
+        self.syslog = Some(SyslogExporter::new(config, warnings));
+        self
+    }
 
-        let id = result.get::<i64, _>("id");
+    /// Mirror audit log entries to the file at `path`, rotating it at
+    /// `max_bytes` and deleting rotated files after `retention_days`
+    pub fn with_audit_file(
+        mut self,
+        path: &Path,
+        max_bytes: u64,
+        retention_days: u32,
+        warnings: Arc<WarningDeduplicator>,
+    ) -> Self {
+        self.audit_file = Some(AuditFileWriter::new(path, max_bytes, retention_days, warnings));
+        self
+    }
 
-        // Log the grant in audit log
-        self.add_audit_log(
-            username,
-            command,
-            "grant",
-            Some(&format!("Granted by {} until {}", granted_by, expires_at)),
-        ).await?;
+    /// Queue audit entries that are not written together with the change
+    /// they describe, such as uses and single revocations, and store them
+    /// in batches from a background task. Entries still queued when the
+    /// process dies are lost; call `flush_audit` before exiting.
+    pub fn with_async_audit(mut self) -> Self {
+        self.audit_buffer = Some(AuditBuffer::spawn(self.pool.clone()));
+        self
+    }
 
-        info!(
-            "Granted permission: id={}, user={}, command={}, expires={}",
-            id, username, command, expires_at
-        );
+    /// Wait until every queued audit entry has been written
+    pub async fn flush_audit(&self) {
+        if let Some(buffer) = &self.audit_buffer {
+            buffer.flush().await;
+        }
+    }
 
-        Ok(id)
+    /// Record only as much in audit entries as `level` allows
+    pub fn with_audit_detail(mut self, level: AuditDetail) -> Self {
+        self.audit_detail = level;
+        self
     }
 
-    /// Revoke an existing permission
-    pub async fn revoke_permission(
-        &self,
-        username: &str,
-        command: &str,
-        revoked_by: &str,
-    ) -> Result<bool> {
-        let now = Utc::now();
-        
-        let result = sqlx::query(
-            r#"
-            UPDATE permission_grants
-            SET revoked = TRUE,
-                revoked_at = ?,
-                revoked_by = ?
-            WHERE username = ?
-                AND command = ?
-                AND NOT revoked
-                AND expires_at > ?
-            "#,
-        )
-        .bind(now)
-        .bind(revoked_by)
-        .bind(username)
-        .bind(command)
-        .bind(now)
-        .execute(&self.pool)
-        .await
-        .map_err(PermissionError::Database)?;
+    /// Audit log `details` value for `details` at the configured level
+    fn audit_details(&self, details: AuditDetails) -> String {
+        details.to_json(self.audit_detail)
+    }
 
-        let revoked = result.rows_affected() > 0;
+    /// Get a reference to the connection pool
+    pub fn get_pool(&self) -> &SqlitePool {
+        &self.pool
+    }
 
-        if revoked {
-            self.add_audit_log(
-                username,
-                command,
-                "revoke",
-                Some(&format!("Revoked by {}", revoked_by)),
-            ).await?;
+    /// Check that the connection works and the required tables exist
+    pub async fn health_check(&self) -> Result<DatabaseHealth> {
+        if let Err(e) = sqlx::query("SELECT 1").execute(&self.pool).await {
+            warn!("Database health check query failed: {}", e);
+            return Ok(DatabaseHealth {
+                connected: false,
+                missing_tables: Vec::new(),
+            });
         }
 
-        Ok(revoked)
+        let mut missing_tables = Vec::new();
+        for table in REQUIRED_TABLES {
+            if self.table_columns(table).await?.is_empty() {
+                missing_tables.push(table.to_string());
+            }
+        }
+
+        Ok(DatabaseHealth {
+            connected: true,
+            missing_tables,
+        })
     }
 
-    /// Check if a permission is currently valid
-    pub async fn check_permission(
-        &self,
-        username: &str,
-        command: &str,
-    ) -> Result<bool> {
-        let now = Utc::now();
-        
-        let result = sqlx::query(
-            r#"
-            SELECT COUNT(*) as count
-            FROM permission_grants
-            WHERE username = ?
-                AND command = ?
-                AND NOT revoked
-                AND expires_at > ?
-            "#,
-        )
-        .bind(username)
-        .bind(command)
-        .bind(now)
-        .fetch_one(&self.pool)
-        .await
-        .map_err(PermissionError::Database)?;
+    /// Check the structure of the database file without reading every
+    /// index entry, cheap enough to run whenever the database is opened
+    async fn quick_check(&self) -> Result<()> {
+        self.run_check("PRAGMA quick_check").await
+    }
+
+    /// Check the whole database file for corruption, including that every
+    /// index matches its table, then fold any write-ahead log left by an
+    /// unclean shutdown back into it. This reads the entire file, so it is
+    /// left to `doctor` and to the daemons as they start.
+    pub async fn integrity_check(&self) -> Result<()> {
+        self.run_check("PRAGMA integrity_check").await?;
 
-        Ok(result.get::<i64, _>("count") > 0)
+        let (busy, _, _): (i64, i64, i64) = sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(corruption_error)?;
+        if busy != 0 {
+            debug!("WAL checkpoint skipped while the database is in use");
+        }
+
+        Ok(())
     }
 
-    /// Update the last used timestamp for a permission
-    pub async fn update_last_used(
+    /// Run a checking pragma, which answers with a single "ok" row or one
+    /// row per problem found
+    async fn run_check(&self, pragma: &str) -> Result<()> {
+        let problems: Vec<String> = sqlx::query_scalar(pragma)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(corruption_error)?;
+
+        if problems.iter().any(|line| line != "ok") {
+            return Err(PermissionError::DatabaseCorrupt(problems.join("; ")));
+        }
+        Ok(())
+    }
+
+    /// Rewrite the database file without free pages and truncate the
+    /// write-ahead log, reporting the space taken before and after. Runs on
+    /// a connection of its own, outside any transaction, since VACUUM
+    /// cannot run inside one; other processes holding the database make it
+    /// wait for the busy timeout.
+    pub async fn compact(&self) -> Result<Compaction> {
+        let mut conn = self.pool.acquire().await.map_err(PermissionError::Database)?;
+        let path: String = sqlx::query_scalar("SELECT file FROM pragma_database_list WHERE name = 'main'")
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(PermissionError::Database)?;
+        let size_before = database_size(Path::new(&path));
+
+        sqlx::query("VACUUM")
+            .execute(&mut *conn)
+            .await
+            .map_err(corruption_error)?;
+        let (busy, _, _): (i64, i64, i64) = sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(corruption_error)?;
+        if busy != 0 {
+            warn!("WAL checkpoint did not complete while the database is in use");
+        }
+
+        Ok(Compaction { size_before, size_after: database_size(Path::new(&path)) })
+    }
+
+    /// Rebuild a damaged database at `db_path` by copying every readable
+    /// row into a fresh one, in the spirit of sqlite3's `.recover`. Tables
+    /// are copied in bulk where possible and row by row otherwise, so a
+    /// damaged page only loses the rows on it. The damaged file is kept
+    /// next to the new one.
+    pub async fn recover(db_path: impl AsRef<Path>) -> Result<RecoveryReport> {
+        let db_path = db_path.as_ref();
+        let file_name = db_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let rebuilt_path = db_path.with_file_name(format!("{}.recovering", file_name));
+        let backup_path = db_path.with_file_name(format!(
+            "{}.corrupt-{}",
+            file_name,
+            Utc::now().format("%Y%m%d%H%M%S")
+        ));
+
+        remove_database_files(&rebuilt_path)?;
+        let rebuilt = Database::new(&rebuilt_path).await?;
+        let mut conn = rebuilt.pool.acquire().await.map_err(PermissionError::Database)?;
+
+        sqlx::query("ATTACH DATABASE ? AS damaged")
+            .bind(db_path.to_string_lossy().into_owned())
+            .execute(&mut *conn)
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let mut tables = Vec::new();
+        for table in REQUIRED_TABLES {
+            let source: Vec<String> = sqlx::query(&format!("PRAGMA damaged.table_info({})", table))
+                .fetch_all(&mut *conn)
+                .await
+                .map(|rows| rows.iter().map(|row| row.get("name")).collect())
+                .unwrap_or_default();
+            let columns: Vec<String> = rebuilt
+                .table_columns(table)
+                .await?
+                .into_iter()
+                .filter(|column| source.contains(column))
+                .collect();
+
+            let mut recovered = RecoveredTable { name: table.to_string(), copied: 0, lost: 0 };
+            if columns.is_empty() {
+                tables.push(recovered);
+                continue;
+            }
+
+            let copy = format!(
+                "INSERT OR IGNORE INTO main.{table} ({columns}) SELECT {columns} FROM damaged.{table}",
+                table = table,
+                columns = columns.join(", ")
+            );
+            match sqlx::query(&copy).execute(&mut *conn).await {
+                Ok(result) => recovered.copied = result.rows_affected(),
+                Err(e) => {
+                    warn!("Copying {} failed ({}), salvaging row by row", table, e);
+                    let max_rowid: i64 = sqlx::query_scalar(&format!("SELECT COALESCE(MAX(rowid), 0) FROM damaged.{}", table))
+                        .fetch_one(&mut *conn)
+                        .await
+                        .unwrap_or(0);
+                    let copy_row = format!("{} WHERE rowid = ?", copy);
+                    for rowid in 1..=max_rowid {
+                        match sqlx::query(&copy_row).bind(rowid).execute(&mut *conn).await {
+                            Ok(result) => recovered.copied += result.rows_affected(),
+                            Err(_) => recovered.lost += 1,
+                        }
+                    }
+                }
+            }
+            tables.push(recovered);
+        }
+
+        sqlx::query("DETACH DATABASE damaged")
+            .execute(&mut *conn)
+            .await
+            .map_err(PermissionError::Database)?;
+        // Everything must be in the main file before it is moved into place
+        sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+            .execute(&mut *conn)
+            .await
+            .map_err(PermissionError::Database)?;
+        drop(conn);
+        rebuilt.pool.close().await;
+
+        // Keep the damaged file and its WAL together, out of the way of the
+        // rebuilt database
+        for suffix in ["", "-wal", "-shm"] {
+            let from = PathBuf::from(format!("{}{}", db_path.display(), suffix));
+            if from.exists() {
+                let to = PathBuf::from(format!("{}{}", backup_path.display(), suffix));
+                fs::rename(&from, &to).map_err(|e| PermissionError::io_error(e, from.clone()))?;
+            }
+        }
+        fs::rename(&rebuilt_path, db_path).map_err(|e| PermissionError::io_error(e, rebuilt_path.clone()))?;
+
+        info!("Recovered database {:?}; damaged copy kept at {:?}", db_path, backup_path);
+        Ok(RecoveryReport { backup_path, tables })
+    }
+
+    /// Bring the schema up to date by running any pending migrations
+    async fn initialize(&self) -> Result<()> {
+        self.adopt_unversioned_schema().await?;
+
+        MIGRATOR
+            .run(&self.pool)
+            .await
+            .map_err(|e| PermissionError::Database(e.into()))?;
+
+        Ok(())
+    }
+
+    /// Prepare a database created before migrations were tracked. The
+    /// baseline migration only creates missing tables, so add any columns an
+    /// older table lacks before it is recorded as applied.
+    async fn adopt_unversioned_schema(&self) -> Result<()> {
+        let versioned = !self.table_columns("_sqlx_migrations").await?.is_empty();
+        if versioned || self.table_columns("permission_grants").await?.is_empty() {
+            return Ok(());
+        }
+
+        info!("Upgrading database created before schema migrations");
+        self.ensure_column("permission_grants", "active_from", "DATETIME").await?;
+        self.ensure_column("permission_grants", "revoke_reason", "TEXT").await?;
+        self.ensure_column("permission_grants", "host", "TEXT").await?;
+        self.ensure_column("grant_requests", "host", "TEXT").await?;
+        self.ensure_column("permission_grants", "use_count", "INTEGER NOT NULL DEFAULT 0").await?;
+        self.ensure_column("permission_grants", "max_uses", "INTEGER").await?;
+
+        Ok(())
+    }
+
+    /// Names of the columns in a table, empty if the table does not exist
+    async fn table_columns(&self, table: &str) -> Result<Vec<String>> {
+        let columns = sqlx::query(&format!("PRAGMA table_info({})", table))
+            .fetch_all(&self.pool)
+            .await
+            .map_err(PermissionError::Database)?;
+
+        Ok(columns.iter().map(|row| row.get("name")).collect())
+    }
+
+    /// Add a column to an existing table if it is missing. Tables that do
+    /// not exist yet are left for the migrations to create.
+    async fn ensure_column(&self, table: &str, column: &str, definition: &str) -> Result<()> {
+        let columns = self.table_columns(table).await?;
+
+        if !columns.is_empty() && !columns.iter().any(|name| name == column) {
+            sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+                .execute(&self.pool)
+                .await
+                .map_err(PermissionError::Database)?;
+        }
+
+        Ok(())
+    }
+
+    /// Grant a new permission with proper logging
+    pub async fn grant_permission(
         &self,
         username: &str,
         command: &str,
-    ) -> Result<()> {
+        expires_at: DateTime<Utc>,
+        granted_by: &str,
+    ) -> Result<PermissionGrant> {
+        self.grant_permission_with_limit(username, command, None, None, expires_at, granted_by, None, None, false, None, None, None)
+            .await
+            .map(|(grant, _)| grant)
+    }
+
+    /// Grant a new permission, refusing it if the command already has
+    /// `max_concurrent_users` other active holders. A grant with an
+    /// `active_from` time is stored but not usable until that time.
+    ///
+    /// A grant with a `host` only applies on that host; `None` applies on
+    /// every host sharing the database. With `max_uses`, the grant is
+    /// exhausted once that many uses have been recorded. With
+    /// `require_password`, its sudoers rule omits `NOPASSWD:` whatever the
+    /// command's configuration says. A `reason` is kept in the audit entry,
+    /// and `on_behalf_of` names the user `granted_by` acted for. A grant the
+    /// user holds for the command on the same host is replaced, or extended
+    /// if `extends` names it. Returns the grant and whether it took the place
+    /// of such a held grant.
+    ///
+    /// The count and insert run in a single `BEGIN IMMEDIATE` transaction, which
+    /// takes the SQLite write lock up front. This serializes concurrent grants
+    /// so two writers cannot both pass the limit check before either inserts.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn grant_permission_with_limit(
+        &self,
+        username: &str,
+        command: &str,
+        host: Option<&str>,
+        active_from: Option<DateTime<Utc>>,
+        expires_at: DateTime<Utc>,
+        granted_by: &str,
+        max_concurrent_users: Option<usize>,
+        max_uses: Option<u32>,
+        require_password: bool,
+        reason: Option<&str>,
+        on_behalf_of: Option<&str>,
+        extends: Option<i64>,
+    ) -> Result<(PermissionGrant, bool)> {
         let now = Utc::now();
-        
-        sqlx::query(
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let (grant, details, replaced_existing) = self.insert_grant(
+            &mut tx,
+            now,
+            username,
+            command,
+            host,
+            active_from,
+            expires_at,
+            granted_by,
+            max_concurrent_users,
+            max_uses,
+            require_password,
+            reason,
+            on_behalf_of,
+            extends,
+        ).await?;
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+        self.export_audit_event(now, username, command, "grant", Some(&details));
+
+        info!(
+            "Granted permission: id={}, user={}, command={}, expires={}",
+            grant.id, username, command, grant.expires_at
+        );
+
+        Ok((grant, replaced_existing))
+    }
+
+    /// Grant `username` several commands at once. Every grant and its audit
+    /// entry is written in one `BEGIN IMMEDIATE` transaction, so if any
+    /// insert fails, for example on a concurrent user limit, none of the
+    /// commands are granted.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn grant_permissions(
+        &self,
+        username: &str,
+        host: Option<&str>,
+        active_from: Option<DateTime<Utc>>,
+        granted_by: &str,
+        reason: Option<&str>,
+        on_behalf_of: Option<&str>,
+        grants: &[BatchGrant<'_>],
+    ) -> Result<Vec<PermissionGrant>> {
+        let now = Utc::now();
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let mut inserted = Vec::with_capacity(grants.len());
+        for batch_grant in grants {
+            inserted.push(self.insert_grant(
+                &mut tx,
+                now,
+                username,
+                batch_grant.command,
+                host,
+                active_from,
+                batch_grant.expires_at,
+                granted_by,
+                batch_grant.max_concurrent_users,
+                batch_grant.max_uses,
+                batch_grant.require_password,
+                reason,
+                on_behalf_of,
+                batch_grant.extends,
+            ).await?);
+        }
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+
+        let mut granted = Vec::with_capacity(inserted.len());
+        for (grant, details, _) in inserted {
+            self.export_audit_event(now, username, &grant.command, "grant", Some(&details));
+            info!(
+                "Granted permission: id={}, user={}, command={}, expires={}",
+                grant.id, username, grant.command, grant.expires_at
+            );
+            granted.push(grant);
+        }
+        Ok(granted)
+    }
+
+    /// The live or scheduled grant `username` holds for `command` on
+    /// `host`, where `None` is the grant for every host. A new grant for the
+    /// same user, command and host takes its place unless it is extended.
+    pub async fn held_grant(&self, username: &str, command: &str, host: Option<&str>) -> Result<Option<PermissionGrant>> {
+        Self::fetch_held_grant(&self.pool, username, command, host, Utc::now()).await
+    }
+
+    async fn fetch_held_grant<'e, E>(
+        executor: E,
+        username: &str,
+        command: &str,
+        host: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<PermissionGrant>>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let row = sqlx::query(
             r#"
-            UPDATE permission_grants
-            SET last_used = ?
+            SELECT * FROM permission_grants
             WHERE username = ?
                 AND command = ?
+                AND IFNULL(host, '') = IFNULL(?, '')
                 AND NOT revoked
                 AND expires_at > ?
+                AND (max_uses IS NULL OR use_count < max_uses)
             "#,
         )
-        .bind(now)
         .bind(username)
         .bind(command)
+        .bind(host)
         .bind(now)
-        .execute(&self.pool)
+        .fetch_optional(executor)
         .await
         .map_err(PermissionError::Database)?;
 
-        Ok(())
+        Ok(row.map(|row| PermissionGrant::from_row(&row)))
     }
 
-    /// List all active permissions for a user
-    pub async fn list_user_permissions(
+    /// Insert a grant and its audit entry inside an open transaction,
+    /// enforcing the concurrent user limit. Returns the grant and the audit
+    /// details so the caller can export them once the transaction commits,
+    /// along with whether a live grant the user held was replaced or
+    /// extended. The held grant is extended if it is the one `extends`
+    /// names, and replaced otherwise.
+    #[allow(clippy::too_many_arguments)]
+    async fn insert_grant(
         &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        now: DateTime<Utc>,
         username: &str,
-    ) -> Result<Vec<PermissionGrant>> {
-        let now = Utc::now();
-        
-        let grants = sqlx::query!(
+        command: &str,
+        host: Option<&str>,
+        active_from: Option<DateTime<Utc>>,
+        expires_at: DateTime<Utc>,
+        granted_by: &str,
+        max_concurrent_users: Option<usize>,
+        max_uses: Option<u32>,
+        require_password: bool,
+        reason: Option<&str>,
+        on_behalf_of: Option<&str>,
+        extends: Option<i64>,
+    ) -> Result<(PermissionGrant, String, bool)> {
+        let held = Self::fetch_held_grant(&mut **tx, username, command, host, now).await?;
+        if let Some(held) = held.clone().filter(|held| Some(held.id) == extends) {
+            let (grant, details) = self
+                .extend_grant(tx, now, held, active_from, expires_at, granted_by, require_password, reason, on_behalf_of)
+                .await?;
+            return Ok((grant, details, true));
+        }
+        let replaced_existing = held.is_some();
+
+        if let Some(limit) = max_concurrent_users {
+            // The user's own grant is replaced on conflict, so it does not count
+            let active = Self::active_users_for_command(&mut **tx, command, Some(username), now).await?;
+            if active >= limit as i64 {
+                return Err(PermissionError::ConcurrentUserLimit {
+                    command: command.to_string(),
+                    limit,
+                });
+            }
+        }
+
+        // Keep whatever grant this one replaces, so its time still counts
+        // towards max_total_duration
+        sqlx::query(
             r#"
-            SELECT * FROM permission_grants
+            UPDATE permission_grants
+            SET revoked = TRUE,
+                revoked_at = ?,
+                revoked_by = ?,
+                revoke_reason = 'Replaced by a new grant'
             WHERE username = ?
+                AND command = ?
+                AND IFNULL(host, '') = IFNULL(?, '')
                 AND NOT revoked
-                AND expires_at > ?
-            ORDER BY expires_at DESC
             "#,
-            username,
-            now
         )
-        .fetch_all(&self.pool)
+        .bind(now)
+        .bind(granted_by)
+        .bind(username)
+        .bind(command)
+        .bind(host)
+        .execute(&mut **tx)
         .await
         .map_err(PermissionError::Database)?;
 
-        Ok(grants
-            .into_iter()
-            .map(|row| PermissionGrant {
-                id: row.id,
-                username: row.username,
-                command: row.command,
-                granted_at: row.granted_at,
-                expires_at: row.expires_at,
-                granted_by: row.granted_by,
-                last_used: row.last_used,
-                revoked: row.revoked != 0,
-                revoked_at: row.revoked_at,
-                revoked_by: row.revoked_by,
-            })
-            .collect())
+        let correlation_id = new_correlation_id();
+        let grant_host = creating_host();
+        let row = sqlx::query(
+            r#"
+            INSERT INTO permission_grants
+                (username, command, granted_at, expires_at, granted_by, active_from, host, max_uses, correlation_id,
+                 require_password, created_host, created_version, on_behalf_of)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING *
+            "#,
+        )
+        .bind(username)
+        .bind(command)
+        .bind(now)
+        .bind(expires_at)
+        .bind(granted_by)
+        .bind(active_from)
+        .bind(host)
+        .bind(max_uses)
+        .bind(&correlation_id)
+        .bind(require_password)
+        .bind(&grant_host)
+        .bind(env!("CARGO_PKG_VERSION"))
+        .bind(on_behalf_of)
+        .fetch_one(&mut **tx)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        let grant = PermissionGrant::from_row(&row);
+
+        // Log the grant in audit log as part of the same transaction
+        let details = self.audit_details(AuditDetails {
+            granted_by: Some(granted_by.to_string()),
+            on_behalf_of: on_behalf_of.map(str::to_string),
+            active_from,
+            expires_at: Some(expires_at),
+            duration_minutes: Some((expires_at - active_from.unwrap_or(now)).num_minutes()),
+            require_password,
+            reason: reason.map(str::to_string),
+            correlation_id: Some(correlation_id),
+            created_host: grant_host,
+            created_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            ..Default::default()
+        });
+        Self::insert_audit_log(tx, now, username, command, "grant", Some(&details)).await?;
+        Self::record_observed_time(&mut **tx, now).await?;
+
+        Ok((grant, details, replaced_existing))
     }
 
-    /// List all active permissions across all users
-    pub async fn list_active_permissions(&self) -> Result<Vec<PermissionGrant>> {
+    /// Merge a new grant into the one `username` already holds: the later
+    /// expiry and the earlier start win, a password is required if either
+    /// grant requires one, and everything else, including `granted_at` and
+    /// the correlation ID, is kept
+    #[allow(clippy::too_many_arguments)]
+    async fn extend_grant(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        now: DateTime<Utc>,
+        held: PermissionGrant,
+        active_from: Option<DateTime<Utc>>,
+        expires_at: DateTime<Utc>,
+        granted_by: &str,
+        require_password: bool,
+        reason: Option<&str>,
+        on_behalf_of: Option<&str>,
+    ) -> Result<(PermissionGrant, String)> {
+        let expires_at = expires_at.max(held.expires_at);
+        let require_password = require_password || held.require_password;
+        let active_from = match (held.active_from, active_from) {
+            (Some(held_from), Some(from)) => Some(held_from.min(from)),
+            _ => None,
+        };
+
+        let row = sqlx::query(
+            "UPDATE permission_grants SET expires_at = ?, active_from = ?, require_password = ? WHERE id = ? RETURNING *",
+        )
+            .bind(expires_at)
+            .bind(active_from)
+            .bind(require_password)
+            .bind(held.id)
+            .fetch_one(&mut **tx)
+            .await
+            .map_err(PermissionError::Database)?;
+        let grant = PermissionGrant::from_row(&row);
+
+        let details = self.audit_details(AuditDetails {
+            granted_by: Some(granted_by.to_string()),
+            on_behalf_of: on_behalf_of.map(str::to_string),
+            active_from,
+            expires_at: Some(expires_at),
+            extended_from: Some(held.expires_at),
+            duration_minutes: Some((expires_at - held.expires_at).num_minutes()),
+            require_password,
+            reason: reason.map(str::to_string),
+            correlation_id: grant.correlation_id.clone(),
+            ..Default::default()
+        });
+        Self::insert_audit_log(tx, now, &grant.username, &grant.command, "grant", Some(&details)).await?;
+        Self::record_observed_time(&mut **tx, now).await?;
+
+        Ok((grant, details))
+    }
+
+    /// Record a request for a command that needs approval before it is granted
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_grant_request(
+        &self,
+        username: &str,
+        command: &str,
+        host: Option<&str>,
+        active_from: Option<DateTime<Utc>>,
+        duration: Duration,
+        requested_by: &str,
+        require_password: bool,
+        reason: Option<&str>,
+        on_behalf_of: Option<&str>,
+    ) -> Result<GrantRequest> {
         let now = Utc::now();
-        
-        let grants = sqlx::query!(
+
+        let mut tx = self.pool.begin().await.map_err(PermissionError::Database)?;
+
+        let row = sqlx::query(
             r#"
-            SELECT * FROM permission_grants
-            WHERE NOT revoked
-                AND expires_at > ?
-            ORDER BY username, command
+            INSERT INTO grant_requests
+                (username, command, duration_minutes, active_from, host, requested_by, requested_at, status,
+                 require_password, reason, on_behalf_of)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING *
             "#,
-            now
         )
-        .fetch_all(&self.pool)
+        .bind(username)
+        .bind(command)
+        .bind(duration.num_minutes())
+        .bind(active_from)
+        .bind(host)
+        .bind(requested_by)
+        .bind(now)
+        .bind(RequestStatus::Pending.as_str())
+        .bind(require_password)
+        .bind(reason)
+        .bind(on_behalf_of)
+        .fetch_one(&mut *tx)
         .await
         .map_err(PermissionError::Database)?;
 
-        Ok(grants
-            .into_iter()
-            .map(|row| PermissionGrant {
-                id: row.id,
-                username: row.username,
-                command: row.command,
-                granted_at: row.granted_at,
-                expires_at: row.expires_at,
-                granted_by: row.granted_by,
-                last_used: row.last_used,
-                revoked: row.revoked != 0,
-                revoked_at: row.revoked_at,
-                revoked_by: row.revoked_by,
-            })
-            .collect())
+        let request = GrantRequest::from_row(&row)?;
+
+        let details = self.audit_details(AuditDetails {
+            requested_by: Some(requested_by.to_string()),
+            on_behalf_of: on_behalf_of.map(str::to_string),
+            request_id: Some(request.id),
+            active_from,
+            duration_minutes: Some(request.duration_minutes),
+            require_password,
+            reason: reason.map(str::to_string),
+            ..Default::default()
+        });
+        Self::insert_audit_log(&mut tx, now, username, command, "request", Some(&details)).await?;
+        Self::record_observed_time(&mut *tx, now).await?;
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+        self.export_audit_event(now, username, command, "request", Some(&details));
+
+        info!(
+            "Created grant request: id={}, user={}, command={}",
+            request.id, username, command
+        );
+
+        Ok(request)
     }
 
-    /// Add an entry to the audit log
-    async fn add_audit_log(
+    /// Issue a confirmation token for a grant, valid for `ttl`. Any earlier
+    /// token for the same user, command and granter is replaced, and tokens
+    /// that have expired are cleared out.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_confirmation(
         &self,
         username: &str,
         command: &str,
-        action: &str,
-        details: Option<&str>,
-    ) -> Result<()> {
+        host: Option<&str>,
+        active_from: Option<DateTime<Utc>>,
+        duration: Duration,
+        granted_by: &str,
+        require_password: bool,
+        ttl: Duration,
+    ) -> Result<GrantConfirmation> {
         let now = Utc::now();
-        
+        let expires_at = now + ttl;
+        let token = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+
+        let mut tx = self.pool.begin().await.map_err(PermissionError::Database)?;
+
         sqlx::query(
             r#"
-            INSERT INTO audit_log 
-                (timestamp, username, command, action, details)
-            VALUES (?, ?, ?, ?, ?)
+            DELETE FROM grant_confirmations
+            WHERE expires_at <= ?
+                OR (username = ? AND command = ? AND granted_by = ?)
             "#,
         )
         .bind(now)
         .bind(username)
         .bind(command)
-        .bind(action)
-        .bind(details)
-        .execute(&self.pool)
+        .bind(granted_by)
+        .execute(&mut *tx)
         .await
         .map_err(PermissionError::Database)?;
 
-        Ok(())
-    }
-
-    /// Clean up expired permissions
-    pub async fn cleanup_expired(&self) -> Result<u64> {
-        let now = Utc::now();
-        
-        let result = sqlx::query(
+        sqlx::query(
             r#"
-            UPDATE permission_grants
-            SET revoked = TRUE,
-                revoked_at = ?,
-                revoked_by = 'system_cleanup'
-            WHERE NOT revoked
-                AND expires_at <= ?
+            INSERT INTO grant_confirmations
+                (token, username, command, host, active_from, duration_minutes, granted_by, created_at, expires_at,
+                 require_password)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
         )
+        .bind(&token)
+        .bind(username)
+        .bind(command)
+        .bind(host)
+        .bind(active_from)
+        .bind(duration.num_minutes())
+        .bind(granted_by)
         .bind(now)
-        .bind(now)
-        .execute(&self.pool)
+        .bind(expires_at)
+        .bind(require_password)
+        .execute(&mut *tx)
         .await
         .map_err(PermissionError::Database)?;
 
-        let count = result.rows_affected();
-        if count > 0 {
-            info!("Cleaned up {} expired permission(s)", count);
-        }
-
-        Ok(count)
-    }
-}
+        // The token itself is a short-lived secret and stays out of the log
+        let details = self.audit_details(AuditDetails {
+            granted_by: Some(granted_by.to_string()),
+            expires_at: Some(expires_at),
+            ..Default::default()
+        });
+        Self::insert_audit_log(&mut tx, now, username, command, "confirm_request", Some(&details)).await?;
+        Self::record_observed_time(&mut *tx, now).await?;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::TempDir;
+        tx.commit().await.map_err(PermissionError::Database)?;
+        self.export_audit_event(now, username, command, "confirm_request", Some(&details));
 
-    async fn create_test_db() -> (Database, TempDir) {
-        let temp_dir = TempDir::new().unwrap();
-        let db_path = temp_dir.path().join("test.db");
-        let db = Database::new(&db_path).await.unwrap();
-        (db, temp_dir)
+        Ok(GrantConfirmation {
+            token,
+            username: username.to_string(),
+            command: command.to_string(),
+            expires_at,
+        })
     }
 
-    #[tokio::test]
-    async fn test_grant_and_check_permission() {
+    /// Use up a confirmation token. Returns true only if an unexpired token
+    /// was issued to `granted_by` for exactly this grant; a token is
+    /// accepted once at most. Any other token counts as a failed attempt
+    /// against the confirmation `granted_by` holds for the user and command,
+    /// which is discarded after `MAX_CONFIRMATION_ATTEMPTS` of them.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn consume_confirmation(
+        &self,
+        token: &str,
+        username: &str,
+        command: &str,
+        host: Option<&str>,
+        active_from: Option<DateTime<Utc>>,
+        duration: Duration,
+        granted_by: &str,
+        require_password: bool,
+    ) -> Result<bool> {
+        let mut tx = self.pool.begin().await.map_err(PermissionError::Database)?;
+        let consumed = sqlx::query(
+            r#"
+            DELETE FROM grant_confirmations
+            WHERE token = ?
+                AND username = ?
+                AND command = ?
+                AND host IS ?
+                AND active_from IS ?
+                AND duration_minutes = ?
+                AND granted_by = ?
+                AND require_password = ?
+                AND expires_at > ?
+            RETURNING id
+            "#,
+        )
+        .bind(token)
+        .bind(username)
+        .bind(command)
+        .bind(host)
+        .bind(active_from)
+        .bind(duration.num_minutes())
+        .bind(granted_by)
+        .bind(require_password)
+        .bind(Utc::now())
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        if consumed.is_none() {
+            sqlx::query(
+                r#"
+                UPDATE grant_confirmations
+                SET failed_attempts = failed_attempts + 1
+                WHERE username = ? AND command = ? AND granted_by = ?
+                "#,
+            )
+            .bind(username)
+            .bind(command)
+            .bind(granted_by)
+            .execute(&mut *tx)
+            .await
+            .map_err(PermissionError::Database)?;
+
+            sqlx::query("DELETE FROM grant_confirmations WHERE failed_attempts >= ?")
+                .bind(MAX_CONFIRMATION_ATTEMPTS)
+                .execute(&mut *tx)
+                .await
+                .map_err(PermissionError::Database)?;
+        }
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+        Ok(consumed.is_some())
+    }
+
+    /// Look up a grant by id, whatever its state
+    pub async fn get_grant(&self, id: i64) -> Result<Option<PermissionGrant>> {
+        let row = sqlx::query("SELECT * FROM permission_grants WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(PermissionError::Database)?;
+
+        Ok(row.as_ref().map(PermissionGrant::from_row))
+    }
+
+    /// Look up a grant request by id
+    pub async fn get_grant_request(&self, id: i64) -> Result<Option<GrantRequest>> {
+        let row = sqlx::query(&format!("SELECT *, {} FROM grant_requests WHERE id = ?", REQUEST_APPROVALS))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(PermissionError::Database)?;
+
+        row.as_ref().map(GrantRequest::from_row).transpose()
+    }
+
+    /// List requests still waiting for approval, oldest first
+    pub async fn list_pending_requests(&self) -> Result<Vec<GrantRequest>> {
+        let rows = sqlx::query(&format!(
+            "SELECT *, {} FROM grant_requests WHERE status = ? ORDER BY requested_at ASC, id ASC",
+            REQUEST_APPROVALS
+        ))
+            .bind(RequestStatus::Pending.as_str())
+            .fetch_all(&self.pool)
+            .await
+            .map_err(PermissionError::Database)?;
+
+        rows.iter().map(GrantRequest::from_row).collect()
+    }
+
+    /// Mark requests still pending since before `requested_before` as
+    /// `expired_unapproved`, returning the requests expired
+    pub async fn expire_pending_requests(&self, requested_before: DateTime<Utc>) -> Result<Vec<GrantRequest>> {
+        let now = Utc::now();
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let rows = sqlx::query(&format!(
+            r#"
+            UPDATE grant_requests
+            SET status = ?,
+                decided_at = ?
+            WHERE status = ?
+                AND requested_at < ?
+            RETURNING *, {}
+            "#,
+            REQUEST_APPROVALS
+        ))
+        .bind(RequestStatus::ExpiredUnapproved.as_str())
+        .bind(now)
+        .bind(RequestStatus::Pending.as_str())
+        .bind(requested_before)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        let mut expired = Vec::with_capacity(rows.len());
+        for row in &rows {
+            let request = GrantRequest::from_row(row)?;
+            let details = self.audit_details(AuditDetails {
+                requested_by: Some(request.requested_by.clone()),
+                request_id: Some(request.id),
+                ..Default::default()
+            });
+            expired.push((request, details));
+        }
+        for (request, details) in &expired {
+            Self::insert_audit_log(&mut tx, now, &request.username, &request.command, "request_expired", Some(details)).await?;
+        }
+        Self::record_observed_time(&mut *tx, now).await?;
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+        for (request, details) in &expired {
+            self.export_audit_event(now, &request.username, &request.command, "request_expired", Some(details));
+        }
+
+        if !expired.is_empty() {
+            info!("Expired {} unapproved grant request(s)", expired.len());
+        }
+        Ok(expired.into_iter().map(|(request, _)| request).collect())
+    }
+
+    /// Record `approved_by`'s approval of a pending request, and create its
+    /// grant once `required_approvals` distinct operators have approved it.
+    /// Neither the requester nor the user the grant is for may approve it,
+    /// and nobody may approve the same request twice.
+    ///
+    /// Recording the approval, marking the request approved and inserting
+    /// the grant happen in one `BEGIN IMMEDIATE` transaction, so a request
+    /// can only ever be approved once even if two approvers act at the same
+    /// time.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn approve_grant_request(
+        &self,
+        id: i64,
+        approved_by: &str,
+        required_approvals: u32,
+        active_from: Option<DateTime<Utc>>,
+        expires_at: DateTime<Utc>,
+        max_concurrent_users: Option<usize>,
+        max_uses: Option<u32>,
+        extends: Option<i64>,
+    ) -> Result<ApprovalOutcome> {
+        let now = Utc::now();
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let row = sqlx::query("SELECT * FROM grant_requests WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(PermissionError::Database)?
+            .ok_or(PermissionError::RequestNotFound(id))?;
+        let request = GrantRequest::from_row(&row)?;
+
+        if request.status == RequestStatus::ExpiredUnapproved {
+            return Err(PermissionError::AccessExpired);
+        }
+        if request.status != RequestStatus::Pending {
+            return Err(PermissionError::RequestNotPending {
+                id,
+                status: request.status.as_str().to_string(),
+            });
+        }
+        // A request filed by a bot belongs to whoever it was filed for
+        if approved_by == request.requested_by
+            || approved_by == request.username
+            || request.on_behalf_of.as_deref() == Some(approved_by)
+        {
+            return Err(PermissionError::SelfApproval { id, user: approved_by.to_string() });
+        }
+
+        let recorded = sqlx::query(
+            r#"
+            INSERT INTO approvals (request_id, approver, approved_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(id)
+        .bind(approved_by)
+        .bind(now)
+        .execute(&mut *tx)
+        .await
+        .map_err(PermissionError::Database)?
+        .rows_affected();
+        if recorded == 0 {
+            return Err(PermissionError::AlreadyApproved { id, approver: approved_by.to_string() });
+        }
+
+        let approvals: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM approvals WHERE request_id = ?")
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(PermissionError::Database)?;
+        let request = GrantRequest { approvals: approvals as u32, ..request };
+
+        if request.approvals < required_approvals {
+            let details = self.audit_details(AuditDetails {
+                approved_by: Some(approved_by.to_string()),
+                request_id: Some(id),
+                ..Default::default()
+            });
+            Self::insert_audit_log(&mut tx, now, &request.username, &request.command, "approve", Some(&details)).await?;
+            Self::record_observed_time(&mut *tx, now).await?;
+
+            tx.commit().await.map_err(PermissionError::Database)?;
+            self.export_audit_event(now, &request.username, &request.command, "approve", Some(&details));
+
+            info!(
+                "Recorded approval of grant request: id={}, approver={}, approvals={}/{}",
+                id, approved_by, request.approvals, required_approvals
+            );
+            return Ok(ApprovalOutcome::Recorded { request, required: required_approvals });
+        }
+
+        let (grant, grant_details, _) = self.insert_grant(
+            &mut tx,
+            now,
+            &request.username,
+            &request.command,
+            request.host.as_deref(),
+            active_from,
+            expires_at,
+            approved_by,
+            max_concurrent_users,
+            max_uses,
+            request.require_password,
+            request.reason.as_deref(),
+            request.on_behalf_of.as_deref(),
+            extends,
+        ).await?;
+
+        sqlx::query(
+            r#"
+            UPDATE grant_requests
+            SET status = ?,
+                decided_by = ?,
+                decided_at = ?,
+                grant_id = ?
+            WHERE id = ?
+            "#,
+        )
+        .bind(RequestStatus::Approved.as_str())
+        .bind(approved_by)
+        .bind(now)
+        .bind(grant.id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        let details = self.audit_details(AuditDetails {
+            approved_by: Some(approved_by.to_string()),
+            request_id: Some(id),
+            correlation_id: grant.correlation_id.clone(),
+            ..Default::default()
+        });
+        Self::insert_audit_log(&mut tx, now, &request.username, &request.command, "approve", Some(&details)).await?;
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+        self.export_audit_event(now, &request.username, &request.command, "approve", Some(&details));
+        self.export_audit_event(now, &request.username, &request.command, "grant", Some(&grant_details));
+
+        info!(
+            "Approved grant request: id={}, grant={}, approver={}",
+            id, grant.id, approved_by
+        );
+
+        Ok(ApprovalOutcome::Granted(grant))
+    }
+
+    /// Revoke an existing permission
+    pub async fn revoke_permission(
+        &self,
+        username: &str,
+        command: &str,
+        revoked_by: &str,
+    ) -> Result<bool> {
+        self.revoke_permission_with_reason(username, command, revoked_by, None).await
+    }
+
+    /// Revoke an existing permission, recording why it was revoked
+    pub async fn revoke_permission_with_reason(
+        &self,
+        username: &str,
+        command: &str,
+        revoked_by: &str,
+        reason: Option<&str>,
+    ) -> Result<bool> {
+        self.revoke_grant(username, command, revoked_by, reason)
+            .await
+            .map(|grants| !grants.is_empty())
+    }
+
+    /// Revoke an existing permission on every host in one transaction,
+    /// returning the grants as revoked, none if the user held no live grant
+    /// for the command
+    pub async fn revoke_grant(
+        &self,
+        username: &str,
+        command: &str,
+        revoked_by: &str,
+        reason: Option<&str>,
+    ) -> Result<Vec<PermissionGrant>> {
+        let now = Utc::now();
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let rows = sqlx::query(
+            r#"
+            UPDATE permission_grants
+            SET revoked = TRUE,
+                revoked_at = ?,
+                revoked_by = ?,
+                revoke_reason = ?
+            WHERE username = ?
+                AND command = ?
+                AND NOT revoked
+                AND expires_at > ?
+            RETURNING *
+            "#,
+        )
+        .bind(now)
+        .bind(revoked_by)
+        .bind(reason)
+        .bind(username)
+        .bind(command)
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        let revoked = self.finish_revoke_all(tx, &rows, now, revoked_by, reason).await?;
+        for grant in &revoked {
+            info!(correlation_id = grant.correlation_id.as_deref(), "Revoked permission: user={}, command={}, host={}", grant.username, grant.command, grant.host.as_deref().unwrap_or("*"));
+        }
+        Ok(revoked)
+    }
+
+    /// Revoke the grant with the given id if it is still live, returning it
+    /// as revoked. Unlike looking the grant up and revoking by user and
+    /// command, this cannot hit a grant that replaced it in the meantime.
+    pub async fn revoke_by_id(
+        &self,
+        id: i64,
+        revoked_by: &str,
+        reason: Option<&str>,
+    ) -> Result<Option<PermissionGrant>> {
+        let now = Utc::now();
+
+        let row = sqlx::query(
+            r#"
+            UPDATE permission_grants
+            SET revoked = TRUE,
+                revoked_at = ?,
+                revoked_by = ?,
+                revoke_reason = ?
+            WHERE id = ?
+                AND NOT revoked
+                AND expires_at > ?
+            RETURNING *
+            "#,
+        )
+        .bind(now)
+        .bind(revoked_by)
+        .bind(reason)
+        .bind(id)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        self.finish_revoke(row, now, revoked_by, reason).await
+    }
+
+    /// Audit a single revocation, if `row` holds the revoked grant
+    async fn finish_revoke(
+        &self,
+        row: Option<SqliteRow>,
+        now: DateTime<Utc>,
+        revoked_by: &str,
+        reason: Option<&str>,
+    ) -> Result<Option<PermissionGrant>> {
+        Self::record_observed_time(&self.pool, now).await?;
+
+        let Some(grant) = row.as_ref().map(PermissionGrant::from_row) else {
+            return Ok(None);
+        };
+        self.add_audit_log(
+            &grant.username,
+            &grant.command,
+            "revoke",
+            Some(&self.audit_details(AuditDetails {
+                revoked_by: Some(revoked_by.to_string()),
+                reason: reason.map(str::to_string),
+                correlation_id: grant.correlation_id.clone(),
+                ..Default::default()
+            })),
+        ).await?;
+        info!(correlation_id = grant.correlation_id.as_deref(), "Revoked permission: user={}, command={}", grant.username, grant.command);
+
+        Ok(Some(grant))
+    }
+
+    /// Revoke every live grant of `username` whose command matches the
+    /// shell-style `pattern` (SQLite `GLOB`: `*`, `?` and `[...]`, case
+    /// sensitive) in one transaction, returning the grants as revoked
+    pub async fn revoke_matching(
+        &self,
+        username: &str,
+        pattern: &str,
+        revoked_by: &str,
+        reason: Option<&str>,
+    ) -> Result<Vec<PermissionGrant>> {
+        let now = Utc::now();
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let rows = sqlx::query(
+            r#"
+            UPDATE permission_grants
+            SET revoked = TRUE,
+                revoked_at = ?,
+                revoked_by = ?,
+                revoke_reason = ?
+            WHERE username = ?
+                AND command GLOB ?
+                AND NOT revoked
+                AND expires_at > ?
+            RETURNING *
+            "#,
+        )
+        .bind(now)
+        .bind(revoked_by)
+        .bind(reason)
+        .bind(username)
+        .bind(pattern)
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        let revoked = self.finish_revoke_all(tx, &rows, now, revoked_by, reason).await?;
+
+        info!("Revoked {} permission(s) of {} matching {}", revoked.len(), username, pattern);
+        Ok(revoked)
+    }
+
+    /// Audit the grants revoked in `tx`, sorted by command, and commit
+    async fn finish_revoke_all(
+        &self,
+        mut tx: Transaction<'_, Sqlite>,
+        rows: &[SqliteRow],
+        now: DateTime<Utc>,
+        revoked_by: &str,
+        reason: Option<&str>,
+    ) -> Result<Vec<PermissionGrant>> {
+        let mut revoked: Vec<(PermissionGrant, String)> = rows
+            .iter()
+            .map(|row| {
+                let grant = PermissionGrant::from_row(row);
+                let details = self.audit_details(AuditDetails {
+                    revoked_by: Some(revoked_by.to_string()),
+                    reason: reason.map(str::to_string),
+                    correlation_id: grant.correlation_id.clone(),
+                    ..Default::default()
+                });
+                (grant, details)
+            })
+            .collect();
+        revoked.sort_by(|a, b| a.0.command.cmp(&b.0.command));
+        for (grant, details) in &revoked {
+            Self::insert_audit_log(&mut tx, now, &grant.username, &grant.command, "revoke", Some(details)).await?;
+        }
+        Self::record_observed_time(&mut *tx, now).await?;
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+        for (grant, details) in &revoked {
+            self.export_audit_event(now, &grant.username, &grant.command, "revoke", Some(details));
+        }
+
+        Ok(revoked.into_iter().map(|(grant, _)| grant).collect())
+    }
+
+    /// Revoke every live grant issued by `granted_by`, returning how many
+    /// were revoked
+    pub async fn revoke_by_granter(&self, granted_by: &str, revoked_by: &str) -> Result<u64> {
+        self.revoke_by_granter_with_reason(granted_by, revoked_by, None).await
+    }
+
+    /// Revoke every live grant issued by `granted_by`, recording why, and
+    /// return how many were revoked
+    pub async fn revoke_by_granter_with_reason(
+        &self,
+        granted_by: &str,
+        revoked_by: &str,
+        reason: Option<&str>,
+    ) -> Result<u64> {
+        self.revoke_grants_by_granter(granted_by, revoked_by, reason)
+            .await
+            .map(|grants| grants.len() as u64)
+    }
+
+    /// Revoke every live grant issued by `granted_by` in one transaction,
+    /// including scheduled ones, with an audit entry for each, returning the
+    /// grants as revoked. Intended for incident response when an admin
+    /// account is compromised.
+    pub async fn revoke_grants_by_granter(
+        &self,
+        granted_by: &str,
+        revoked_by: &str,
+        reason: Option<&str>,
+    ) -> Result<Vec<PermissionGrant>> {
+        let now = Utc::now();
+        let reason = match reason {
+            Some(reason) => format!("Mass revocation of grants issued by {}: {}", granted_by, reason),
+            None => format!("Mass revocation of grants issued by {}", granted_by),
+        };
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let rows = sqlx::query(
+            r#"
+            UPDATE permission_grants
+            SET revoked = TRUE,
+                revoked_at = ?,
+                revoked_by = ?,
+                revoke_reason = ?
+            WHERE granted_by = ?
+                AND NOT revoked
+                AND expires_at > ?
+            RETURNING *
+            "#,
+        )
+        .bind(now)
+        .bind(revoked_by)
+        .bind(&reason)
+        .bind(granted_by)
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        let revoked: Vec<(PermissionGrant, String)> = rows
+            .iter()
+            .map(|row| {
+                let grant = PermissionGrant::from_row(row);
+                let details = self.audit_details(AuditDetails {
+                    revoked_by: Some(revoked_by.to_string()),
+                    reason: Some(reason.to_string()),
+                    correlation_id: grant.correlation_id.clone(),
+                    ..Default::default()
+                });
+                (grant, details)
+            })
+            .collect();
+        for (grant, details) in &revoked {
+            Self::insert_audit_log(&mut tx, now, &grant.username, &grant.command, "revoke", Some(details)).await?;
+        }
+        Self::record_observed_time(&mut *tx, now).await?;
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+        for (grant, details) in &revoked {
+            self.export_audit_event(now, &grant.username, &grant.command, "revoke", Some(details));
+        }
+
+        info!(
+            "Revoked {} permission(s) granted by {}",
+            revoked.len(), granted_by
+        );
+
+        Ok(revoked.into_iter().map(|(grant, _)| grant).collect())
+    }
+
+    /// Revoke every live grant held by `username` that applies on `host`
+    /// because the account no longer exists there, recording each with the
+    /// audit action `orphan_cleanup` so the user is not left with rules that
+    /// would apply to a recycled name. Grants scoped to other hosts, where
+    /// the account may still exist, are left alone. Returns the grants as
+    /// revoked.
+    pub async fn revoke_orphaned(&self, username: &str, host: &str, revoked_by: &str) -> Result<Vec<PermissionGrant>> {
+        let now = Utc::now();
+        let reason = "User account no longer exists";
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let rows = sqlx::query(
+            r#"
+            UPDATE permission_grants
+            SET revoked = TRUE,
+                revoked_at = ?,
+                revoked_by = ?,
+                revoke_reason = ?
+            WHERE username = ?
+                AND (host IS NULL OR host = ?)
+                AND NOT revoked
+                AND expires_at > ?
+            RETURNING *
+            "#,
+        )
+        .bind(now)
+        .bind(revoked_by)
+        .bind(reason)
+        .bind(username)
+        .bind(host)
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        let revoked: Vec<(PermissionGrant, String)> = rows
+            .iter()
+            .map(|row| {
+                let grant = PermissionGrant::from_row(row);
+                let details = self.audit_details(AuditDetails {
+                    revoked_by: Some(revoked_by.to_string()),
+                    reason: Some(reason.to_string()),
+                    correlation_id: grant.correlation_id.clone(),
+                    ..Default::default()
+                });
+                (grant, details)
+            })
+            .collect();
+        for (grant, details) in &revoked {
+            Self::insert_audit_log(&mut tx, now, &grant.username, &grant.command, "orphan_cleanup", Some(details)).await?;
+        }
+        Self::record_observed_time(&mut *tx, now).await?;
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+        for (grant, details) in &revoked {
+            self.export_audit_event(now, &grant.username, &grant.command, "orphan_cleanup", Some(details));
+        }
+
+        info!("Revoked {} permission(s) of deleted user {}", revoked.len(), username);
+
+        Ok(revoked.into_iter().map(|(grant, _)| grant).collect())
+    }
+
+    /// Users and commands with more than one live or scheduled grant on the
+    /// same host, with how many each has. The schema allows only one grant
+    /// per user, command and host, so any result means the database was
+    /// edited outside permctl.
+    pub async fn duplicate_grants(&self) -> Result<Vec<(String, String, i64)>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT username, command, COUNT(*) AS grants
+            FROM permission_grants
+            WHERE NOT revoked AND expires_at > ?
+            GROUP BY username, command, host
+            HAVING COUNT(*) > 1
+            ORDER BY username, command
+            "#,
+        )
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        Ok(rows.iter().map(|row| (row.get("username"), row.get("command"), row.get("grants"))).collect())
+    }
+
+    /// Revoke all but the latest-expiring live grant of every user, command
+    /// and host with more than one, recording each with the audit action
+    /// `duplicate_cleanup`. Returns the grants as revoked.
+    pub async fn revoke_duplicates(&self, revoked_by: &str) -> Result<Vec<PermissionGrant>> {
+        let now = Utc::now();
+        let reason = "Duplicate of a later-expiring grant";
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let rows = sqlx::query(
+            r#"
+            UPDATE permission_grants
+            SET revoked = TRUE,
+                revoked_at = ?,
+                revoked_by = ?,
+                revoke_reason = ?
+            WHERE id IN (
+                SELECT id FROM (
+                    SELECT id, ROW_NUMBER() OVER (
+                        PARTITION BY username, command, host
+                        ORDER BY expires_at DESC, id DESC
+                    ) AS rank
+                    FROM permission_grants
+                    WHERE NOT revoked AND expires_at > ?
+                )
+                WHERE rank > 1
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(now)
+        .bind(revoked_by)
+        .bind(reason)
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        let revoked: Vec<(PermissionGrant, String)> = rows
+            .iter()
+            .map(|row| {
+                let grant = PermissionGrant::from_row(row);
+                let details = self.audit_details(AuditDetails {
+                    revoked_by: Some(revoked_by.to_string()),
+                    reason: Some(reason.to_string()),
+                    correlation_id: grant.correlation_id.clone(),
+                    ..Default::default()
+                });
+                (grant, details)
+            })
+            .collect();
+        for (grant, details) in &revoked {
+            Self::insert_audit_log(&mut tx, now, &grant.username, &grant.command, "duplicate_cleanup", Some(details)).await?;
+        }
+        Self::record_observed_time(&mut *tx, now).await?;
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+        for (grant, details) in &revoked {
+            self.export_audit_event(now, &grant.username, &grant.command, "duplicate_cleanup", Some(details));
+        }
+
+        info!("Revoked {} duplicate permission(s)", revoked.len());
+
+        Ok(revoked.into_iter().map(|(grant, _)| grant).collect())
+    }
+
+    /// Revoke the live grants with the given ids because their holder left
+    /// the paired required group, recording each with the audit action
+    /// `group_revalidation`. Only grants that apply on `host`, where the
+    /// membership was checked, are revoked; grants that ended in the
+    /// meantime are skipped. Returns the grants as revoked.
+    pub async fn revoke_for_group_loss(
+        &self,
+        grants: &[(i64, String)],
+        host: &str,
+        revoked_by: &str,
+    ) -> Result<Vec<PermissionGrant>> {
+        let now = Utc::now();
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let mut revoked = Vec::with_capacity(grants.len());
+        for (id, group) in grants {
+            let reason = format!("No longer a member of required group {}", group);
+            let row = sqlx::query(
+                r#"
+                UPDATE permission_grants
+                SET revoked = TRUE,
+                    revoked_at = ?,
+                    revoked_by = ?,
+                    revoke_reason = ?
+                WHERE id = ?
+                    AND (host IS NULL OR host = ?)
+                    AND NOT revoked
+                    AND expires_at > ?
+                RETURNING *
+                "#,
+            )
+            .bind(now)
+            .bind(revoked_by)
+            .bind(&reason)
+            .bind(id)
+            .bind(host)
+            .bind(now)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(PermissionError::Database)?;
+
+            if let Some(row) = row {
+                let grant = PermissionGrant::from_row(&row);
+                let details = self.audit_details(AuditDetails {
+                    revoked_by: Some(revoked_by.to_string()),
+                    reason: Some(reason),
+                    correlation_id: grant.correlation_id.clone(),
+                    ..Default::default()
+                });
+                Self::insert_audit_log(&mut tx, now, &grant.username, &grant.command, "group_revalidation", Some(&details)).await?;
+                revoked.push((grant, details));
+            }
+        }
+        Self::record_observed_time(&mut *tx, now).await?;
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+        for (grant, details) in &revoked {
+            self.export_audit_event(now, &grant.username, &grant.command, "group_revalidation", Some(details));
+        }
+
+        info!("Revoked {} permission(s) of users no longer in required groups", revoked.len());
+
+        Ok(revoked.into_iter().map(|(grant, _)| grant).collect())
+    }
+
+    /// Revoke the live grants with the given ids because they went the
+    /// paired number of minutes without a recorded use, recording each with
+    /// the audit action `idle_timeout`. Grants used or ended in the meantime
+    /// are skipped. Returns the grants as revoked.
+    pub async fn revoke_idle(&self, grants: &[(i64, i64)], revoked_by: &str) -> Result<Vec<PermissionGrant>> {
+        let now = Utc::now();
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let mut revoked = Vec::with_capacity(grants.len());
+        for (id, minutes) in grants {
+            let Some(unused_since) = Duration::try_minutes(*minutes).and_then(|idle| now.checked_sub_signed(idle)) else {
+                continue;
+            };
+            let reason = format!("Unused for {} minutes", minutes);
+            let row = sqlx::query(
+                r#"
+                UPDATE permission_grants
+                SET revoked = TRUE,
+                    revoked_at = ?,
+                    revoked_by = ?,
+                    revoke_reason = ?
+                WHERE id = ?
+                    AND NOT revoked
+                    AND expires_at > ?
+                    AND COALESCE(last_used, active_from, granted_at) <= ?
+                RETURNING *
+                "#,
+            )
+            .bind(now)
+            .bind(revoked_by)
+            .bind(&reason)
+            .bind(id)
+            .bind(now)
+            .bind(unused_since)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(PermissionError::Database)?;
+
+            if let Some(row) = row {
+                let grant = PermissionGrant::from_row(&row);
+                let details = self.audit_details(AuditDetails {
+                    revoked_by: Some(revoked_by.to_string()),
+                    reason: Some(reason),
+                    correlation_id: grant.correlation_id.clone(),
+                    ..Default::default()
+                });
+                Self::insert_audit_log(&mut tx, now, &grant.username, &grant.command, "idle_timeout", Some(&details)).await?;
+                revoked.push((grant, details));
+            }
+        }
+        Self::record_observed_time(&mut *tx, now).await?;
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+        for (grant, details) in &revoked {
+            self.export_audit_event(now, &grant.username, &grant.command, "idle_timeout", Some(details));
+        }
+
+        info!("Revoked {} idle permission(s)", revoked.len());
+
+        Ok(revoked.into_iter().map(|(grant, _)| grant).collect())
+    }
+
+    /// Revoke `username`'s own live grant of `command`, or every live grant
+    /// they hold if `command` is `None`, at their request. Each grant is
+    /// revoked by the user and recorded with the audit action
+    /// `self_revoke`. Returns the grants as revoked, sorted by command.
+    pub async fn revoke_own(&self, username: &str, command: Option<&str>) -> Result<Vec<PermissionGrant>> {
+        let now = Utc::now();
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let rows = sqlx::query(
+            r#"
+            UPDATE permission_grants
+            SET revoked = TRUE,
+                revoked_at = ?,
+                revoked_by = username
+            WHERE username = ?
+                AND (? IS NULL OR command = ?)
+                AND NOT revoked
+                AND expires_at > ?
+            RETURNING *
+            "#,
+        )
+        .bind(now)
+        .bind(username)
+        .bind(command)
+        .bind(command)
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        let mut revoked: Vec<(PermissionGrant, String)> = rows
+            .iter()
+            .map(|row| {
+                let grant = PermissionGrant::from_row(row);
+                let details = self.audit_details(AuditDetails {
+                    revoked_by: Some(username.to_string()),
+                    correlation_id: grant.correlation_id.clone(),
+                    ..Default::default()
+                });
+                (grant, details)
+            })
+            .collect();
+        revoked.sort_by(|a, b| a.0.command.cmp(&b.0.command));
+        for (grant, details) in &revoked {
+            Self::insert_audit_log(&mut tx, now, &grant.username, &grant.command, "self_revoke", Some(details)).await?;
+        }
+        Self::record_observed_time(&mut *tx, now).await?;
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+        for (grant, details) in &revoked {
+            self.export_audit_event(now, &grant.username, &grant.command, "self_revoke", Some(details));
+        }
+
+        info!("{} dropped {} permission(s)", username, revoked.len());
+        Ok(revoked.into_iter().map(|(grant, _)| grant).collect())
+    }
+
+    /// Check if a permission is currently valid
+    pub async fn check_permission(
+        &self,
+        username: &str,
+        command: &str,
+    ) -> Result<bool> {
+        self.check_permission_on_host(username, command, None).await
+    }
+
+    /// Check for an active grant that applies on `host`. Grants without a
+    /// host apply everywhere; with `host` of `None`, any host matches.
+    pub async fn check_permission_on_host(
+        &self,
+        username: &str,
+        command: &str,
+        host: Option<&str>,
+    ) -> Result<bool> {
+        Ok(self.active_grant_on_host(username, command, host).await?.is_some())
+    }
+
+    /// The active grant `check_permission_on_host` looks for, if any
+    pub async fn active_grant_on_host(
+        &self,
+        username: &str,
+        command: &str,
+        host: Option<&str>,
+    ) -> Result<Option<PermissionGrant>> {
+        let now = Utc::now();
+        
+        let row = sqlx::query(
+            r#"
+            SELECT *
+            FROM permission_grants
+            WHERE username = ?
+                AND command = ?
+                AND NOT revoked
+                AND expires_at > ?
+                AND (active_from IS NULL OR active_from <= ?)
+                AND (max_uses IS NULL OR use_count < max_uses)
+                AND (? IS NULL OR host IS NULL OR host = ?)
+                AND NOT EXISTS (SELECT 1 FROM settings WHERE key = 'maintenance')
+            LIMIT 1
+            "#,
+        )
+        .bind(username)
+        .bind(command)
+        .bind(now)
+        .bind(now)
+        .bind(host)
+        .bind(host)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        Ok(row.map(|row| PermissionGrant::from_row(&row)))
+    }
+
+    /// Check several commands for one user in a single query, mapping each
+    /// command to whether the user holds an active grant for it
+    pub async fn check_permissions(
+        &self,
+        username: &str,
+        commands: &[String],
+    ) -> Result<HashMap<String, bool>> {
+        self.check_permissions_on_host(username, commands, None).await
+    }
+
+    /// Batched form of `check_permission_on_host`
+    pub async fn check_permissions_on_host(
+        &self,
+        username: &str,
+        commands: &[String],
+        host: Option<&str>,
+    ) -> Result<HashMap<String, bool>> {
+        if commands.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let now = Utc::now();
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT DISTINCT command FROM permission_grants WHERE username = ");
+        query.push_bind(username);
+        query.push(" AND command IN (");
+        let mut separated = query.separated(", ");
+        for command in commands {
+            separated.push_bind(command.as_str());
+        }
+        query.push(")");
+        query.push(" AND NOT revoked AND expires_at > ").push_bind(now);
+        query.push(" AND (active_from IS NULL OR active_from <= ").push_bind(now).push(")");
+        query.push(" AND (max_uses IS NULL OR use_count < max_uses)");
+        query.push(" AND NOT EXISTS (SELECT 1 FROM settings WHERE key = 'maintenance')");
+        if let Some(host) = host {
+            query.push(" AND (host IS NULL OR host = ").push_bind(host).push(")");
+        }
+
+        let rows = query
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let mut allowed: HashMap<String, bool> = commands.iter().map(|c| (c.clone(), false)).collect();
+        for row in rows {
+            allowed.insert(row.get("command"), true);
+        }
+        Ok(allowed)
+    }
+
+    /// Record a use of an active permission, updating its last used
+    /// timestamp and use count. Returns the updated grant, or `None` if the
+    /// user holds no active grant for the command. Fails with
+    /// `UsageQuotaExceeded` if the grant has no uses left.
+    pub async fn update_last_used(
+        &self,
+        username: &str,
+        command: &str,
+    ) -> Result<Option<PermissionGrant>> {
+        self.record_use(username, command, None, false).await
+    }
+
+    /// Record a use as `update_last_used` does, of the grant that applies on
+    /// `host`, and with `audit` log it. Only one grant is spent, preferring
+    /// one for `host` over one without a host. Without an async audit buffer
+    /// the log entry is written in the same transaction as the update, so a
+    /// check costs one write, not two.
+    pub async fn record_use(
+        &self,
+        username: &str,
+        command: &str,
+        host: Option<&str>,
+        audit: bool,
+    ) -> Result<Option<PermissionGrant>> {
+        let now = Utc::now();
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+        let row = sqlx::query(
+            r#"
+            UPDATE permission_grants
+            SET last_used = ?,
+                use_count = use_count + 1
+            WHERE id = (
+                SELECT id FROM permission_grants
+                WHERE username = ?
+                    AND command = ?
+                    AND NOT revoked
+                    AND expires_at > ?
+                    AND (active_from IS NULL OR active_from <= ?)
+                    AND (max_uses IS NULL OR use_count < max_uses)
+                    AND (? IS NULL OR host IS NULL OR host = ?)
+                ORDER BY host IS NULL
+                LIMIT 1
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(now)
+        .bind(username)
+        .bind(command)
+        .bind(now)
+        .bind(now)
+        .bind(host)
+        .bind(host)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        if let Some(row) = row {
+            let grant = PermissionGrant::from_row(&row);
+            if !audit {
+                tx.commit().await.map_err(PermissionError::Database)?;
+                return Ok(Some(grant));
+            }
+            let details = self.audit_details(AuditDetails {
+                correlation_id: grant.correlation_id.clone(),
+                ..Default::default()
+            });
+            if self.audit_buffer.is_some() {
+                tx.commit().await.map_err(PermissionError::Database)?;
+                self.add_audit_log(username, command, "use", Some(&details)).await?;
+            } else {
+                Self::insert_audit_log(&mut tx, now, username, command, "use", Some(&details)).await?;
+                tx.commit().await.map_err(PermissionError::Database)?;
+                self.export_audit_event(now, username, command, "use", Some(&details));
+            }
+            return Ok(Some(grant));
+        }
+        tx.commit().await.map_err(PermissionError::Database)?;
+
+        // Tell an exhausted grant apart from no grant at all
+        let exhausted = sqlx::query(
+            r#"
+            SELECT max_uses, correlation_id FROM permission_grants
+            WHERE username = ?
+                AND command = ?
+                AND NOT revoked
+                AND expires_at > ?
+                AND max_uses IS NOT NULL
+                AND use_count >= max_uses
+                AND (? IS NULL OR host IS NULL OR host = ?)
+            LIMIT 1
+            "#,
+        )
+        .bind(username)
+        .bind(command)
+        .bind(now)
+        .bind(host)
+        .bind(host)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        match exhausted {
+            Some(row) => Err(PermissionError::UsageQuotaExceeded {
+                command: command.to_string(),
+                max_uses: row.get("max_uses"),
+                correlation_id: row.get("correlation_id"),
+            }),
+            None => Ok(None),
+        }
+    }
+
+    /// Sum the minutes granted to a user for a command that fall after
+    /// `since`, from the grants table. Each grant counts from its start to
+    /// the expiry it was given, even if it was revoked or replaced sooner,
+    /// and an extended grant counts once up to its latest expiry.
+    pub async fn total_granted_minutes(
+        &self,
+        username: &str,
+        command: &str,
+        since: DateTime<Utc>,
+    ) -> Result<i64> {
+        let rows = sqlx::query(
+            r#"
+            SELECT granted_at, active_from, expires_at FROM permission_grants
+            WHERE username = ?
+                AND command = ?
+                AND expires_at > ?
+            "#,
+        )
+        .bind(username)
+        .bind(command)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        let total = rows
+            .iter()
+            .map(|row| {
+                let active_from: Option<DateTime<Utc>> = row.get("active_from");
+                let start = active_from.unwrap_or_else(|| row.get("granted_at"));
+                let expires_at: DateTime<Utc> = row.get("expires_at");
+                (expires_at - start.max(since)).num_minutes().max(0)
+            })
+            .sum();
+
+        Ok(total)
+    }
+
+    /// Number of distinct users holding live or scheduled grants of
+    /// `command`, as counted against its `max_concurrent_users`
+    pub async fn count_active_grants_for_command(&self, command: &str) -> Result<i64> {
+        Self::active_users_for_command(&self.pool, command, None, Utc::now()).await
+    }
+
+    /// Number of users other than `username` holding live or scheduled
+    /// grants of `command`, which a grant to `username` would be counted
+    /// against
+    pub async fn count_other_active_users(&self, command: &str, username: &str) -> Result<i64> {
+        Self::active_users_for_command(&self.pool, command, Some(username), Utc::now()).await
+    }
+
+    /// Users other than `excluding` holding live or scheduled grants of
+    /// `command`. Users are counted once however many rows they hold.
+    async fn active_users_for_command<'e, E>(
+        executor: E,
+        command: &str,
+        excluding: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<i64>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        let row = sqlx::query(
+            r#"
+            SELECT COUNT(DISTINCT username) as count
+            FROM permission_grants
+            WHERE command = ?
+                AND username IS NOT ?
+                AND NOT revoked
+                AND expires_at > ?
+            "#,
+        )
+        .bind(command)
+        .bind(excluding)
+        .bind(now)
+        .fetch_one(executor)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        Ok(row.get("count"))
+    }
+
+    /// Number of grants made to `username` since the given time, whatever
+    /// has become of them since
+    pub async fn count_recent_grants(&self, username: &str, since: DateTime<Utc>) -> Result<i64> {
+        let row = sqlx::query("SELECT COUNT(*) as count FROM permission_grants WHERE username = ? AND granted_at >= ?")
+            .bind(username)
+            .bind(since)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(PermissionError::Database)?;
+
+        Ok(row.get("count"))
+    }
+
+    /// List all active permissions for a user
+    pub async fn list_user_permissions(
+        &self,
+        username: &str,
+    ) -> Result<Vec<PermissionGrant>> {
+        let now = Utc::now();
+        
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM permission_grants
+            WHERE username = ?
+                AND NOT revoked
+                AND expires_at > ?
+            ORDER BY expires_at DESC
+            "#,
+        )
+        .bind(username)
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        Ok(rows.iter().map(PermissionGrant::from_row).collect())
+    }
+
+    /// List all active permissions across all users, excluding scheduled
+    /// grants that have not started yet
+    pub async fn list_active_permissions(&self) -> Result<Vec<PermissionGrant>> {
+        self.list_permissions(&ListOptions::default()).await
+    }
+
+    /// List permissions matching the given filters, honouring the limit and
+    /// offset. Results are ordered by user then command so pages are stable.
+    pub async fn list_permissions(&self, options: &ListOptions) -> Result<Vec<PermissionGrant>> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM permission_grants");
+        Self::push_list_filters(&mut query, options);
+        match options.sort {
+            Some(field) => {
+                let direction = if options.descending { "DESC" } else { "ASC" };
+                query.push(format!(" ORDER BY {} {}, id {}", field.column(), direction, direction));
+            }
+            None => {
+                query.push(" ORDER BY username, command, id");
+            }
+        }
+        if let Some(limit) = options.limit {
+            query.push(" LIMIT ").push_bind(limit).push(" OFFSET ").push_bind(options.offset);
+        } else if options.offset > 0 {
+            query.push(" LIMIT -1 OFFSET ").push_bind(options.offset);
+        }
+
+        let rows = query
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(PermissionError::Database)?;
+
+        Ok(rows.iter().map(PermissionGrant::from_row).collect())
+    }
+
+    /// Count permissions matching the given filters, ignoring limit and offset
+    pub async fn count_permissions(&self, options: &ListOptions) -> Result<i64> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) as count FROM permission_grants");
+        Self::push_list_filters(&mut query, options);
+
+        let row = query
+            .build()
+            .fetch_one(&self.pool)
+            .await
+            .map_err(PermissionError::Database)?;
+
+        Ok(row.get("count"))
+    }
+
+    /// Append the WHERE clause for a listing
+    fn push_list_filters(query: &mut QueryBuilder<'_, Sqlite>, options: &ListOptions) {
+        let now = Utc::now();
+
+        query.push(" WHERE 1 = 1");
+        if let Some(username) = &options.username {
+            query.push(" AND username = ").push_bind(username.clone());
+        }
+        if !options.include_revoked {
+            query.push(" AND NOT revoked");
+        }
+        if !options.include_expired {
+            query.push(" AND expires_at > ").push_bind(now);
+            query.push(" AND (max_uses IS NULL OR use_count < max_uses)");
+        }
+        if let Some(host) = &options.host {
+            query.push(" AND (host IS NULL OR host = ").push_bind(host.clone()).push(")");
+        }
+        if !options.include_scheduled {
+            query.push(" AND (active_from IS NULL OR active_from <= ").push_bind(now).push(")");
+        }
+    }
+
+    /// The next moment the set of active grants changes on its own, either
+    /// because a grant expires or because a scheduled grant starts
+    pub async fn next_transition(&self) -> Result<Option<DateTime<Utc>>> {
+        let now = Utc::now();
+
+        let row = sqlx::query(
+            r#"
+            SELECT at FROM (
+                SELECT expires_at AS at FROM permission_grants
+                WHERE NOT revoked AND expires_at > ?
+                UNION ALL
+                SELECT active_from AS at FROM permission_grants
+                WHERE NOT revoked AND active_from > ? AND expires_at > ?
+            )
+            ORDER BY at ASC
+            LIMIT 1
+            "#,
+        )
+        .bind(now)
+        .bind(now)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        Ok(row.map(|row| row.get("at")))
+    }
+
+    /// The unrevoked grant that expires next, if any
+    pub async fn next_expiry(&self) -> Result<Option<PermissionGrant>> {
+        let row = sqlx::query(
+            r#"
+            SELECT * FROM permission_grants
+            WHERE NOT revoked AND expires_at > ?
+            ORDER BY expires_at ASC, id ASC
+            LIMIT 1
+            "#,
+        )
+        .bind(Utc::now())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        Ok(row.as_ref().map(PermissionGrant::from_row))
+    }
+
+    /// List active permissions that expire within the given duration,
+    /// soonest first
+    pub async fn list_expiring(&self, within: Duration) -> Result<Vec<PermissionGrant>> {
+        let now = Utc::now();
+
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM permission_grants
+            WHERE NOT revoked
+                AND expires_at > ?
+                AND expires_at <= ?
+            ORDER BY expires_at ASC
+            "#,
+        )
+        .bind(now)
+        .bind(now + within)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        Ok(rows.iter().map(PermissionGrant::from_row).collect())
+    }
+
+    /// List grants that applied at any point during `[start, end)`,
+    /// including ones revoked since. A grant applies from its scheduled
+    /// start (or grant time) until it expires or is revoked, whichever is
+    /// first. Purged grants are no longer available.
+    pub async fn list_active_during(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<PermissionGrant>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT * FROM permission_grants
+            WHERE COALESCE(active_from, granted_at) < ?
+                AND CASE
+                    WHEN revoked AND revoked_at IS NOT NULL AND revoked_at < expires_at THEN revoked_at
+                    ELSE expires_at
+                END > ?
+            ORDER BY granted_at ASC, id ASC
+            "#,
+        )
+        .bind(end)
+        .bind(start)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        Ok(rows.iter().map(PermissionGrant::from_row).collect())
+    }
+
+    /// Add an entry to the audit log
+    async fn add_audit_log(
+        &self,
+        username: &str,
+        command: &str,
+        action: &str,
+        details: Option<&str>,
+    ) -> Result<()> {
+        let now = Utc::now();
+
+        if let Some(buffer) = &self.audit_buffer {
+            let queued = buffer.push(QueuedEntry {
+                timestamp: now,
+                username: username.to_string(),
+                command: command.to_string(),
+                action: action.to_string(),
+                details: details.map(str::to_string),
+            });
+            if queued.is_ok() {
+                self.export_audit_event(now, username, command, action, details);
+                return Ok(());
+            }
+        }
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+        Self::insert_audit_log(&mut tx, now, username, command, action, details).await?;
+        tx.commit().await.map_err(PermissionError::Database)?;
+        self.export_audit_event(now, username, command, action, details);
+
+        Ok(())
+    }
+
+    /// Write an audit log row chained to the latest one. The connection
+    /// must be inside a transaction so no other entry can take the same
+    /// place in the chain.
+    pub(crate) async fn insert_audit_log(
+        conn: &mut SqliteConnection,
+        timestamp: DateTime<Utc>,
+        username: &str,
+        command: &str,
+        action: &str,
+        details: Option<&str>,
+    ) -> Result<()> {
+        let prev_hash: Option<String> = sqlx::query_scalar("SELECT entry_hash FROM audit_log ORDER BY id DESC LIMIT 1")
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(PermissionError::Database)?
+            .flatten();
+        let prev_hash = prev_hash.unwrap_or_else(|| AUDIT_CHAIN_GENESIS.to_string());
+        let entry_hash = audit_entry_hash(&prev_hash, timestamp, username, command, action, details);
+
+        sqlx::query(
+            r#"
+            INSERT INTO audit_log 
+                (timestamp, username, command, action, details, prev_hash, entry_hash)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(timestamp)
+        .bind(username)
+        .bind(command)
+        .bind(action)
+        .bind(details)
+        .bind(&prev_hash)
+        .bind(&entry_hash)
+        .execute(&mut *conn)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        Ok(())
+    }
+
+    /// Read audit log entries matching `query`, newest first
+    pub async fn audit_entries(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+        let mut sql = QueryBuilder::<Sqlite>::new(
+            "SELECT id, timestamp, username, command, action, details FROM audit_log WHERE 1 = 1",
+        );
+        if let Some(username) = &query.username {
+            sql.push(" AND username = ").push_bind(username.clone());
+        }
+        if let Some(command) = &query.command {
+            sql.push(" AND command = ").push_bind(command.clone());
+        }
+        if let Some(action) = &query.action {
+            sql.push(" AND action = ").push_bind(action.clone());
+        }
+        sql.push(" ORDER BY id DESC");
+        if let Some(limit) = query.limit {
+            sql.push(" LIMIT ").push_bind(limit);
+        }
+
+        let rows = sql
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(PermissionError::Database)?;
+
+        Ok(rows
+            .iter()
+            .map(|row| AuditEntry {
+                id: row.get("id"),
+                timestamp: row.get("timestamp"),
+                username: row.get("username"),
+                command: row.get("command"),
+                action: row.get("action"),
+                details: row.get("details"),
+            })
+            .collect())
+    }
+
+    /// Recompute the audit log hash chain from the oldest entry, stopping at
+    /// the first entry that was altered or whose predecessor was removed.
+    /// Entries from before the chain existed are counted but not checked.
+    pub async fn verify_audit_chain(&self) -> Result<AuditChainReport> {
+        let rows = sqlx::query(
+            r#"
+            SELECT id, timestamp, username, command, action, details, prev_hash, entry_hash
+            FROM audit_log
+            ORDER BY id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        let mut report = AuditChainReport { verified: 0, unchained: 0, broken_at: None };
+        let mut last_hash: Option<String> = None;
+        for row in &rows {
+            let entry_hash: Option<String> = row.get("entry_hash");
+            let Some(entry_hash) = entry_hash else {
+                if last_hash.is_some() {
+                    report.broken_at = Some(row.get("id"));
+                    break;
+                }
+                report.unchained += 1;
+                continue;
+            };
+
+            let prev_hash: Option<String> = row.get("prev_hash");
+            let prev_hash = prev_hash.unwrap_or_default();
+            let details: Option<String> = row.get("details");
+            let expected = audit_entry_hash(
+                &prev_hash,
+                row.get("timestamp"),
+                row.get("username"),
+                row.get("command"),
+                row.get("action"),
+                details.as_deref(),
+            );
+            if prev_hash != last_hash.as_deref().unwrap_or(AUDIT_CHAIN_GENESIS) || expected != entry_hash {
+                report.broken_at = Some(row.get("id"));
+                break;
+            }
+
+            report.verified += 1;
+            last_hash = Some(entry_hash);
+        }
+
+        Ok(report)
+    }
+
+    /// Remember the latest wall clock time seen, never moving it backwards
+    async fn record_observed_time<'e, E>(executor: E, now: DateTime<Utc>) -> Result<()>
+    where
+        E: Executor<'e, Database = Sqlite>,
+    {
+        sqlx::query(
+            r#"
+            INSERT INTO clock_state (id, last_seen) VALUES (1, ?)
+            ON CONFLICT(id) DO UPDATE SET last_seen = MAX(last_seen, excluded.last_seen)
+            "#,
+        )
+        .bind(now)
+        .execute(executor)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        Ok(())
+    }
+
+    /// Turn maintenance mode on or off, recording the change in the audit
+    /// log as `maintenance_on` or `maintenance_off`. Returns whether the
+    /// mode changed.
+    pub async fn set_maintenance(&self, enabled: bool, changed_by: &str) -> Result<bool> {
+        let now = Utc::now();
+
+        let mut tx = self.pool
+            .begin_with("BEGIN IMMEDIATE")
+            .await
+            .map_err(PermissionError::Database)?;
+
+        let result = if enabled {
+            sqlx::query("INSERT OR IGNORE INTO settings (key, value, updated_at, updated_by) VALUES (?, 'on', ?, ?)")
+                .bind(MAINTENANCE_KEY)
+                .bind(now)
+                .bind(changed_by)
+                .execute(&mut *tx)
+                .await
+        } else {
+            sqlx::query("DELETE FROM settings WHERE key = ?")
+                .bind(MAINTENANCE_KEY)
+                .execute(&mut *tx)
+                .await
+        }
+        .map_err(PermissionError::Database)?;
+
+        let changed = result.rows_affected() > 0;
+        let action = if enabled { "maintenance_on" } else { "maintenance_off" };
+        if changed {
+            Self::insert_audit_log(&mut tx, now, changed_by, "*", action, None).await?;
+        }
+        Self::record_observed_time(&mut *tx, now).await?;
+
+        tx.commit().await.map_err(PermissionError::Database)?;
+        if changed {
+            self.export_audit_event(now, changed_by, "*", action, None);
+            info!("Maintenance mode turned {} by {}", if enabled { "on" } else { "off" }, changed_by);
+        }
+
+        Ok(changed)
+    }
+
+    /// Maintenance mode, if it is on
+    pub async fn maintenance(&self) -> Result<Option<Maintenance>> {
+        let row = sqlx::query("SELECT updated_at, updated_by FROM settings WHERE key = ?")
+            .bind(MAINTENANCE_KEY)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(PermissionError::Database)?;
+
+        Ok(row.map(|row| Maintenance { since: row.get("updated_at"), enabled_by: row.get("updated_by") }))
+    }
+
+    /// The counter every revoke, other change to a grant's reach and switch
+    /// to maintenance mode bumps, whichever process makes it
+    pub async fn grant_generation(&self) -> Result<i64> {
+        let generation: Option<i64> = sqlx::query_scalar("SELECT CAST(value AS INTEGER) FROM settings WHERE key = ?")
+            .bind(GRANT_GENERATION_KEY)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(PermissionError::Database)?;
+
+        Ok(generation.unwrap_or(0))
+    }
+
+    /// Latest wall clock time recorded by a mutation, if any
+    pub async fn last_observed_time(&self) -> Result<Option<DateTime<Utc>>> {
+        let row = sqlx::query("SELECT last_seen FROM clock_state WHERE id = 1")
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(PermissionError::Database)?;
+
+        Ok(row.map(|row| row.get("last_seen")))
+    }
+
+    /// Send a recorded audit event to any configured exporters
+    fn export_audit_event(
+        &self,
+        timestamp: DateTime<Utc>,
+        username: &str,
+        command: &str,
+        action: &str,
+        details: Option<&str>,
+    ) {
+        let event = AuditEvent {
+            timestamp,
+            username,
+            command,
+            action,
+            details,
+        };
+        if let Some(syslog) = &self.syslog {
+            syslog.emit(&event);
+        }
+        if let Some(audit_file) = &self.audit_file {
+            audit_file.emit(&event);
+        }
+    }
+
+    /// Clean up expired permissions
+    pub async fn cleanup_expired(&self) -> Result<u64> {
+        self.expire_grants().await.map(|grants| grants.len() as u64)
+    }
+
+    /// Mark expired grants as revoked, returning them
+    pub async fn expire_grants(&self) -> Result<Vec<PermissionGrant>> {
+        let now = Utc::now();
+        
+        let rows = sqlx::query(&format!(
+            r#"
+            UPDATE permission_grants
+            SET revoked = TRUE,
+                revoked_at = ?,
+                revoked_by = 'system_cleanup'
+            WHERE {}
+            RETURNING *
+            "#,
+            EXPIRED_PREDICATE
+        ))
+        .bind(now)
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        let grants: Vec<PermissionGrant> = rows.iter().map(PermissionGrant::from_row).collect();
+        for grant in &grants {
+            info!(
+                correlation_id = grant.correlation_id.as_deref(),
+                "Expired permission: user={}, command={}",
+                grant.username,
+                grant.command,
+            );
+        }
+        Self::record_observed_time(&self.pool, now).await?;
+        if !grants.is_empty() {
+            info!("Cleaned up {} expired permission(s)", grants.len());
+        }
+
+        Ok(grants)
+    }
+
+    /// Permanently delete grants that were revoked before the given time,
+    /// leaving their audit log entries in place
+    pub async fn purge_revoked(&self, older_than: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query(&format!(
+            "DELETE FROM permission_grants WHERE {}",
+            PURGEABLE_PREDICATE
+        ))
+        .bind(older_than)
+        .execute(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?;
+
+        let count = result.rows_affected();
+        if count > 0 {
+            info!("Purged {} revoked permission(s)", count);
+        }
+
+        Ok(count)
+    }
+
+    /// Count the grants `cleanup_expired` would revoke right now, without
+    /// changing anything
+    pub async fn count_expired(&self) -> Result<u64> {
+        let count: i64 = sqlx::query(&format!(
+            "SELECT COUNT(*) as count FROM permission_grants WHERE {}",
+            EXPIRED_PREDICATE
+        ))
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?
+        .get("count");
+
+        Ok(count as u64)
+    }
+
+    /// Count the grants `purge_revoked` would delete for the same cutoff,
+    /// without changing anything
+    pub async fn count_purgeable(&self, older_than: DateTime<Utc>) -> Result<u64> {
+        let count: i64 = sqlx::query(&format!(
+            "SELECT COUNT(*) as count FROM permission_grants WHERE {}",
+            PURGEABLE_PREDICATE
+        ))
+        .bind(older_than)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(PermissionError::Database)?
+        .get("count");
+
+        Ok(count as u64)
+    }
+}
+
+/// Bytes taken by the database file at `path` and its write-ahead log
+fn database_size(path: &Path) -> u64 {
+    ["", "-wal"]
+        .iter()
+        .filter_map(|suffix| fs::metadata(format!("{}{}", path.display(), suffix)).ok())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+/// Report SQLite's "corrupt" and "not a database" errors as corruption
+/// rather than a generic database failure
+fn corruption_error(e: sqlx::Error) -> PermissionError {
+    if let sqlx::Error::Database(db_err) = &e {
+        let primary = db_err.code().and_then(|code| code.parse::<i32>().ok()).map(|code| code & 0xff);
+        if matches!(primary, Some(SQLITE_CORRUPT | SQLITE_NOTADB)) {
+            return PermissionError::DatabaseCorrupt(db_err.message().to_string());
+        }
+    }
+    PermissionError::Database(e)
+}
+
+/// Map a failure to open the database, blaming the key for an encrypted
+/// database SQLite cannot read
+fn open_error(e: sqlx::Error, encrypted: bool) -> PermissionError {
+    match corruption_error(e) {
+        PermissionError::DatabaseCorrupt(message) if encrypted => PermissionError::DatabaseEncryption(format!(
+            "the key does not decrypt the database ({})",
+            message
+        )),
+        other => other,
+    }
+}
+
+/// Read the SQLCipher passphrase from `path`, which must be owned by the
+/// current user and inaccessible to anyone else. A trailing newline is
+/// not part of the key.
+fn read_encryption_key(path: &Path) -> Result<String> {
+    read_secret_file(path, "key file", PermissionError::DatabaseEncryption)
+}
+
+/// `PRAGMA key` statement setting `key` as the passphrase
+fn key_pragma(key: &str) -> String {
+    format!("PRAGMA key = '{}'", key.replace('\'', "''"))
+}
+
+/// Check that an existing, non-empty file starts with the SQLite header
+fn check_database_header(path: &Path) -> Result<()> {
+    let mut header = [0u8; SQLITE_HEADER.len()];
+    let read = match fs::File::open(path) {
+        Ok(mut file) => file.read(&mut header).map_err(|e| PermissionError::io_error(e, path.to_path_buf()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(PermissionError::io_error(e, path.to_path_buf())),
+    };
+
+    if read > 0 && header != *SQLITE_HEADER {
+        return Err(PermissionError::DatabaseCorrupt(format!("{:?} is not a SQLite database", path)));
+    }
+    Ok(())
+}
+
+/// Remove a database file along with its WAL and shared-memory files
+fn remove_database_files(path: &Path) -> Result<()> {
+    for suffix in ["", "-wal", "-shm"] {
+        let file = PathBuf::from(format!("{}{}", path.display(), suffix));
+        if file.exists() {
+            fs::remove_file(&file).map_err(|e| PermissionError::io_error(e, file.clone()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Name of the host a grant is being created on, recorded for provenance;
+/// `None` if it cannot be read
+fn creating_host() -> Option<String> {
+    match hostname::get() {
+        Ok(name) => Some(name.to_string_lossy().into_owned()),
+        Err(e) => {
+            warn!("Failed to read hostname: {}", e);
+            None
+        }
+    }
+}
+
+/// Generate a random (version 4) UUID identifying a grant across its
+/// lifecycle
+pub fn new_correlation_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Hash of an audit entry's fields chained to the previous entry's hash,
+/// as lowercase hex
+fn audit_entry_hash(
+    prev_hash: &str,
+    timestamp: DateTime<Utc>,
+    username: &str,
+    command: &str,
+    action: &str,
+    details: Option<&str>,
+) -> String {
+    let fields = serde_json::json!([
+        timestamp.to_rfc3339_opts(SecondsFormat::Nanos, true),
+        username,
+        command,
+        action,
+        details,
+    ]);
+
+    let mut hasher = Sha256::new();
+    hasher.update(prev_hash.as_bytes());
+    hasher.update(fields.to_string().as_bytes());
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    async fn create_test_db() -> (Database, TempDir) {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = Database::new(&db_path).await.unwrap();
+        (db, temp_dir)
+    }
+
+    #[tokio::test]
+    async fn test_connection_tuning_is_applied() {
+        let temp_dir = TempDir::new().unwrap();
+        let tuning = DatabaseTuning { busy_timeout_ms: 2500, wal_autocheckpoint: 0, encryption_key_path: None };
+        let db = Database::new_with_tuning(temp_dir.path().join("test.db"), tuning).await.unwrap();
+
+        let busy: i64 = sqlx::query_scalar("PRAGMA busy_timeout").fetch_one(&db.pool).await.unwrap();
+        let checkpoint: i64 = sqlx::query_scalar("PRAGMA wal_autocheckpoint").fetch_one(&db.pool).await.unwrap();
+        assert_eq!((busy, checkpoint), (2500, 0));
+
+        let db = Database::new(temp_dir.path().join("default.db")).await.unwrap();
+        let busy: i64 = sqlx::query_scalar("PRAGMA busy_timeout").fetch_one(&db.pool).await.unwrap();
+        assert_eq!(busy, 10_000);
+    }
+
+    #[tokio::test]
+    async fn test_encryption_key_is_checked() {
+        let temp_dir = TempDir::new().unwrap();
+        let key_path = temp_dir.path().join("db.key");
+        fs::write(&key_path, "it's secret\n").unwrap();
+        let tuning = DatabaseTuning { encryption_key_path: Some(key_path.clone()), ..DatabaseTuning::default() };
+        let db_path = temp_dir.path().join("test.db");
+
+        fs::set_permissions(&key_path, fs::Permissions::from_mode(0o644)).unwrap();
+        let err = Database::new_with_tuning(&db_path, tuning.clone()).await.err().unwrap();
+        assert!(matches!(err, PermissionError::DatabaseEncryption(ref msg) if msg.contains("0400")), "{}", err);
+
+        // The bundled SQLite has no SQLCipher, which must not pass silently
+        fs::set_permissions(&key_path, fs::Permissions::from_mode(0o400)).unwrap();
+        let err = Database::new_with_tuning(&db_path, tuning).await.err().unwrap();
+        assert!(matches!(err, PermissionError::DatabaseEncryption(ref msg) if msg.contains("SQLCipher")), "{}", err);
+
+        assert_eq!(read_encryption_key(&key_path).unwrap(), "it's secret");
+        assert_eq!(key_pragma("it's secret"), "PRAGMA key = 'it''s secret'");
+    }
+
+    #[tokio::test]
+    async fn test_compact_reclaims_space() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+        for i in 0..500 {
+            db.grant_permission(&format!("user{}", i), "/test/command", expires_at, "admin").await.unwrap();
+        }
+        sqlx::query("DELETE FROM permission_grants").execute(db.get_pool()).await.unwrap();
+
+        let compaction = db.compact().await.unwrap();
+        assert!(compaction.size_after < compaction.size_before);
+        assert_eq!(db.compact().await.unwrap().size_before, compaction.size_after);
+    }
+
+    #[tokio::test]
+    async fn test_grant_and_check_permission() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+
+        let grant = db.grant_permission(
+            "testuser",
+            "/test/command",
+            expires_at,
+            "admin"
+        ).await.unwrap();
+
+        assert!(grant.id > 0);
+        assert_eq!(grant.username, "testuser");
+        assert_eq!(grant.expires_at, expires_at);
+        assert_eq!(grant.granted_by, "admin");
+        assert!(!grant.revoked);
+        assert_eq!(grant.created_host, creating_host());
+        assert_eq!(grant.created_version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+        assert!(db.check_permission("testuser", "/test/command").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_permission() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+
+        db.grant_permission(
+            "testuser",
+            "/test/command",
+            expires_at,
+            "admin"
+        ).await.unwrap();
+
+        assert!(db.revoke_permission(
+            "testuser",
+            "/test/command",
+            "admin"
+        ).await.unwrap());
+
+        assert!(!db.check_permission("testuser", "/test/command").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_with_reason() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+
+        db.grant_permission("testuser", "/test/command", expires_at, "admin").await.unwrap();
+        assert!(db.revoke_permission_with_reason(
+            "testuser",
+            "/test/command",
+            "admin",
+            Some("incident closed"),
+        ).await.unwrap());
+
+        let grants = db.list_permissions(&ListOptions::all()).await.unwrap();
+        assert_eq!(grants[0].revoke_reason.as_deref(), Some("incident closed"));
+
+        let details: String = sqlx::query("SELECT details FROM audit_log WHERE action = 'revoke'")
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap()
+            .get("details");
+        let details = AuditDetails::parse(&details).unwrap();
+        assert_eq!(details.revoked_by.as_deref(), Some("admin"));
+        assert_eq!(details.reason.as_deref(), Some("incident closed"));
+        assert_eq!(details.correlation_id, grants[0].correlation_id);
+    }
+
+    #[tokio::test]
+    async fn test_minimal_audit_detail() {
+        let (db, _temp) = create_test_db().await;
+        let db = db.with_audit_detail(AuditDetail::Minimal);
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+
+        db.grant_permission("testuser", "/test/command", expires_at, "admin").await.unwrap();
+        db.revoke_permission_with_reason("testuser", "/test/command", "admin", Some("ticket 4711")).await.unwrap();
+
+        let entries = db.audit_entries(&AuditQuery::default()).await.unwrap();
+        let actions: Vec<&str> = entries.iter().map(|e| e.action.as_str()).collect();
+        assert_eq!(actions, vec!["revoke", "grant"]);
+
+        let revoke = AuditDetails::parse(entries[0].details.as_deref().unwrap()).unwrap();
+        assert_eq!((revoke.revoked_by.as_deref(), revoke.reason), (Some("admin"), None));
+        let grant = AuditDetails::parse(entries[1].details.as_deref().unwrap()).unwrap();
+        assert_eq!((grant.expires_at, grant.duration_minutes), (Some(expires_at), None));
+        assert!(grant.correlation_id.is_some());
+
+        // The window survives, so cumulative limits still work
+        let minutes = db.total_granted_minutes("testuser", "/test/command", Utc::now() - chrono::Duration::hours(1))
+            .await
+            .unwrap();
+        assert_eq!(minutes, 59);
+    }
+
+    #[tokio::test]
+    async fn test_correlation_id_follows_grant() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+
+        let grant = db.grant_permission("testuser", "/test/command", expires_at, "admin").await.unwrap();
+        let id = grant.correlation_id.clone().unwrap();
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "4");
+
+        let other = db.grant_permission("testuser", "/other/command", expires_at, "admin").await.unwrap();
+        assert_ne!(other.correlation_id.as_deref(), Some(id.as_str()));
+
+        db.revoke_permission("testuser", "/test/command", "admin").await.unwrap();
+
+        let rows = sqlx::query("SELECT action FROM audit_log WHERE details LIKE ? ORDER BY id")
+            .bind(format!("%{}%", id))
+            .fetch_all(db.get_pool())
+            .await
+            .unwrap();
+        let actions: Vec<String> = rows.iter().map(|row| row.get("action")).collect();
+        assert_eq!(actions, vec!["grant", "revoke"]);
+
+        // The prefix must not break the cumulative duration accounting
+        let minutes = db.total_granted_minutes("testuser", "/test/command", Utc::now() - chrono::Duration::hours(1))
+            .await
+            .unwrap();
+        assert!((59..=60).contains(&minutes));
+    }
+
+    #[tokio::test]
+    async fn test_total_granted_minutes() {
+        let (db, _temp) = create_test_db().await;
+        let since = Utc::now() - chrono::Duration::hours(24);
+
+        db.grant_permission("testuser", "/test/command", Utc::now() + chrono::Duration::minutes(30), "admin")
+            .await.unwrap();
+        db.grant_permission("testuser", "/test/command", Utc::now() + chrono::Duration::minutes(45), "admin")
+            .await.unwrap();
+        db.grant_permission("other", "/test/command", Utc::now() + chrono::Duration::minutes(60), "admin")
+            .await.unwrap();
+
+        let total = db.total_granted_minutes("testuser", "/test/command", since).await.unwrap();
+        assert!((73..=75).contains(&total), "unexpected total {}", total);
+
+        // Totals come from the grants themselves, not the audit log, and
+        // only the part of a grant inside the window counts
+        sqlx::query("DELETE FROM audit_log").execute(&db.pool).await.unwrap();
+        let total = db.total_granted_minutes("testuser", "/test/command", since).await.unwrap();
+        assert!((73..=75).contains(&total), "unexpected total {}", total);
+        let total = db.total_granted_minutes("testuser", "/test/command", Utc::now() + chrono::Duration::minutes(40)).await.unwrap();
+        assert!((4..=5).contains(&total), "unexpected total {}", total);
+    }
+
+    #[tokio::test]
+    async fn test_list_expiring() {
+        let (db, _temp) = create_test_db().await;
+
+        db.grant_permission("alice", "/test/late", Utc::now() + Duration::hours(3), "admin")
+            .await.unwrap();
+        db.grant_permission("alice", "/test/soon", Utc::now() + Duration::minutes(10), "admin")
+            .await.unwrap();
+        db.grant_permission("bob", "/test/sooner", Utc::now() + Duration::minutes(5), "admin")
+            .await.unwrap();
+
+        let expiring = db.list_expiring(Duration::minutes(30)).await.unwrap();
+        let commands: Vec<_> = expiring.iter().map(|g| g.command.as_str()).collect();
+        assert_eq!(commands, vec!["/test/sooner", "/test/soon"]);
+        assert!(expiring[0].time_remaining() <= Duration::minutes(5));
+    }
+
+    #[tokio::test]
+    async fn test_purge_revoked() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + Duration::hours(1);
+
+        db.grant_permission("old", "/test/command", expires_at, "admin").await.unwrap();
+        db.grant_permission("recent", "/test/command", expires_at, "admin").await.unwrap();
+        db.revoke_permission("old", "/test/command", "admin").await.unwrap();
+        db.revoke_permission("recent", "/test/command", "admin").await.unwrap();
+
+        // Backdate the first revocation past the purge threshold
+        sqlx::query("UPDATE permission_grants SET revoked_at = ? WHERE username = 'old'")
+            .bind(Utc::now() - Duration::days(40))
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+
+        let cutoff = Utc::now() - Duration::days(30);
+        assert_eq!(db.count_purgeable(cutoff).await.unwrap(), 1);
+        let purged = db.purge_revoked(cutoff).await.unwrap();
+        assert_eq!(purged, 1);
+        assert_eq!(db.count_purgeable(cutoff).await.unwrap(), 0);
+
+        let remaining: Vec<String> = sqlx::query("SELECT username FROM permission_grants")
+            .fetch_all(db.get_pool())
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get("username"))
+            .collect();
+        assert_eq!(remaining, vec!["recent".to_string()]);
+
+        // The audit trail outlives the deleted grant
+        let audit: i64 = sqlx::query("SELECT COUNT(*) as count FROM audit_log WHERE username = 'old'")
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap()
+            .get("count");
+        assert_eq!(audit, 2);
+    }
+
+    #[tokio::test]
+    async fn test_revoke_matching() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + Duration::hours(1);
+        for (user, command) in [
+            ("alice", "/usr/bin/systemctl"),
+            ("alice", "/usr/bin/docker"),
+            ("alice", "/opt/tool"),
+            ("bob", "/usr/bin/docker"),
+        ] {
+            db.grant_permission(user, command, expires_at, "admin").await.unwrap();
+        }
+
+        let revoked = db.revoke_matching("alice", "/usr/bin/*", "admin", Some("offboarding")).await.unwrap();
+        let commands: Vec<&str> = revoked.iter().map(|g| g.command.as_str()).collect();
+        assert_eq!(commands, ["/usr/bin/docker", "/usr/bin/systemctl"]);
+        assert!(revoked.iter().all(|g| g.revoke_reason.as_deref() == Some("offboarding")));
+
+        assert!(db.check_permission("alice", "/opt/tool").await.unwrap());
+        assert!(db.check_permission("bob", "/usr/bin/docker").await.unwrap());
+        assert!(db.revoke_matching("alice", "/usr/bin/*", "admin", None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_by_granter() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + Duration::hours(1);
+
+        db.grant_permission("alice", "/test/one", expires_at, "mallory").await.unwrap();
+        db.grant_permission("bob", "/test/two", expires_at, "mallory").await.unwrap();
+        db.grant_permission("carol", "/test/one", expires_at, "admin").await.unwrap();
+
+        assert_eq!(db.revoke_by_granter("mallory", "security").await.unwrap(), 2);
+        assert!(!db.check_permission("alice", "/test/one").await.unwrap());
+        assert!(!db.check_permission("bob", "/test/two").await.unwrap());
+        assert!(db.check_permission("carol", "/test/one").await.unwrap());
+
+        let audit: i64 = sqlx::query(
+            "SELECT COUNT(*) as count FROM audit_log WHERE action = 'revoke' AND details LIKE '%Mass revocation of grants issued by mallory%'"
+        )
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap()
+            .get("count");
+        assert_eq!(audit, 2);
+
+        // Nothing left to revoke the second time
+        assert_eq!(db.revoke_by_granter("mallory", "security").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_usage_quota_exhausts_grant() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + Duration::hours(1);
+
+        db.grant_permission_with_limit("testuser", "/test/command", None, None, expires_at, "admin", None, Some(2), false, None, None, None)
+            .await
+            .unwrap();
+
+        let grant = db.update_last_used("testuser", "/test/command").await.unwrap().unwrap();
+        assert_eq!(grant.remaining_uses(), Some(1));
+        assert!(db.check_permission("testuser", "/test/command").await.unwrap());
+
+        let grant = db.update_last_used("testuser", "/test/command").await.unwrap().unwrap();
+        assert!(grant.is_exhausted());
+        assert!(!db.check_permission("testuser", "/test/command").await.unwrap());
+        assert!(db.list_active_permissions().await.unwrap().is_empty());
+
+        let err = db.update_last_used("testuser", "/test/command").await.unwrap_err();
+        assert!(matches!(err, PermissionError::UsageQuotaExceeded { max_uses: 2, .. }));
+
+        // Without a grant there is nothing to record
+        assert!(db.update_last_used("other", "/test/command").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_usage_quota_is_per_host() {
         let (db, _temp) = create_test_db().await;
-        let expires_at = Utc::now() + chrono::Duration::hours(1);
+        let expires_at = Utc::now() + Duration::hours(1);
+        for host in ["a", "b"] {
+            db.grant_permission_with_limit("alice", "/bin/x", Some(host), None, expires_at, "admin", None, Some(1), false, None, None, None)
+                .await
+                .unwrap();
+        }
+
+        let grant = db.record_use("alice", "/bin/x", Some("a"), false).await.unwrap().unwrap();
+        assert_eq!(grant.host.as_deref(), Some("a"));
+        let err = db.record_use("alice", "/bin/x", Some("a"), false).await.unwrap_err();
+        assert!(matches!(err, PermissionError::UsageQuotaExceeded { .. }));
+
+        // The grant on the other host is untouched
+        assert!(db.check_permission_on_host("alice", "/bin/x", Some("b")).await.unwrap());
+        let grant = db.record_use("alice", "/bin/x", Some("b"), false).await.unwrap().unwrap();
+        assert_eq!((grant.host.as_deref(), grant.use_count), (Some("b"), 1));
+        assert!(db.record_use("alice", "/bin/x", Some("c"), false).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_grant_on_every_host() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + Duration::hours(1);
+        for host in ["a", "b"] {
+            db.grant_permission_with_limit("alice", "/bin/x", Some(host), None, expires_at, "admin", None, None, false, None, None, None)
+                .await
+                .unwrap();
+        }
+
+        let revoked = db.revoke_grant("alice", "/bin/x", "admin", None).await.unwrap();
+        let hosts: Vec<_> = revoked.iter().map(|grant| grant.host.as_deref()).collect();
+        assert_eq!(hosts.len(), 2);
+        assert!(hosts.contains(&Some("a")) && hosts.contains(&Some("b")));
+        let audited: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM audit_log WHERE action = 'revoke'")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(audited, 2);
+        assert!(db.revoke_grant("alice", "/bin/x", "admin", None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_record_use_logs_with_the_update() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + Duration::hours(1);
+        db.grant_permission("alice", "/test/command", expires_at, "admin").await.unwrap();
+
+        let grant = db.record_use("alice", "/test/command", None, true).await.unwrap().unwrap();
+        assert_eq!(grant.use_count, 1);
+        db.record_use("alice", "/test/command", None, false).await.unwrap();
+        let uses: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM audit_log WHERE action = 'use'")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(uses, 1);
+        assert!(db.verify_audit_chain().await.unwrap().is_intact());
+    }
+
+    #[tokio::test]
+    async fn test_host_scoped_grants() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + Duration::hours(1);
+
+        db.grant_permission_with_limit("alice", "/test/command", Some("web1"), None, expires_at, "admin", None, None, false, None, None, None)
+            .await
+            .unwrap();
+        db.grant_permission_with_limit("bob", "/test/command", None, None, expires_at, "admin", None, None, false, None, None, None)
+            .await
+            .unwrap();
 
-        let id = db.grant_permission(
+        assert!(db.check_permission_on_host("alice", "/test/command", Some("web1")).await.unwrap());
+        assert!(!db.check_permission_on_host("alice", "/test/command", Some("db1")).await.unwrap());
+        assert!(db.check_permission_on_host("bob", "/test/command", Some("db1")).await.unwrap());
+
+        let on_db1 = db.list_permissions(&ListOptions {
+            host: Some("db1".to_string()),
+            ..ListOptions::default()
+        }).await.unwrap();
+        let users: Vec<_> = on_db1.iter().map(|g| g.username.as_str()).collect();
+        assert_eq!(users, vec!["bob"]);
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_grant_inactive_until_start() {
+        let (db, _temp) = create_test_db().await;
+        let active_from = Utc::now() + Duration::hours(1);
+
+        db.grant_permission_with_limit(
             "testuser",
             "/test/command",
-            expires_at,
-            "admin"
+            None,
+            Some(active_from),
+            active_from + Duration::hours(1),
+            "admin",
+            None,
+            None,
+            false,
+            None,
+            None,
+            None,
         ).await.unwrap();
 
-        assert!(id > 0);
+        assert!(!db.check_permission("testuser", "/test/command").await.unwrap());
+        assert!(db.list_active_permissions().await.unwrap().is_empty());
+
+        let pending = db.list_user_permissions("testuser").await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert!(!pending[0].is_active_now());
+
+        // Only the scheduled hour counts towards the cumulative total
+        let total = db.total_granted_minutes("testuser", "/test/command", Utc::now() - Duration::hours(1))
+            .await.unwrap();
+        assert!((59..=60).contains(&total), "unexpected total {}", total);
+    }
+
+    #[tokio::test]
+    async fn test_list_permissions_options() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + Duration::hours(1);
+
+        db.grant_permission("alice", "/test/active", expires_at, "admin").await.unwrap();
+        db.grant_permission("alice", "/test/revoked", expires_at, "admin").await.unwrap();
+        db.revoke_permission("alice", "/test/revoked", "admin").await.unwrap();
+        db.grant_permission("bob", "/test/expired", Utc::now() - Duration::minutes(1), "admin").await.unwrap();
+
+        let active = db.list_permissions(&ListOptions::default()).await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].command, "/test/active");
+
+        let all = db.list_permissions(&ListOptions::all()).await.unwrap();
+        assert_eq!(all.len(), 3);
+
+        let alice = db.list_permissions(&ListOptions {
+            username: Some("alice".to_string()),
+            include_revoked: true,
+            ..ListOptions::default()
+        }).await.unwrap();
+        assert_eq!(alice.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_list_permissions_pagination() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + Duration::hours(1);
+
+        for i in 0..5 {
+            db.grant_permission(&format!("user{}", i), "/test/command", expires_at, "admin").await.unwrap();
+        }
+
+        let options = ListOptions {
+            limit: Some(2),
+            offset: 2,
+            ..ListOptions::default()
+        };
+        let page = db.list_permissions(&options).await.unwrap();
+        let users: Vec<_> = page.iter().map(|g| g.username.as_str()).collect();
+        assert_eq!(users, vec!["user2", "user3"]);
+        assert_eq!(db.count_permissions(&options).await.unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_regrant_replaces_or_extends() {
+        let (db, _temp) = create_test_db().await;
+        let now = Utc::now();
+        let grant = |expires_at, extends| {
+            db.grant_permission_with_limit("alice", "/test/command", None, None, expires_at, "other", None, None, false, None, None, extends)
+        };
+
+        // A new grant replaces the held one unless told to extend it
+        let first = db.grant_permission("alice", "/test/command", now + Duration::hours(2), "admin").await.unwrap();
+        let (second, replaced) = grant(now + Duration::hours(1), None).await.unwrap();
+        assert!(replaced);
+        assert_ne!(first.id, second.id);
+        assert_eq!(second.expires_at, now + Duration::hours(1));
+        let held = db.held_grant("alice", "/test/command", None).await.unwrap();
+        assert_eq!(held.map(|grant| grant.id), Some(second.id));
+        assert!(db.held_grant("alice", "/test/command", Some("web1")).await.unwrap().is_none());
+
+        // Extending keeps the row and its start, taking the later expiry
+        let (shorter, _) = grant(now + Duration::minutes(30), Some(second.id)).await.unwrap();
+        assert_eq!((shorter.id, shorter.granted_at, shorter.expires_at), (second.id, second.granted_at, second.expires_at));
+        let granted_minutes = || db.total_granted_minutes("alice", "/test/command", now - Duration::hours(1));
+        let before = granted_minutes().await.unwrap();
+        let (longer, _) = grant(now + Duration::hours(3), Some(second.id)).await.unwrap();
+        assert_eq!((longer.id, longer.granted_at), (second.id, second.granted_at));
+        assert_eq!(longer.expires_at, now + Duration::hours(3));
+        assert_eq!(longer.correlation_id, second.correlation_id);
+        // Only the two hours past the held grant's expiry count as granted
+        assert_eq!(granted_minutes().await.unwrap() - before, 120);
+
+        // A grant that is no longer held cannot be extended, so it is granted afresh
+        db.revoke_permission("alice", "/test/command", "admin").await.unwrap();
+        let (fresh, replaced) = grant(now + Duration::hours(1), Some(second.id)).await.unwrap();
+        assert!(!replaced);
+        assert_ne!(fresh.id, second.id);
+    }
+
+    #[tokio::test]
+    async fn test_list_permissions_sorting() {
+        let (db, _temp) = create_test_db().await;
+        let now = Utc::now();
+
+        // Each grant ranks differently per field so every sort is distinguishable
+        let rows = [
+            ("carol", "/test/a", now - Duration::hours(3), now + Duration::hours(2), Some(now - Duration::minutes(5))),
+            ("alice", "/test/c", now - Duration::hours(1), now + Duration::hours(3), None),
+            ("bob", "/test/b", now - Duration::hours(2), now + Duration::hours(1), Some(now - Duration::minutes(1))),
+        ];
+        for (username, command, granted_at, expires_at, last_used) in rows {
+            db.grant_permission(username, command, expires_at, "admin").await.unwrap();
+            sqlx::query("UPDATE permission_grants SET granted_at = ?, last_used = ? WHERE username = ?")
+                .bind(granted_at)
+                .bind(last_used)
+                .bind(username)
+                .execute(&db.pool)
+                .await
+                .unwrap();
+        }
+
+        let order = |sort, descending| {
+            let db = &db;
+            async move {
+                let options = ListOptions { sort: Some(sort), descending, ..ListOptions::default() };
+                db.list_permissions(&options)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|g| g.username)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(order(SortField::User, false).await, ["alice", "bob", "carol"]);
+        assert_eq!(order(SortField::User, true).await, ["carol", "bob", "alice"]);
+        assert_eq!(order(SortField::Command, false).await, ["carol", "bob", "alice"]);
+        assert_eq!(order(SortField::Command, true).await, ["alice", "bob", "carol"]);
+        assert_eq!(order(SortField::Granted, false).await, ["carol", "bob", "alice"]);
+        assert_eq!(order(SortField::Granted, true).await, ["alice", "bob", "carol"]);
+        assert_eq!(order(SortField::Expires, false).await, ["bob", "carol", "alice"]);
+        assert_eq!(order(SortField::Expires, true).await, ["alice", "carol", "bob"]);
+        // SQLite orders NULLs first, so never-used grants lead the ascending list
+        assert_eq!(order(SortField::LastUsed, false).await, ["alice", "carol", "bob"]);
+        assert_eq!(order(SortField::LastUsed, true).await, ["bob", "carol", "alice"]);
+
+        let unsorted = db.list_permissions(&ListOptions::default()).await.unwrap();
+        let users: Vec<_> = unsorted.iter().map(|g| g.username.as_str()).collect();
+        assert_eq!(users, ["alice", "bob", "carol"]);
+    }
+
+    #[tokio::test]
+    async fn test_revoke_by_id_leaves_replacement_grants() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + Duration::minutes(30);
+
+        let first = db.grant_permission("testuser", "/test/command", expires_at, "admin").await.unwrap();
+        let second = db.grant_permission("testuser", "/test/command", expires_at, "admin").await.unwrap();
+        assert_ne!(first.id, second.id);
+
+        assert!(db.revoke_by_id(first.id, "admin", None).await.unwrap().is_none());
         assert!(db.check_permission("testuser", "/test/command").await.unwrap());
+
+        let revoked = db.revoke_by_id(second.id, "admin", Some("done")).await.unwrap().unwrap();
+        assert_eq!(revoked.revoked_by.as_deref(), Some("admin"));
+        assert!(!db.check_permission("testuser", "/test/command").await.unwrap());
+        assert!(db.revoke_by_id(second.id, "admin", None).await.unwrap().is_none());
     }
 
     #[tokio::test]
-    async fn test_revoke_permission() {
+    async fn test_approve_grant_request_once() {
+        let (db, _temp) = create_test_db().await;
+
+        let request = db.create_grant_request("testuser", "/test/command", None, None, Duration::minutes(30), "testuser", false, Some("INC-42"), None)
+            .await
+            .unwrap();
+        assert_eq!(request.status, RequestStatus::Pending);
+        assert!(!db.check_permission("testuser", "/test/command").await.unwrap());
+        assert_eq!(db.list_pending_requests().await.unwrap().len(), 1);
+
+        let expires_at = Utc::now() + request.duration();
+        let grant = db.approve_grant_request(request.id, "admin", 1, None, expires_at, None, None, None).await.unwrap().granted().unwrap();
+        assert_eq!(grant.granted_by, "admin");
+        assert!(db.check_permission("testuser", "/test/command").await.unwrap());
+
+        let stored = db.get_grant_request(request.id).await.unwrap().unwrap();
+        assert_eq!(stored.status, RequestStatus::Approved);
+        assert_eq!(stored.decided_by.as_deref(), Some("admin"));
+        assert_eq!(stored.grant_id, Some(grant.id));
+        assert_eq!(stored.reason.as_deref(), Some("INC-42"));
+        assert!(db.list_pending_requests().await.unwrap().is_empty());
+
+        // The request's reason goes into the grant's audit entry
+        let granted = db.audit_entries(&AuditQuery { action: Some("grant".to_string()), ..Default::default() }).await.unwrap();
+        assert!(granted[0].details.as_deref().unwrap().contains("INC-42"));
+
+        let err = db.approve_grant_request(request.id, "admin", 1, None, expires_at, None, None, None).await.unwrap_err();
+        assert!(matches!(err, PermissionError::RequestNotPending { .. }));
+        let err = db.approve_grant_request(999, "admin", 1, None, expires_at, None, None, None).await.unwrap_err();
+        assert!(matches!(err, PermissionError::RequestNotFound(999)));
+
+        // Approvals are deleted with their request
+        sqlx::query("DELETE FROM grant_requests WHERE id = ?").bind(request.id).execute(db.get_pool()).await.unwrap();
+        let approvals: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM approvals").fetch_one(db.get_pool()).await.unwrap();
+        assert_eq!(approvals, 0);
+    }
+
+    #[tokio::test]
+    async fn test_bot_filed_request_is_not_approved_by_its_requester() {
+        let (db, _temp) = create_test_db().await;
+
+        let request = db.create_grant_request("testuser", "/test/command", None, None, Duration::minutes(30), "deploy-bot", false, None, Some("alice"))
+            .await
+            .unwrap();
+        let expires_at = Utc::now() + request.duration();
+        for approver in ["deploy-bot", "testuser", "alice"] {
+            let err = db.approve_grant_request(request.id, approver, 2, None, expires_at, None, None, None).await.unwrap_err();
+            assert!(matches!(err, PermissionError::SelfApproval { .. }), "{}", approver);
+        }
+
+        // Refused approvals do not count towards the quorum
+        let outcome = db.approve_grant_request(request.id, "admin", 2, None, expires_at, None, None, None).await.unwrap();
+        assert!(matches!(outcome, ApprovalOutcome::Recorded { request, .. } if request.approvals == 1));
+    }
+
+    #[tokio::test]
+    async fn test_next_transition() {
+        let (db, _temp) = create_test_db().await;
+        assert!(db.next_transition().await.unwrap().is_none());
+        assert!(db.next_expiry().await.unwrap().is_none());
+
+        let later = Utc::now() + Duration::hours(2);
+        let sooner = Utc::now() + Duration::hours(1);
+        let start = Utc::now() + Duration::minutes(30);
+        db.grant_permission("later", "/test/command", later, "admin").await.unwrap();
+        db.grant_permission("sooner", "/test/command", sooner, "admin").await.unwrap();
+        assert_eq!(db.next_transition().await.unwrap(), Some(sooner));
+
+        // A scheduled grant starting earlier changes the sudoers file first
+        db.grant_permission_with_limit("scheduled", "/test/command", None, Some(start), later, "admin", None, None, false, None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(db.next_transition().await.unwrap(), Some(start));
+        assert_eq!(db.next_expiry().await.unwrap().unwrap().username, "sooner");
+    }
+
+    #[tokio::test]
+    async fn test_audit_chain_detects_tampering() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + Duration::hours(1);
+        for user in ["alice", "bob", "carol"] {
+            db.grant_permission(user, "/test/command", expires_at, "admin").await.unwrap();
+        }
+        db.revoke_permission("bob", "/test/command", "admin").await.unwrap();
+
+        let report = db.verify_audit_chain().await.unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.verified, 4);
+
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM audit_log ORDER BY id")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE audit_log SET details = 'Granted by nobody' WHERE id = ?")
+            .bind(ids[1])
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(db.verify_audit_chain().await.unwrap().broken_at, Some(ids[1]));
+    }
+
+    #[tokio::test]
+    async fn test_audit_chain_detects_removed_entry() {
+        let (db, _temp) = create_test_db().await;
+        let expires_at = Utc::now() + Duration::hours(1);
+        for user in ["alice", "bob", "carol"] {
+            db.grant_permission(user, "/test/command", expires_at, "admin").await.unwrap();
+        }
+
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM audit_log ORDER BY id")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM audit_log WHERE id = ?")
+            .bind(ids[1])
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let report = db.verify_audit_chain().await.unwrap();
+        assert_eq!(report.verified, 1);
+        assert_eq!(report.broken_at, Some(ids[2]));
+    }
+
+    #[tokio::test]
+    async fn test_async_audit_writes_every_entry() {
+        let (db, _temp) = create_test_db().await;
+        let db = db.with_async_audit();
+        let expires_at = Utc::now() + Duration::hours(1);
+        db.grant_permission("alice", "/test/command", expires_at, "admin").await.unwrap();
+
+        for _ in 0..250 {
+            db.record_use("alice", "/test/command", None, true).await.unwrap();
+        }
+        // Queued entries land without an explicit flush
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM audit_log WHERE action = 'use'")
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+            if count == 250 {
+                break;
+            }
+            assert!(std::time::Instant::now() < deadline, "only {} of 250 entries written", count);
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+
+        // Buffered entries interleave with ones written inline
+        db.revoke_permission("alice", "/test/command", "admin").await.unwrap();
+        db.grant_permission("bob", "/test/command", expires_at, "admin").await.unwrap();
+        db.revoke_permission("bob", "/test/command", "admin").await.unwrap();
+        db.flush_audit().await;
+
+        let report = db.verify_audit_chain().await.unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.verified, 254);
+    }
+
+    #[tokio::test]
+    async fn test_count_expired_matches_cleanup() {
+        let (db, _temp) = create_test_db().await;
+
+        db.grant_permission("expired", "/test/command", Utc::now() - Duration::minutes(1), "admin").await.unwrap();
+        db.grant_permission("active", "/test/command", Utc::now() + Duration::hours(1), "admin").await.unwrap();
+
+        assert_eq!(db.count_expired().await.unwrap(), 1);
+        assert_eq!(db.cleanup_expired().await.unwrap(), 1);
+        assert_eq!(db.count_expired().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_observed_time_never_moves_backwards() {
+        let (db, _temp) = create_test_db().await;
+        assert!(db.last_observed_time().await.unwrap().is_none());
+
+        db.cleanup_expired().await.unwrap();
+        let first = db.last_observed_time().await.unwrap().unwrap();
+
+        let future = Utc::now() + Duration::hours(2);
+        Database::record_observed_time(db.get_pool(), future).await.unwrap();
+        db.cleanup_expired().await.unwrap();
+
+        let last = db.last_observed_time().await.unwrap().unwrap();
+        assert!(last > first);
+        assert_eq!(last, future);
+    }
+
+    #[tokio::test]
+    async fn test_migrates_unversioned_database() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+
+        // Schema as created by releases before migrations were tracked
+        let pool = SqlitePool::connect(&format!("sqlite:{}?mode=rwc", db_path.display())).await.unwrap();
+        sqlx::raw_sql(
+            r#"
+            CREATE TABLE permission_grants (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                username TEXT NOT NULL,
+                command TEXT NOT NULL,
+                granted_at DATETIME NOT NULL,
+                expires_at DATETIME NOT NULL,
+                granted_by TEXT NOT NULL,
+                last_used DATETIME,
+                revoked BOOLEAN NOT NULL DEFAULT FALSE,
+                revoked_at DATETIME,
+                revoked_by TEXT,
+                UNIQUE(username, command) ON CONFLICT REPLACE
+            );
+            CREATE TABLE audit_log (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp DATETIME NOT NULL,
+                username TEXT NOT NULL,
+                command TEXT NOT NULL,
+                action TEXT NOT NULL,
+                details TEXT
+            );
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO permission_grants (username, command, granted_at, expires_at, granted_by) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind("alice")
+        .bind("/usr/bin/docker")
+        .bind(Utc::now())
+        .bind(Utc::now() + chrono::Duration::hours(1))
+        .bind("root")
+        .execute(&pool)
+        .await
+        .unwrap();
+        pool.close().await;
+
+        let db = Database::new(&db_path).await.unwrap();
+        let columns = db.table_columns("permission_grants").await.unwrap();
+        for column in ["active_from", "revoke_reason", "host", "use_count", "max_uses"] {
+            assert!(columns.iter().any(|name| name == column), "missing column {}", column);
+        }
+        assert!(db.health_check().await.unwrap().is_healthy());
+
+        let row = sqlx::query("SELECT use_count, max_uses, host FROM permission_grants WHERE username = 'alice'")
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap();
+        assert_eq!(row.get::<i64, _>("use_count"), 0);
+        assert_eq!(row.get::<Option<i64>, _>("max_uses"), None);
+        assert_eq!(row.get::<Option<String>, _>("host"), None);
+        assert!(db.check_permission("alice", "/usr/bin/docker").await.unwrap());
+        drop(db);
+
+        // Reopening finds the migrations already applied
+        let db = Database::new(&db_path).await.unwrap();
+        assert!(db.check_permission("alice", "/usr/bin/docker").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_check_permissions_batched() {
         let (db, _temp) = create_test_db().await;
         let expires_at = Utc::now() + chrono::Duration::hours(1);
 
-        db.grant_permission(
-            "testuser",
-            "/test/command",
-            expires_at,
-            "admin"
-        ).await.unwrap();
+        db.grant_permission("testuser", "/usr/bin/docker", expires_at, "admin").await.unwrap();
+        db.grant_permission("testuser", "/usr/bin/systemctl", expires_at, "admin").await.unwrap();
+        db.revoke_permission("testuser", "/usr/bin/systemctl", "admin").await.unwrap();
 
-        assert!(db.revoke_permission(
-            "testuser",
-            "/test/command",
-            "admin"
-        ).await.unwrap());
+        let commands = vec![
+            "/usr/bin/docker".to_string(),
+            "/usr/bin/systemctl".to_string(),
+            "/usr/bin/x') OR 1=1 --".to_string(),
+        ];
+        let allowed = db.check_permissions("testuser", &commands).await.unwrap();
+        assert_eq!(allowed.len(), 3);
+        assert!(allowed["/usr/bin/docker"]);
+        assert!(!allowed["/usr/bin/systemctl"]);
+        assert!(!allowed["/usr/bin/x') OR 1=1 --"]);
 
-        assert!(!db.check_permission("testuser", "/test/command").await.unwrap());
+        assert!(db.check_permissions("testuser", &[]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_list_active_during() {
+        let (db, _temp) = create_test_db().await;
+        let now = Utc::now();
+        let hours = chrono::Duration::hours;
+        let minutes = chrono::Duration::minutes;
+
+        db.grant_permission("alice", "/usr/bin/docker", now + hours(2), "admin").await.unwrap();
+        db.grant_permission("bob", "/usr/bin/docker", now + hours(2), "admin").await.unwrap();
+        db.revoke_permission("bob", "/usr/bin/docker", "admin").await.unwrap();
+
+        // Move both grants into the past: alice's ran 5h-3h ago, bob's
+        // started 5h ago and was revoked 4h ago
+        sqlx::query("UPDATE permission_grants SET granted_at = ?, expires_at = ?")
+            .bind(now - hours(5))
+            .bind(now - hours(3))
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+        sqlx::query("UPDATE permission_grants SET revoked_at = ? WHERE username = 'bob'")
+            .bind(now - hours(4))
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+
+        let users = |grants: Vec<PermissionGrant>| grants.into_iter().map(|g| g.username).collect::<Vec<_>>();
+        assert_eq!(users(db.list_active_during(now - hours(6), now - hours(4) - minutes(30)).await.unwrap()), vec!["alice", "bob"]);
+        assert_eq!(users(db.list_active_during(now - hours(4) + minutes(30), now - hours(3) - minutes(30)).await.unwrap()), vec!["alice"]);
+        assert!(db.list_active_during(now - hours(3), now).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let (db, _temp) = create_test_db().await;
+
+        let health = db.health_check().await.unwrap();
+        assert!(health.is_healthy());
+
+        sqlx::query("DROP TABLE audit_log")
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+
+        let health = db.health_check().await.unwrap();
+        assert!(health.connected);
+        assert_eq!(health.missing_tables, vec!["audit_log".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_database_is_detected() {
+        let (db, temp) = create_test_db().await;
+        db.integrity_check().await.unwrap();
+        db.get_pool().close().await;
+
+        // Clobber the file header so SQLite no longer recognises the file
+        let db_path = temp.path().join("test.db");
+        let mut bytes = fs::read(&db_path).unwrap();
+        bytes[..16].fill(0xff);
+        fs::write(&db_path, bytes).unwrap();
+
+        let err = Database::new(&db_path).await.err().unwrap();
+        assert!(matches!(err, PermissionError::DatabaseCorrupt(_)), "unexpected error: {}", err);
+    }
+
+    #[tokio::test]
+    async fn test_integrity_check_reports_damaged_index() {
+        let (db, temp) = create_test_db().await;
+        db.grant_permission("alice", "/usr/bin/docker", Utc::now() + chrono::Duration::hours(1), "admin")
+            .await
+            .unwrap();
+
+        // Point an index at the wrong b-tree, as a torn write might
+        let mut conn = db.get_pool().acquire().await.unwrap();
+        sqlx::query("PRAGMA writable_schema = ON").execute(&mut *conn).await.unwrap();
+        sqlx::query(
+            "UPDATE sqlite_master SET rootpage = (SELECT rootpage FROM sqlite_master WHERE name = 'audit_log') \
+             WHERE name = 'idx_permissions_user'",
+        )
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+        drop(conn);
+        db.get_pool().close().await;
+
+        let err = Database::new(temp.path().join("test.db")).await.err().unwrap();
+        assert!(matches!(err, PermissionError::DatabaseCorrupt(_)), "unexpected error: {}", err);
+    }
+
+    #[tokio::test]
+    async fn test_recover_copies_rows_into_fresh_database() {
+        let (db, temp) = create_test_db().await;
+        let expires_at = Utc::now() + chrono::Duration::hours(1);
+        db.grant_permission("alice", "/usr/bin/docker", expires_at, "admin").await.unwrap();
+        db.grant_permission("bob", "/usr/bin/docker", expires_at, "admin").await.unwrap();
+        db.get_pool().close().await;
+
+        let db_path = temp.path().join("test.db");
+        let report = Database::recover(&db_path).await.unwrap();
+        assert!(report.backup_path.exists());
+        let grants = report.tables.iter().find(|t| t.name == "permission_grants").unwrap();
+        assert_eq!((grants.copied, grants.lost), (2, 0));
+
+        let db = Database::new(&db_path).await.unwrap();
+        assert!(db.check_permission("alice", "/usr/bin/docker").await.unwrap());
+        assert!(db.check_permission("bob", "/usr/bin/docker").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_grants_are_detected_and_revoked() {
+        let (db, _temp) = create_test_db().await;
+        let now = Utc::now();
+        db.grant_permission("alice", "/usr/bin/docker", now + Duration::hours(1), "admin").await.unwrap();
+        db.grant_permission("bob", "/usr/bin/docker", now + Duration::hours(1), "admin").await.unwrap();
+        assert!(db.duplicate_grants().await.unwrap().is_empty());
+
+        // Simulate an out-of-band edit: rebuild the table without its
+        // uniqueness constraint and copy alice's grant with a later expiry
+        for statement in [
+            "CREATE TABLE grants_copy AS SELECT * FROM permission_grants",
+            "DROP TABLE permission_grants",
+            "ALTER TABLE grants_copy RENAME TO permission_grants",
+            "INSERT INTO permission_grants SELECT * FROM permission_grants WHERE username = 'alice'",
+        ] {
+            sqlx::query(statement).execute(&db.pool).await.unwrap();
+        }
+        sqlx::query("UPDATE permission_grants SET id = id + 100, expires_at = ? WHERE rowid = (SELECT MAX(rowid) FROM permission_grants)")
+            .bind(now + Duration::hours(2))
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            db.duplicate_grants().await.unwrap(),
+            vec![("alice".to_string(), "/usr/bin/docker".to_string(), 2)]
+        );
+        assert_eq!(db.count_active_grants_for_command("/usr/bin/docker").await.unwrap(), 2);
+
+        let revoked = db.revoke_duplicates("admin").await.unwrap();
+        assert_eq!(revoked.len(), 1);
+        assert_eq!(revoked[0].expires_at.timestamp(), (now + Duration::hours(1)).timestamp());
+        assert!(db.duplicate_grants().await.unwrap().is_empty());
+
+        let remaining = db.list_user_permissions("alice").await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].expires_at.timestamp(), (now + Duration::hours(2)).timestamp());
+        assert!(db.check_permission("bob", "/usr/bin/docker").await.unwrap());
     }
-}
\ No newline at end of file
+}
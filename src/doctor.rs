@@ -0,0 +1,661 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::io;
+use std::path::{Path, PathBuf};
+use nix::unistd::{chown, Gid, Group, Uid};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::db::{Database, RecoveryReport};
+use crate::error::{PermissionError, Result};
+use crate::manager::PermissionManager;
+
+/// Main sudoers file, where sudo starts reading its policy
+pub const SUDOERS_MAIN: &str = "/etc/sudoers";
+
+/// How deeply `#include` directives are followed, matching sudo's own limit
+const MAX_INCLUDE_DEPTH: usize = 128;
+
+/// Whether sudo reads a sudoers drop-in file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SudoersInclusion {
+    /// An `include` or `includedir` directive covers the file
+    Included,
+    /// The file's directory is included, but sudo skips files whose names
+    /// contain a `.` or end in `~`
+    SkippedName,
+    /// No directive reaches the file
+    NotIncluded,
+    /// The main sudoers file could not be read, usually for lack of root
+    Unknown(String),
+}
+
+/// Work out whether sudo, starting from `main`, reads `drop_in`
+pub fn sudoers_inclusion(main: &Path, drop_in: &Path) -> SudoersInclusion {
+    match find_inclusion(main, drop_in, 0) {
+        Ok(inclusion) => inclusion,
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            SudoersInclusion::Unknown(format!("could not read {:?} (needs root)", main))
+        }
+        Err(e) => SudoersInclusion::Unknown(format!("could not read {:?}: {}", main, e)),
+    }
+}
+
+fn find_inclusion(file: &Path, drop_in: &Path, depth: usize) -> io::Result<SudoersInclusion> {
+    let content = fs::read_to_string(file)?;
+    let base = file.parent().unwrap_or(Path::new("/"));
+    let mut found = SudoersInclusion::NotIncluded;
+
+    for line in content.lines() {
+        let Some((directive, target)) = include_directive(line) else {
+            continue;
+        };
+        // Relative paths are resolved against the including file's directory
+        let target = base.join(target);
+
+        if directive == "includedir" {
+            if drop_in.parent() == Some(target.as_path()) {
+                let name = drop_in.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+                if name.contains('.') || name.ends_with('~') {
+                    found = SudoersInclusion::SkippedName;
+                } else {
+                    return Ok(SudoersInclusion::Included);
+                }
+            }
+        } else if target == drop_in {
+            return Ok(SudoersInclusion::Included);
+        } else if depth < MAX_INCLUDE_DEPTH {
+            // A nested file that cannot be read is skipped rather than
+            // failing the whole check, as long as the main file was readable
+            if let Ok(SudoersInclusion::Included) = find_inclusion(&target, drop_in, depth + 1) {
+                return Ok(SudoersInclusion::Included);
+            }
+        }
+    }
+
+    Ok(found)
+}
+
+/// Parse a `#include`, `@include`, `#includedir` or `@includedir` line into
+/// the directive name and its path
+fn include_directive(line: &str) -> Option<(&'static str, PathBuf)> {
+    let line = line.trim_start();
+    let rest = line.strip_prefix('#').or_else(|| line.strip_prefix('@'))?;
+    let (directive, path) = if let Some(path) = rest.strip_prefix("includedir") {
+        ("includedir", path)
+    } else if let Some(path) = rest.strip_prefix("include") {
+        ("include", path)
+    } else {
+        return None;
+    };
+
+    // The directive must be followed by whitespace, otherwise it is a comment
+    if !path.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let path = path.trim();
+    let path = path.strip_prefix('"').and_then(|p| p.strip_suffix('"')).unwrap_or(path);
+    (!path.is_empty()).then(|| (directive, PathBuf::from(path)))
+}
+
+/// A file or directory permctl manages whose owner or mode differs from the
+/// ones it is given when written
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileDrift {
+    pub path: PathBuf,
+    /// Each difference, e.g. "mode 0644, expected 0440"
+    pub problems: Vec<String>,
+    #[serde(skip)]
+    expected: ExpectedMode,
+}
+
+/// Owner and mode of a managed path; the group is only checked for files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ExpectedMode {
+    mode: u32,
+    uid: u32,
+    gid: Option<u32>,
+}
+
+impl FileDrift {
+    /// Restore the expected owner and mode
+    pub fn fix(&self) -> Result<()> {
+        chown(&self.path, Some(Uid::from_raw(self.expected.uid)), self.expected.gid.map(Gid::from_raw))
+            .map_err(|e| PermissionError::io_error(io::Error::from(e), self.path.clone()))?;
+        fs::set_permissions(&self.path, fs::Permissions::from_mode(self.expected.mode))
+            .map_err(|e| PermissionError::io_error(e, self.path.clone()))
+    }
+}
+
+/// Managed paths that have drifted from the owner and mode permctl gives
+/// them: the sudoers file root:root 0440, the database root:root 0644 and
+/// its directories root-owned 0755. Paths that do not exist are skipped.
+pub fn file_drift(config: &Config) -> Vec<FileDrift> {
+    let mut expected = Vec::new();
+    if config.sudoers_enabled {
+        expected.push((config.sudoers_path.as_path(), ExpectedMode { mode: 0o440, uid: 0, gid: Some(0) }));
+    }
+    expected.push((config.db_path.as_path(), ExpectedMode { mode: 0o644, uid: 0, gid: Some(0) }));
+    let mut dirs = config.required_directories();
+    dirs.sort_unstable();
+    dirs.dedup();
+    expected.extend(dirs.into_iter().map(|dir| (dir, ExpectedMode { mode: 0o755, uid: 0, gid: None })));
+
+    expected.into_iter().filter_map(|(path, expected)| path_drift(path, expected)).collect()
+}
+
+fn path_drift(path: &Path, expected: ExpectedMode) -> Option<FileDrift> {
+    let meta = fs::metadata(path).ok()?;
+    let mut problems = Vec::new();
+
+    let mode = meta.mode() & 0o7777;
+    if mode != expected.mode {
+        problems.push(format!("mode {:04o}, expected {:04o}", mode, expected.mode));
+    }
+    if meta.uid() != expected.uid {
+        problems.push(format!("owner uid {}, expected {}", meta.uid(), expected.uid));
+    }
+    if let Some(gid) = expected.gid.filter(|gid| *gid != meta.gid()) {
+        problems.push(format!("group gid {}, expected {}", meta.gid(), gid));
+    }
+
+    if problems.is_empty() {
+        return None;
+    }
+    Some(FileDrift { path: path.to_path_buf(), problems, expected })
+}
+
+/// Correct the owner and mode of every drifted path of the configuration
+/// at `config_path`, returning what was fixed
+pub fn fix_file_modes(config_path: &Path) -> Result<Vec<FileDrift>> {
+    let mut config = Config::parse_from(config_path)?;
+    config.apply_env_overrides()?;
+
+    let drift = file_drift(&config);
+    for path in &drift {
+        path.fix()?;
+    }
+    Ok(drift)
+}
+
+/// Outcome of a single check, ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    /// Marker printed in front of the check, as used by `verify`
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::Pass => "✓",
+            Self::Warn => "!",
+            Self::Fail => "✗",
+        }
+    }
+
+    /// Process exit code for a run whose worst result is this status
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Pass => 0,
+            Self::Warn => 1,
+            Self::Fail => 2,
+        }
+    }
+
+    /// Name used in reports, matching the JSON form
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Warn => "warn",
+            Self::Fail => "fail",
+        }
+    }
+}
+
+/// Result of one health check
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self { name, status, detail: detail.into() }
+    }
+}
+
+/// Worst status among the results, `Pass` if there are none
+pub fn overall_status(results: &[CheckResult]) -> CheckStatus {
+    results.iter().map(|r| r.status).max().unwrap_or(CheckStatus::Pass)
+}
+
+/// Run every health check against the configuration at `config_path`.
+///
+/// Unlike `verify`, a failing check never stops the run; checks that depend
+/// on something that failed earlier report why they could not run.
+pub async fn run_checks(config_path: &Path) -> Vec<CheckResult> {
+    let mut config = match Config::parse_from(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![CheckResult::new("config", CheckStatus::Fail, format!("Failed to load {:?}: {}", config_path, e))];
+        }
+    };
+
+    let config_result = match config.apply_env_overrides() {
+        Ok(()) => check_config(&config),
+        Err(e) => CheckResult::new("config", CheckStatus::Fail, e.to_string()),
+    };
+    let mut results = vec![
+        config_result,
+        check_commands(&config),
+        check_groups(&config),
+        check_directories(&config),
+        check_file_modes(&config),
+        check_privileges(),
+    ];
+
+    match PermissionManager::connect(config).await {
+        Ok(manager) => {
+            results.push(check_integrity(&manager).await);
+            results.push(check_database(&manager).await);
+            results.push(check_audit_chain(&manager).await);
+            results.push(check_clock(&manager).await);
+            results.push(check_sudoers(&manager).await);
+            results.push(check_sudoers_included(manager.config()));
+            results.push(check_orphans(&manager).await);
+            results.push(check_duplicates(&manager).await);
+            results.push(check_maintenance(&manager).await);
+        }
+        Err(e @ PermissionError::DatabaseCorrupt(_)) => {
+            results.push(CheckResult::new(
+                "integrity",
+                CheckStatus::Fail,
+                format!("{}; run permctl doctor --recover to rebuild it", e),
+            ));
+        }
+        Err(e) => {
+            results.push(CheckResult::new("database", CheckStatus::Fail, format!("Could not open database: {}", e)));
+        }
+    }
+
+    results
+}
+
+/// Rebuild the database configured at `config_path` if it fails its
+/// integrity check, returning what was salvaged, or `None` if it was intact
+pub async fn recover_database(config_path: &Path) -> Result<Option<RecoveryReport>> {
+    let mut config = Config::parse_from(config_path)?;
+    config.apply_env_overrides()?;
+
+    let checked = match Database::new(&config.db_path).await {
+        Ok(db) => {
+            let checked = db.integrity_check().await;
+            db.get_pool().close().await;
+            checked
+        }
+        Err(e) => Err(e),
+    };
+    match checked {
+        Ok(()) => Ok(None),
+        Err(PermissionError::DatabaseCorrupt(_)) => Database::recover(&config.db_path).await.map(Some),
+        Err(e) => Err(e),
+    }
+}
+
+async fn check_integrity(manager: &PermissionManager) -> CheckResult {
+    match manager.integrity_check().await {
+        Ok(()) => CheckResult::new("integrity", CheckStatus::Pass, "Database integrity check passed"),
+        Err(e @ PermissionError::DatabaseCorrupt(_)) => CheckResult::new(
+            "integrity",
+            CheckStatus::Fail,
+            format!("{}; run permctl doctor --recover to rebuild it", e),
+        ),
+        Err(e) => CheckResult::new("integrity", CheckStatus::Fail, format!("Integrity check failed: {}", e)),
+    }
+}
+
+fn check_config(config: &Config) -> CheckResult {
+    let errors = config.validation_errors();
+    if !errors.is_empty() {
+        let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        return CheckResult::new("config", CheckStatus::Fail, messages.join("; "));
+    }
+
+    let warnings = config.validation_warnings();
+    if !warnings.is_empty() {
+        return CheckResult::new("config", CheckStatus::Warn, warnings.join("; "));
+    }
+
+    CheckResult::new("config", CheckStatus::Pass, "Configuration is valid")
+}
+
+/// Allowed commands that are missing or not executable can be granted but
+/// never run
+fn check_commands(config: &Config) -> CheckResult {
+    let mut missing: Vec<&str> = config
+        .allowed_commands
+        .keys()
+        .filter(|cmd| {
+            !fs::metadata(cmd)
+                .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        })
+        .map(String::as_str)
+        .collect();
+    missing.sort_unstable();
+
+    if missing.is_empty() {
+        CheckResult::new("commands", CheckStatus::Pass, format!("All {} allowed commands are executable", config.allowed_commands.len()))
+    } else {
+        CheckResult::new("commands", CheckStatus::Warn, format!("Missing or not executable: {}", missing.join(", ")))
+    }
+}
+
+/// Required groups that do not exist make a command impossible to grant
+fn check_groups(config: &Config) -> CheckResult {
+    let groups: BTreeSet<&str> = config
+        .allowed_commands
+        .values()
+        .flat_map(|cmd| cmd.required_groups.iter().map(String::as_str))
+        .collect();
+    let missing: Vec<&str> = groups
+        .into_iter()
+        .filter(|group| !matches!(Group::from_name(group), Ok(Some(_))))
+        .collect();
+
+    if missing.is_empty() {
+        CheckResult::new("groups", CheckStatus::Pass, "All required groups exist")
+    } else {
+        CheckResult::new("groups", CheckStatus::Warn, format!("Required groups not found: {}", missing.join(", ")))
+    }
+}
+
+fn check_directories(config: &Config) -> CheckResult {
+    let missing: Vec<String> = config
+        .required_directories()
+        .into_iter()
+        .filter(|path| !path.is_dir())
+        .map(|path| path.display().to_string())
+        .collect();
+
+    if missing.is_empty() {
+        CheckResult::new("directories", CheckStatus::Pass, "All directories present")
+    } else {
+        CheckResult::new("directories", CheckStatus::Fail, format!("Required directories not found: {}", missing.join(", ")))
+    }
+}
+
+/// sudo ignores a sudoers file others can write to, and one others can read
+/// exposes the grants
+fn check_file_modes(config: &Config) -> CheckResult {
+    let drift = file_drift(config);
+    if drift.is_empty() {
+        return CheckResult::new("file_modes", CheckStatus::Pass, "Managed files have the expected owner and mode");
+    }
+
+    let status = if drift.iter().any(|d| d.path == config.sudoers_path) {
+        CheckStatus::Fail
+    } else {
+        CheckStatus::Warn
+    };
+    let details: Vec<String> = drift
+        .iter()
+        .map(|d| format!("{:?}: {}", d.path, d.problems.join(", ")))
+        .collect();
+    CheckResult::new("file_modes", status, format!("{}; run permctl doctor --fix to correct", details.join("; ")))
+}
+
+fn check_privileges() -> CheckResult {
+    if Uid::effective().is_root() {
+        CheckResult::new("privileges", CheckStatus::Pass, "Running as root")
+    } else {
+        CheckResult::new("privileges", CheckStatus::Warn, "Not running as root; some operations may fail")
+    }
+}
+
+async fn check_database(manager: &PermissionManager) -> CheckResult {
+    match manager.database_health().await {
+        Ok(health) if !health.connected => {
+            CheckResult::new("database", CheckStatus::Fail, "Database connection failed")
+        }
+        Ok(health) if !health.schema_ok() => CheckResult::new(
+            "database",
+            CheckStatus::Fail,
+            format!("Schema missing tables: {}", health.missing_tables.join(", ")),
+        ),
+        Ok(_) => CheckResult::new("database", CheckStatus::Pass, "Connected and schema intact"),
+        Err(e) => CheckResult::new("database", CheckStatus::Fail, e.to_string()),
+    }
+}
+
+async fn check_audit_chain(manager: &PermissionManager) -> CheckResult {
+    match manager.verify_audit_chain().await {
+        Ok(report) => match report.broken_at {
+            Some(id) => CheckResult::new(
+                "audit_chain",
+                CheckStatus::Fail,
+                format!("Audit log hash chain broken at entry {}; entries were edited or removed", id),
+            ),
+            None => CheckResult::new(
+                "audit_chain",
+                CheckStatus::Pass,
+                format!("Audit log hash chain intact ({} entries)", report.verified),
+            ),
+        },
+        Err(e) => CheckResult::new("audit_chain", CheckStatus::Fail, format!("Could not read audit log: {}", e)),
+    }
+}
+
+async fn check_clock(manager: &PermissionManager) -> CheckResult {
+    match manager.check_clock().await {
+        Ok(()) => CheckResult::new("clock", CheckStatus::Pass, "System clock consistent with recorded history"),
+        Err(e @ PermissionError::ClockAnomaly { .. }) => CheckResult::new("clock", CheckStatus::Fail, e.to_string()),
+        Err(e) => CheckResult::new("clock", CheckStatus::Fail, format!("Could not check clock: {}", e)),
+    }
+}
+
+/// The sudoers file must exist and hold exactly the rules of active grants
+async fn check_sudoers(manager: &PermissionManager) -> CheckResult {
+    let config = manager.config();
+    if !config.sudoers_enabled {
+        return CheckResult::new("sudoers", CheckStatus::Warn, "Sudoers management disabled");
+    }
+    if !config.sudoers_path.exists() {
+        return CheckResult::new("sudoers", CheckStatus::Fail, format!("Sudoers file not found: {:?}", config.sudoers_path));
+    }
+
+    match manager.sudoers_in_sync().await {
+        Ok(true) => CheckResult::new("sudoers", CheckStatus::Pass, "Sudoers file matches active grants"),
+        Ok(false) => CheckResult::new(
+            "sudoers",
+            CheckStatus::Fail,
+            "Sudoers file does not match active grants; run permctl sync to regenerate it",
+        ),
+        Err(e) => CheckResult::new("sudoers", CheckStatus::Fail, format!("Could not read sudoers file: {}", e)),
+    }
+}
+
+/// Rules in a drop-in file that sudo never reads silently do nothing
+fn check_sudoers_included(config: &Config) -> CheckResult {
+    if !config.sudoers_enabled {
+        return CheckResult::new("sudoers_included", CheckStatus::Pass, "Sudoers management disabled");
+    }
+
+    let path = &config.sudoers_path;
+    match sudoers_inclusion(Path::new(SUDOERS_MAIN), path) {
+        SudoersInclusion::Included => {
+            CheckResult::new("sudoers_included", CheckStatus::Pass, format!("sudo reads {:?}", path))
+        }
+        SudoersInclusion::SkippedName => CheckResult::new(
+            "sudoers_included",
+            CheckStatus::Fail,
+            format!("sudo skips {:?} because its name contains a '.' or ends in '~'; grants have no effect", path),
+        ),
+        SudoersInclusion::NotIncluded => CheckResult::new(
+            "sudoers_included",
+            CheckStatus::Fail,
+            format!("{} does not include {:?} or its directory; grants have no effect", SUDOERS_MAIN, path),
+        ),
+        SudoersInclusion::Unknown(reason) => CheckResult::new(
+            "sudoers_included",
+            CheckStatus::Warn,
+            format!("Could not verify that sudo reads {:?}: {}", path, reason),
+        ),
+    }
+}
+
+/// Grants of deleted users leave rules behind for whoever gets the name next
+async fn check_orphans(manager: &PermissionManager) -> CheckResult {
+    match manager.orphaned_users().await {
+        Ok(orphans) if orphans.is_empty() => CheckResult::new("orphans", CheckStatus::Pass, "No grants held by deleted users"),
+        Ok(orphans) => CheckResult::new(
+            "orphans",
+            CheckStatus::Warn,
+            format!("Grants held by users that no longer exist: {}; run permctl cleanup --orphans", orphans.join(", ")),
+        ),
+        Err(e) => CheckResult::new("orphans", CheckStatus::Fail, format!("Could not look up grant holders: {}", e)),
+    }
+}
+
+/// Duplicate grants for a user and command only arise from edits outside
+/// permctl, and leave revoking or extending the pair ambiguous
+async fn check_duplicates(manager: &PermissionManager) -> CheckResult {
+    match manager.duplicate_grants().await {
+        Ok(duplicates) if duplicates.is_empty() => CheckResult::new("duplicates", CheckStatus::Pass, "No duplicate active grants"),
+        Ok(duplicates) => {
+            let pairs: Vec<String> = duplicates
+                .iter()
+                .map(|(username, command, grants)| format!("{} {} ({} grants)", username, command, grants))
+                .collect();
+            CheckResult::new(
+                "duplicates",
+                CheckStatus::Warn,
+                format!("Users with more than one active grant for a command: {}; run permctl cleanup --dedupe", pairs.join(", ")),
+            )
+        }
+        Err(e) => CheckResult::new("duplicates", CheckStatus::Fail, format!("Could not look for duplicate grants: {}", e)),
+    }
+}
+
+/// Maintenance mode is deliberate, but leaving it on denies every grant
+async fn check_maintenance(manager: &PermissionManager) -> CheckResult {
+    match manager.maintenance().await {
+        Ok(None) => CheckResult::new("maintenance", CheckStatus::Pass, "Maintenance mode off"),
+        Ok(Some(maintenance)) => CheckResult::new(
+            "maintenance",
+            CheckStatus::Warn,
+            format!(
+                "Maintenance mode on since {} ({}); no grant is honored until permctl maintenance off",
+                maintenance.since, maintenance.enabled_by
+            ),
+        ),
+        Err(e) => CheckResult::new("maintenance", CheckStatus::Fail, format!("Could not read maintenance mode: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CommandConfig;
+    use tempfile::TempDir;
+
+    fn status_of(results: &[CheckResult], name: &str) -> Option<CheckStatus> {
+        results.iter().find(|r| r.name == name).map(|r| r.status)
+    }
+
+    #[tokio::test]
+    async fn test_runs_every_check() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = Config {
+            sudoers_path: temp_dir.path().join("sudoers"),
+            db_path: temp_dir.path().join("test.db"),
+            log_path: temp_dir.path().join("test.log"),
+            ..Config::default()
+        };
+        config.allowed_commands.insert("/usr/bin/docker".to_string(), CommandConfig {
+            description: "Docker command access".to_string(),
+            max_duration: 60,
+            required_groups: vec!["no-such-group-permctl".to_string()],
+            max_concurrent_users: 5,
+            ..Default::default()
+        });
+        let config_path = temp_dir.path().join("config.yaml");
+        config.save_to(&config_path).unwrap();
+
+        // The sudoers file has not been written yet, which must not stop
+        // the remaining checks
+        let results = run_checks(&config_path).await;
+        assert_eq!(status_of(&results, "config"), Some(CheckStatus::Pass));
+        assert_eq!(status_of(&results, "groups"), Some(CheckStatus::Warn));
+        assert_eq!(status_of(&results, "database"), Some(CheckStatus::Pass));
+        assert_eq!(status_of(&results, "audit_chain"), Some(CheckStatus::Pass));
+        assert_eq!(status_of(&results, "clock"), Some(CheckStatus::Pass));
+        assert_eq!(status_of(&results, "duplicates"), Some(CheckStatus::Pass));
+        assert_eq!(status_of(&results, "maintenance"), Some(CheckStatus::Pass));
+        assert_eq!(status_of(&results, "sudoers"), Some(CheckStatus::Fail));
+        assert_eq!(overall_status(&results), CheckStatus::Fail);
+
+        PermissionManager::new(config).await.unwrap();
+        let results = run_checks(&config_path).await;
+        assert_eq!(status_of(&results, "sudoers"), Some(CheckStatus::Pass));
+    }
+
+    #[test]
+    fn test_file_drift_is_detected_and_fixed() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("sudoers");
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let uid = Uid::effective().as_raw();
+        let expected = ExpectedMode { mode: 0o440, uid, gid: None };
+        let drift = path_drift(&path, expected).unwrap();
+        assert_eq!(drift.problems, vec!["mode 0644, expected 0440"]);
+
+        drift.fix().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().mode() & 0o7777, 0o440);
+        assert_eq!(path_drift(&path, expected), None);
+        assert_eq!(path_drift(&temp_dir.path().join("missing"), expected), None);
+    }
+
+    #[test]
+    fn test_sudoers_inclusion() {
+        let temp_dir = TempDir::new().unwrap();
+        let main = temp_dir.path().join("sudoers");
+        let drop_ins = temp_dir.path().join("sudoers.d");
+        let permctl = drop_ins.join("permctl");
+
+        fs::write(&main, "root ALL=(ALL) ALL\n# includedir sudoers.d is only a comment\n").unwrap();
+        assert_eq!(sudoers_inclusion(&main, &permctl), SudoersInclusion::NotIncluded);
+
+        fs::write(&main, format!("root ALL=(ALL) ALL\n#includedir {}\n", drop_ins.display())).unwrap();
+        assert_eq!(sudoers_inclusion(&main, &permctl), SudoersInclusion::Included);
+        assert_eq!(sudoers_inclusion(&main, &drop_ins.join("permctl.conf")), SudoersInclusion::SkippedName);
+
+        // Relative paths and nested includes are followed
+        fs::write(temp_dir.path().join("extra"), "@includedir sudoers.d\n").unwrap();
+        fs::write(&main, "@include \"extra\"\n").unwrap();
+        assert_eq!(sudoers_inclusion(&main, &permctl), SudoersInclusion::Included);
+
+        assert!(matches!(
+            sudoers_inclusion(&temp_dir.path().join("missing"), &permctl),
+            SudoersInclusion::Unknown(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_config_fails() {
+        let temp_dir = TempDir::new().unwrap();
+        let results = run_checks(&temp_dir.path().join("missing.yaml")).await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(overall_status(&results), CheckStatus::Fail);
+        assert_eq!(CheckStatus::Fail.exit_code(), 2);
+    }
+}
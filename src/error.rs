@@ -1,12 +1,22 @@
 use std::path::PathBuf;
 use thiserror::Error;
 
+/// SQLite primary result codes for a database another connection holds
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+
 /// Custom error types for the permission manager
 #[derive(Error, Debug)]
 pub enum PermissionError {
     #[error("Database error: {0}")]
     Database(#[from] sqlx::Error),
 
+    #[error("Database is corrupt: {0}")]
+    DatabaseCorrupt(String),
+
+    #[error("Database encryption error: {0}")]
+    DatabaseEncryption(String),
+
     #[error("IO error at {path:?}: {source}")]
     Io {
         #[source]
@@ -33,6 +43,54 @@ pub enum PermissionError {
     #[error("Invalid duration: {0}")]
     InvalidDuration(String),
 
+    #[error("Total granted duration exceeded: {requested} more minutes would exceed the limit of {limit} ({used} already granted)")]
+    TotalDurationExceeded {
+        used: i64,
+        requested: i64,
+        limit: i64,
+    },
+
+    #[error("Concurrent user limit reached for {command}: {limit} user(s) already hold access")]
+    ConcurrentUserLimit {
+        command: String,
+        limit: usize,
+    },
+
+    #[error("Usage quota exceeded for {command}: all {max_uses} use(s) have been recorded")]
+    UsageQuotaExceeded {
+        command: String,
+        max_uses: i64,
+        correlation_id: Option<String>,
+    },
+
+    #[error("Cannot grant {command} while {conflict} is held: the commands are mutually exclusive")]
+    ConflictingGrant {
+        command: String,
+        conflict: String,
+        /// Correlation ID of the grant that is in the way
+        correlation_id: Option<String>,
+    },
+
+    #[error("{} of the requested commands cannot be granted, so none were", .failures.len())]
+    BatchRejected {
+        /// Each command that failed validation with the reason
+        failures: Vec<(String, PermissionError)>,
+    },
+
+    #[error("{0} requires approval or confirmation and must be granted on its own")]
+    NotBatchable(String),
+
+    #[error("{username} already holds {command} until {expires_at}; revoke it before granting it again")]
+    GrantExists {
+        username: String,
+        command: String,
+        expires_at: chrono::DateTime<chrono::Utc>,
+        correlation_id: Option<String>,
+    },
+
+    #[error("Invalid username {0:?}: it does not match the allowed username pattern")]
+    InvalidUsername(String),
+
     #[error("Command not allowed: {0}")]
     CommandNotAllowed(String),
 
@@ -42,17 +100,69 @@ pub enum PermissionError {
         group: String,
     },
 
+    #[error("Grant request {0} not found")]
+    RequestNotFound(i64),
+
+    #[error("Grant request {id} is already {status}")]
+    RequestNotPending {
+        id: i64,
+        status: String,
+    },
+
+    #[error("{approver} has already approved grant request {id}")]
+    AlreadyApproved {
+        id: i64,
+        approver: String,
+    },
+
+    #[error("{user} cannot approve grant request {id}, which is their own")]
+    SelfApproval {
+        id: i64,
+        user: String,
+    },
+
+    #[error("Command does not require approval: {0}")]
+    ApprovalNotRequired(String),
+
+    #[error("A reason is required to {0}")]
+    ReasonRequired(String),
+
+    #[error("Confirmation token for {0} is invalid or has expired")]
+    InvalidConfirmation(String),
+
     #[error("User not found: {0}")]
     UserNotFound(String),
 
+    #[error("Unsafe command target for {command}: {reason}, so users granted it could run anything")]
+    UnsafeCommandTarget {
+        command: String,
+        reason: String,
+    },
+
     #[error("Access expired")]
     AccessExpired,
+
+    #[error("Clock anomaly: system time {now} is behind the last recorded time {last_seen}")]
+    ClockAnomaly {
+        now: chrono::DateTime<chrono::Utc>,
+        last_seen: chrono::DateTime<chrono::Utc>,
+    },
 }
 
 /// Result type alias for Permission operations
 pub type Result<T> = std::result::Result<T, PermissionError>;
 
 impl PermissionError {
+    /// Correlation ID of the grant the error concerns, if any
+    pub fn correlation_id(&self) -> Option<&str> {
+        match self {
+            Self::UsageQuotaExceeded { correlation_id, .. }
+            | Self::ConflictingGrant { correlation_id, .. }
+            | Self::GrantExists { correlation_id, .. } => correlation_id.as_deref(),
+            _ => None,
+        }
+    }
+
     /// Creates a new IO error with associated path
     pub fn io_error(source: std::io::Error, path: impl Into<PathBuf>) -> Self {
         Self::Io {
@@ -76,7 +186,21 @@ impl PermissionError {
 
     /// Check if error is related to user configuration
     pub fn is_user_error(&self) -> bool {
-        matches!(self, Self::User(_) | Self::UserNotFound(_) | Self::GroupRequirementNotMet { .. })
+        matches!(
+            self,
+            Self::User(_) | Self::UserNotFound(_) | Self::GroupRequirementNotMet { .. } | Self::ReasonRequired(_)
+        )
+    }
+
+    /// Whether the database stayed locked by another connection until the
+    /// busy timeout ran out
+    pub fn is_database_locked(&self) -> bool {
+        let Self::Database(sqlx::Error::Database(db_err)) = self else {
+            return false;
+        };
+        // Extended result codes keep the primary code in the low byte
+        let primary = db_err.code().and_then(|code| code.parse::<i32>().ok()).map(|code| code & 0xff);
+        matches!(primary, Some(SQLITE_BUSY | SQLITE_LOCKED))
     }
 
     /// Returns true if this is a transient error that might succeed if retried
@@ -130,4 +254,54 @@ mod tests {
         assert!(db_err.is_transient());
         assert!(!db_err.is_user_error());
     }
+
+    /// SQLite error carrying just a result code
+    #[derive(Debug)]
+    struct SqliteCode(&'static str);
+
+    impl std::fmt::Display for SqliteCode {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "SQLite error {}", self.0)
+        }
+    }
+
+    impl std::error::Error for SqliteCode {}
+
+    impl sqlx::error::DatabaseError for SqliteCode {
+        fn message(&self) -> &str {
+            "database is locked"
+        }
+
+        fn code(&self) -> Option<std::borrow::Cow<'_, str>> {
+            Some(self.0.into())
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> sqlx::error::ErrorKind {
+            sqlx::error::ErrorKind::Other
+        }
+    }
+
+    #[test]
+    fn test_database_locked_classification() {
+        let sqlite_error = |code| PermissionError::Database(sqlx::Error::Database(Box::new(SqliteCode(code))));
+
+        assert!(sqlite_error("5").is_database_locked());
+        assert!(sqlite_error("6").is_database_locked());
+        // SQLITE_BUSY_SNAPSHOT, an extended code of SQLITE_BUSY
+        assert!(sqlite_error("517").is_database_locked());
+        assert!(!sqlite_error("11").is_database_locked());
+        assert!(!PermissionError::Database(sqlx::Error::PoolTimedOut).is_database_locked());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,402 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get};
+use axum::{Extension, Json, Router};
+use chrono::Duration;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+use tracing::info;
+
+use crate::config::CommandConfig;
+use crate::db::{AuditEntry, AuditQuery, GrantPage, ListOptions};
+use crate::error::{PermissionError, Result};
+use crate::manager::{GrantOptions, GrantOutcome, PermissionManager};
+use crate::server::{refresh_sudoers_loop, shutdown_signal};
+
+/// Address the HTTP API listens on unless another is given
+pub const DEFAULT_HTTP_ADDR: &str = "127.0.0.1:8080";
+/// Acting user recorded for requests authorized by the shared API token
+const TOKEN_ACTOR: &str = "http-api";
+/// Most grants or audit entries one request returns
+const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Decides whether an HTTP request may use the API
+pub trait HttpAuthorizer: Send + Sync {
+    /// Name to record as the acting user of an authorized request, or
+    /// `None` to refuse it
+    fn authorize(&self, headers: &HeaderMap) -> Option<String>;
+}
+
+/// Authorizes requests that carry `Authorization: Bearer <token>` with the
+/// token read from `api_token_path`
+pub struct BearerToken {
+    token: String,
+}
+
+impl BearerToken {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self { token: token.into() }
+    }
+}
+
+impl HttpAuthorizer for BearerToken {
+    fn authorize(&self, headers: &HeaderMap) -> Option<String> {
+        let presented = headers
+            .get(header::AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")?;
+        constant_time_eq(presented.as_bytes(), self.token.as_bytes()).then(|| TOKEN_ACTOR.to_string())
+    }
+}
+
+/// Compare without stopping at the first differing byte, so response times
+/// do not reveal how much of a guessed token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Serve the REST API on `addr` until SIGINT or SIGTERM, then flush queued
+/// audit entries. The API speaks plain HTTP and is meant to sit behind a
+/// TLS-terminating proxy; listening on anything but a loopback address
+/// requires `allow_remote`.
+///
+/// As with the socket API, the database gets a full integrity check before
+/// serving starts and the sudoers file is rewritten at each grant expiry
+/// while serving.
+pub async fn serve_http(
+    manager: Arc<PermissionManager>,
+    addr: SocketAddr,
+    allow_remote: bool,
+    authorizer: Arc<dyn HttpAuthorizer>,
+) -> Result<()> {
+    if !addr.ip().is_loopback() && !allow_remote {
+        return Err(PermissionError::Config(format!(
+            "refusing to serve HTTP on non-loopback address {}; pass --allow-remote to opt in",
+            addr
+        )));
+    }
+    manager.integrity_check().await?;
+
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| PermissionError::Config(format!("Failed to listen on {}: {}", addr, e)))?;
+    info!("Serving HTTP API on {}", addr);
+
+    let grants_changed = Arc::new(Notify::new());
+    tokio::spawn(refresh_sudoers_loop(manager.clone(), grants_changed.clone()));
+
+    axum::serve(listener, router(manager.clone(), authorizer, grants_changed))
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .map_err(|e| PermissionError::Config(format!("HTTP API failed: {}", e)))?;
+
+    info!("Shutting down");
+    manager.flush_audit().await;
+    Ok(())
+}
+
+#[derive(Clone)]
+struct AppState {
+    manager: Arc<PermissionManager>,
+    authorizer: Arc<dyn HttpAuthorizer>,
+    grants_changed: Arc<Notify>,
+}
+
+/// Acting user of an authorized request
+#[derive(Clone)]
+struct Caller(String);
+
+fn router(manager: Arc<PermissionManager>, authorizer: Arc<dyn HttpAuthorizer>, grants_changed: Arc<Notify>) -> Router {
+    let state = AppState { manager, authorizer, grants_changed };
+    Router::new()
+        .route("/grants", get(list_grants).post(create_grant))
+        .route("/grants/{id}", delete(revoke_grant))
+        .route("/commands", get(list_commands))
+        .route("/audit", get(list_audit))
+        .layer(middleware::from_fn_with_state(state.clone(), require_authorization))
+        .with_state(state)
+}
+
+async fn require_authorization(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    match state.authorizer.authorize(request.headers()) {
+        Some(caller) => {
+            request.extensions_mut().insert(Caller(caller));
+            next.run(request).await
+        }
+        None => error_response(StatusCode::UNAUTHORIZED, "missing or invalid bearer token"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GrantParams {
+    user: Option<String>,
+    /// Include revoked and expired grants
+    #[serde(default)]
+    all: bool,
+    limit: Option<i64>,
+    #[serde(default)]
+    offset: i64,
+}
+
+async fn list_grants(State(state): State<AppState>, Query(params): Query<GrantParams>) -> ApiResult<Json<GrantPage>> {
+    let page = state.manager.list_permissions_page(&ListOptions {
+        username: params.user,
+        include_revoked: params.all,
+        include_expired: params.all,
+        limit: Some(params.limit.unwrap_or(MAX_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)),
+        offset: params.offset.max(0),
+        ..ListOptions::default()
+    }).await?;
+    Ok(Json(page))
+}
+
+/// Body of `POST /grants`
+#[derive(Debug, Deserialize)]
+struct GrantBody {
+    username: String,
+    command: String,
+    duration_minutes: i64,
+    /// Defaults to the server's host; `*` applies on all hosts
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    confirm: Option<String>,
+    #[serde(default)]
+    require_password: bool,
+    #[serde(default)]
+    reason: Option<String>,
+    #[serde(default)]
+    on_behalf_of: Option<String>,
+}
+
+async fn create_grant(
+    State(state): State<AppState>,
+    Extension(Caller(caller)): Extension<Caller>,
+    Json(body): Json<GrantBody>,
+) -> ApiResult<(StatusCode, Json<GrantOutcome>)> {
+    let duration = Duration::try_minutes(body.duration_minutes)
+        .ok_or_else(|| PermissionError::InvalidDuration("duration too large".to_string()))?;
+    let outcome = state.manager.grant_permission_on_host(&body.username, &body.command, duration, &caller, GrantOptions {
+        host: body.host.as_deref(),
+        require_password: body.require_password,
+        confirmation: body.confirm.as_deref(),
+        reason: body.reason.as_deref(),
+        on_behalf_of: body.on_behalf_of.as_deref(),
+        ..Default::default()
+    }).await?;
+
+    // Requests still waiting for approval or confirmation grant nothing yet
+    let status = match outcome {
+        GrantOutcome::Granted(_) => {
+            state.grants_changed.notify_one();
+            StatusCode::CREATED
+        }
+        GrantOutcome::Pending(_) | GrantOutcome::ConfirmationRequired(_) => StatusCode::ACCEPTED,
+    };
+    Ok((status, Json(outcome)))
+}
+
+#[derive(Debug, Deserialize)]
+struct RevokeParams {
+    reason: Option<String>,
+}
+
+async fn revoke_grant(
+    State(state): State<AppState>,
+    Extension(Caller(caller)): Extension<Caller>,
+    Path(id): Path<i64>,
+    Query(params): Query<RevokeParams>,
+) -> ApiResult<Response> {
+    let revoked = state.manager.revoke_grant_by_id(id, &caller, params.reason.as_deref()).await?;
+    if revoked.is_some() {
+        state.grants_changed.notify_one();
+        return Ok(Json(json!({ "revoked": true })).into_response());
+    }
+
+    match state.manager.get_grant(id).await? {
+        Some(_) => Ok(error_response(StatusCode::CONFLICT, &format!("grant {} is no longer active", id))),
+        None => Ok(error_response(StatusCode::NOT_FOUND, &format!("grant {} does not exist", id))),
+    }
+}
+
+async fn list_commands(State(state): State<AppState>) -> Json<BTreeMap<String, CommandConfig>> {
+    let commands = state.manager.config().allowed_commands.clone();
+    Json(commands.into_iter().collect())
+}
+
+#[derive(Debug, Deserialize)]
+struct AuditParams {
+    user: Option<String>,
+    command: Option<String>,
+    action: Option<String>,
+    limit: Option<i64>,
+}
+
+async fn list_audit(State(state): State<AppState>, Query(params): Query<AuditParams>) -> ApiResult<Json<Vec<AuditEntry>>> {
+    let entries = state.manager.audit_entries(&AuditQuery {
+        username: params.user,
+        command: params.command,
+        action: params.action,
+        limit: Some(params.limit.unwrap_or(100).clamp(1, MAX_PAGE_SIZE)),
+    }).await?;
+    Ok(Json(entries))
+}
+
+type ApiResult<T> = std::result::Result<T, ApiError>;
+
+/// A `PermissionError` answered with the matching HTTP status
+struct ApiError(PermissionError);
+
+impl From<PermissionError> for ApiError {
+    fn from(err: PermissionError) -> Self {
+        Self(err)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        error_response(status_for(&self.0), &self.0.to_string())
+    }
+}
+
+fn status_for(err: &PermissionError) -> StatusCode {
+    match err {
+        PermissionError::PermissionDenied(_)
+        | PermissionError::CommandNotAllowed(_)
+        | PermissionError::GroupRequirementNotMet { .. }
+        | PermissionError::SelfApproval { .. }
+        | PermissionError::UnsafeCommandTarget { .. } => StatusCode::FORBIDDEN,
+        PermissionError::UserNotFound(_) | PermissionError::RequestNotFound(_) => StatusCode::NOT_FOUND,
+        PermissionError::GrantExists { .. }
+        | PermissionError::ConcurrentUserLimit { .. }
+        | PermissionError::ConflictingGrant { .. }
+        | PermissionError::TotalDurationExceeded { .. }
+        | PermissionError::UsageQuotaExceeded { .. }
+        | PermissionError::RequestNotPending { .. }
+        | PermissionError::AlreadyApproved { .. }
+        | PermissionError::AccessExpired => StatusCode::CONFLICT,
+        PermissionError::InvalidDuration(_)
+        | PermissionError::InvalidUsername(_)
+        | PermissionError::InvalidConfirmation(_)
+        | PermissionError::ReasonRequired(_)
+        | PermissionError::BatchRejected { .. }
+        | PermissionError::NotBatchable(_)
+        | PermissionError::ApprovalNotRequired(_)
+        | PermissionError::User(_)
+        | PermissionError::Config(_) => StatusCode::BAD_REQUEST,
+        err if err.is_database_locked() => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use serde_json::Value;
+    use tempfile::TempDir;
+    use tower::ServiceExt;
+
+    use crate::config::Config;
+
+    const TOKEN: &str = "s3cret";
+
+    async fn test_manager(temp_dir: &TempDir) -> Arc<PermissionManager> {
+        let mut config = Config {
+            sudoers_path: temp_dir.path().join("sudoers"),
+            db_path: temp_dir.path().join("test.db"),
+            log_path: temp_dir.path().join("test.log"),
+            ..Config::default()
+        };
+        config.allowed_commands.insert("/test/command".to_string(), CommandConfig {
+            description: "Test command".to_string(),
+            max_duration: 60,
+            required_groups: Vec::new(),
+            max_concurrent_users: 5,
+            ..Default::default()
+        });
+        Arc::new(PermissionManager::new(config).await.unwrap())
+    }
+
+    async fn call(app: &Router, method: &str, uri: &str, token: Option<&str>, body: Option<Value>) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let body = match body {
+            Some(body) => {
+                request = request.header(header::CONTENT_TYPE, "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+
+        let response = app.clone().oneshot(request.body(body).unwrap()).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn test_rest_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = test_manager(&temp_dir).await;
+        let command = "/test/command";
+        let app = router(manager, Arc::new(BearerToken::new(TOKEN)), Arc::new(Notify::new()));
+
+        assert_eq!(call(&app, "GET", "/grants", None, None).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(call(&app, "GET", "/grants", Some("wrong"), None).await.0, StatusCode::UNAUTHORIZED);
+
+        let (status, commands) = call(&app, "GET", "/commands", Some(TOKEN), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(commands.get(command).is_some());
+
+        let grant = json!({ "username": "root", "command": command, "duration_minutes": 30 });
+        let (status, outcome) = call(&app, "POST", "/grants", Some(TOKEN), Some(grant)).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", outcome);
+        assert_eq!(outcome["outcome"], "granted");
+        assert_eq!(outcome["granted_by"], TOKEN_ACTOR);
+        let id = outcome["id"].as_i64().unwrap();
+
+        let (status, page) = call(&app, "GET", "/grants?user=root", Some(TOKEN), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["grants"][0]["id"], id);
+
+        let too_long = json!({ "username": "root", "command": command, "duration_minutes": 100000 });
+        assert_eq!(call(&app, "POST", "/grants", Some(TOKEN), Some(too_long)).await.0, StatusCode::BAD_REQUEST);
+
+        let (status, revoked) = call(&app, "DELETE", &format!("/grants/{}", id), Some(TOKEN), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(revoked["revoked"], true);
+        assert_eq!(call(&app, "DELETE", &format!("/grants/{}", id), Some(TOKEN), None).await.0, StatusCode::CONFLICT);
+        assert_eq!(call(&app, "DELETE", "/grants/9999", Some(TOKEN), None).await.0, StatusCode::NOT_FOUND);
+
+        let (status, audit) = call(&app, "GET", "/audit?action=revoke", Some(TOKEN), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(audit.as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_refuses_remote_address_without_opt_in() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = test_manager(&temp_dir).await;
+
+        let err = serve_http(manager, "0.0.0.0:0".parse().unwrap(), false, Arc::new(BearerToken::new(TOKEN)))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("--allow-remote"));
+    }
+}
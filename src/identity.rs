@@ -0,0 +1,62 @@
+use std::process::Command;
+
+use nix::unistd::User;
+
+use crate::error::{PermissionError, Result};
+
+/// Source of the user accounts and group memberships that grant policy is
+/// checked against
+pub trait IdentityProvider: Send + Sync {
+    /// Whether `username` is a known account
+    fn user_exists(&self, username: &str) -> Result<bool>;
+
+    /// Names of every group `username` belongs to, empty for unknown users
+    fn user_groups(&self, username: &str) -> Result<Vec<String>>;
+
+    /// Whether `username` belongs to `group`
+    fn user_in_group(&self, username: &str, group: &str) -> Result<bool> {
+        Ok(self.user_groups(username)?.iter().any(|g| g == group))
+    }
+}
+
+/// Accounts and groups as the host resolves them through NSS, so users from
+/// LDAP, SSSD or Active Directory are seen just as `getent` would see them
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemIdentity;
+
+impl IdentityProvider for SystemIdentity {
+    fn user_exists(&self, username: &str) -> Result<bool> {
+        User::from_name(username)
+            .map(|user| user.is_some())
+            .map_err(|e| PermissionError::system_command(e.into(), "getpwnam"))
+    }
+
+    fn user_groups(&self, username: &str) -> Result<Vec<String>> {
+        let output = Command::new("id")
+            .args(["-Gn", "--", username])
+            .output()
+            .map_err(|e| PermissionError::system_command(e, "id"))?;
+
+        // id fails for unknown users, which simply belong to no groups
+        if !output.status.success() {
+            return Ok(Vec::new());
+        }
+        let groups = String::from_utf8_lossy(&output.stdout);
+        Ok(groups.split_whitespace().map(str::to_string).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_system_identity_resolves_root() {
+        let identity = SystemIdentity;
+        assert!(identity.user_exists("root").unwrap());
+        assert!(identity.user_in_group("root", "root").unwrap());
+
+        assert!(!identity.user_exists("permctl-no-such-user").unwrap());
+        assert!(identity.user_groups("permctl-no-such-user").unwrap().is_empty());
+    }
+}
@@ -1,9 +1,24 @@
 pub mod error;
 pub mod config;
+pub mod config_diff;
 pub mod db;
 pub mod manager;
+pub mod audit_export;
+pub mod audit_details;
+pub mod audit_buffer;
+pub mod server;
+pub mod http;
+pub mod doctor;
+pub mod log_dedup;
+pub mod notify;
+pub mod identity;
+pub mod check_cache;
+pub mod command_target;
 
-pub use manager::PermissionManager;
-pub use db::{Database, PermissionGrant};
+pub use manager::{CommandQuota, DurationQuota, GrantCheck, GrantOptions, GrantOutcome, IssuedGrant, PermissionManager, PermissionManagerBuilder, QuotaReport, UserQuota};
+pub use db::{ApprovalOutcome, AuditEntry, AuditQuery, Compaction, Database, DatabaseHealth, GrantPage, GrantRequest, ListOptions, Maintenance, PermissionGrant, RequestStatus, SortField};
 pub use error::{PermissionError, Result};
-pub use config::{Config, CommandConfig};
\ No newline at end of file
+pub use audit_details::AuditDetails;
+pub use config_diff::{ConfigDiff, FieldChange};
+pub use config::{AuditDetail, CheckCacheConfig, Config, CommandConfig, ExpiryRounding, RegrantPolicy, SyslogConfig, SyslogFormat};
+pub use identity::{IdentityProvider, SystemIdentity};
\ No newline at end of file
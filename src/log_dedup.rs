@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// When a message was last logged and how many repeats were dropped since
+struct Seen {
+    logged_at: Instant,
+    suppressed: u64,
+}
+
+/// Rate limiter for warnings that repeat while an outage lasts, such as an
+/// unwritable sudoers file in the daemon. Each distinct message is logged at
+/// most once per interval; the next time it is logged, the number of
+/// suppressed repeats is appended.
+pub struct WarningDeduplicator {
+    interval: Duration,
+    seen: Mutex<HashMap<String, Seen>>,
+}
+
+impl WarningDeduplicator {
+    /// Create a deduplicator; an interval of zero logs every message
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Log `message` as a warning unless it was logged within the interval
+    pub fn warn(&self, message: &str) {
+        match self.check(message, Instant::now()) {
+            Some(0) => warn!("{}", message),
+            Some(suppressed) => warn!("{} (repeated {} more time(s))", message, suppressed),
+            None => {}
+        }
+    }
+
+    /// Decide whether to log `message` at `now`, returning how many repeats
+    /// were suppressed since it was last logged, or `None` to drop it
+    fn check(&self, message: &str, now: Instant) -> Option<u64> {
+        let mut seen = match self.seen.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if let Some(entry) = seen.get_mut(message) {
+            if now.duration_since(entry.logged_at) < self.interval {
+                entry.suppressed += 1;
+                return None;
+            }
+            let suppressed = entry.suppressed;
+            *entry = Seen { logged_at: now, suppressed: 0 };
+            return Some(suppressed);
+        }
+
+        // Forget messages that have gone quiet so the map stays small
+        let interval = self.interval;
+        seen.retain(|_, entry| now.duration_since(entry.logged_at) < interval);
+        seen.insert(message.to_string(), Seen { logged_at: now, suppressed: 0 });
+        Some(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeats_are_suppressed_within_interval() {
+        let dedup = WarningDeduplicator::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert_eq!(dedup.check("sudoers unwritable", start), Some(0));
+        assert_eq!(dedup.check("sudoers unwritable", start + Duration::from_secs(10)), None);
+        assert_eq!(dedup.check("sudoers unwritable", start + Duration::from_secs(20)), None);
+        assert_eq!(dedup.check("database locked", start + Duration::from_secs(20)), Some(0));
+
+        assert_eq!(dedup.check("sudoers unwritable", start + Duration::from_secs(61)), Some(2));
+        assert_eq!(dedup.check("sudoers unwritable", start + Duration::from_secs(62)), None);
+    }
+
+    #[test]
+    fn test_zero_interval_logs_everything() {
+        let dedup = WarningDeduplicator::new(Duration::ZERO);
+        let now = Instant::now();
+        assert_eq!(dedup.check("same", now), Some(0));
+        assert_eq!(dedup.check("same", now), Some(0));
+    }
+}
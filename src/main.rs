@@ -1,14 +1,56 @@
-use std::process;
-use clap::{Parser, Subcommand};
-use chrono::{Duration, Utc};
-use tracing::{info, warn, error};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+use std::fmt::Display;
+use std::sync::{Arc, OnceLock};
+use clap::{Parser, Subcommand, ValueEnum};
+use comfy_table::{presets::{ASCII_FULL_CONDENSED, UTF8_FULL_CONDENSED}, Cell, Color, ContentArrangement, Table};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
 
 use linux_permission_manager::{
+    ApprovalOutcome,
+    AuditEntry,
+    AuditQuery,
+    CommandConfig,
+    CommandQuota,
     Config,
+    config::{EXAMPLE_COMMAND_COMMENT, MAX_DURATION_MINUTES},
+    command_target::check_command_target,
+    FieldChange,
+    GrantOptions,
+    GrantOutcome,
+    GrantPage,
+    ListOptions,
+    PermissionGrant,
+    RegrantPolicy,
+    SortField,
     PermissionManager,
+    doctor::{self, CheckStatus, SudoersInclusion},
     error::{Result, PermissionError},
+    http,
+    server,
 };
 
+/// Print a line marked as a success, like `println!`
+macro_rules! success {
+    ($($arg:tt)*) => { println!("{}", Mark::Success.line(format_args!($($arg)*))) };
+}
+
+/// Print a line marked as a failure, like `println!`
+macro_rules! failure {
+    ($($arg:tt)*) => { println!("{}", Mark::Failure.line(format_args!($($arg)*))) };
+}
+
+/// Print a line marked as a warning, like `println!`
+macro_rules! warning {
+    ($($arg:tt)*) => { println!("{}", Mark::Warning.line(format_args!($($arg)*))) };
+}
+
 #[derive(Parser)]
 #[command(
     name = "permctl",
@@ -23,13 +65,20 @@ struct Cli {
     config: Option<String>,
 
     /// Enable debug logging
-    #[arg(short, long, global = true)]
+    #[arg(long, global = true)]
     debug: bool,
 
+    /// Print without ANSI colours and with ASCII markers and table borders.
+    /// Setting NO_COLOR does the same.
+    #[arg(long, global = true)]
+    no_color: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+// Variants are the subcommand names, and `commands` is one of them
+#[allow(clippy::enum_variant_names)]
 #[derive(Subcommand)]
 enum Commands {
     /// Grant temporary permission to a user
@@ -37,12 +86,60 @@ enum Commands {
         /// Username to grant permission to
         username: String,
         
-        /// Command to grant permission for
-        command: String,
+        /// Commands to grant permission for, as separate arguments or comma
+        /// separated; several commands are granted all or nothing
+        #[arg(value_name = "COMMAND", value_delimiter = ',', required_unless_present = "template", conflicts_with = "template")]
+        commands: Vec<String>,
+
+        /// Grant the command rendered from this configured template instead
+        #[arg(short, long, value_name = "NAME")]
+        template: Option<String>,
+
+        /// Value for a template variable, e.g. --var service=nginx
+        #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var, requires = "template")]
+        vars: Vec<(String, String)>,
         
-        /// Duration in minutes
-        #[arg(short, long, default_value = "60")]
+        /// Duration in minutes, or with units such as 90m, 1.5h, 1h30m or 1d
+        #[arg(short, long, default_value = "60", value_parser = parse_duration)]
         duration: i64,
+
+        /// Schedule the grant to start at this time (RFC 3339, e.g. 2024-06-01T09:00:00Z)
+        #[arg(long, value_name = "TIME")]
+        from: Option<DateTime<Utc>>,
+
+        /// Host the grant applies to; defaults to this host, `*` for all hosts
+        #[arg(long)]
+        host: Option<String>,
+
+        /// Token printed by an earlier attempt, for commands that require
+        /// confirmation
+        #[arg(long, value_name = "TOKEN")]
+        confirm: Option<String>,
+
+        /// Make sudo ask for the user's own password, even if the command
+        /// is configured not to
+        #[arg(long)]
+        require_password: bool,
+
+        /// Why the grant is needed, kept in the audit log; must match the
+        /// command's reason_pattern if it has one
+        #[arg(short, long)]
+        reason: Option<String>,
+
+        /// User the grant is made for, recorded alongside the granting
+        /// account when granting on someone else's request, e.g. from a bot
+        #[arg(long, value_name = "USER")]
+        on_behalf_of: Option<String>,
+
+        /// Tell the user about the grant on their terminals; requires
+        /// user_notifications in the configuration
+        #[arg(long)]
+        notify_user: bool,
+
+        /// Run every check the grant has to pass and report each one,
+        /// without granting anything
+        #[arg(long, conflicts_with_all = ["confirm", "notify_user"])]
+        check: bool,
     },
 
     /// Revoke permission from a user
@@ -50,8 +147,38 @@ enum Commands {
         /// Username to revoke permission from
         username: String,
         
-        /// Command to revoke permission for
+        /// Command to revoke permission for, or a pattern with --glob
         command: String,
+
+        /// Revoke every active grant of the user whose command matches
+        /// COMMAND as a glob, e.g. '/usr/bin/*'
+        #[arg(long)]
+        glob: bool,
+
+        /// Why the permission is being revoked
+        #[arg(short, long)]
+        reason: Option<String>,
+    },
+
+    /// Revoke every active grant issued by an admin
+    RevokeByGranter {
+        /// Admin whose grants should be revoked
+        admin: String,
+
+        /// Why the grants are being revoked
+        #[arg(short, long)]
+        reason: Option<String>,
+    },
+
+    /// Give up your own access to a command before it expires
+    Drop {
+        /// Command to drop your permission for
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        command: Option<String>,
+
+        /// Drop every active grant you hold
+        #[arg(long)]
+        all: bool,
     },
 
     /// List permissions
@@ -63,6 +190,55 @@ enum Commands {
         /// Show permissions for specific user
         #[arg(short, long)]
         user: Option<String>,
+
+        /// Only show permissions expiring within this many minutes
+        #[arg(short, long, value_name = "MINUTES", value_parser = clap::value_parser!(i64).range(1..=MAX_DURATION_MINUTES))]
+        expiring: Option<i64>,
+
+        /// Maximum number of permissions to show
+        #[arg(short, long, default_value = "50")]
+        limit: u32,
+
+        /// Number of permissions to skip
+        #[arg(short, long, default_value = "0")]
+        offset: u32,
+
+        /// Show grant requests waiting for approval instead
+        #[arg(short, long)]
+        pending: bool,
+
+        /// Show grants that applied at any time between two RFC 3339
+        /// times, including revoked and expired ones
+        #[arg(long, num_args = 2, value_names = ["START", "END"])]
+        active_during: Option<Vec<DateTime<Utc>>>,
+
+        /// Order permissions by this field instead of by user and command
+        #[arg(long, value_enum, conflicts_with_all = ["pending", "expiring", "active_during"])]
+        sort: Option<ListSort>,
+
+        /// Reverse the --sort order, e.g. most recently granted first
+        #[arg(long, requires = "sort")]
+        desc: bool,
+
+        /// Redraw the listing every SECONDS seconds until interrupted,
+        /// highlighting grants that appeared or ended since the last refresh
+        #[arg(
+            long,
+            value_name = "SECONDS",
+            value_parser = clap::value_parser!(u64).range(1..),
+            conflicts_with_all = ["pending", "expiring", "active_during"]
+        )]
+        watch: Option<u64>,
+
+        /// Output format for the listing
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+
+    /// Approve a pending grant request
+    Approve {
+        /// ID of the request, as printed by grant or list --pending
+        id: i64,
     },
 
     /// Show allowed commands
@@ -70,29 +246,412 @@ enum Commands {
         /// Show detailed information about commands
         #[arg(short, long)]
         verbose: bool,
+
+        /// Only show commands carrying this tag
+        #[arg(short, long)]
+        tag: Option<String>,
+    },
+
+    /// Show how close each command is to its concurrent user limit and
+    /// what each user currently holds
+    Quota {
+        /// Output format for the report
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+
+    /// Print a single line summarizing your active grants, for shell prompts
+    ///
+    /// Prints e.g. `permctl: 2 active (docker 42m, systemctl 1h3m)`, soonest
+    /// expiry first, or `permctl: none`.
+    Status {
+        /// Exit 0 if you hold any active grant and 1 otherwise
+        #[arg(long)]
+        exit_code: bool,
     },
 
     /// Clean up expired permissions
-    Cleanup,
+    Cleanup {
+        /// Report how many grants would be revoked and purged without
+        /// changing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Instead of expired grants, revoke the grants of users whose
+        /// accounts no longer exist
+        #[arg(long)]
+        orphans: bool,
+
+        /// Instead of expired grants, revoke all but the latest-expiring
+        /// grant of users holding several active grants for one command
+        #[arg(long, conflicts_with = "orphans")]
+        dedupe: bool,
+
+        /// Also revoke grants whose holder is no longer in one of the
+        /// command's required groups, as revalidate_groups does
+        #[arg(long)]
+        revalidate_groups: bool,
+    },
+
+    /// Rebuild the sudoers file from the database without changing any grants
+    ///
+    /// Use after restoring a database backup or when doctor reports that the
+    /// sudoers file has drifted from the active grants.
+    Sync,
+
+    /// Reclaim space in the database file left by purged rows and the
+    /// write-ahead log
+    Compact,
+
+    /// Stop honoring every grant at once, or resume
+    ///
+    /// While maintenance mode is on, the sudoers file holds no rules and
+    /// every permission check fails. Grants are kept in the database, so
+    /// turning it off restores them.
+    Maintenance {
+        #[arg(value_enum)]
+        state: MaintenanceState,
+    },
+
+    /// Print the active and scheduled grants for infrastructure-as-code tools
+    ///
+    /// Meant for reconciliation loops that diff desired against actual
+    /// state; nothing is changed.
+    Dump {
+        /// Structure of the output
+        #[arg(long, value_enum, default_value_t = DumpFormat::Yaml)]
+        format: DumpFormat,
+    },
+
+    /// Show recent audit log entries, newest first
+    Audit {
+        /// Only show entries about this user
+        #[arg(short, long)]
+        user: Option<String>,
+
+        /// Only show entries about this command
+        #[arg(long = "command", value_name = "COMMAND")]
+        command_filter: Option<String>,
+
+        /// Only show entries with this action, e.g. grant or revoke
+        #[arg(short, long)]
+        action: Option<String>,
+
+        /// Maximum number of entries to show
+        #[arg(short, long, default_value_t = 50)]
+        limit: i64,
+
+        /// Output format; json keeps the structured details of each entry
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
 
     /// Initialize configuration
     Init {
         /// Force overwrite existing configuration
         #[arg(short, long)]
         force: bool,
+
+        /// Build the configuration by answering prompts
+        #[arg(short, long)]
+        interactive: bool,
+
+        /// Also allow a curated set of commands: docker, kubernetes,
+        /// database or web; may be repeated or comma separated
+        #[arg(long = "with", value_name = "PRESET", value_delimiter = ',', conflicts_with = "interactive")]
+        presets: Vec<String>,
     },
 
     /// Verify configuration and permissions
     Verify,
+
+    /// Run every health check and report all problems at once
+    ///
+    /// Unlike verify, doctor never stops at the first failure. Exits 0 when
+    /// every check passes, 1 if the worst result is a warning and 2 if any
+    /// check failed.
+    Doctor {
+        /// Output format for the report
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+
+        /// Rebuild the database from its readable rows first if it fails
+        /// the integrity check, keeping the damaged file alongside
+        #[arg(long)]
+        recover: bool,
+
+        /// Restore the owner and mode of the sudoers file, database and
+        /// their directories first if they have drifted
+        #[arg(long)]
+        fix: bool,
+    },
+
+    /// Check whether a user currently holds grants for one or more commands
+    ///
+    /// Exits 0 if every grant is active and 1 otherwise, printing nothing
+    /// unless --verbose is given. Intended for sudo wrappers and plugins, e.g.
+    /// `permctl check "$SUDO_USER" /usr/bin/docker || exit 1`.
+    Check {
+        /// Username to check
+        username: String,
+
+        /// Commands to check, resolved together in one query
+        #[arg(required = true)]
+        commands: Vec<String>,
+
+        /// Record this check as a use of the grant
+        #[arg(short, long)]
+        record: bool,
+
+        /// Print the result of the check
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
+    /// Serve the JSON API on a Unix domain socket
+    ///
+    /// Accepts line-delimited JSON requests (grant, revoke, check, list) from
+    /// root or members of the configured api_group.
+    Serve {
+        /// Path of the socket to listen on
+        #[arg(short, long, default_value = server::DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+    },
+
+    /// Serve a REST API over HTTP
+    ///
+    /// Clients authenticate with the bearer token in api_token_path. The API
+    /// speaks plain HTTP, so put it behind a TLS-terminating proxy; it only
+    /// listens on loopback addresses unless --allow-remote is given.
+    Http {
+        /// Address and port to listen on
+        #[arg(long, default_value = http::DEFAULT_HTTP_ADDR)]
+        addr: SocketAddr,
+
+        /// Allow listening on an address other hosts can reach, such as
+        /// 0.0.0.0
+        #[arg(long)]
+        allow_remote: bool,
+    },
+
+    /// Validate a configuration file without touching the system
+    ///
+    /// Reports every validation error and exits nonzero if any are found,
+    /// making it suitable for CI and pre-commit hooks.
+    ConfigCheck {
+        /// Configuration file to check (defaults to --config or the default path)
+        #[arg(value_name = "CONFIG")]
+        file: Option<String>,
+    },
+
+    /// Show what changes between two configuration files
+    ///
+    /// Lists added and removed commands, the changed fields of every other
+    /// command and changed settings, so policy changes can be reviewed
+    /// before they are deployed. Both files must be valid.
+    ConfigDiff {
+        /// Current configuration file
+        old: PathBuf,
+
+        /// Proposed configuration file
+        new: PathBuf,
+
+        /// Output format for the changes
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+}
+
+/// Fields `list --sort` accepts
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ListSort {
+    User,
+    Command,
+    Granted,
+    Expires,
+    LastUsed,
+}
+
+impl From<ListSort> for SortField {
+    fn from(sort: ListSort) -> Self {
+        match sort {
+            ListSort::User => SortField::User,
+            ListSort::Command => SortField::Command,
+            ListSort::Granted => SortField::Granted,
+            ListSort::Expires => SortField::Expires,
+            ListSort::LastUsed => SortField::LastUsed,
+        }
+    }
+}
+
+/// How `list`, `audit`, `quota` and `doctor` render their results
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// One block of fields per grant, or one line per check
+    Text,
+    /// The results as JSON
+    Json,
+    /// Aligned columns, one row per grant or check
+    Table,
+}
+
+/// What `maintenance` switches to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum MaintenanceState {
+    On,
+    Off,
+}
+
+/// How `dump` renders grants
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum DumpFormat {
+    /// A YAML list of Ansible-style tasks, one per grant
+    Ansible,
+    /// The grants as a JSON array
+    Json,
+    /// The grants as a YAML list
+    Yaml,
+}
+
+/// One grant as an Ansible-style task
+#[derive(Serialize)]
+struct AnsibleTask<'a> {
+    name: String,
+    permctl: AnsibleGrant<'a>,
+}
+
+#[derive(Serialize)]
+struct AnsibleGrant<'a> {
+    state: &'static str,
+    #[serde(flatten)]
+    grant: &'a PermissionGrant,
+}
+
+impl Commands {
+    /// Whether the command only queries permissions, so it can use a
+    /// read-only manager that works without root
+    fn is_query_only(&self) -> bool {
+        matches!(
+            self,
+            Commands::Check { record: false, .. }
+                | Commands::Grant { check: true, .. }
+                | Commands::Cleanup { dry_run: true, .. }
+                | Commands::List { .. }
+                | Commands::Commands { .. }
+                | Commands::Dump { .. }
+                | Commands::Audit { .. }
+                | Commands::Quota { .. }
+                | Commands::Status { .. }
+        )
+    }
+
+    /// Whether the command changes grants or the configuration, so the
+    /// invoking user must be an admin when `admin_groups` is set
+    fn requires_admin(&self) -> bool {
+        matches!(
+            self,
+            Commands::Grant { .. }
+                | Commands::Approve { .. }
+                | Commands::Revoke { .. }
+                | Commands::RevokeByGranter { .. }
+                | Commands::Cleanup { dry_run: false, .. }
+                | Commands::Maintenance { .. }
+                | Commands::Compact
+        )
+    }
+
+    /// Whether the command writes the database, sudoers file or API socket,
+    /// which only root may do with the default paths
+    fn requires_root(&self) -> bool {
+        matches!(
+            self,
+            Commands::Grant { check: false, .. }
+                | Commands::Approve { .. }
+                | Commands::Revoke { .. }
+                | Commands::RevokeByGranter { .. }
+                | Commands::Drop { .. }
+                | Commands::Cleanup { dry_run: false, .. }
+                | Commands::Sync
+                | Commands::Maintenance { .. }
+                | Commands::Compact
+                | Commands::Check { record: true, .. }
+                | Commands::Serve { .. }
+                | Commands::Http { .. }
+        )
+    }
+
+    /// Whether the command can skip the directory setup and sudoers
+    /// regeneration done on startup
+    fn skips_setup(&self) -> bool {
+        matches!(self, Commands::Check { .. } | Commands::Sync)
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<ExitCode> {
+    let mut manager = None;
+    let result = run(&mut manager).await;
+    // Entries queued by async_audit are lost unless written before exiting,
+    // however the command ended
+    if let Some(manager) = &manager {
+        manager.flush_audit().await;
+    }
+    if let Err(e) = &result {
+        if e.is_database_locked() {
+            eprintln!("{}", Mark::Warning.line(
+                "The database is busy; another permctl process may be running. Retry shortly."
+            ));
+        }
+    }
+    result
+}
+
+/// Run the command line, keeping the manager it opens in `manager` so the
+/// caller can flush its audit entries afterwards
+async fn run(manager: &mut Option<Arc<PermissionManager>>) -> Result<ExitCode> {
     // Parse command line arguments
     let cli = Cli::parse();
 
     // Setup logging
     setup_logging(cli.debug)?;
+    let _ = THEME.set(Theme::detect(cli.no_color));
+
+    // Linting a config must not load it as the active one or touch the system
+    if let Commands::ConfigCheck { file } = &cli.command {
+        if !check_config(file.as_deref().or(cli.config.as_deref()))? {
+            return Ok(ExitCode::FAILURE);
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Commands::ConfigDiff { old, new, output } = &cli.command {
+        show_config_diff(old, new, *output)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Diagnosing must report a broken config or database rather than fail on it
+    if let Commands::Doctor { output, recover, fix } = cli.command {
+        let path = match &cli.config {
+            Some(path) => PathBuf::from(path),
+            None => Config::default_config_path()?,
+        };
+        process::exit(run_doctor(&path, output, recover, fix).await?);
+    }
+
+    // Writing a new config must work before any config or directories exist
+    if let Commands::Init { force, interactive, presets } = cli.command {
+        let path = match &cli.config {
+            Some(path) => PathBuf::from(path),
+            None => Config::default_config_path()?,
+        };
+        // Replacing a config is limited to the admins it names; a config
+        // that cannot be read is left for init to repair
+        if let Ok(existing) = Config::parse_from(&path) {
+            PermissionManager::authorize_admin(&existing, &invoking_user())?;
+        }
+        initialize_config(&path, force, interactive, &presets)?;
+        return Ok(ExitCode::SUCCESS);
+    }
 
     // Load configuration
     let config = match &cli.config {
@@ -100,41 +659,218 @@ async fn main() -> Result<()> {
         None => Config::load(),
     }?;
 
+    // Refuse up front rather than fail halfway through writing. A process
+    // sudo already started as another user is not sent back to sudo.
+    if cli.command.requires_root() && config.sudoers_enabled && !nix::unistd::Uid::effective().is_root() {
+        if config.auto_sudo && std::env::var_os("SUDO_UID").is_none() {
+            return Err(reexec_with_sudo());
+        }
+        return Err(PermissionError::PermissionDenied(
+            "this operation requires root; re-run with sudo".to_string()
+        ));
+    }
+
+    if cli.command.requires_admin() {
+        PermissionManager::authorize_admin(&config, &invoking_user())?;
+    }
+
     // Initialize permission manager
-    let manager = PermissionManager::new(config).await?;
+    let manager = manager.insert(Arc::new(if cli.command.is_query_only() {
+        PermissionManager::new_readonly(config).await?
+    } else if cli.command.skips_setup() {
+        PermissionManager::connect(config).await?
+    } else {
+        PermissionManager::new(config).await?
+    }));
 
     // Process commands
     match cli.command {
-        Commands::Grant { username, command, duration } => {
-            grant_permission(&manager, &username, &command, duration).await?;
+        Commands::Grant {
+            username, commands, template, vars, duration, from, host, confirm, require_password, reason, on_behalf_of, notify_user, check,
+        } => {
+            let grant_options = GrantOptions {
+                host: host.as_deref(),
+                active_from: from,
+                require_password,
+                confirmation: confirm.as_deref(),
+                reason: reason.as_deref(),
+                on_behalf_of: on_behalf_of.as_deref(),
+            };
+            if check {
+                let commands = match template {
+                    Some(name) => vec![manager.config().render_template(&name, &vars.into_iter().collect())?],
+                    None => commands,
+                };
+                if !check_grant(manager, &username, &commands, duration, grant_options).await {
+                    return Ok(ExitCode::FAILURE);
+                }
+                return Ok(ExitCode::SUCCESS);
+            }
+            if notify_user && !manager.config().user_notifications {
+                warning!("user_notifications is disabled in the configuration; {} will not be notified", username);
+            }
+            let command = match (commands.as_slice(), template) {
+                ([command], _) => command.clone(),
+                ([], Some(name)) => manager.config().render_template(&name, &vars.into_iter().collect())?,
+                ([], None) => unreachable!("clap requires a command or a template"),
+                (commands, _) => {
+                    grant_permissions(manager, &username, commands, duration, grant_options, notify_user).await?;
+                    return Ok(ExitCode::SUCCESS);
+                }
+            };
+            grant_permission(manager, &username, &command, duration, grant_options, notify_user).await?;
+        }
+
+        Commands::Revoke { username, command, glob: true, reason } => {
+            revoke_matching(manager, &username, &command, reason.as_deref()).await?;
+        }
+
+        Commands::Revoke { username, command, glob: false, reason } => {
+            revoke_permission(manager, &username, &command, reason.as_deref()).await?;
+        }
+
+        Commands::RevokeByGranter { admin, reason } => {
+            revoke_by_granter(manager, &admin, reason.as_deref()).await?;
+        }
+
+        Commands::Drop { command, all: _ } => {
+            drop_own(manager, command.as_deref()).await?;
+        }
+
+        Commands::List { all, user, expiring, limit, offset, pending, active_during, sort, desc, watch, output } => {
+            if pending {
+                list_pending(manager).await?;
+            } else if let Some(window) = active_during {
+                list_active_during(manager, window[0], window[1], user, output).await?;
+            } else {
+                match expiring {
+                    Some(minutes) => list_expiring(manager, minutes, user).await?,
+                    None => {
+                        let options = list_options(all, user, limit, offset, sort, desc);
+                        match watch {
+                            Some(seconds) => watch_permissions(manager, &options, all, output, seconds).await?,
+                            None => list_permissions(manager, &options, all, output).await?,
+                        }
+                    }
+                }
+            }
+        }
+
+        Commands::Approve { id } => {
+            approve_request(manager, id).await?;
+        }
+
+        Commands::Commands { verbose, tag } => {
+            show_commands(manager, verbose, tag.as_deref())?;
+        }
+
+        Commands::Quota { output } => {
+            show_quota(manager, output).await?;
         }
 
-        Commands::Revoke { username, command } => {
-            revoke_permission(&manager, &username, &command).await?;
+        Commands::Cleanup { dry_run, orphans, dedupe, revalidate_groups } => {
+            // A plain cleanup also revalidates when the configuration enables it
+            if revalidate_groups || (manager.config().revalidate_groups && !orphans && !dedupe) {
+                if dry_run {
+                    preview_group_revalidation(manager).await?;
+                } else {
+                    cleanup_group_revalidation(manager).await?;
+                }
+            }
+            match (dry_run, orphans, dedupe) {
+                (true, false, false) => preview_cleanup(manager).await?,
+                (false, false, false) => cleanup_expired(manager).await?,
+                (true, true, _) => preview_orphans(manager).await?,
+                (false, true, _) => cleanup_orphans(manager).await?,
+                (true, false, true) => preview_duplicates(manager).await?,
+                (false, false, true) => cleanup_duplicates(manager).await?,
+            }
+            report_next_expiry(manager).await?;
+        }
+
+
+        Commands::Sync => {
+            sync_sudoers(manager).await?;
         }
 
-        Commands::List { all, user } => {
-            list_permissions(&manager, all, user).await?;
+        Commands::Maintenance { state } => {
+            set_maintenance(manager, state == MaintenanceState::On).await?;
         }
 
-        Commands::Commands { verbose } => {
-            show_commands(&manager, verbose)?;
+        Commands::Compact => {
+            let compaction = manager.compact_database().await?;
+            success!(
+                "Compacted the database from {} to {}",
+                format_bytes(compaction.size_before),
+                format_bytes(compaction.size_after)
+            );
         }
 
-        Commands::Cleanup => {
-            cleanup_expired(&manager).await?;
+        Commands::Dump { format } => {
+            dump_grants(manager, format).await?;
         }
 
-        Commands::Init { force } => {
-            initialize_config(force)?;
+        Commands::Audit { user, command_filter, action, limit, output } => {
+            let query = AuditQuery { username: user, command: command_filter, action, limit: Some(limit) };
+            show_audit(manager, &query, output).await?;
         }
 
         Commands::Verify => {
-            verify_setup(&manager).await?;
+            verify_setup(manager).await?;
+        }
+
+        Commands::Status { exit_code } => {
+            let grants = manager.usable_grants(&invoking_user()).await?;
+            println!("{}", status_line(&grants, Utc::now()));
+            if exit_code && grants.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+
+        Commands::Check { username, commands, record, verbose } => {
+            if !check_permissions(manager, &username, &commands, record, verbose).await? {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+
+        Commands::Serve { socket } => {
+            server::serve(manager.clone(), &socket).await?;
+        }
+
+        Commands::Http { addr, allow_remote } => {
+            let Some(token) = manager.config().api_token()? else {
+                return Err(PermissionError::Config("api_token_path must be set to serve the HTTP API".to_string()));
+            };
+            http::serve_http(manager.clone(), addr, allow_remote, Arc::new(http::BearerToken::new(token))).await?;
+        }
+
+        Commands::ConfigCheck { .. }
+        | Commands::ConfigDiff { .. }
+        | Commands::Init { .. }
+        | Commands::Doctor { .. } => {
+            unreachable!("handled before loading configuration")
         }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Replace this process with the same invocation run under `sudo`,
+/// returning only if that fails
+fn reexec_with_sudo() -> PermissionError {
+    use std::os::unix::process::CommandExt;
+
+    // sudo resets PATH, so pass the binary by its full path
+    let program = match std::env::current_exe() {
+        Ok(path) => path.into_os_string(),
+        Err(e) => return PermissionError::system_command(e, "current_exe"),
+    };
+    let e = process::Command::new("sudo")
+        .arg("--")
+        .arg(program)
+        .args(std::env::args_os().skip(1))
+        .exec();
+    PermissionError::system_command(e, "sudo")
 }
 
 fn setup_logging(debug: bool) -> Result<()> {
@@ -153,22 +889,156 @@ async fn grant_permission(
     username: &str,
     command: &str,
     duration_mins: i64,
+    options: GrantOptions<'_>,
+    notify_user: bool,
 ) -> Result<()> {
     let duration = Duration::minutes(duration_mins);
-    let granted_by = whoami::username();
+    let granted_by = invoking_user();
 
-    match manager.grant_permission(username, command, duration, &granted_by).await {
-        Ok(id) => {
-            println!("✓ Permission granted successfully");
-            println!("  ID: {}", id);
-            println!("  User: {}", username);
-            println!("  Command: {}", command);
+    let granted = manager.grant_permission_on_host(username, command, duration, &granted_by, options).await;
+    match granted {
+        Ok(GrantOutcome::ConfirmationRequired(confirmation)) => {
+            warning!("{} requires confirmation", confirmation.command);
+            println!("  Token: {}", confirmation.token);
+            println!("  Valid until: {}", confirmation.expires_at);
+            println!("  Repeat this grant with --confirm {} to complete it", confirmation.token);
+            Ok(())
+        }
+        Ok(GrantOutcome::Pending(request)) => {
+            warning!("{} requires approval", request.command);
+            println!("  Request ID: {}", request.id);
+            println!("  User: {}", request.username);
+            println!("  Duration: {} minutes", request.duration_minutes);
+            println!("  Approve with: permctl approve {}", request.id);
+            Ok(())
+        }
+        Ok(GrantOutcome::Granted(issued)) => {
+            let grant = &issued.grant;
+            success!("Permission granted successfully");
+            println!("  ID: {}", issued.id());
+            println!("  User: {}", grant.username);
+            println!("  Command: {}", grant.command);
+            println!("  Duration: {} minutes", duration_mins);
+            if let Some(from) = grant.active_from {
+                println!("  Active from: {}", from);
+            }
+            println!("  Host: {}", grant.host.as_deref().unwrap_or("all"));
+            if let Some(user) = &grant.on_behalf_of {
+                println!("  On behalf of: {}", user);
+            }
+            println!("  Expires: {}", grant.expires_at);
+            if issued.jitter_secs != 0 {
+                println!("  Expiry jitter: {:+}s", issued.jitter_secs);
+            }
+            if issued.replaced_existing {
+                let verb = match manager.config().regrant_policy {
+                    RegrantPolicy::Extend => "Extended",
+                    _ => "Replaced",
+                };
+                println!("  {} the grant {} already held", verb, grant.username);
+            }
+            if grant.require_password {
+                println!("  Password: required");
+            }
+            if notify_user {
+                manager.notify_user(username, grant);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            failure!("Failed to grant permission");
+            println!("  Error: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Report each check that granting `commands` to `username` would have to
+/// pass, returning whether they all passed
+async fn check_grant(
+    manager: &PermissionManager,
+    username: &str,
+    commands: &[String],
+    duration_mins: i64,
+    options: GrantOptions<'_>,
+) -> bool {
+    let duration = Duration::minutes(duration_mins);
+    let granted_by = invoking_user();
+    let mut all_passed = true;
+
+    for command in commands {
+        println!("{}:", command);
+        for check in manager.validate_grant(username, command, duration, &granted_by, options).await {
+            match &check.result {
+                Ok(()) => println!("  {}", Mark::Success.line(check.name)),
+                Err(e) => {
+                    all_passed = false;
+                    println!("  {}", Mark::Failure.line(format_args!("{}: {}", check.name, e)));
+                }
+            }
+        }
+        if let Some(cmd_config) = manager.config().command_config(command) {
+            if cmd_config.require_confirmation {
+                println!("  Granting would first ask for confirmation");
+            }
+            if cmd_config.requires_approval {
+                println!("  Granting would record a request for approval");
+            }
+        }
+    }
+
+    if all_passed {
+        success!("All checks passed; nothing was granted");
+    } else {
+        failure!("Some checks failed; nothing was granted");
+    }
+    all_passed
+}
+
+async fn grant_permissions(
+    manager: &PermissionManager,
+    username: &str,
+    commands: &[String],
+    duration_mins: i64,
+    options: GrantOptions<'_>,
+    notify_user: bool,
+) -> Result<()> {
+    let duration = Duration::minutes(duration_mins);
+    let granted_by = invoking_user();
+
+    let granted = manager.grant_permissions_on_host(username, commands, duration, &granted_by, options).await;
+    match granted {
+        Ok(grants) => {
+            success!("Granted {} permission(s) to {}", grants.len(), username);
             println!("  Duration: {} minutes", duration_mins);
-            println!("  Expires: {}", Utc::now() + duration);
+            println!("  Host: {}", manager.grant_scope(options.host).as_deref().unwrap_or("all"));
+            for grant in &grants {
+                println!("  {}", Mark::Success.line(format_args!("{} (ID: {}, expires: {})", grant.command, grant.id, grant.expires_at)));
+            }
+            if notify_user {
+                for grant in &grants {
+                    manager.notify_user(username, grant);
+                }
+            }
             Ok(())
         }
+        Err(PermissionError::BatchRejected { failures }) => {
+            failure!("Failed to grant permissions; nothing was granted");
+            let mut shown = Vec::new();
+            for command in commands {
+                if shown.contains(&command) {
+                    continue;
+                }
+                shown.push(command);
+                match failures.iter().find(|(failed, _)| failed == command) {
+                    Some((_, e)) => println!("  {}", Mark::Failure.line(format_args!("{}: {}", command, e))),
+                    None => println!("  - {}: allowed, but not granted", command),
+                }
+            }
+            Err(PermissionError::BatchRejected { failures })
+        }
         Err(e) => {
-            println!("✗ Failed to grant permission");
+            failure!("Failed to grant permissions; nothing was granted");
             println!("  Error: {}", e);
             Err(e)
         }
@@ -179,129 +1049,1645 @@ async fn revoke_permission(
     manager: &PermissionManager,
     username: &str,
     command: &str,
+    reason: Option<&str>,
 ) -> Result<()> {
-    let revoked_by = whoami::username();
+    let revoked_by = invoking_user();
 
-    match manager.revoke_permission(username, command, &revoked_by).await {
+    match manager.revoke_permission_with_reason(username, command, &revoked_by, reason).await {
         Ok(true) => {
-            println!("✓ Permission revoked successfully");
+            success!("Permission revoked successfully");
             println!("  User: {}", username);
             println!("  Command: {}", command);
+            if let Some(reason) = reason {
+                println!("  Reason: {}", reason);
+            }
             Ok(())
         }
         Ok(false) => {
-            println!("! No active permission found to revoke");
+            warning!("No active permission found to revoke");
             Ok(())
         }
         Err(e) => {
-            println!("✗ Failed to revoke permission");
+            failure!("Failed to revoke permission");
             println!("  Error: {}", e);
             Err(e)
         }
     }
 }
 
-async fn list_permissions(
+async fn revoke_matching(
     manager: &PermissionManager,
-    all: bool,
-    user: Option<String>,
+    username: &str,
+    pattern: &str,
+    reason: Option<&str>,
 ) -> Result<()> {
-    if let Some(username) = user {
-        let permissions = manager.list_user_permissions(&username).await?;
-        if permissions.is_empty() {
-            println!("No permissions found for user {}", username);
-            return Ok(());
-        }
+    let revoked_by = invoking_user();
 
-        println!("Permissions for user {}:", username);
-        for perm in permissions {
-            println!("  Command: {}", perm.command);
-            println!("    Granted: {}", perm.granted_at);
-            println!("    Expires: {}", perm.expires_at);
-            if let Some(last_used) = perm.last_used {
-                println!("    Last used: {}", last_used);
+    match manager.revoke_matching(username, pattern, &revoked_by, reason).await {
+        Ok(revoked) if revoked.is_empty() => {
+            warning!("No active permission of {} matches {}", username, pattern);
+            Ok(())
+        }
+        Ok(revoked) => {
+            for grant in &revoked {
+                success!("Revoked {} from {}", grant.command, username);
             }
-            println!();
+            success!("Revoked {} permission(s) matching {}", revoked.len(), pattern);
+            if let Some(reason) = reason {
+                println!("  Reason: {}", reason);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            failure!("Failed to revoke permissions");
+            println!("  Error: {}", e);
+            Err(e)
         }
-    } else {
-        // TODO: Implement listing all permissions
-        println!("Listing all permissions not implemented yet");
     }
-
-    Ok(())
 }
 
-fn show_commands(manager: &PermissionManager, verbose: bool) -> Result<()> {
-    println!("Allowed commands:");
-    
-    for (cmd, config) in &manager.config().allowed_commands {
-        if verbose {
-            println!("\n{}", cmd);
+/// Check each command, returning whether the user holds all of them
+async fn check_permissions(
+    manager: &PermissionManager,
+    username: &str,
+    commands: &[String],
+    record: bool,
+    verbose: bool,
+) -> Result<bool> {
+    let results = manager.check_permissions(username, commands).await?;
+
+    for command in commands {
+        let allowed = results.get(command).copied().unwrap_or(false);
+
+        if allowed && record {
+            manager.record_usage(username, command).await?;
+        }
+
+        if verbose {
+            if allowed {
+                success!("{} may run {}", username, command);
+            } else {
+                failure!("{} has no active permission for {}", username, command);
+            }
+        }
+    }
+
+    Ok(commands.iter().all(|command| results.get(command).copied().unwrap_or(false)))
+}
+
+async fn revoke_by_granter(
+    manager: &PermissionManager,
+    admin: &str,
+    reason: Option<&str>,
+) -> Result<()> {
+    let revoked_by = invoking_user();
+
+    match manager.revoke_by_granter(admin, &revoked_by, reason).await {
+        Ok(0) => {
+            println!("No active permissions granted by {}", admin);
+            Ok(())
+        }
+        Ok(count) => {
+            success!("Revoked {} permission(s) granted by {}", count, admin);
+            Ok(())
+        }
+        Err(e) => {
+            failure!("Failed to revoke permissions granted by {}", admin);
+            println!("  Error: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// The user running permctl. Under sudo, as when `auto_sudo` re-runs a
+/// command, this is the user who invoked sudo rather than root.
+fn invoking_user() -> String {
+    sudo_invoker(nix::unistd::Uid::effective().is_root(), std::env::var("SUDO_USER").ok())
+        .unwrap_or_else(whoami::username)
+}
+
+/// The user who ran sudo, given whether permctl runs as root and the
+/// `SUDO_USER` it was started with
+fn sudo_invoker(is_root: bool, sudo_user: Option<String>) -> Option<String> {
+    sudo_user.filter(|user| is_root && !user.is_empty())
+}
+
+async fn drop_own(manager: &PermissionManager, command: Option<&str>) -> Result<()> {
+    let username = invoking_user();
+
+    match manager.drop_own(&username, command).await {
+        Ok(dropped) if dropped.is_empty() => {
+            match command {
+                Some(command) => warning!("{} holds no active permission for {}", username, command),
+                None => warning!("{} holds no active permissions", username),
+            }
+            Ok(())
+        }
+        Ok(dropped) => {
+            for grant in &dropped {
+                success!("Dropped {} for {}", grant.command, username);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            failure!("Failed to drop permissions");
+            println!("  Error: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Filters for `permctl list`. Scheduled grants are shown (and labelled)
+/// alongside active ones.
+fn list_options(
+    all: bool,
+    user: Option<String>,
+    limit: u32,
+    offset: u32,
+    sort: Option<ListSort>,
+    descending: bool,
+) -> ListOptions {
+    ListOptions {
+        username: user,
+        include_scheduled: true,
+        limit: Some(limit.into()),
+        offset: offset.into(),
+        sort: sort.map(SortField::from),
+        descending,
+        ..if all { ListOptions::all() } else { ListOptions::default() }
+    }
+}
+
+async fn list_permissions(
+    manager: &PermissionManager,
+    options: &ListOptions,
+    all: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    let page = manager.list_permissions_page(options).await?;
+    print_permissions(&page, options, all, output, None)
+}
+
+/// ANSI colours used for status markers, changes between `list --watch`
+/// refreshes and commands near their limit in `quota`
+const ANSI_GREEN: &str = "\x1b[32m";
+const ANSI_YELLOW: &str = "\x1b[33m";
+const ANSI_RED: &str = "\x1b[31m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// Theme chosen at startup
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+/// How output is decorated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Theme {
+    /// Whether to emit ANSI colours
+    color: bool,
+    /// Whether to use Unicode markers and table borders
+    unicode: bool,
+}
+
+impl Theme {
+    /// Colour only a terminal, and fall back to ASCII outside a UTF-8
+    /// locale; `--no-color` or a non-empty `NO_COLOR` turns both off
+    fn detect(no_color: bool) -> Self {
+        let no_color = no_color || std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        Self {
+            color: !no_color && std::io::stdout().is_terminal(),
+            unicode: !no_color && locale_is_utf8(),
+        }
+    }
+
+    /// The theme chosen at startup, or the detected one if none was
+    fn current() -> Self {
+        *THEME.get_or_init(|| Self::detect(false))
+    }
+}
+
+/// Whether the locale's character set is UTF-8. The first of `LC_ALL`,
+/// `LC_CTYPE` and `LANG` that is set decides, as it does for setlocale.
+fn locale_is_utf8() -> bool {
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    locale.contains("utf-8") || locale.contains("utf8")
+}
+
+/// Outcome a status line reports, shown as a marker in front of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    Success,
+    Failure,
+    Warning,
+}
+
+impl Mark {
+    fn symbol(self, unicode: bool) -> &'static str {
+        match (self, unicode) {
+            (Mark::Success, true) => "✓",
+            (Mark::Success, false) => "+",
+            (Mark::Failure, true) => "✗",
+            (Mark::Failure, false) => "x",
+            (Mark::Warning, _) => "!",
+        }
+    }
+
+    fn ansi(self) -> &'static str {
+        match self {
+            Mark::Success => ANSI_GREEN,
+            Mark::Failure => ANSI_RED,
+            Mark::Warning => ANSI_YELLOW,
+        }
+    }
+
+    /// `text` behind this marker, decorated for the current theme
+    fn line(self, text: impl Display) -> String {
+        self.render(Theme::current(), text)
+    }
+
+    fn render(self, theme: Theme, text: impl Display) -> String {
+        let symbol = self.symbol(theme.unicode);
+        if theme.color {
+            format!("{}{}{} {}", self.ansi(), symbol, ANSI_RESET, text)
+        } else {
+            format!("{} {}", symbol, text)
+        }
+    }
+}
+
+impl From<CheckStatus> for Mark {
+    fn from(status: CheckStatus) -> Self {
+        match status {
+            CheckStatus::Pass => Mark::Success,
+            CheckStatus::Warn => Mark::Warning,
+            CheckStatus::Fail => Mark::Failure,
+        }
+    }
+}
+
+/// Empty table with borders the current theme can show, sized to the
+/// terminal
+fn new_table() -> Table {
+    let preset = if Theme::current().unicode { UTF8_FULL_CONDENSED } else { ASCII_FULL_CONDENSED };
+    let mut table = Table::new();
+    table
+        .load_preset(preset)
+        .set_content_arrangement(ContentArrangement::Dynamic);
+    table
+}
+
+/// Whether a grant appeared or ended since the previous refresh
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Change {
+    Added,
+    Ended,
+}
+
+impl Change {
+    fn ansi(self) -> &'static str {
+        match self {
+            Change::Added => ANSI_GREEN,
+            Change::Ended => ANSI_RED,
+        }
+    }
+
+    fn table_color(self) -> Color {
+        match self {
+            Change::Added => Color::Green,
+            Change::Ended => Color::Red,
+        }
+    }
+}
+
+/// How a listing changed since the previous `list --watch` refresh
+#[derive(Debug, Default)]
+struct ListChanges {
+    /// Grants listed now that were not listed before
+    added: HashSet<i64>,
+    /// Grants still listed that have expired, been revoked or run out of
+    /// uses since
+    ended: HashSet<i64>,
+    /// Grants listed before that no longer are
+    removed: Vec<PermissionGrant>,
+    /// Whether to colour the changes; off when stdout is not a terminal
+    color: bool,
+}
+
+impl ListChanges {
+    /// Compare the grants listed at `before_at` with those listed at `now`
+    fn between(
+        before: &[PermissionGrant],
+        before_at: DateTime<Utc>,
+        after: &[PermissionGrant],
+        now: DateTime<Utc>,
+    ) -> Self {
+        let before_by_id: HashMap<i64, &PermissionGrant> = before.iter().map(|g| (g.id, g)).collect();
+        let after_ids: HashSet<i64> = after.iter().map(|g| g.id).collect();
+        let live_at = |grant: &PermissionGrant, at: DateTime<Utc>| {
+            !grant.revoked && grant.expires_at > at && !grant.is_exhausted()
+        };
+
+        let mut changes = Self::default();
+        for grant in after {
+            match before_by_id.get(&grant.id) {
+                None => {
+                    changes.added.insert(grant.id);
+                }
+                Some(old) if live_at(old, before_at) && !live_at(grant, now) => {
+                    changes.ended.insert(grant.id);
+                }
+                Some(_) => {}
+            }
+        }
+        changes.removed = before.iter().filter(|g| !after_ids.contains(&g.id)).cloned().collect();
+        changes
+    }
+
+    /// How a listed grant changed, if it did
+    fn change_of(&self, id: i64) -> Option<Change> {
+        if self.added.contains(&id) {
+            Some(Change::Added)
+        } else if self.ended.contains(&id) {
+            Some(Change::Ended)
+        } else {
+            None
+        }
+    }
+
+    /// `text` coloured for `change`, or unchanged when colour is off
+    fn paint(&self, text: &str, change: Option<Change>) -> String {
+        match change {
+            Some(change) if self.color => format!("{}{}{}", change.ansi(), text, ANSI_RESET),
+            _ => text.to_string(),
+        }
+    }
+}
+
+/// Redraw the listing every `seconds` seconds until Ctrl-C. Nothing about
+/// the listing itself changes; each refresh is an ordinary `list`.
+async fn watch_permissions(
+    manager: &PermissionManager,
+    options: &ListOptions,
+    all: bool,
+    output: OutputFormat,
+    seconds: u64,
+) -> Result<()> {
+    let terminal = std::io::stdout().is_terminal();
+    let mut ticker = tokio::time::interval(std::time::Duration::from_secs(seconds));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let interrupt = tokio::signal::ctrl_c();
+    tokio::pin!(interrupt);
+
+    let mut previous: Option<(GrantPage, DateTime<Utc>)> = None;
+    loop {
+        tokio::select! {
+            // Polled first so the handler is installed before any refresh
+            biased;
+            result = &mut interrupt => {
+                result.map_err(|e| PermissionError::system_command(e, "ctrl-c handler"))?;
+                return Ok(());
+            }
+            _ = ticker.tick() => {}
+        }
+
+        let now = Utc::now();
+        let page = manager.list_permissions_page(options).await?;
+        let mut changes = match &previous {
+            Some((before, before_at)) => ListChanges::between(&before.grants, *before_at, &page.grants, now),
+            None => ListChanges::default(),
+        };
+        changes.color = Theme::current().color;
+
+        if terminal {
+            // Clear the screen and move the cursor home
+            print!("\x1b[2J\x1b[H");
+        }
+        println!("Every {}s, refreshed {} (Ctrl-C to stop)", seconds, now.format("%Y-%m-%d %H:%M:%S UTC"));
+        println!();
+        print_permissions(&page, options, all, output, Some(&changes))?;
+        std::io::stdout().flush().map_err(|e| PermissionError::system_command(e, "stdout"))?;
+
+        previous = Some((page, now));
+    }
+}
+
+/// Print one page of a listing, marking `changes` from a previous refresh
+fn print_permissions(
+    page: &GrantPage,
+    options: &ListOptions,
+    all: bool,
+    output: OutputFormat,
+    changes: Option<&ListChanges>,
+) -> Result<()> {
+    if output == OutputFormat::Json {
+        let json = serde_json::to_string_pretty(page)
+            .map_err(|e| PermissionError::Config(format!("Failed to serialize grants: {}", e)))?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    if page.total_count == 0 {
+        match &options.username {
+            Some(username) => println!("No permissions found for user {}", username),
+            None => println!("No permissions found"),
+        }
+        print_removed(changes);
+        return Ok(());
+    }
+
+    match &options.username {
+        Some(username) => println!("Permissions for user {}:", username),
+        None if all => println!("All permissions:"),
+        None => println!("Active permissions:"),
+    }
+    if page.grants.is_empty() {
+        println!("  No permissions at offset {} of {}", options.offset, page.total_count);
+        print_removed(changes);
+        return Ok(());
+    }
+    if output == OutputFormat::Table {
+        println!("{}", grants_table(&page.grants, changes));
+    } else {
+        for perm in &page.grants {
+            let heading = if options.username.is_some() {
+                format!("  Command: {}", perm.command)
+            } else {
+                format!("  {} {}", perm.username, perm.command)
+            };
+            match changes {
+                Some(changes) => println!("{}", changes.paint(&heading, changes.change_of(perm.id))),
+                None => println!("{}", heading),
+            }
+            println!("    Granted: {}", perm.granted_at);
+            if let Some(user) = &perm.on_behalf_of {
+                println!("    Granted by {} on behalf of {}", perm.granted_by, user);
+            }
+            if let Some(id) = &perm.correlation_id {
+                println!("    Correlation ID: {}", id);
+            }
+            if let Some(host) = &perm.host {
+                println!("    Host: {}", host);
+            }
+            if let Some(created_on) = &perm.created_host {
+                println!("    Created on: {}", created_on);
+            }
+            if let Some(version) = &perm.created_version {
+                println!("    Created by: permctl {}", version);
+            }
+            if perm.require_password {
+                println!("    Password: required");
+            }
+            if let Some(from) = perm.active_from.filter(|_| !perm.is_active_now()) {
+                println!("    Active from: {} (scheduled)", from);
+            }
+            println!("    Expires: {}", perm.expires_at);
+            if let Some(last_used) = perm.last_used {
+                println!("    Last used: {}", last_used);
+            }
+            if let (Some(max), Some(remaining)) = (perm.max_uses, perm.remaining_uses()) {
+                println!("    Uses: {} of {} ({} remaining)", perm.use_count, max, remaining);
+            }
+            if perm.revoked {
+                println!("    Revoked: {} by {}",
+                    perm.revoked_at.map(|t| t.to_string()).unwrap_or_default(),
+                    perm.revoked_by.as_deref().unwrap_or("unknown"));
+                if let Some(reason) = &perm.revoke_reason {
+                    println!("    Revoke reason: {}", reason);
+                }
+            } else if perm.expires_at <= Utc::now() {
+                println!("    Status: expired");
+            } else if perm.is_exhausted() {
+                println!("    Status: exhausted");
+            }
+            println!();
+        }
+    }
+
+    println!(
+        "Showing {}-{} of {}",
+        page.offset + 1,
+        page.offset + page.grants.len() as i64,
+        page.total_count
+    );
+    print_removed(changes);
+
+    Ok(())
+}
+
+/// List the grants that dropped out of the listing since the last refresh
+fn print_removed(changes: Option<&ListChanges>) {
+    let Some(changes) = changes.filter(|c| !c.removed.is_empty()) else {
+        return;
+    };
+    println!();
+    println!("No longer listed:");
+    for grant in &changes.removed {
+        let line = format!("  {} {}", grant.username, grant.command);
+        println!("{}", changes.paint(&line, Some(Change::Ended)));
+    }
+}
+
+/// Widest command path shown in a table before it is shortened
+const TABLE_COMMAND_WIDTH: usize = 40;
+
+/// Render grants as a table sized to the terminal
+fn grants_table(grants: &[PermissionGrant], changes: Option<&ListChanges>) -> Table {
+    let mut table = new_table();
+    table
+        .set_header(vec!["User", "Command", "Granted", "Expires", "Remaining", "Granted By"]);
+
+    for grant in grants {
+        let remaining = if grant.revoked {
+            "revoked".to_string()
+        } else if grant.expires_at <= Utc::now() {
+            "expired".to_string()
+        } else if grant.is_exhausted() {
+            "exhausted".to_string()
+        } else if !grant.is_active_now() {
+            "scheduled".to_string()
+        } else {
+            format_duration(grant.time_remaining())
+        };
+
+        let color = changes.filter(|c| c.color).and_then(|c| c.change_of(grant.id)).map(Change::table_color);
+        table.add_row([
+            grant.username.clone(),
+            truncate_command(&grant.command, TABLE_COMMAND_WIDTH),
+            grant.granted_at.format("%Y-%m-%d %H:%M").to_string(),
+            grant.expires_at.format("%Y-%m-%d %H:%M").to_string(),
+            remaining,
+            grant.granted_by.clone(),
+        ].map(|text| match color {
+            Some(color) => Cell::new(text).fg(color),
+            None => Cell::new(text),
+        }));
+    }
+
+    table
+}
+
+/// Shorten a command path to at most `width` characters, keeping the end
+/// (the binary name) and marking the cut with an ellipsis
+fn truncate_command(command: &str, width: usize) -> String {
+    let len = command.chars().count();
+    if len <= width {
+        return command.to_string();
+    }
+
+    let tail: String = command.chars().skip(len - (width - 1)).collect();
+    format!("…{}", tail)
+}
+
+async fn list_expiring(
+    manager: &PermissionManager,
+    minutes: i64,
+    user: Option<String>,
+) -> Result<()> {
+    let permissions = manager
+        .list_expiring(Duration::minutes(minutes), user.as_deref())
+        .await?;
+
+    if permissions.is_empty() {
+        println!("No permissions expiring within {} minutes", minutes);
+        return Ok(());
+    }
+
+    println!("Permissions expiring within {} minutes:", minutes);
+    for perm in permissions {
+        println!("  {} {}", perm.username, perm.command);
+        println!("    Expires: {} ({} remaining)", perm.expires_at, format_duration(perm.time_remaining()));
+    }
+
+    Ok(())
+}
+
+async fn list_active_during(
+    manager: &PermissionManager,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    user: Option<String>,
+    output: OutputFormat,
+) -> Result<()> {
+    let grants = manager.list_active_during(start, end, user.as_deref()).await?;
+
+    if output == OutputFormat::Json {
+        let json = serde_json::to_string_pretty(&grants)
+            .map_err(|e| PermissionError::Config(format!("Failed to serialize grants: {}", e)))?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    if grants.is_empty() {
+        println!("No permissions active between {} and {}", start, end);
+        return Ok(());
+    }
+
+    println!("Permissions active between {} and {}:", start, end);
+    if output == OutputFormat::Table {
+        println!("{}", grants_table(&grants, None));
+        return Ok(());
+    }
+    for perm in grants {
+        println!("  {} {}", perm.username, perm.command);
+        println!("    Active: {} to {}", perm.effective_start(), perm.effective_end());
+        if perm.revoked {
+            println!("    Revoked by {}", perm.revoked_by.as_deref().unwrap_or("unknown"));
+        }
+    }
+
+    Ok(())
+}
+
+async fn list_pending(manager: &PermissionManager) -> Result<()> {
+    let requests = manager.list_pending_requests().await?;
+
+    if requests.is_empty() {
+        println!("No pending grant requests");
+        return Ok(());
+    }
+
+    let now = Utc::now();
+    println!("Pending grant requests:");
+    for request in requests {
+        println!("  #{} {} {}", request.id, request.username, request.command);
+        match &request.on_behalf_of {
+            Some(user) => println!("    Requested by {} on behalf of {} at {}", request.requested_by, user, request.requested_at),
+            None => println!("    Requested by {} at {}", request.requested_by, request.requested_at),
+        }
+        println!("    Duration: {} minutes", request.duration_minutes);
+        if let Some(from) = request.active_from {
+            println!("    Active from: {}", from);
+        }
+        if let Some(reason) = &request.reason {
+            println!("    Reason: {}", reason);
+        }
+        println!("    Approvals: {}/{}", request.approvals, manager.required_approvals(&request));
+        match manager.review_deadline(&request) {
+            Some(deadline) if deadline > now => {
+                println!("    Review within: {} (until {})", format_duration(deadline - now), deadline);
+            }
+            Some(_) => println!("    Review within: expired, removed at next cleanup"),
+            None => {}
+        }
+    }
+
+    Ok(())
+}
+
+async fn approve_request(manager: &PermissionManager, id: i64) -> Result<()> {
+    let approved_by = invoking_user();
+
+    match manager.approve_request(id, &approved_by).await {
+        Ok(ApprovalOutcome::Recorded { request, required }) => {
+            success!("Approval of request {} recorded", id);
+            println!("  Approvals: {}/{}", request.approvals, required);
+            println!("  User: {}", request.username);
+            println!("  Command: {}", request.command);
+            Ok(())
+        }
+        Ok(ApprovalOutcome::Granted(grant)) => {
+            success!("Request {} approved", id);
+            println!("  Grant ID: {}", grant.id);
+            println!("  User: {}", grant.username);
+            println!("  Command: {}", grant.command);
+            if let Some(from) = grant.active_from {
+                println!("  Active from: {}", from);
+            }
+            println!("  Expires: {}", grant.expires_at);
+            Ok(())
+        }
+        Err(e) => {
+            failure!("Failed to approve request {}", id);
+            println!("  Error: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Format a duration as hours and minutes, e.g. `1h 05m`
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.num_minutes();
+    if minutes >= 60 {
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+/// Format a size in bytes with binary units, e.g. `1.5 MiB`
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// One-line summary of `grants` for `status`, naming each command by its
+/// file name with the time left rounded up to the minute
+fn status_line(grants: &[PermissionGrant], now: DateTime<Utc>) -> String {
+    if grants.is_empty() {
+        return "permctl: none".to_string();
+    }
+
+    let entries: Vec<String> = grants
+        .iter()
+        .map(|grant| {
+            let name = Path::new(&grant.command)
+                .file_name()
+                .map_or(grant.command.clone(), |name| name.to_string_lossy().into_owned());
+            let minutes = ((grant.expires_at - now).num_seconds().max(0) + 59) / 60;
+            let remaining = if minutes >= 60 {
+                format!("{}h{}m", minutes / 60, minutes % 60)
+            } else {
+                format!("{}m", minutes)
+            };
+            format!("{} {}", name, remaining)
+        })
+        .collect();
+    format!("permctl: {} active ({})", grants.len(), entries.join(", "))
+}
+
+async fn show_quota(manager: &PermissionManager, output: OutputFormat) -> Result<()> {
+    let report = manager.quota_report().await?;
+
+    match output {
+        OutputFormat::Json => {
+            let json = serde_json::to_string_pretty(&report)
+                .map_err(|e| PermissionError::Config(format!("Failed to serialize quota report: {}", e)))?;
+            println!("{}", json);
+        }
+        OutputFormat::Table => {
+            let mut commands = new_table();
+            commands
+                .set_header(vec!["Command", "Active", "Limit", "Status"]);
+            for quota in &report.commands {
+                let color = quota_color(quota).filter(|_| Theme::current().color).map(|(_, color)| color);
+                commands.add_row([
+                    truncate_command(&quota.command, TABLE_COMMAND_WIDTH),
+                    quota.active_users.to_string(),
+                    quota.max_concurrent_users.map(|limit| limit.to_string()).unwrap_or_else(|| "-".to_string()),
+                    quota_status(quota).to_string(),
+                ].map(|text| match color {
+                    Some(color) => Cell::new(text).fg(color),
+                    None => Cell::new(text),
+                }));
+            }
+            println!("{}", commands);
+
+            let mut users = new_table();
+            users
+                .set_header(vec![
+                    "User".to_string(),
+                    "Active".to_string(),
+                    format!("Granted ({}h)", manager.config().total_duration_window_hours),
+                    "Duration caps".to_string(),
+                ]);
+            for quota in &report.users {
+                let caps: Vec<String> = quota.total_duration
+                    .iter()
+                    .map(|cap| format!("{} {}/{}m", truncate_command(&cap.command, TABLE_COMMAND_WIDTH), cap.used_minutes, cap.limit_minutes))
+                    .collect();
+                users.add_row(vec![
+                    quota.username.clone(),
+                    quota.active_grants.to_string(),
+                    quota.recent_grants.to_string(),
+                    caps.join("\n"),
+                ]);
+            }
+            println!("{}", users);
+        }
+        OutputFormat::Text => {
+            let color = Theme::current().color;
+            println!("Commands:");
+            for quota in &report.commands {
+                let limit = quota.max_concurrent_users.map(|limit| limit.to_string()).unwrap_or_else(|| "-".to_string());
+                let mut line = format!("  {} {}/{}", quota.command, quota.active_users, limit);
+                if let Some((ansi, _)) = quota_color(quota) {
+                    line = format!("{} ({})", line, quota_status(quota));
+                    if color {
+                        line = format!("{}{}{}", ansi, line, ANSI_RESET);
+                    }
+                }
+                println!("{}", line);
+            }
+
+            println!();
+            if report.users.is_empty() {
+                println!("No users hold grants");
+            } else {
+                println!("Users:");
+            }
+            for quota in &report.users {
+                println!(
+                    "  {}: {} active, {} granted in the last {}h",
+                    quota.username, quota.active_grants, quota.recent_grants, manager.config().total_duration_window_hours
+                );
+                for cap in &quota.total_duration {
+                    println!("    {}: {} of {} minutes used", cap.command, cap.used_minutes, cap.limit_minutes);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Warning colour for a command at or near its concurrent user limit
+fn quota_color(quota: &CommandQuota) -> Option<(&'static str, Color)> {
+    if quota.at_limit() {
+        Some((ANSI_RED, Color::Red))
+    } else if quota.near_limit() {
+        Some((ANSI_YELLOW, Color::Yellow))
+    } else {
+        None
+    }
+}
+
+fn quota_status(quota: &CommandQuota) -> &'static str {
+    if quota.at_limit() {
+        "at limit"
+    } else if quota.near_limit() {
+        "near limit"
+    } else {
+        "ok"
+    }
+}
+
+fn show_commands(manager: &PermissionManager, verbose: bool, tag: Option<&str>) -> Result<()> {
+    println!("Allowed commands:");
+    
+    for (cmd, config) in &manager.config().allowed_commands {
+        if tag.is_some_and(|tag| !config.has_tag(tag)) {
+            continue;
+        }
+
+        if verbose {
+            println!("\n{}", cmd);
             println!("  Description: {}", config.description);
             println!("  Max duration: {} minutes", config.max_duration);
+            if let Some(min) = config.min_duration {
+                println!("  Min duration: {} minutes", min);
+            }
             println!("  Required groups: {}", config.required_groups.join(", "));
             if config.audit_usage {
                 println!("  Auditing: enabled");
             }
-            println!("  Max concurrent users: {}", config.max_concurrent_users);
-        } else {
-            println!("  {}", cmd);
+            println!("  Max concurrent users: {}", config.max_concurrent_users);
+            if let Some(total) = config.max_total_duration {
+                println!("  Max total duration: {} minutes per {} hours", total, manager.config().total_duration_window_hours);
+            }
+            if let Some(idle) = config.idle_timeout_minutes {
+                println!("  Idle timeout: {} minutes", idle);
+            }
+            if let Some(runas) = &config.runas {
+                println!("  Run as: {}", runas);
+            }
+            if config.require_password {
+                println!("  Password: required");
+            }
+            if config.requires_approval {
+                println!("  Approval: required");
+            }
+            if config.require_confirmation {
+                println!("  Confirmation: required");
+            }
+            if let Some(max_uses) = config.max_uses {
+                println!("  Max uses per grant: {}", max_uses);
+            }
+            if let Some(jitter) = config.expiry_jitter_minutes {
+                println!("  Expiry jitter: ±{} minutes", jitter);
+            }
+            if let Some(rounding) = config.expiry_round_to {
+                println!("  Expiry rounded to: {:?}", rounding);
+            }
+            if !config.tags.is_empty() {
+                println!("  Tags: {}", config.tags.join(", "));
+            }
+            if !config.conflicts_with.is_empty() {
+                println!("  Conflicts with: {}", config.conflicts_with.join(", "));
+            }
+        } else {
+            println!("  {}", cmd);
+        }
+    }
+
+    // Plain output is also used for shell completion, so templates are
+    // only listed in verbose mode
+    let mut templates: Vec<_> = manager.config().templates.iter()
+        .filter(|(_, template)| tag.is_none_or(|tag| template.policy.has_tag(tag)))
+        .collect();
+    if verbose && !templates.is_empty() {
+        templates.sort_by(|a, b| a.0.cmp(b.0));
+        println!("\nTemplates:");
+        for (name, template) in templates {
+            println!("\n{}: {}", name, template.command);
+            println!("  Description: {}", template.policy.description);
+            println!("  Max duration: {} minutes", template.policy.max_duration);
+            if let Some(min) = template.policy.min_duration {
+                println!("  Min duration: {} minutes", min);
+            }
+            for variable in template.placeholders() {
+                let values = template.variables.get(&variable).map(|v| v.join(", ")).unwrap_or_default();
+                println!("  {{{}}}: {}", variable, values);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn dump_grants(manager: &PermissionManager, format: DumpFormat) -> Result<()> {
+    let grants = manager.list_active_permissions(&ListOptions {
+        include_scheduled: true,
+        ..ListOptions::default()
+    }).await?;
+    print!("{}", render_dump(&grants, format)?);
+    Ok(())
+}
+
+async fn show_audit(manager: &PermissionManager, query: &AuditQuery, output: OutputFormat) -> Result<()> {
+    let entries = manager.audit_entries(query).await?;
+
+    match output {
+        OutputFormat::Json => {
+            println!("{}", render_audit_json(&entries)?);
+        }
+        OutputFormat::Table => {
+            let mut table = new_table();
+            table
+                .set_header(vec!["Time", "Action", "User", "Command", "Details"]);
+            for entry in &entries {
+                table.add_row(vec![
+                    entry.timestamp.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    entry.action.clone(),
+                    entry.username.clone(),
+                    truncate_command(&entry.command, TABLE_COMMAND_WIDTH),
+                    entry.summary().unwrap_or_default(),
+                ]);
+            }
+            println!("{}", table);
+        }
+        OutputFormat::Text => {
+            if entries.is_empty() {
+                println!("No audit entries found");
+            }
+            for entry in &entries {
+                println!("{} {} {} {}", entry.timestamp, entry.action, entry.username, entry.command);
+                if let Some(summary) = entry.summary() {
+                    println!("  {}", summary);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Render audit entries as pretty-printed JSON, with structured details
+/// nested as objects rather than strings
+fn render_audit_json(entries: &[AuditEntry]) -> Result<String> {
+    let entries: Vec<serde_json::Value> = entries
+        .iter()
+        .map(|entry| {
+            let details = entry.details.as_deref().map(|details| {
+                serde_json::from_str::<serde_json::Value>(details)
+                    .ok()
+                    .filter(serde_json::Value::is_object)
+                    .unwrap_or_else(|| serde_json::Value::from(details))
+            });
+            serde_json::json!({
+                "id": entry.id,
+                "timestamp": entry.timestamp,
+                "username": entry.username,
+                "command": entry.command,
+                "action": entry.action,
+                "details": details,
+            })
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&entries)
+        .map_err(|e| PermissionError::Config(format!("Failed to serialize audit entries: {}", e)))
+}
+
+/// Render grants in the structure `dump` emits for `format`
+fn render_dump(grants: &[PermissionGrant], format: DumpFormat) -> Result<String> {
+    let rendered = match format {
+        DumpFormat::Json => serde_json::to_string_pretty(grants)
+            .map(|json| json + "\n")
+            .map_err(|e| e.to_string()),
+        DumpFormat::Yaml => serde_yaml::to_string(grants).map_err(|e| e.to_string()),
+        DumpFormat::Ansible => {
+            let tasks: Vec<AnsibleTask> = grants
+                .iter()
+                .map(|grant| AnsibleTask {
+                    name: format!("Grant {} to {}", grant.command, grant.username),
+                    permctl: AnsibleGrant { state: "present", grant },
+                })
+                .collect();
+            serde_yaml::to_string(&tasks).map_err(|e| e.to_string())
         }
+    };
+    rendered.map_err(|e| PermissionError::Config(format!("Failed to serialize grants: {}", e)))
+}
+
+async fn sync_sudoers(manager: &PermissionManager) -> Result<()> {
+    let config = manager.config();
+    if !config.sudoers_enabled {
+        warning!("Sudoers management disabled; nothing to write");
+        return Ok(());
     }
 
+    let written = manager.refresh_sudoers().await?;
+    success!("Wrote {} rule(s) to {:?}", written, config.sudoers_path);
+    Ok(())
+}
+
+async fn set_maintenance(manager: &PermissionManager, enabled: bool) -> Result<()> {
+    let changed = manager.set_maintenance(enabled, &invoking_user()).await?;
+    match (enabled, changed) {
+        (true, true) => {
+            warning!("Maintenance mode on: no grant is honored until permctl maintenance off");
+        }
+        (true, false) => println!("Maintenance mode was already on"),
+        (false, true) => success!("Maintenance mode off: active grants are honored again"),
+        (false, false) => println!("Maintenance mode was already off"),
+    }
     Ok(())
 }
 
 async fn cleanup_expired(manager: &PermissionManager) -> Result<()> {
     let count = manager.cleanup_expired().await?;
     if count > 0 {
-        println!("✓ Cleaned up {} expired permission(s)", count);
+        success!("Cleaned up {} expired permission(s)", count);
     } else {
         println!("No expired permissions to clean up");
     }
+
+    let idle = manager.revoke_idle(&invoking_user()).await?;
+    if !idle.is_empty() {
+        success!("Revoked {} permission(s) left unused past their idle timeout", idle.len());
+    }
+
+    if let Some(purged) = manager.purge_revoked().await? {
+        success!("Purged {} revoked permission(s) past retention", purged);
+    }
+
+    let expired_requests = manager.expire_stale_requests().await?;
+    if !expired_requests.is_empty() {
+        success!("Expired {} grant request(s) left unapproved", expired_requests.len());
+    }
     Ok(())
 }
 
-fn initialize_config(force: bool) -> Result<()> {
-    let config_path = Config::default_config_path()?;
-    
+/// Tell the operator when cleanup next has something to do
+async fn report_next_expiry(manager: &PermissionManager) -> Result<()> {
+    match manager.next_expiry().await? {
+        Some(grant) => println!(
+            "Next expiry: {} for {} at {} (in {})",
+            grant.command,
+            grant.username,
+            grant.expires_at,
+            format_duration(grant.time_remaining())
+        ),
+        None => println!("No active grants"),
+    }
+    Ok(())
+}
+
+async fn cleanup_orphans(manager: &PermissionManager) -> Result<()> {
+    let revoked = manager.revoke_orphaned(&invoking_user()).await?;
+    if revoked.is_empty() {
+        println!("No grants held by deleted users");
+        return Ok(());
+    }
+
+    for grant in &revoked {
+        success!("Revoked {} for deleted user {}", grant.command, grant.username);
+    }
+    success!("Revoked {} permission(s) of deleted users", revoked.len());
+    Ok(())
+}
+
+async fn preview_orphans(manager: &PermissionManager) -> Result<()> {
+    let orphans = manager.orphaned_users().await?;
+    if orphans.is_empty() {
+        println!("No grants held by deleted users");
+    } else {
+        println!("Would revoke every grant of deleted users: {}", orphans.join(", "));
+    }
+    Ok(())
+}
+
+async fn cleanup_group_revalidation(manager: &PermissionManager) -> Result<()> {
+    let revoked = manager.revalidate_groups(&invoking_user()).await?;
+    for grant in &revoked {
+        success!("Revoked {} for {}: {}", grant.command, grant.username, grant.revoke_reason.as_deref().unwrap_or_default());
+    }
+    if !revoked.is_empty() {
+        success!("Revoked {} permission(s) of users no longer in required groups", revoked.len());
+    }
+    Ok(())
+}
+
+async fn preview_group_revalidation(manager: &PermissionManager) -> Result<()> {
+    for (grant, group) in manager.grants_failing_groups().await? {
+        println!("Would revoke {} for {}, who is no longer in {}", grant.command, grant.username, group);
+    }
+    Ok(())
+}
+
+async fn cleanup_duplicates(manager: &PermissionManager) -> Result<()> {
+    let revoked = manager.revoke_duplicates(&invoking_user()).await?;
+    if revoked.is_empty() {
+        println!("No duplicate active grants");
+        return Ok(());
+    }
+
+    for grant in &revoked {
+        success!("Revoked duplicate {} for {} expiring {}", grant.command, grant.username, grant.expires_at);
+    }
+    success!("Revoked {} duplicate permission(s)", revoked.len());
+    Ok(())
+}
+
+async fn preview_duplicates(manager: &PermissionManager) -> Result<()> {
+    let duplicates = manager.duplicate_grants().await?;
+    if duplicates.is_empty() {
+        println!("No duplicate active grants");
+    }
+    for (username, command, grants) in &duplicates {
+        println!("Would keep the latest-expiring of {} grants of {} for {}", grants, command, username);
+    }
+    Ok(())
+}
+
+async fn preview_cleanup(manager: &PermissionManager) -> Result<()> {
+    let expired = manager.count_expired().await?;
+    println!("Would revoke {} expired permission(s)", expired);
+
+    let idle = manager.idle_grants().await?.len();
+    if idle > 0 {
+        println!("Would revoke {} permission(s) left unused past their idle timeout", idle);
+    }
+
+    match manager.count_purgeable().await? {
+        Some(purgeable) => println!("Would purge {} revoked permission(s) past retention", purgeable),
+        None => println!("Purging is disabled (hard_delete_after_days not set)"),
+    }
+
+    let now = Utc::now();
+    let stale = manager
+        .list_pending_requests()
+        .await?
+        .iter()
+        .filter(|request| manager.review_deadline(request).is_some_and(|deadline| deadline <= now))
+        .count();
+    if stale > 0 {
+        println!("Would expire {} grant request(s) left unapproved", stale);
+    }
+
+    if expired > 0 || idle > 0 {
+        println!("The sudoers file would be regenerated without the expired entries");
+    }
+    Ok(())
+}
+
+fn initialize_config(config_path: &Path, force: bool, interactive: bool, presets: &[String]) -> Result<()> {
     if config_path.exists() && !force {
-        println!("! Configuration file already exists at {:?}", config_path);
+        warning!("Configuration file already exists at {:?}", config_path);
         println!("  Use --force to overwrite");
         return Ok(());
     }
 
-    let config = Config::default();
-    config.save_to(config_path.clone())?;
+    if interactive {
+        let config = prompt_config()?;
+        config.validate()?;
+        config.save_to(config_path)?;
+        success!("Created configuration at {:?}", config_path);
+        return Ok(());
+    }
+
+    let mut config = Config::detect();
+    config.apply_presets(presets)?;
+    config.save_to(config_path)?;
+    if config.allowed_commands.is_empty() {
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(config_path)
+            .map_err(|e| PermissionError::io_error(e, config_path.to_path_buf()))?;
+        file.write_all(EXAMPLE_COMMAND_COMMENT.as_bytes())
+            .map_err(|e| PermissionError::io_error(e, config_path.to_path_buf()))?;
+    }
 
-    println!("✓ Created default configuration at {:?}", config_path);
+    success!("Created default configuration at {:?}", config_path);
     println!("  Please review and customize before using");
     
     Ok(())
 }
 
+/// Commands offered during interactive setup: path, description, max
+/// duration in minutes and required groups
+const COMMON_COMMANDS: &[(&str, &str, i64, &str)] = &[
+    ("/usr/bin/docker", "Docker command access", 480, "docker"),
+    ("/usr/bin/systemctl", "Service management", 60, "wheel"),
+];
+
+/// Build a configuration from prompts, rejecting each bad answer as it is
+/// entered rather than after the whole questionnaire
+fn prompt_config() -> Result<Config> {
+    let theme = ColorfulTheme::default();
+    let defaults = Config::default();
+
+    let sudoers_path = prompt_path(&theme, "Sudoers file", &defaults.sudoers_path)?;
+    let db_path = prompt_path(&theme, "Database file", &defaults.db_path)?;
+    let log_path = prompt_path(&theme, "Access log file", &defaults.log_path)?;
+
+    let mut allowed_commands = HashMap::new();
+    for (command, description, max_duration, groups) in COMMON_COMMANDS {
+        let add = Confirm::with_theme(&theme)
+            .with_prompt(format!("Allow temporary access to {}?", command))
+            .default(true)
+            .interact()
+            .map_err(prompt_error)?;
+        if add {
+            let config = prompt_command(&theme, command, description, *max_duration, groups)?;
+            allowed_commands.insert(command.to_string(), config);
+        }
+    }
+
+    loop {
+        let more = Confirm::with_theme(&theme)
+            .with_prompt("Add another command?")
+            .default(false)
+            .interact()
+            .map_err(prompt_error)?;
+        if !more {
+            break;
+        }
+
+        let command: String = Input::with_theme(&theme)
+            .with_prompt("Command path")
+            .validate_with(|input: &String| check_absolute_path(input))
+            .interact_text()
+            .map_err(prompt_error)?;
+        let description: String = Input::with_theme(&theme)
+            .with_prompt("Description")
+            .interact_text()
+            .map_err(prompt_error)?;
+        let config = prompt_command(&theme, &command, &description, 60, "")?;
+        allowed_commands.insert(command, config);
+    }
+
+    Ok(Config {
+        allowed_commands,
+        sudoers_path,
+        db_path,
+        log_path,
+        ..defaults
+    })
+}
+
+fn prompt_path(theme: &ColorfulTheme, prompt: &str, default: &Path) -> Result<PathBuf> {
+    let path: String = Input::with_theme(theme)
+        .with_prompt(prompt)
+        .default(default.display().to_string())
+        .validate_with(|input: &String| check_absolute_path(input))
+        .interact_text()
+        .map_err(prompt_error)?;
+    Ok(PathBuf::from(path))
+}
+
+fn prompt_command(
+    theme: &ColorfulTheme,
+    command: &str,
+    description: &str,
+    max_duration: i64,
+    groups: &str,
+) -> Result<CommandConfig> {
+    let max_duration: i64 = Input::with_theme(theme)
+        .with_prompt(format!("Maximum duration for {} (minutes)", command))
+        .default(max_duration)
+        .validate_with(|input: &i64| if *input > 0 { Ok(()) } else { Err("must be positive") })
+        .interact_text()
+        .map_err(prompt_error)?;
+    let groups: String = Input::with_theme(theme)
+        .with_prompt(format!("Required groups for {} (comma separated)", command))
+        .default(groups.to_string())
+        .allow_empty(true)
+        .validate_with(|input: &String| parse_groups(input).map(|_| ()))
+        .interact_text()
+        .map_err(prompt_error)?;
+
+    Ok(CommandConfig {
+        description: description.to_string(),
+        max_duration,
+        required_groups: parse_groups(&groups).map_err(PermissionError::User)?,
+        audit_usage: true,
+        max_concurrent_users: 5,
+        ..Default::default()
+    })
+}
+
+fn check_absolute_path(input: &str) -> std::result::Result<(), String> {
+    if Path::new(input).is_absolute() {
+        Ok(())
+    } else {
+        Err(format!("{} is not an absolute path", input))
+    }
+}
+
+/// Parse a `NAME=VALUE` template variable
+fn parse_var(input: &str) -> std::result::Result<(String, String), String> {
+    match input.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!("{} is not in NAME=VALUE form", input)),
+    }
+}
+
+/// Parse a duration given as bare minutes or as numbers with `d`, `h` or
+/// `m` units, e.g. `90`, `1h30m` or `1.5h`, into a positive number of whole
+/// minutes no larger than `MAX_DURATION_MINUTES`
+fn parse_duration(input: &str) -> std::result::Result<i64, String> {
+    let minutes = parse_duration_minutes(input)?;
+    if minutes <= 0 {
+        return Err(format!("duration {:?} must be positive", input));
+    }
+    if minutes > MAX_DURATION_MINUTES {
+        return Err(format!("duration {:?} exceeds the maximum of {} minutes", input, MAX_DURATION_MINUTES));
+    }
+    Ok(minutes)
+}
+
+fn parse_duration_minutes(input: &str) -> std::result::Result<i64, String> {
+    let invalid = || format!("invalid duration {:?}; use minutes or units such as 90m, 1.5h, 1h30m or 1d", input);
+
+    if let Ok(minutes) = input.parse::<i64>() {
+        return Ok(minutes);
+    }
+
+    let mut rest = input.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut total = 0.0;
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(number_len);
+        let unit_len = tail.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+
+        let value: f64 = number.parse().map_err(|_| invalid())?;
+        let minutes_per_unit = match unit {
+            "d" => 24.0 * 60.0,
+            "h" => 60.0,
+            "m" => 1.0,
+            _ => return Err(invalid()),
+        };
+        total += value * minutes_per_unit;
+        rest = tail;
+    }
+
+    let minutes = total.round();
+    if (total - minutes).abs() > 1e-9 || minutes > i64::MAX as f64 {
+        return Err(format!("duration {:?} is not a whole number of minutes", input));
+    }
+    Ok(minutes as i64)
+}
+
+/// Split a comma separated group list, rejecting names that cannot be groups
+fn parse_groups(input: &str) -> std::result::Result<Vec<String>, String> {
+    input
+        .split(',')
+        .map(str::trim)
+        .filter(|group| !group.is_empty())
+        .map(|group| {
+            let valid = group
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+            if valid {
+                Ok(group.to_string())
+            } else {
+                Err(format!("{} is not a valid group name", group))
+            }
+        })
+        .collect()
+}
+
+fn prompt_error(e: dialoguer::Error) -> PermissionError {
+    PermissionError::User(format!("Interactive setup failed: {}", e))
+}
+
+fn check_config(path: Option<&str>) -> Result<bool> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => Config::default_config_path()?,
+    };
+
+    let config = match Config::parse_from(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            failure!("Failed to load {:?}", path);
+            println!("  Error: {}", e);
+            return Ok(false);
+        }
+    };
+
+    for warning in config.validation_warnings() {
+        warning!("{}", warning);
+    }
+
+    let errors = config.validation_errors();
+    if errors.is_empty() {
+        success!("Configuration {:?} is valid", path);
+        return Ok(true);
+    }
+
+    failure!("Found {} error(s) in {:?}", errors.len(), path);
+    for error in errors {
+        println!("  {}", error);
+    }
+    Ok(false)
+}
+
+/// Print the changes from the `old` configuration file to `new`
+fn show_config_diff(old: &Path, new: &Path, output: OutputFormat) -> Result<()> {
+    // The files are compared as written, without environment overrides
+    let diff = Config::parse_from(old)?.diff(&Config::parse_from(new)?);
+
+    if output == OutputFormat::Json {
+        let json = serde_json::to_string_pretty(&diff)
+            .map_err(|e| PermissionError::Config(format!("Failed to serialize config diff: {}", e)))?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    if diff.is_empty() {
+        println!("No differences between {:?} and {:?}", old, new);
+        return Ok(());
+    }
+    for command in &diff.added_commands {
+        println!("+ {}", command);
+    }
+    for command in &diff.removed_commands {
+        println!("- {}", command);
+    }
+    for (command, changes) in &diff.modified_commands {
+        println!("~ {}", command);
+        print_field_changes(changes);
+    }
+    if !diff.settings.is_empty() {
+        println!("Settings:");
+        print_field_changes(&diff.settings);
+    }
+    Ok(())
+}
+
+fn print_field_changes(changes: &[FieldChange]) {
+    let show = |value: &Option<serde_json::Value>| match value {
+        Some(value) => value.to_string(),
+        None => "(unset)".to_string(),
+    };
+    for change in changes {
+        println!("    {}: {} -> {}", change.field, show(&change.old), show(&change.new));
+    }
+}
+
+/// Print the doctor report and return the exit code for its worst result
+async fn run_doctor(config_path: &Path, output: OutputFormat, recover: bool, fix: bool) -> Result<i32> {
+    let recovery = if recover {
+        doctor::recover_database(config_path).await?
+    } else {
+        None
+    };
+    let fixed = if fix { doctor::fix_file_modes(config_path)? } else { Vec::new() };
+    if output != OutputFormat::Json {
+        for drift in &fixed {
+            println!("Fixed {:?}: {}", drift.path, drift.problems.join(", "));
+        }
+        if !fixed.is_empty() {
+            println!();
+        }
+        match &recovery {
+            Some(report) => {
+                println!("Rebuilt damaged database; original kept at {:?}", report.backup_path);
+                for table in &report.tables {
+                    println!("  {}: {} rows recovered, {} lost", table.name, table.copied, table.lost);
+                }
+                println!();
+            }
+            None if recover => println!("Database passed its integrity check; nothing to recover\n"),
+            None => {}
+        }
+    }
+
+    let results = doctor::run_checks(config_path).await;
+    let overall = doctor::overall_status(&results);
+
+    match output {
+        OutputFormat::Json => {
+            let report = serde_json::json!({ "status": overall, "checks": results, "recovery": recovery, "fixed": fixed });
+            let json = serde_json::to_string_pretty(&report)
+                .map_err(|e| PermissionError::Config(format!("Failed to serialize report: {}", e)))?;
+            println!("{}", json);
+        }
+        OutputFormat::Table => {
+            let mut table = new_table();
+            table
+                .set_header(vec!["Check", "Status", "Detail"]);
+            for result in &results {
+                table.add_row(vec![result.name, result.status.as_str(), &result.detail]);
+            }
+            println!("{}", table);
+            println!("Overall: {}", overall.as_str());
+        }
+        OutputFormat::Text => {
+            for result in &results {
+                println!("{}", Mark::from(result.status).line(format_args!("{}: {}", result.name, result.detail)));
+            }
+            let count = |status| results.iter().filter(|r| r.status == status).count();
+            println!(
+                "\nOverall: {} ({} passed, {} warnings, {} failed)",
+                overall.as_str(),
+                count(CheckStatus::Pass),
+                count(CheckStatus::Warn),
+                count(CheckStatus::Fail),
+            );
+        }
+    }
+
+    Ok(overall.exit_code())
+}
+
 async fn verify_setup(manager: &PermissionManager) -> Result<()> {
     println!("Verifying setup...");
 
     // Check sudoers file
-    if !manager.config().sudoers_path.exists() {
-        println!("✗ Sudoers file not found");
+    if !manager.config().sudoers_enabled {
+        warning!("Sudoers management disabled");
+    } else if !manager.config().sudoers_path.exists() {
+        failure!("Sudoers file not found");
         return Err(PermissionError::Config("Sudoers file not found".to_string()));
     }
 
     // Check database
-    // This will fail if database connection fails
-    manager.list_user_permissions("test").await?;
-    println!("✓ Database connection successful");
+    let health = manager.database_health().await?;
+    if !health.connected {
+        failure!("Database connection failed");
+        return Err(PermissionError::Config("Database connection failed".to_string()));
+    }
+    success!("Database connection successful");
+    manager.integrity_check().await?;
+    success!("Database integrity check passed");
+
+    if !health.schema_ok() {
+        failure!("Database schema incomplete, missing tables: {}", health.missing_tables.join(", "));
+        return Err(PermissionError::Config(format!(
+            "Database schema missing tables: {}",
+            health.missing_tables.join(", ")
+        )));
+    }
+    success!("Database schema intact");
+
+    if let Some(maintenance) = manager.maintenance().await? {
+        warning!("Maintenance mode on since {} ({}); no grant is honored", maintenance.since, maintenance.enabled_by);
+    }
+
+    let chain = manager.verify_audit_chain().await?;
+    if let Some(id) = chain.broken_at {
+        failure!("Audit log hash chain broken at entry {}", id);
+        return Err(PermissionError::Config(format!(
+            "Audit log hash chain broken at entry {}; entries were edited or removed",
+            id
+        )));
+    }
+    success!("Audit log hash chain intact ({} entries)", chain.verified);
+
+    // Check for a backward clock jump since the last recorded mutation
+    match manager.check_clock().await {
+        Ok(()) => success!("System clock consistent with recorded history"),
+        Err(e @ PermissionError::ClockAnomaly { .. }) => {
+            failure!("{}", e);
+            return Err(e);
+        }
+        Err(e) => return Err(e),
+    }
 
     // Check directories
-    for path in [
-        manager.config().sudoers_path.parent(),
-        manager.config().db_path.parent(),
-        manager.config().log_path.parent(),
-    ].iter().flatten() {
+    for path in manager.config().required_directories() {
         if !path.exists() {
-            println!("✗ Required directory not found: {:?}", path);
+            failure!("Required directory not found: {:?}", path);
             return Err(PermissionError::Config(format!(
                 "Required directory not found: {:?}", 
                 path
@@ -309,14 +2695,303 @@ async fn verify_setup(manager: &PermissionManager) -> Result<()> {
         }
     }
 
-    println!("✓ All directories present");
+    success!("All directories present");
+
+    if manager.config().sudoers_enabled {
+        let path = &manager.config().sudoers_path;
+        let problem = match doctor::sudoers_inclusion(Path::new(doctor::SUDOERS_MAIN), path) {
+            SudoersInclusion::Included => {
+                success!("Sudoers file is read by sudo");
+                None
+            }
+            SudoersInclusion::SkippedName => Some("sudo skips drop-in files whose names contain a '.' or end in '~'"),
+            SudoersInclusion::NotIncluded => Some("no #includedir or #include directive covers it"),
+            SudoersInclusion::Unknown(reason) => {
+                warning!("Could not verify that sudo reads {:?}: {}", path, reason);
+                None
+            }
+        };
+        if let Some(reason) = problem {
+            failure!("Sudoers file is not read by sudo: {}", reason);
+            return Err(PermissionError::Config(format!(
+                "{:?} is not read by sudo ({}); grants have no effect",
+                path, reason
+            )));
+        }
+    }
+
+    let orphans = manager.orphaned_users().await?;
+    if orphans.is_empty() {
+        success!("No grants held by deleted users");
+    } else {
+        warning!("Grants held by users that no longer exist: {}", orphans.join(", "));
+        println!("  Run permctl cleanup --orphans to revoke them");
+    }
+
+    let mut commands: Vec<&String> = manager.config().allowed_commands.keys().collect();
+    commands.sort();
+    let unsafe_targets: Vec<PermissionError> = commands
+        .into_iter()
+        .filter_map(|command| check_command_target(command).err())
+        .collect();
+    for e in &unsafe_targets {
+        if manager.config().reject_writable_commands {
+            failure!("{}", e);
+        } else {
+            warning!("{}", e);
+        }
+    }
+    match unsafe_targets.into_iter().next() {
+        None => success!("No command target is writable by users other than root"),
+        Some(e) if manager.config().reject_writable_commands => return Err(e),
+        Some(_) => {}
+    }
 
     // Verify current process permissions
     if !nix::unistd::Uid::effective().is_root() {
-        println!("! Warning: Not running as root");
+        warning!("Warning: Not running as root");
         println!("  Some operations may fail");
     }
 
-    println!("✓ Setup verification complete");
+    success!("Setup verification complete");
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    /// A live grant of `command` to alice, made by admin at 09:00 on
+    /// 2024-06-01
+    fn sample_grant(id: i64, command: &str, expires_at: &str) -> PermissionGrant {
+        PermissionGrant {
+            id,
+            username: "alice".to_string(),
+            command: command.to_string(),
+            granted_at: "2024-06-01T09:00:00Z".parse().unwrap(),
+            expires_at: expires_at.parse().unwrap(),
+            granted_by: "admin".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[tokio::test]
+    async fn test_users_under_sudo_can_request_and_approve() {
+        assert_eq!(sudo_invoker(false, Some("alice".to_string())), None);
+        assert_eq!(sudo_invoker(true, Some(String::new())), None);
+        let requester = sudo_invoker(true, Some("alice".to_string())).unwrap();
+        let approver = sudo_invoker(true, Some("bob".to_string())).unwrap();
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut config = Config {
+            allowed_commands: HashMap::new(),
+            db_path: temp_dir.path().join("test.db"),
+            sudoers_path: temp_dir.path().join("sudoers"),
+            log_path: temp_dir.path().join("test.log"),
+            sudoers_enabled: false,
+            ..Config::default()
+        };
+        config.allowed_commands.insert("/usr/bin/true".to_string(), CommandConfig {
+            description: "Test command".to_string(),
+            requires_approval: true,
+            ..Default::default()
+        });
+        let manager = PermissionManager::new(config).await.unwrap();
+
+        let outcome = manager.grant_permission("root", "/usr/bin/true", Duration::minutes(30), &requester).await.unwrap();
+        let GrantOutcome::Pending(request) = outcome else {
+            panic!("expected a pending request");
+        };
+        assert_eq!(request.requested_by, "alice");
+        let grant = manager.approve_request(request.id, &approver).await.unwrap().granted().unwrap();
+        assert_eq!(grant.granted_by, "bob");
+    }
+
+    #[test]
+    fn test_requires_root() {
+        let command = |args: &[&str]| Cli::parse_from([&["permctl"], args].concat()).command;
+
+        assert!(command(&["revoke", "alice", "/usr/bin/docker"]).requires_root());
+        assert!(command(&["cleanup"]).requires_root());
+        assert!(command(&["check", "--record", "alice", "/usr/bin/docker"]).requires_root());
+        assert!(command(&["compact"]).requires_root());
+        assert!(!command(&["cleanup", "--dry-run"]).requires_root());
+        assert!(!command(&["check", "alice", "/usr/bin/docker"]).requires_root());
+        assert!(!command(&["status"]).requires_root());
+        assert!(!command(&["list"]).requires_root());
+    }
+
+    #[test]
+    fn test_list_paging_rejects_negative_values() {
+        assert!(Cli::try_parse_from(["permctl", "list", "--limit", "-1"]).is_err());
+        assert!(Cli::try_parse_from(["permctl", "list", "--offset", "-5"]).is_err());
+        assert!(Cli::try_parse_from(["permctl", "list", "--limit", "10", "--offset", "20"]).is_ok());
+    }
+
+    #[test]
+    fn test_list_expiring_bounds() {
+        let max = MAX_DURATION_MINUTES.to_string();
+        let too_long = (MAX_DURATION_MINUTES + 1).to_string();
+        assert!(Cli::try_parse_from(["permctl", "list", "--expiring", "1"]).is_ok());
+        assert!(Cli::try_parse_from(["permctl", "list", "--expiring", &max]).is_ok());
+        assert!(Cli::try_parse_from(["permctl", "list", "--expiring", "0"]).is_err());
+        assert!(Cli::try_parse_from(["permctl", "list", "--expiring=-5"]).is_err());
+        assert!(Cli::try_parse_from(["permctl", "list", "--expiring", &too_long]).is_err());
+        assert!(Cli::try_parse_from(["permctl", "list", "--expiring", "9223372036854775807"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("480"), Ok(480));
+        assert_eq!(parse_duration("90m"), Ok(90));
+        assert_eq!(parse_duration("1.5h"), Ok(90));
+        assert_eq!(parse_duration("1h30m"), Ok(90));
+        assert_eq!(parse_duration("1d"), Ok(1440));
+        assert_eq!(parse_duration("2h"), Ok(120));
+
+        for nonsense in ["10x", "", "h", "1h30", "1..5h", "0.5m"] {
+            assert!(parse_duration(nonsense).is_err(), "{:?} should be rejected", nonsense);
+        }
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[test]
+    fn test_parse_duration_bounds() {
+        assert_eq!(parse_duration(&MAX_DURATION_MINUTES.to_string()), Ok(MAX_DURATION_MINUTES));
+
+        let max = i64::MAX.to_string();
+        for out_of_range in ["0", "0m", "-5", "-1h", max.as_str(), "99999999999999999999d"] {
+            assert!(parse_duration(out_of_range).is_err(), "{:?} should be rejected", out_of_range);
+        }
+    }
+
+    #[test]
+    fn test_render_dump() {
+        let grants = [PermissionGrant {
+            host: Some("db1".to_string()),
+            ..sample_grant(1, "/usr/bin/docker", "2024-06-01T10:00:00Z")
+        }];
+
+        let json: serde_json::Value = serde_json::from_str(&render_dump(&grants, DumpFormat::Json).unwrap()).unwrap();
+        assert_eq!(json[0]["host"], "db1");
+
+        let yaml: serde_yaml::Value = serde_yaml::from_str(&render_dump(&grants, DumpFormat::Yaml).unwrap()).unwrap();
+        assert_eq!(yaml[0]["username"], "alice");
+
+        let tasks: serde_yaml::Value = serde_yaml::from_str(&render_dump(&grants, DumpFormat::Ansible).unwrap()).unwrap();
+        assert_eq!(tasks[0]["name"], "Grant /usr/bin/docker to alice");
+        assert_eq!(tasks[0]["permctl"]["state"], "present");
+        assert_eq!(tasks[0]["permctl"]["expires_at"], "2024-06-01T10:00:00Z");
+    }
+
+    #[test]
+    fn test_render_audit_json() {
+        let entry = |details: &str| AuditEntry {
+            id: 1,
+            timestamp: "2024-06-01T09:00:00Z".parse().unwrap(),
+            username: "alice".to_string(),
+            command: "/usr/bin/docker".to_string(),
+            action: "grant".to_string(),
+            details: Some(details.to_string()),
+        };
+        let entries = [entry(r#"{"granted_by":"admin","duration_minutes":60}"#), entry("Granted by admin until then")];
+
+        let json: serde_json::Value = serde_json::from_str(&render_audit_json(&entries).unwrap()).unwrap();
+        assert_eq!(json[0]["details"]["granted_by"], "admin");
+        assert_eq!(json[0]["details"]["duration_minutes"], 60);
+        assert_eq!(json[1]["details"], "Granted by admin until then");
+    }
+
+    #[test]
+    fn test_prompt_validation() {
+        assert!(check_absolute_path("/etc/sudoers.d/permctl").is_ok());
+        assert!(check_absolute_path("sudoers.d/permctl").is_err());
+
+        assert_eq!(parse_groups(" docker, wheel ,").unwrap(), vec!["docker", "wheel"]);
+        assert!(parse_groups("").unwrap().is_empty());
+        assert!(parse_groups("docker, bad group").is_err());
+
+        assert_eq!(parse_var("service=nginx").unwrap(), ("service".to_string(), "nginx".to_string()));
+        assert!(parse_var("service").is_err());
+        assert!(parse_var("=nginx").is_err());
+    }
+
+    #[test]
+    fn test_list_changes_between() {
+        let grant = |id: i64, expires_at: &str, revoked: bool| PermissionGrant {
+            revoked,
+            ..sample_grant(id, &format!("/usr/bin/cmd{}", id), expires_at)
+        };
+        let before_at: DateTime<Utc> = "2024-06-01T09:30:00Z".parse().unwrap();
+        let now: DateTime<Utc> = "2024-06-01T10:30:00Z".parse().unwrap();
+
+        let before = [
+            grant(1, "2024-06-01T12:00:00Z", false),
+            grant(2, "2024-06-01T10:00:00Z", false),
+            grant(3, "2024-06-01T12:00:00Z", false),
+            grant(4, "2024-06-01T12:00:00Z", false),
+        ];
+        let after = [
+            grant(1, "2024-06-01T12:00:00Z", false),
+            grant(2, "2024-06-01T10:00:00Z", false),
+            grant(3, "2024-06-01T12:00:00Z", true),
+            grant(5, "2024-06-01T12:00:00Z", false),
+        ];
+
+        let changes = ListChanges::between(&before, before_at, &after, now);
+        assert_eq!(changes.change_of(1), None);
+        assert_eq!(changes.change_of(2), Some(Change::Ended));
+        assert_eq!(changes.change_of(3), Some(Change::Ended));
+        assert_eq!(changes.change_of(5), Some(Change::Added));
+        assert_eq!(changes.removed.iter().map(|g| g.id).collect::<Vec<_>>(), vec![4]);
+
+        assert_eq!(changes.paint("x", Some(Change::Added)), "x");
+        let colored = ListChanges { color: true, ..changes };
+        assert_eq!(colored.paint("x", Some(Change::Added)), "\x1b[32mx\x1b[0m");
+    }
+
+    #[test]
+    fn test_mark_rendering() {
+        let plain = Theme { color: false, unicode: false };
+        assert_eq!(Mark::Success.render(plain, "done"), "+ done");
+        assert_eq!(Mark::Failure.render(plain, "failed"), "x failed");
+        assert_eq!(Mark::from(CheckStatus::Warn).render(plain, "careful"), "! careful");
+
+        let fancy = Theme { color: true, unicode: true };
+        assert_eq!(Mark::Success.render(fancy, "done"), "\x1b[32m✓\x1b[0m done");
+    }
+
+    #[test]
+    fn test_status_line() {
+        let now: DateTime<Utc> = "2024-06-01T10:00:00Z".parse().unwrap();
+
+        assert_eq!(status_line(&[], now), "permctl: none");
+        let grants = [
+            sample_grant(1, "/usr/bin/docker", "2024-06-01T10:41:30Z"),
+            sample_grant(2, "/usr/bin/systemctl", "2024-06-01T11:03:00Z"),
+        ];
+        assert_eq!(status_line(&grants, now), "permctl: 2 active (docker 42m, systemctl 1h3m)");
+    }
+
+    #[test]
+    fn test_truncate_command() {
+        assert_eq!(truncate_command("/usr/bin/docker", 40), "/usr/bin/docker");
+
+        let long = "/opt/vendor/toolchain/current/bin/database-maintenance";
+        let short = truncate_command(long, 20);
+        assert_eq!(short.chars().count(), 20);
+        assert_eq!(short, "…atabase-maintenance");
+    }
+}
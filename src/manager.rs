@@ -1,61 +1,393 @@
-use std::process::Command;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::Arc;
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
-use chrono::{Utc, Duration};
-use tracing::{info, warn, error};
+use chrono::{DateTime, Utc, Duration};
+use nix::unistd::User;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::{debug, field, info, instrument, warn, Span};
 
-use crate::config::Config;
-use crate::db::{Database, PermissionGrant};
+use crate::config::{normalize_command, CommandConfig, Config, RegrantPolicy};
+use crate::db::{ApprovalOutcome, AuditChainReport, AuditEntry, AuditQuery, BatchGrant, Compaction, Database, DatabaseHealth, GrantConfirmation, GrantPage, GrantRequest, ListOptions, Maintenance, PermissionGrant, RequestStatus, SortField};
 use crate::error::{Result, PermissionError};
+use crate::identity::{IdentityProvider, SystemIdentity};
+use crate::check_cache::CheckCache;
+use crate::command_target::check_command_target;
+use crate::log_dedup::WarningDeduplicator;
+use crate::notify;
+
+/// How far the clock may fall behind the last recorded time before it is
+/// treated as an anomaly
+const CLOCK_SKEW_TOLERANCE_SECS: i64 = 300;
+
+/// Header written at the top of the sudoers file unless one is configured
+const DEFAULT_SUDOERS_HEADER: &str = "# This file is managed by permctl. Do not edit manually.\n";
+
+/// Host name that scopes a grant to every host sharing the database
+pub const ALL_HOSTS: &str = "*";
+
+/// Events buffered per subscriber before the oldest are dropped
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Compute when a grant of `duration` starting at `start` expires, failing
+/// instead of panicking when the result is beyond what a timestamp can hold
+fn expiry_after(start: DateTime<Utc>, duration: Duration) -> Result<DateTime<Utc>> {
+    start
+        .checked_add_signed(duration)
+        .ok_or_else(|| PermissionError::InvalidDuration("duration too large".to_string()))
+}
+
+/// Result of asking for a grant
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum GrantOutcome {
+    /// The permission was granted immediately
+    Granted(IssuedGrant),
+    /// The command requires approval and a request was recorded instead
+    Pending(GrantRequest),
+    /// The command requires confirmation; repeating the grant with this
+    /// token completes it
+    ConfirmationRequired(GrantConfirmation),
+}
+
+/// How close commands and users are to the limits on granting
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaReport {
+    pub commands: Vec<CommandQuota>,
+    pub users: Vec<UserQuota>,
+}
+
+/// Use of a command's concurrent user limit
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandQuota {
+    pub command: String,
+    /// Users holding live or scheduled grants, as counted against the limit
+    pub active_users: i64,
+    /// `None` for commands no longer in the configuration
+    pub max_concurrent_users: Option<usize>,
+}
+
+impl CommandQuota {
+    /// Whether no further user can be granted the command
+    pub fn at_limit(&self) -> bool {
+        self.max_concurrent_users.is_some_and(|limit| self.active_users >= limit as i64)
+    }
+
+    /// Whether the command is at or above 80% of its limit
+    pub fn near_limit(&self) -> bool {
+        self.max_concurrent_users.is_some_and(|limit| self.active_users * 5 >= limit as i64 * 4)
+    }
+}
+
+/// Grants held by a user and their use of cumulative duration caps
+#[derive(Debug, Clone, Serialize)]
+pub struct UserQuota {
+    pub username: String,
+    /// Live and scheduled grants
+    pub active_grants: i64,
+    /// Grants made within the last `total_duration_window_hours`
+    pub recent_grants: i64,
+    /// Commands with a `max_total_duration` the user has used some of
+    pub total_duration: Vec<DurationQuota>,
+}
+
+/// Minutes of a command granted to a user within the rolling window
+#[derive(Debug, Clone, Serialize)]
+pub struct DurationQuota {
+    pub command: String,
+    pub used_minutes: i64,
+    pub limit_minutes: i64,
+}
+
+/// A grant that was made, with how its terms came about. Serialised with
+/// the grant's own fields at the top level.
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedGrant {
+    #[serde(flatten)]
+    pub grant: PermissionGrant,
+    /// Whether a live grant of the command the user already held was
+    /// replaced, or extended under the `extend` regrant policy
+    pub replaced_existing: bool,
+    /// Seconds `expiry_jitter_minutes` moved the expiry by, negative if it
+    /// was brought forward
+    pub jitter_secs: i64,
+}
+
+impl IssuedGrant {
+    pub fn id(&self) -> i64 {
+        self.grant.id
+    }
+}
+
+impl GrantOutcome {
+    /// The grant, if it was made immediately
+    pub fn granted(self) -> Option<PermissionGrant> {
+        match self {
+            Self::Granted(issued) => Some(issued.grant),
+            Self::Pending(_) | Self::ConfirmationRequired(_) => None,
+        }
+    }
+}
+
+/// Optional terms of a grant. The default grant starts now, applies to the
+/// local host and records no reason.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GrantOptions<'a> {
+    /// Host the grant is scoped to: the local host when `None`, or every
+    /// host sharing the database for `ALL_HOSTS`
+    pub host: Option<&'a str>,
+    /// When the grant becomes active, immediately when `None`
+    pub active_from: Option<DateTime<Utc>>,
+    /// Have sudo ask for the user's own password even if the command's
+    /// configuration does not
+    pub require_password: bool,
+    /// Token completing a grant of a command with `require_confirmation`
+    pub confirmation: Option<&'a str>,
+    /// Why the grant is made, checked against the command's `reason_pattern`
+    pub reason: Option<&'a str>,
+    /// User the granter acts for, who must exist
+    pub on_behalf_of: Option<&'a str>,
+}
+
+/// Outcome of one of the checks a grant has to pass
+#[derive(Debug)]
+pub struct GrantCheck {
+    /// What was checked, e.g. "required groups"
+    pub name: &'static str,
+    pub result: Result<()>,
+}
+
+impl GrantCheck {
+    fn new(name: &'static str, result: Result<()>) -> Self {
+        Self { name, result }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// The first check that failed, in the order they were made
+fn first_failure(checks: Vec<GrantCheck>) -> Result<()> {
+    checks.into_iter().find_map(|check| check.result.err()).map_or(Ok(()), Err)
+}
+
+/// What happened to a grant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Granted,
+    Revoked,
+    Expired,
+}
+
+/// A change to a grant, published to subscribers as it happens
+#[derive(Debug, Clone, Serialize)]
+pub struct PermissionEvent {
+    pub kind: EventKind,
+    pub grant: PermissionGrant,
+}
 
 /// Core permission manager that handles all permission-related operations
 pub struct PermissionManager {
     config: Config,
     db: Database,
+    /// Name of this host, used to select host-scoped grants
+    hostname: String,
+    /// Rate limit for warnings that repeat during a sustained failure
+    warnings: Arc<WarningDeduplicator>,
+    /// Whether mutating operations are refused
+    read_only: bool,
+    /// Grants, revocations and expiries made through this manager
+    events: broadcast::Sender<PermissionEvent>,
+    /// Where user accounts and group memberships are looked up
+    identity: Box<dyn IdentityProvider>,
+    /// Recent positive permission checks, when `check_cache` is configured
+    check_cache: Option<CheckCache>,
 }
 
 impl PermissionManager {
     /// Create a new permission manager instance with the provided configuration
     pub async fn new(config: Config) -> Result<Self> {
-        // Validate the configuration before proceeding
+        Self::builder().config(config).build().await
+    }
+
+    /// Start configuring a manager for embedding in another program, with
+    /// control over the sudoers file, identity lookups and the database
+    pub fn builder() -> PermissionManagerBuilder {
+        PermissionManagerBuilder::default()
+    }
+
+    /// Open a manager over the database without preparing directories or
+    /// regenerating the sudoers file. Used by fast read paths such as
+    /// `permctl check`, which run on every sudo invocation.
+    pub async fn connect(config: Config) -> Result<Self> {
         config.validate()?;
 
-        // Set up the required directory structure
-        Self::setup_directories(&config)?;
+        let warnings = Self::warning_deduplicator(&config);
+        let db = Self::open_database(&config, &warnings).await?;
 
-        // Initialize the database connection
-        let db = Database::new(&config.db_path).await?;
-        
-        let manager = Self { config, db };
-        manager.initialize().await?;
-        
-        Ok(manager)
+        Self::from_parts(config, db, warnings, false)
     }
 
-    /// Initialize the permission manager and set up required components
-    async fn initialize(&self) -> Result<()> {
-        // Create and set up required directories
-        for path in [
-            self.config.sudoers_path.parent(),
-            self.config.db_path.parent(),
-            self.config.log_path.parent(),
-        ].iter().flatten() {
-            fs::create_dir_all(path)
-                .map_err(|e| PermissionError::io_error(e, path.to_path_buf()))?;
-            
-            // Set appropriate directory permissions
-            let mut perms = fs::metadata(path)?.permissions();
-            perms.set_mode(0o755);
-            fs::set_permissions(path, perms)
-                .map_err(|e| PermissionError::io_error(e, path.to_path_buf()))?;
+    /// Open a manager that can only query permissions. Nothing on disk is
+    /// created or changed, so tools such as dashboards can run without root
+    /// as long as the database is readable. Grants, revocations, cleanup and
+    /// sudoers refreshes fail with `PermissionError::PermissionDenied`.
+    pub async fn new_readonly(config: Config) -> Result<Self> {
+        config.validate()?;
+
+        let warnings = Self::warning_deduplicator(&config);
+        let db = Database::open_readonly_with_tuning(&config.db_path, config.db_tuning()).await?;
+
+        Self::from_parts(config, db, warnings, true)
+    }
+
+    /// Look up users and groups through `identity` instead of the host's
+    /// account databases
+    pub fn with_identity_provider(mut self, identity: impl IdentityProvider + 'static) -> Self {
+        self.identity = Box::new(identity);
+        self
+    }
+
+    /// Whether this manager refuses to change anything
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Refuse `action` on a read-only manager
+    fn ensure_writable(&self, action: &str) -> Result<()> {
+        if self.read_only {
+            return Err(PermissionError::PermissionDenied(format!(
+                "cannot {} through a read-only manager",
+                action
+            )));
+        }
+        Ok(())
+    }
+
+    fn warning_deduplicator(config: &Config) -> Arc<WarningDeduplicator> {
+        Arc::new(WarningDeduplicator::new(std::time::Duration::from_secs(config.log_dedup_secs)))
+    }
+
+    /// Assemble a manager around an opened database
+    fn from_parts(config: Config, db: Database, warnings: Arc<WarningDeduplicator>, read_only: bool) -> Result<Self> {
+        let check_cache = config.check_cache.as_ref().map(|cache| {
+            CheckCache::new(std::time::Duration::from_secs(cache.ttl_secs), cache.capacity)
+        });
+        Ok(Self {
+            config,
+            db,
+            hostname: local_hostname()?,
+            warnings,
+            read_only,
+            events: event_channel(),
+            identity: Box::new(SystemIdentity),
+            check_cache,
+        })
+    }
+
+    /// Open the database with any configured audit exporters attached
+    async fn open_database(config: &Config, warnings: &Arc<WarningDeduplicator>) -> Result<Database> {
+        let mut db = Database::new_with_tuning(&config.db_path, config.db_tuning())
+            .await?
+            .with_audit_detail(config.audit_detail)
+            .with_audit_file(&config.log_path, config.log_max_bytes, config.log_retention_days, warnings.clone());
+        if config.async_audit {
+            db = db.with_async_audit();
+        }
+        if let Some(syslog) = &config.syslog {
+            db = db.with_syslog(syslog, warnings.clone());
+        }
+        Ok(db)
+    }
+
+    /// Receive an event for every grant, revocation and expiry made through
+    /// this manager from now on. A subscriber that falls more than
+    /// `EVENT_CHANNEL_CAPACITY` events behind loses the oldest ones and is
+    /// told how many it missed.
+    pub fn subscribe(&self) -> broadcast::Receiver<PermissionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Send an event to current subscribers, if there are any, and to the
+    /// webhook configured for the grant's command
+    fn publish(&self, kind: EventKind, grant: &PermissionGrant) {
+        if let Some(cache) = &self.check_cache {
+            cache.invalidate(&grant.username, &grant.command);
         }
+        let event = PermissionEvent { kind, grant: grant.clone() };
+        if let Some(url) = self.config.notify_webhook_for(&grant.command) {
+            if let Err(e) = notify::send_webhook(url, &event) {
+                self.warn_deduplicated(&format!("Failed to send notification to {}: {}", url, e));
+            }
+        }
+        // Sending only fails when nobody is subscribed
+        let _ = self.events.send(event);
+    }
+
+    /// Tell `username` on their terminals what `grant` lets them run and
+    /// until when, if `user_notifications` is enabled. Best effort: a user
+    /// who is not logged in or refuses messages only causes a warning.
+    pub fn notify_user(&self, username: &str, grant: &PermissionGrant) {
+        if !self.config.user_notifications {
+            return;
+        }
+        if let Err(e) = notify::write_to_user(username, &notify::grant_message(grant)) {
+            self.warn_deduplicated(&format!("Failed to notify {} of their grant: {}", username, e));
+        }
+    }
+
+    /// Log a warning for a failure that may repeat on every tick, at most
+    /// once per `log_dedup_secs` for identical messages
+    pub fn warn_deduplicated(&self, message: &str) {
+        self.warnings.warn(message);
+    }
 
-        // Initialize sudoers file configuration
+    /// Bring the sudoers file in line with the database
+    async fn initialize(&self) -> Result<()> {
+        if !self.config.sudoers_enabled {
+            info!("Sudoers management is disabled; grants are recorded in the database only");
+        }
         self.update_sudoers_file().await?;
 
         Ok(())
     }
 
+    /// Check that the system clock has not jumped backwards.
+    ///
+    /// Expiry is a security boundary: if the clock moves back (an NTP step,
+    /// a restored VM snapshot), grants that already expired look active
+    /// again. The database records the latest time any mutation observed, and
+    /// a current time more than a few minutes behind it is reported as
+    /// `PermissionError::ClockAnomaly`.
+    pub async fn check_clock(&self) -> Result<()> {
+        let now = Utc::now();
+        if let Some(last_seen) = self.db.last_observed_time().await? {
+            if last_seen - now > Duration::seconds(CLOCK_SKEW_TOLERANCE_SECS) {
+                return Err(PermissionError::ClockAnomaly { now, last_seen });
+            }
+        }
+        Ok(())
+    }
+
+    /// Name of the host this manager renders sudoers rules for
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    /// Resolve the host a grant should be scoped to: the local host when none
+    /// is requested, or every host for `ALL_HOSTS`
+    pub fn grant_scope(&self, requested: Option<&str>) -> Option<String> {
+        match requested {
+            Some(ALL_HOSTS) => None,
+            Some(host) => Some(host.to_string()),
+            None => Some(self.hostname.clone()),
+        }
+    }
+
     /// Get a reference to the current configuration
     pub fn config(&self) -> &Config {
         &self.config
@@ -68,229 +400,2788 @@ impl PermissionManager {
         command: &str,
         duration: Duration,
         granted_by: &str,
-    ) -> Result<i64> {
-        // Validate command is allowed
-        let cmd_config = self.config.allowed_commands.get(command)
-            .ok_or_else(|| PermissionError::CommandNotAllowed(command.to_string()))?;
+    ) -> Result<GrantOutcome> {
+        self.grant_permission_from(username, command, None, duration, granted_by).await
+    }
+
+    /// Grant permission that becomes active at `active_from` (or immediately
+    /// when `None`) and lasts for `duration` from that point. Commands that
+    /// require approval are recorded as a pending request instead.
+    pub async fn grant_permission_from(
+        &self,
+        username: &str,
+        command: &str,
+        active_from: Option<DateTime<Utc>>,
+        duration: Duration,
+        granted_by: &str,
+    ) -> Result<GrantOutcome> {
+        self.grant_permission_on_host(username, command, duration, granted_by, GrantOptions { active_from, ..Default::default() }).await
+    }
 
-        // Validate duration
-        if duration > cmd_config.max_duration_as_duration() {
+    /// Grant permission that expires exactly at `expires_at`, which must be
+    /// in the future and no further from now than the command's
+    /// `max_duration`. No jitter is applied to the expiry.
+    pub async fn grant_permission_until(
+        &self,
+        username: &str,
+        command: &str,
+        expires_at: DateTime<Utc>,
+        granted_by: &str,
+    ) -> Result<GrantOutcome> {
+        let duration = expires_at - Utc::now();
+        if duration <= Duration::zero() {
             return Err(PermissionError::InvalidDuration(format!(
-                "Duration exceeds maximum allowed ({} minutes)",
-                cmd_config.max_duration
+                "Expiry time {} is in the past",
+                expires_at
             )));
         }
+        self.grant(username, command, duration, Some(expires_at), granted_by, GrantOptions::default()).await
+    }
 
-        // Validate user exists on system
-        if !self.user_exists(username)? {
-            return Err(PermissionError::UserNotFound(username.to_string()));
+    /// Grant permission on the terms in `options`, scoped to its `host` as
+    /// resolved by `grant_scope`.
+    ///
+    /// For commands with `require_confirmation`, a call without a
+    /// `confirmation` only issues a token; the grant is made when the same
+    /// request is repeated with that token before it expires. The token is
+    /// ignored for other commands.
+    ///
+    /// The `reason` must match the command's `reason_pattern`, if it has one,
+    /// and is recorded in the grant's audit entry.
+    ///
+    /// When `granted_by` acts for someone else, such as an automation account
+    /// granting on a person's request, `on_behalf_of` names that user. It is
+    /// recorded with the grant alongside `granted_by`.
+    pub async fn grant_permission_on_host(
+        &self,
+        username: &str,
+        command: &str,
+        duration: Duration,
+        granted_by: &str,
+        options: GrantOptions<'_>,
+    ) -> Result<GrantOutcome> {
+        let reason = options.reason.map(str::trim).filter(|r| !r.is_empty());
+        self.grant(username, command, duration, None, granted_by, GrantOptions { reason, ..options }).await
+    }
+
+    /// Run every check a grant of `command` to `username` would have to pass
+    /// without granting anything. Unlike a grant, a failed check does not
+    /// stop the ones after it, so every reason for a refusal is reported.
+    /// The concurrent user limit and the regrant policy, which are enforced
+    /// as the grant is stored, are checked against the grants held now.
+    pub async fn validate_grant(
+        &self,
+        username: &str,
+        command: &str,
+        duration: Duration,
+        granted_by: &str,
+        options: GrantOptions<'_>,
+    ) -> Vec<GrantCheck> {
+        let GrantOptions { host, active_from, reason, on_behalf_of, .. } = options;
+        let host = self.grant_scope(host);
+        let reason = reason.map(str::trim).filter(|r| !r.is_empty());
+        let command = normalize_command(command);
+        let command = command.as_str();
+        let mut checks = self.request_checks(username, active_from, duration).await;
+        if on_behalf_of.is_some() {
+            checks.push(GrantCheck::new("on behalf of", self.check_on_behalf_of(on_behalf_of)));
         }
+        checks.push(GrantCheck::new("delegation", self.check_delegation(granted_by, command).await));
 
-        // Check user group requirements
-        for group in &cmd_config.required_groups {
-            if !self.user_in_group(username, group)? {
-                return Err(PermissionError::GroupRequirementNotMet {
-                    user: username.to_string(),
-                    group: group.to_string(),
-                });
+        let (cmd_config, policy) = self.policy_checks(username, command, duration).await;
+        checks.extend(policy);
+        let Some(cmd_config) = cmd_config else { return checks };
+        checks.push(GrantCheck::new("reason", cmd_config.check_reason(reason)));
+
+        let limit = cmd_config.max_concurrent_users;
+        let concurrent = self.db.count_other_active_users(command, username).await.and_then(|active| {
+            if active >= limit as i64 {
+                Err(PermissionError::ConcurrentUserLimit { command: command.to_string(), limit })
+            } else {
+                Ok(())
+            }
+        });
+        checks.push(GrantCheck::new("concurrent users", concurrent));
+
+        if self.config.regrant_policy == RegrantPolicy::Reject {
+            let held = self.regrant_target(username, command, host.as_deref()).await.map(|_| ());
+            checks.push(GrantCheck::new("existing grant", held));
+        }
+
+        checks
+    }
+
+    /// Grant a single command for `duration`, expiring at `expires_at` when
+    /// given instead of after the jittered duration. Requests that end up
+    /// pending approval keep the reason but not the expiry; those pending
+    /// confirmation keep neither.
+    #[instrument(skip_all, fields(user = username, command = command, correlation_id = field::Empty))]
+    async fn grant(
+        &self,
+        username: &str,
+        command: &str,
+        duration: Duration,
+        expires_at: Option<DateTime<Utc>>,
+        granted_by: &str,
+        options: GrantOptions<'_>,
+    ) -> Result<GrantOutcome> {
+        let GrantOptions { host, active_from, require_password, confirmation, reason, on_behalf_of } = options;
+        let host = self.grant_scope(host);
+        let host = host.as_deref();
+        let command = normalize_command(command);
+        let command = command.as_str();
+        self.check_grant_request(username, active_from, duration).await?;
+        self.check_on_behalf_of(on_behalf_of)?;
+        self.check_delegation(granted_by, command).await?;
+
+        let cmd_config = self.check_grant_policy(username, command, duration).await?;
+        cmd_config.check_reason(reason)?;
+
+        if cmd_config.require_confirmation {
+            match confirmation {
+                None => {
+                    let ttl = Duration::seconds(self.config.confirmation_timeout_secs as i64);
+                    let confirmation = self.db.create_confirmation(
+                        username,
+                        command,
+                        host,
+                        active_from,
+                        duration,
+                        granted_by,
+                        require_password,
+                        ttl,
+                    ).await?;
+                    return Ok(GrantOutcome::ConfirmationRequired(confirmation));
+                }
+                Some(token) => {
+                    let confirmed = self.db.consume_confirmation(
+                        token,
+                        username,
+                        command,
+                        host,
+                        active_from,
+                        duration,
+                        granted_by,
+                        require_password,
+                    ).await?;
+                    if !confirmed {
+                        return Err(PermissionError::InvalidConfirmation(command.to_string()));
+                    }
+                }
             }
         }
 
-        // Calculate expiration time
-        let expires_at = Utc::now() + duration;
+        if cmd_config.requires_approval {
+            let request = self.db.create_grant_request(
+                username,
+                command,
+                host,
+                active_from,
+                duration,
+                granted_by,
+                require_password,
+                reason,
+                on_behalf_of,
+            ).await?;
+            return Ok(GrantOutcome::Pending(request));
+        }
+
+        let extends = self.regrant_target(username, command, host).await?;
+        let (expires_at, jitter) = match expires_at {
+            Some(expires_at) => (expires_at, Duration::zero()),
+            None => {
+                let start = active_from.unwrap_or_else(Utc::now);
+                self.grant_expiry(username, command, cmd_config, start, duration).await?
+            }
+        };
 
         // Grant permission in database
-        let id = self.db.grant_permission(username, command, expires_at, granted_by).await?;
+        let (grant, replaced_existing) = self.db.grant_permission_with_limit(
+            username,
+            command,
+            host,
+            active_from,
+            expires_at,
+            granted_by,
+            Some(cmd_config.max_concurrent_users),
+            cmd_config.max_uses,
+            require_password,
+            reason,
+            on_behalf_of,
+            extends,
+        ).await?;
+
+        if let Some(id) = &grant.correlation_id {
+            Span::current().record("correlation_id", id.as_str());
+        }
+        self.publish(EventKind::Granted, &grant);
 
         // Update sudoers configuration
         self.update_sudoers_file().await?;
 
         info!(
             "Granted permission: id={}, user={}, command={}, expires={}",
-            id, username, command, expires_at
+            grant.id, username, command, grant.expires_at
         );
 
-        Ok(id)
+        Ok(GrantOutcome::Granted(IssuedGrant { grant, replaced_existing, jitter_secs: jitter.num_seconds() }))
     }
 
-    /// Revoke permission from a user for a specific command
-    pub async fn revoke_permission(
+    /// Grant `username` every command in `commands` for `duration`, all or
+    /// nothing. Each command is checked against the grant policy first and
+    /// every failure is reported together in `PermissionError::BatchRejected`;
+    /// the grants are then made in a single transaction and the sudoers file
+    /// is rewritten once. Commands that require approval or confirmation
+    /// cannot be part of a batch, so a `confirmation` token is refused. The
+    /// `reason` is checked against each command's `reason_pattern` and
+    /// recorded with every grant, as is `on_behalf_of`.
+    #[instrument(skip_all, fields(user = username, commands = commands.len()))]
+    pub async fn grant_permissions_on_host(
         &self,
         username: &str,
-        command: &str,
-        revoked_by: &str,
-    ) -> Result<bool> {
-        // Revoke in database
-        let revoked = self.db.revoke_permission(username, command, revoked_by).await?;
+        commands: &[String],
+        duration: Duration,
+        granted_by: &str,
+        options: GrantOptions<'_>,
+    ) -> Result<Vec<PermissionGrant>> {
+        let GrantOptions { host, active_from, require_password, confirmation, reason, on_behalf_of } = options;
+        if confirmation.is_some() {
+            return Err(PermissionError::User(
+                "a confirmation token completes a single grant; grant commands that require confirmation on their own".to_string(),
+            ));
+        }
+        let reason = reason.map(str::trim).filter(|r| !r.is_empty());
+        let host = self.grant_scope(host);
+        let host = host.as_deref();
+        self.check_grant_request(username, active_from, duration).await?;
+        self.check_on_behalf_of(on_behalf_of)?;
 
-        if revoked {
-            // Update sudoers configuration
-            self.update_sudoers_file().await?;
-            info!("Revoked permission: user={}, command={}", username, command);
-        } else {
-            warn!("No active permission found to revoke: user={}, command={}", username, command);
+        let commands: Vec<String> = commands.iter().map(|command| normalize_command(command)).collect();
+        let mut unique: Vec<&str> = Vec::with_capacity(commands.len());
+        for command in &commands {
+            if !unique.contains(&command.as_str()) {
+                unique.push(command);
+            }
         }
 
-        Ok(revoked)
+        let mut configs = Vec::with_capacity(unique.len());
+        let mut failures = Vec::new();
+        for &command in &unique {
+            if let Err(e) = self.check_delegation(granted_by, command).await {
+                failures.push((command.to_string(), e));
+                continue;
+            }
+            let checked = match self.check_grant_policy(username, command, duration).await {
+                Ok(cmd_config) if cmd_config.requires_approval || cmd_config.require_confirmation => {
+                    Err(PermissionError::NotBatchable(command.to_string()))
+                }
+                Ok(cmd_config) => match unique.iter().find(|&&other| other != command && self.config.commands_conflict(command, other)) {
+                    Some(conflict) => Err(PermissionError::ConflictingGrant {
+                        command: command.to_string(),
+                        conflict: conflict.to_string(),
+                        correlation_id: None,
+                    }),
+                    None => Ok(cmd_config),
+                },
+                Err(e) => Err(e),
+            };
+            let checked = checked.and_then(|cmd_config| cmd_config.check_reason(reason).map(|()| cmd_config));
+            let checked = match checked {
+                Ok(cmd_config) => self.regrant_target(username, command, host).await.map(|extends| (cmd_config, extends)),
+                Err(e) => Err(e),
+            };
+            match checked {
+                Ok((cmd_config, extends)) => configs.push((command, cmd_config, extends)),
+                Err(e) => failures.push((command.to_string(), e)),
+            }
+        }
+        if !failures.is_empty() {
+            return Err(PermissionError::BatchRejected { failures });
+        }
+
+        let start = active_from.unwrap_or_else(Utc::now);
+        let mut batch = Vec::with_capacity(configs.len());
+        for (command, cmd_config, extends) in configs {
+            let (expires_at, _) = self.grant_expiry(username, command, cmd_config, start, duration).await?;
+            batch.push(BatchGrant {
+                command,
+                expires_at,
+                max_concurrent_users: Some(cmd_config.max_concurrent_users),
+                max_uses: cmd_config.max_uses,
+                require_password,
+                extends,
+            });
+        }
+
+        let grants = self.db.grant_permissions(username, host, active_from, granted_by, reason, on_behalf_of, &batch).await?;
+        for grant in &grants {
+            self.publish(EventKind::Granted, grant);
+        }
+
+        self.update_sudoers_file().await?;
+
+        Ok(grants)
     }
 
-    /// List all active permissions for a user
-    pub async fn list_user_permissions(&self, username: &str) -> Result<Vec<PermissionGrant>> {
-        self.db.list_user_permissions(username).await
+    /// Under `delegation_mode`, refuse to let `admin` grant a command they do
+    /// not hold on this host themselves and that `admin_commands` does not
+    /// list for them. Root may grant anything.
+    async fn check_delegation(&self, admin: &str, command: &str) -> Result<()> {
+        if !self.config.delegation_mode || self.config.admin_may_delegate(admin, command) {
+            return Ok(());
+        }
+        if matches!(User::from_name(admin), Ok(Some(user)) if user.uid.is_root()) {
+            return Ok(());
+        }
+        if self.db.check_permission_on_host(admin, command, Some(&self.hostname)).await? {
+            return Ok(());
+        }
+
+        Err(PermissionError::PermissionDenied(format!(
+            "{} may only grant commands they hold or that admin_commands lists for them, not {}",
+            admin, command
+        )))
     }
 
-    /// Clean up expired permissions
-    pub async fn cleanup_expired(&self) -> Result<u64> {
-        let count = self.db.cleanup_expired().await?;
-        if count > 0 {
-            self.update_sudoers_file().await?;
-            info!("Cleaned up {} expired permission(s)", count);
+    /// Refuse to record a grant as made on behalf of a user who does not exist
+    fn check_on_behalf_of(&self, on_behalf_of: Option<&str>) -> Result<()> {
+        let Some(user) = on_behalf_of else {
+            return Ok(());
+        };
+        self.validate_username(user)?;
+        match self.identity.user_exists(user)? {
+            true => Ok(()),
+            false => Err(PermissionError::UserNotFound(user.to_string())),
         }
-        Ok(count)
     }
 
-    /// Update the sudoers file with current permissions
-    async fn update_sudoers_file(&self) -> Result<()> {
-        let header = "# This file is managed by permctl. Do not edit manually.\n\n";
-        let mut content = String::from(header);
+    /// Checks shared by every way of making a grant, before any command's
+    /// policy is considered
+    async fn check_grant_request(
+        &self,
+        username: &str,
+        active_from: Option<DateTime<Utc>>,
+        duration: Duration,
+    ) -> Result<()> {
+        self.ensure_writable("grant permissions")?;
+        first_failure(self.request_checks(username, active_from, duration).await)
+    }
 
-        // Get all active permissions
-        let all_permissions = self.db.list_active_permissions().await?;
+    /// Each of the checks made by `check_grant_request` other than the
+    /// manager being writable
+    async fn request_checks(
+        &self,
+        username: &str,
+        active_from: Option<DateTime<Utc>>,
+        duration: Duration,
+    ) -> Vec<GrantCheck> {
+        let mut checks = vec![GrantCheck::new("username", self.validate_username(username))];
 
-        // Group permissions by user for better organization
-        use std::collections::HashMap;
-        let mut user_permissions: HashMap<String, Vec<String>> = HashMap::new();
+        let positive = if duration <= Duration::zero() {
+            Err(PermissionError::InvalidDuration("Duration must be positive".to_string()))
+        } else {
+            Ok(())
+        };
+        checks.push(GrantCheck::new("duration", positive));
 
-        for grant in all_permissions {
-            user_permissions
-                .entry(grant.username)
-                .or_default()
-                .push(grant.command);
+        if let Some(from) = active_from {
+            let future = if from < Utc::now() {
+                Err(PermissionError::InvalidDuration(format!("Start time {} is in the past", from)))
+            } else {
+                Ok(())
+            };
+            checks.push(GrantCheck::new("start time", future));
         }
 
-        // Build sudoers content
-        for (username, commands) in user_permissions {
-            for command in commands {
-                content.push_str(&format!(
-                    "{} ALL=(ALL) NOPASSWD: {}\n",
-                    username, command
-                ));
-            }
+        if self.config.strict_clock {
+            checks.push(GrantCheck::new("clock", self.check_clock().await));
         }
 
-        // Write to temporary file first
-        let temp_path = self.config.sudoers_path.with_extension("tmp");
-        fs::write(&temp_path, content.as_bytes())
-            .map_err(|e| PermissionError::io_error(e, temp_path.clone()))?;
+        checks
+    }
 
-        // Set correct permissions (0440)
-        let mut perms = fs::metadata(&temp_path)?.permissions();
-        perms.set_mode(0o440);
-        fs::set_permissions(&temp_path, perms)
-            .map_err(|e| PermissionError::io_error(e, temp_path.clone()))?;
+    /// Approve a pending request for a command that requires approval.
+    /// The grant is only made once the command's `required_approvals`
+    /// distinct operators have approved; until then the approval is
+    /// recorded and the request stays pending.
+    ///
+    /// The grant policy is checked again at approval time, since group
+    /// membership or cumulative usage may have changed while it was pending.
+    /// The grant lasts the requested duration from the final approval, or
+    /// from the requested start time if that is still in the future.
+    pub async fn approve_request(&self, id: i64, approved_by: &str) -> Result<ApprovalOutcome> {
+        self.ensure_writable("approve requests")?;
+        let request = self.db.get_grant_request(id).await?
+            .ok_or(PermissionError::RequestNotFound(id))?;
+        if request.status == RequestStatus::Pending
+            && self.review_deadline(&request).is_some_and(|deadline| deadline <= Utc::now())
+        {
+            return Err(PermissionError::AccessExpired);
+        }
 
-        // Move temporary file to final location
-        fs::rename(&temp_path, &self.config.sudoers_path)
-            .map_err(|e| PermissionError::io_error(e, self.config.sudoers_path.clone()))?;
+        if self.config.strict_clock {
+            self.check_clock().await?;
+        }
 
-        Ok(())
-    }
+        self.check_delegation(approved_by, &request.command).await?;
+        let cmd_config = self.check_grant_policy(&request.username, &request.command, request.duration()).await?;
+        if !cmd_config.requires_approval {
+            return Err(PermissionError::ApprovalNotRequired(request.command));
+        }
+
+        let now = Utc::now();
+        let active_from = request.active_from.filter(|from| *from > now);
+        let start = active_from.unwrap_or(now);
+        let (expires_at, _) = self.grant_expiry(&request.username, &request.command, cmd_config, start, request.duration()).await?;
+        let extends = self.regrant_target(&request.username, &request.command, request.host.as_deref()).await?;
 
-    /// Check if a user exists on the system
-    fn user_exists(&self, username: &str) -> Result<bool> {
-        let output = Command::new("id")
-            .arg(username)
-            .output()
-            .map_err(|e| PermissionError::system_command(e, "id"))?;
+        let outcome = self.db.approve_grant_request(
+            id,
+            approved_by,
+            cmd_config.required_approvals,
+            active_from,
+            expires_at,
+            Some(cmd_config.max_concurrent_users),
+            cmd_config.max_uses,
+            extends,
+        ).await?;
+        if let ApprovalOutcome::Granted(grant) = &outcome {
+            self.publish(EventKind::Granted, grant);
+            self.update_sudoers_file().await?;
+        }
 
-        Ok(output.status.success())
+        Ok(outcome)
     }
 
-    /// Check if a user is member of a group
-    fn user_in_group(&self, username: &str, group: &str) -> Result<bool> {
-        let output = Command::new("groups")
-            .arg(username)
-            .output()
-            .map_err(|e| PermissionError::system_command(e, "groups"))?;
+    /// Number of distinct approvals `request` needs before it is granted,
+    /// under the current configuration
+    pub fn required_approvals(&self, request: &GrantRequest) -> u32 {
+        self.config
+            .command_config(&request.command)
+            .map_or(1, |cmd_config| cmd_config.required_approvals)
+    }
 
-        let groups = String::from_utf8_lossy(&output.stdout);
-        Ok(groups.split_whitespace().any(|g| g == group))
+    /// Look up a grant by id, whatever its state
+    pub async fn get_grant(&self, id: i64) -> Result<Option<PermissionGrant>> {
+        self.db.get_grant(id).await
     }
 
-    /// Set up required directories with appropriate permissions
-    fn setup_directories(config: &Config) -> Result<()> {
-        let dirs = [
-            config.db_path.parent(),
-            config.log_path.parent(),
-            config.sudoers_path.parent(),
-        ];
+    /// List requests waiting for approval, oldest first
+    pub async fn list_pending_requests(&self) -> Result<Vec<GrantRequest>> {
+        self.db.list_pending_requests().await
+    }
 
-        for dir in dirs.iter().flatten() {
-            fs::create_dir_all(dir)
-                .map_err(|e| PermissionError::io_error(e, dir.to_path_buf()))?;
-            
-            let mut perms = fs::metadata(dir)?.permissions();
-            perms.set_mode(0o755);
-            fs::set_permissions(dir, perms)
-                .map_err(|e| PermissionError::io_error(e, dir.to_path_buf()))?;
+    /// When a pending request expires unapproved, or `None` if
+    /// `approval_request_ttl_minutes` is not set
+    pub fn review_deadline(&self, request: &GrantRequest) -> Option<DateTime<Utc>> {
+        self.config
+            .approval_request_ttl_minutes
+            .map(|minutes| request.requested_at + Duration::minutes(minutes as i64))
+    }
+
+    /// Mark requests left pending longer than `approval_request_ttl_minutes`
+    /// as expired so they can no longer be approved
+    pub async fn expire_stale_requests(&self) -> Result<Vec<GrantRequest>> {
+        self.ensure_writable("expire grant requests")?;
+        match self.config.approval_request_ttl_minutes {
+            Some(minutes) => self.db.expire_pending_requests(Utc::now() - Duration::minutes(minutes as i64)).await,
+            None => Ok(Vec::new()),
         }
+    }
 
-        Ok(())
+    /// Check that `username` may be granted `command` for `duration` under
+    /// the configured policy, returning the command's configuration
+    async fn check_grant_policy(
+        &self,
+        username: &str,
+        command: &str,
+        duration: Duration,
+    ) -> Result<&CommandConfig> {
+        let (cmd_config, checks) = self.policy_checks(username, command, duration).await;
+        first_failure(checks)?;
+        cmd_config.ok_or_else(|| PermissionError::CommandNotAllowed(format!("{:?}", command)))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::TempDir;
-    use std::collections::HashMap;
+    /// Each of the checks made by `check_grant_policy`, along with the
+    /// command's configuration if it is allowed at all. Checks that depend
+    /// on the configuration are left out for commands that are not.
+    async fn policy_checks(
+        &self,
+        username: &str,
+        command: &str,
+        duration: Duration,
+    ) -> (Option<&CommandConfig>, Vec<GrantCheck>) {
+        let cmd_config = self.config.command_config(command);
+        let mut checks = vec![GrantCheck::new(
+            "command allowed",
+            cmd_config.map(|_| ()).ok_or_else(|| PermissionError::CommandNotAllowed(format!("{:?}", command))),
+        )];
 
-    async fn create_test_manager() -> (PermissionManager, TempDir) {
-        let temp_dir = TempDir::new().unwrap();
-        
-        let mut config = Config {
-            allowed_commands: HashMap::new(),
-            sudoers_path: temp_dir.path().join("sudoers"),
-            db_path: temp_dir.path().join("test.db"),
-            log_path: temp_dir.path().join("test.log"),
+        if let Some(cmd_config) = cmd_config {
+            // Granting a target users can edit would hand out arbitrary root access
+            let target = match check_command_target(command) {
+                Err(e) if self.config.reject_writable_commands => Err(e),
+                Err(e) => {
+                    self.warn_deduplicated(&e.to_string());
+                    Ok(())
+                }
+                Ok(()) => Ok(()),
+            };
+            checks.push(GrantCheck::new("command target", target));
+
+            let limits = if duration > cmd_config.max_duration_as_duration() {
+                Err(PermissionError::InvalidDuration(format!(
+                    "Duration exceeds maximum allowed ({} minutes)",
+                    cmd_config.max_duration
+                )))
+            } else {
+                match cmd_config.min_duration {
+                    Some(min) if duration < Duration::minutes(min) => Err(PermissionError::InvalidDuration(format!(
+                        "Duration is below the minimum allowed ({} minutes)",
+                        min
+                    ))),
+                    _ => Ok(()),
+                }
+            };
+            checks.push(GrantCheck::new("duration limits", limits));
+
+            // Enforce the cumulative cap so re-grants cannot extend access indefinitely
+            if let Some(limit) = cmd_config.max_total_duration {
+                checks.push(GrantCheck::new("total duration", self.check_total_duration(username, command, duration, limit).await));
+            }
+        }
+
+        // Enforce separation of duties between mutually exclusive commands.
+        // Exhausted grants can no longer be used, so they do not conflict.
+        let conflicts = self.db.list_user_permissions(username).await.and_then(|held| {
+            match held.iter().find(|grant| !grant.is_exhausted() && self.config.commands_conflict(command, &grant.command)) {
+                Some(conflict) => Err(PermissionError::ConflictingGrant {
+                    command: command.to_string(),
+                    conflict: conflict.command.clone(),
+                    correlation_id: conflict.correlation_id.clone(),
+                }),
+                None => Ok(()),
+            }
+        });
+        checks.push(GrantCheck::new("separation of duties", conflicts));
+
+        // Validate user exists on system
+        let exists = self.identity.user_exists(username).and_then(|exists| match exists {
+            true => Ok(()),
+            false => Err(PermissionError::UserNotFound(username.to_string())),
+        });
+        checks.push(GrantCheck::new("user exists", exists));
+
+        // Check user group requirements
+        if let Some(cmd_config) = cmd_config {
+            if !cmd_config.required_groups.is_empty() {
+                checks.push(GrantCheck::new("required groups", self.check_required_groups(username, cmd_config)));
+            }
+        }
+
+        (cmd_config, checks)
+    }
+
+    /// Apply the regrant policy to a grant of `command` to `username` on
+    /// `host`, which a grant the user already holds there would stand in the
+    /// way of. Returns the held grant's id if it is to be extended rather
+    /// than replaced, and fails with `GrantExists` if it may not be touched.
+    /// Grants on other hosts are left alone either way.
+    async fn regrant_target(&self, username: &str, command: &str, host: Option<&str>) -> Result<Option<i64>> {
+        let Some(held) = self.db.held_grant(username, command, host).await? else {
+            return Ok(None);
+        };
+        match self.config.regrant_policy {
+            RegrantPolicy::Replace => Ok(None),
+            RegrantPolicy::Extend => Ok(Some(held.id)),
+            RegrantPolicy::Reject => Err(PermissionError::GrantExists {
+                username: username.to_string(),
+                command: command.to_string(),
+                expires_at: held.expires_at,
+                correlation_id: held.correlation_id,
+            }),
+        }
+    }
+
+    /// When a grant of `command` requested for `duration` from `start`
+    /// expires, spread out by any configured jitter and rounded to any
+    /// configured boundary, along with the jitter applied. Both can lengthen
+    /// the grant, so neither may take it past what is left of
+    /// `max_total_duration`, and the duration it ends up with is checked
+    /// against it again.
+    async fn grant_expiry(
+        &self,
+        username: &str,
+        command: &str,
+        cmd_config: &CommandConfig,
+        start: DateTime<Utc>,
+        duration: Duration,
+    ) -> Result<(DateTime<Utc>, Duration)> {
+        let jittered = cmd_config.jittered_duration(duration, &mut rand::thread_rng());
+        let Some(limit) = cmd_config.max_total_duration else {
+            let expires_at = cmd_config.rounded_expiry(start, expiry_after(start, jittered)?, duration);
+            return Ok((expires_at, jittered - duration));
+        };
+
+        let used = self.total_granted_minutes(username, command).await?;
+        let remaining = Duration::try_minutes(limit.saturating_sub(used)).unwrap_or(Duration::MAX).max(duration);
+        let jittered = jittered.min(remaining);
+        let unrounded = expiry_after(start, jittered)?;
+        let expires_at = match cmd_config.rounded_expiry(start, unrounded, duration) {
+            rounded if rounded - start > remaining => unrounded,
+            rounded => rounded,
+        };
+        let requested = (expires_at - start).num_minutes();
+        if used.saturating_add(requested) > limit {
+            return Err(PermissionError::TotalDurationExceeded { used, requested, limit });
+        }
+        Ok((expires_at, jittered - duration))
+    }
+
+    /// Minutes of `command` granted to `username` within the window
+    /// `max_total_duration` is enforced over
+    async fn total_granted_minutes(&self, username: &str, command: &str) -> Result<i64> {
+        let since = Utc::now() - self.config.total_duration_window();
+        self.db.total_granted_minutes(username, command, since).await
+    }
+
+    async fn check_total_duration(
+        &self,
+        username: &str,
+        command: &str,
+        duration: Duration,
+        limit: i64,
+    ) -> Result<()> {
+        let used = self.total_granted_minutes(username, command).await?;
+        let requested = duration.num_minutes();
+        if used.saturating_add(requested) > limit {
+            return Err(PermissionError::TotalDurationExceeded { used, requested, limit });
+        }
+        Ok(())
+    }
+
+    fn check_required_groups(&self, username: &str, cmd_config: &CommandConfig) -> Result<()> {
+        for group in &cmd_config.required_groups {
+            if !self.identity.user_in_group(username, group)? {
+                return Err(PermissionError::GroupRequirementNotMet {
+                    user: username.to_string(),
+                    group: group.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Revoke permission from a user for a specific command
+    pub async fn revoke_permission(
+        &self,
+        username: &str,
+        command: &str,
+        revoked_by: &str,
+    ) -> Result<bool> {
+        self.revoke_permission_with_reason(username, command, revoked_by, None).await
+    }
+
+    /// Revoke permission, recording why it was revoked. The reason is
+    /// mandatory when `require_reason` is set in the configuration.
+    pub async fn revoke_permission_with_reason(
+        &self,
+        username: &str,
+        command: &str,
+        revoked_by: &str,
+        reason: Option<&str>,
+    ) -> Result<bool> {
+        self.ensure_writable("revoke permissions")?;
+        let reason = reason.map(str::trim).filter(|r| !r.is_empty());
+        if self.config.require_reason && reason.is_none() {
+            return Err(PermissionError::ReasonRequired("revoke a permission".to_string()));
+        }
+        let command = normalize_command(command);
+        let command = command.as_str();
+
+        // Revoke in database, on every host the user holds the command
+        let revoked = self.db.revoke_grant(username, command, revoked_by, reason).await?;
+
+        if revoked.is_empty() {
+            warn!("No active permission found to revoke: user={}, command={}", username, command);
+        } else {
+            for grant in &revoked {
+                self.publish(EventKind::Revoked, grant);
+            }
+            // Update sudoers configuration
+            self.update_sudoers_file().await?;
+            info!("Revoked permission: user={}, command={}", username, command);
+        }
+
+        Ok(!revoked.is_empty())
+    }
+
+    /// Revoke the grant with the given id, returning it as revoked, or
+    /// `None` if it does not exist or is no longer live. The reason is
+    /// mandatory when `require_reason` is set in the configuration.
+    pub async fn revoke_grant_by_id(
+        &self,
+        id: i64,
+        revoked_by: &str,
+        reason: Option<&str>,
+    ) -> Result<Option<PermissionGrant>> {
+        self.ensure_writable("revoke permissions")?;
+        let reason = reason.map(str::trim).filter(|r| !r.is_empty());
+        if self.config.require_reason && reason.is_none() {
+            return Err(PermissionError::ReasonRequired("revoke a permission".to_string()));
+        }
+
+        let revoked = self.db.revoke_by_id(id, revoked_by, reason).await?;
+        if let Some(grant) = &revoked {
+            self.publish(EventKind::Revoked, grant);
+            self.update_sudoers_file().await?;
+        }
+
+        Ok(revoked)
+    }
+
+    /// Revoke every active grant of `username` whose command matches the
+    /// glob `pattern`, such as `/usr/bin/*`, regenerating the sudoers file
+    /// once afterwards. Returns the revoked grants, sorted by command.
+    pub async fn revoke_matching(
+        &self,
+        username: &str,
+        pattern: &str,
+        revoked_by: &str,
+        reason: Option<&str>,
+    ) -> Result<Vec<PermissionGrant>> {
+        self.ensure_writable("revoke permissions")?;
+        let reason = reason.map(str::trim).filter(|r| !r.is_empty());
+        if self.config.require_reason && reason.is_none() {
+            return Err(PermissionError::ReasonRequired("revoke permissions".to_string()));
+        }
+
+        let revoked = self.db.revoke_matching(username, pattern, revoked_by, reason).await?;
+        for grant in &revoked {
+            self.publish(EventKind::Revoked, grant);
+        }
+        if !revoked.is_empty() {
+            self.update_sudoers_file().await?;
+        }
+
+        Ok(revoked)
+    }
+
+    /// Revoke `username`'s own grant of `command`, or all of their grants
+    /// if `command` is `None`, at their request. Only the user's own grants
+    /// are touched, so no admin rights or reason are required. Returns the
+    /// revoked grants, sorted by command.
+    pub async fn drop_own(&self, username: &str, command: Option<&str>) -> Result<Vec<PermissionGrant>> {
+        self.ensure_writable("drop permissions")?;
+        let command = command.map(normalize_command);
+
+        let revoked = self.db.revoke_own(username, command.as_deref()).await?;
+        for grant in &revoked {
+            self.publish(EventKind::Revoked, grant);
+        }
+        if !revoked.is_empty() {
+            self.update_sudoers_file().await?;
+        }
+
+        Ok(revoked)
+    }
+
+    /// Revoke every live grant issued by `granted_by`, regenerating the
+    /// sudoers file once afterwards. Returns how many grants were revoked.
+    pub async fn revoke_by_granter(
+        &self,
+        granted_by: &str,
+        revoked_by: &str,
+        reason: Option<&str>,
+    ) -> Result<u64> {
+        self.ensure_writable("revoke permissions")?;
+        let reason = reason.map(str::trim).filter(|r| !r.is_empty());
+        if self.config.require_reason && reason.is_none() {
+            return Err(PermissionError::ReasonRequired("revoke permissions".to_string()));
+        }
+
+        let revoked = self.db.revoke_grants_by_granter(granted_by, revoked_by, reason).await?;
+        for grant in &revoked {
+            self.publish(EventKind::Revoked, grant);
+        }
+        if !revoked.is_empty() {
+            self.update_sudoers_file().await?;
+        }
+
+        Ok(revoked.len() as u64)
+    }
+
+    /// Check whether a user currently holds an active grant for a command.
+    /// A grant left unused past its command's idle timeout no longer counts,
+    /// even before cleanup revokes it.
+    pub async fn check_permission(&self, username: &str, command: &str) -> Result<bool> {
+        let command = normalize_command(command);
+        let command = command.as_str();
+        let idle_timeout = self.config.command_config(command).and_then(|c| c.idle_timeout());
+        let Some(cache) = &self.check_cache else {
+            if idle_timeout.is_none() {
+                return self.db.check_permission_on_host(username, command, Some(&self.hostname)).await;
+            }
+            let grant = self.db.active_grant_on_host(username, command, Some(&self.hostname)).await?;
+            return Ok(grant.is_some_and(|grant| !self.is_idle(&grant, Utc::now())));
+        };
+        // Revokes and maintenance mode may come from another process
+        cache.sync(self.db.grant_generation().await?);
+        if cache.contains(username, command) {
+            return Ok(true);
+        }
+
+        let generation = cache.generation();
+        let Some(grant) = self.db.active_grant_on_host(username, command, Some(&self.hostname)).await? else {
+            return Ok(false);
+        };
+        let idle_deadline = self.idle_deadline(&grant);
+        if idle_deadline.is_some_and(|deadline| deadline <= Utc::now()) {
+            return Ok(false);
+        }
+        // Grants with a usage quota can run out between checks, so only
+        // those that end purely by time are cached, and only until they
+        // would go idle
+        if grant.max_uses.is_none() {
+            let until = idle_deadline.map_or(grant.expires_at, |deadline| deadline.min(grant.expires_at));
+            cache.insert(username, command, until, generation);
+        }
+        Ok(true)
+    }
+
+    /// When `grant` lapses for lack of use, if its command sets
+    /// `idle_timeout_minutes`
+    pub fn idle_deadline(&self, grant: &PermissionGrant) -> Option<DateTime<Utc>> {
+        let timeout = self.config.command_config(&grant.command)?.idle_timeout()?;
+        Some(grant.idle_since() + timeout)
+    }
+
+    fn is_idle(&self, grant: &PermissionGrant, now: DateTime<Utc>) -> bool {
+        self.idle_deadline(grant).is_some_and(|deadline| deadline <= now)
+    }
+
+    /// Turn maintenance mode on or off. While it is on, the sudoers file
+    /// holds no rules and every permission check fails, but grants are kept
+    /// so turning it off restores them. Returns whether the mode changed.
+    pub async fn set_maintenance(&self, enabled: bool, changed_by: &str) -> Result<bool> {
+        self.ensure_writable("change maintenance mode")?;
+        let changed = self.db.set_maintenance(enabled, changed_by).await?;
+        if let Some(cache) = &self.check_cache {
+            cache.clear();
+        }
+        self.update_sudoers_file().await?;
+        Ok(changed)
+    }
+
+    /// Maintenance mode, if it is on
+    pub async fn maintenance(&self) -> Result<Option<Maintenance>> {
+        self.db.maintenance().await
+    }
+
+    /// Check several commands for a user at once, on this host. The result
+    /// is keyed by the commands as given.
+    pub async fn check_permissions(&self, username: &str, commands: &[String]) -> Result<HashMap<String, bool>> {
+        let normalized: Vec<String> = commands.iter().map(|command| normalize_command(command)).collect();
+        let mut allowed = self.db.check_permissions_on_host(username, &normalized, Some(&self.hostname)).await?;
+        for (command, held) in allowed.iter_mut() {
+            let idle_timeout = self.config.command_config(command).and_then(|c| c.idle_timeout());
+            if *held && idle_timeout.is_some() {
+                let grant = self.db.active_grant_on_host(username, command, Some(&self.hostname)).await?;
+                *held = grant.is_some_and(|grant| !self.is_idle(&grant, Utc::now()));
+            }
+        }
+        Ok(commands
+            .iter()
+            .zip(&normalized)
+            .map(|(command, normalized)| (command.clone(), allowed.get(normalized).copied().unwrap_or(false)))
+            .collect())
+    }
+
+    /// Record a use of an active grant by updating its last used timestamp
+    /// and use count. The sudoers rule is dropped once a grant with a usage
+    /// quota has used it up.
+    #[instrument(skip_all, fields(user = username, command = command, correlation_id = field::Empty))]
+    pub async fn record_usage(&self, username: &str, command: &str) -> Result<()> {
+        self.ensure_writable("record usage")?;
+        let command = normalize_command(command);
+        let command = command.as_str();
+        let audit = self.config.command_config(command).is_some_and(|c| c.audit_usage);
+        if let Some(grant) = self.db.record_use(username, command, Some(&self.hostname), audit).await? {
+            if let Some(id) = &grant.correlation_id {
+                Span::current().record("correlation_id", id.as_str());
+            }
+            debug!("Recorded use {} of grant {}", grant.use_count, grant.id);
+            if grant.is_exhausted() {
+                info!("Grant {} for {} on {} has used all its uses", grant.id, username, command);
+                self.update_sudoers_file().await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait until audit entries queued by `async_audit` have been written.
+    /// Call before exiting so none are lost.
+    pub async fn flush_audit(&self) {
+        self.db.flush_audit().await;
+    }
+
+    /// List all active permissions for a user
+    pub async fn list_user_permissions(&self, username: &str) -> Result<Vec<PermissionGrant>> {
+        self.db.list_user_permissions(username).await
+    }
+
+    /// Grants `username` can use on this host right now, soonest expiry first
+    pub async fn usable_grants(&self, username: &str) -> Result<Vec<PermissionGrant>> {
+        let mut grants = self.db.list_permissions(&ListOptions {
+            username: Some(username.to_string()),
+            host: Some(self.hostname.clone()),
+            sort: Some(SortField::Expires),
+            ..ListOptions::default()
+        }).await?;
+        grants.retain(|grant| !grant.is_exhausted());
+        Ok(grants)
+    }
+
+    /// List permissions across all users. `ListOptions::default()` lists only
+    /// currently active grants.
+    pub async fn list_active_permissions(&self, options: &ListOptions) -> Result<Vec<PermissionGrant>> {
+        self.db.list_permissions(options).await
+    }
+
+    /// Number of distinct users holding live or scheduled grants of
+    /// `command`, which is what its `max_concurrent_users` limits
+    pub async fn active_user_count(&self, command: &str) -> Result<usize> {
+        Ok(self.db.count_active_grants_for_command(command).await? as usize)
+    }
+
+    /// Report how much of each command's concurrent user limit is taken
+    /// and what every user with grants holds, so operators can see what a
+    /// grant would run into before making it
+    pub async fn quota_report(&self) -> Result<QuotaReport> {
+        let active = self.db.list_permissions(&ListOptions {
+            include_scheduled: true,
+            ..ListOptions::default()
+        }).await?;
+
+        let mut commands: Vec<&str> = self.config.allowed_commands.keys().map(String::as_str).collect();
+        commands.extend(active.iter().map(|grant| grant.command.as_str()));
+        commands.sort_unstable();
+        commands.dedup();
+
+        let mut command_quotas = Vec::with_capacity(commands.len());
+        for command in commands {
+            command_quotas.push(CommandQuota {
+                command: command.to_string(),
+                active_users: self.db.count_active_grants_for_command(command).await?,
+                max_concurrent_users: self.config.command_config(command).map(|c| c.max_concurrent_users),
+            });
+        }
+
+        let mut usernames: Vec<&str> = active.iter().map(|grant| grant.username.as_str()).collect();
+        usernames.sort_unstable();
+        usernames.dedup();
+
+        let now = Utc::now();
+        let mut user_quotas = Vec::with_capacity(usernames.len());
+        for username in usernames {
+            let mut total_duration = Vec::new();
+            for (command, cmd_config) in &self.config.allowed_commands {
+                let Some(limit) = cmd_config.max_total_duration else { continue };
+                let since = now - self.config.total_duration_window();
+                let used = self.db.total_granted_minutes(username, command, since).await?;
+                if used > 0 {
+                    total_duration.push(DurationQuota { command: command.clone(), used_minutes: used, limit_minutes: limit });
+                }
+            }
+            total_duration.sort_by(|a, b| a.command.cmp(&b.command));
+
+            user_quotas.push(UserQuota {
+                username: username.to_string(),
+                active_grants: active.iter().filter(|grant| grant.username == username).count() as i64,
+                recent_grants: self.db
+                    .count_recent_grants(username, now - self.config.total_duration_window())
+                    .await?,
+                total_duration,
+            });
+        }
+
+        Ok(QuotaReport { commands: command_quotas, users: user_quotas })
+    }
+
+    /// List one page of permissions along with the total number matching
+    pub async fn list_permissions_page(&self, options: &ListOptions) -> Result<GrantPage> {
+        let grants = self.db.list_permissions(options).await?;
+        let total_count = self.db.count_permissions(options).await?;
+        Ok(GrantPage {
+            grants,
+            total_count,
+            offset: options.offset,
+        })
+    }
+
+    /// List active permissions expiring within the given duration, optionally
+    /// limited to a single user
+    pub async fn list_expiring(
+        &self,
+        within: Duration,
+        username: Option<&str>,
+    ) -> Result<Vec<PermissionGrant>> {
+        let mut grants = self.db.list_expiring(within).await?;
+        if let Some(username) = username {
+            grants.retain(|grant| grant.username == username);
+        }
+        Ok(grants)
+    }
+
+    /// List grants that applied at any time between `start` and `end`,
+    /// optionally for a single user
+    pub async fn list_active_during(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        username: Option<&str>,
+    ) -> Result<Vec<PermissionGrant>> {
+        if start >= end {
+            return Err(PermissionError::InvalidDuration(format!(
+                "Start of the range ({}) must be before its end ({})",
+                start, end
+            )));
+        }
+
+        let mut grants = self.db.list_active_during(start, end).await?;
+        if let Some(username) = username {
+            grants.retain(|grant| grant.username == username);
+        }
+        Ok(grants)
+    }
+
+    /// Users holding live or scheduled grants on this host who no longer
+    /// have an account here, sorted by name. Their sudoers rules would
+    /// apply to anyone later created with the same name.
+    pub async fn orphaned_users(&self) -> Result<Vec<String>> {
+        let grants = self.db.list_permissions(&ListOptions {
+            include_scheduled: true,
+            host: Some(self.hostname.clone()),
+            ..ListOptions::default()
+        }).await?;
+
+        let usernames: BTreeSet<String> = grants.into_iter().map(|grant| grant.username).collect();
+        let mut orphans = Vec::new();
+        for username in usernames {
+            if !self.identity.user_exists(&username)? {
+                orphans.push(username);
+            }
+        }
+        Ok(orphans)
+    }
+
+    /// Revoke every live grant on this host of the users `orphaned_users`
+    /// reports, regenerating the sudoers file once afterwards. Returns the
+    /// revoked grants.
+    pub async fn revoke_orphaned(&self, revoked_by: &str) -> Result<Vec<PermissionGrant>> {
+        self.ensure_writable("revoke permissions")?;
+
+        let mut revoked = Vec::new();
+        for username in self.orphaned_users().await? {
+            revoked.extend(self.db.revoke_orphaned(&username, &self.hostname, revoked_by).await?);
+        }
+        for grant in &revoked {
+            self.publish(EventKind::Revoked, grant);
+        }
+        if !revoked.is_empty() {
+            self.update_sudoers_file().await?;
+        }
+
+        Ok(revoked)
+    }
+
+    /// Live or scheduled grants on this host whose holder is no longer in
+    /// one of the command's `required_groups`, each with the first group
+    /// missing. Grants of commands that are no longer configured and of
+    /// deleted users are left to other checks.
+    pub async fn grants_failing_groups(&self) -> Result<Vec<(PermissionGrant, String)>> {
+        let grants = self.db.list_permissions(&ListOptions {
+            include_scheduled: true,
+            host: Some(self.hostname.clone()),
+            ..ListOptions::default()
+        }).await?;
+
+        let mut failing = Vec::new();
+        for grant in grants {
+            let Some(cmd_config) = self.config.command_config(&grant.command) else {
+                continue;
+            };
+            if cmd_config.required_groups.is_empty() || !self.identity.user_exists(&grant.username)? {
+                continue;
+            }
+            let groups = self.identity.user_groups(&grant.username)?;
+            if let Some(missing) = cmd_config.required_groups.iter().find(|group| !groups.contains(group)) {
+                let missing = missing.clone();
+                failing.push((grant, missing));
+            }
+        }
+        Ok(failing)
+    }
+
+    /// Revoke the grants `grants_failing_groups` reports, regenerating the
+    /// sudoers file once afterwards. Returns the revoked grants.
+    pub async fn revalidate_groups(&self, revoked_by: &str) -> Result<Vec<PermissionGrant>> {
+        self.ensure_writable("revoke permissions")?;
+
+        let failing: Vec<(i64, String)> = self.grants_failing_groups().await?
+            .into_iter()
+            .map(|(grant, group)| (grant.id, group))
+            .collect();
+        if failing.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let revoked = self.db.revoke_for_group_loss(&failing, &self.hostname, revoked_by).await?;
+        for grant in &revoked {
+            self.publish(EventKind::Revoked, grant);
+        }
+        if !revoked.is_empty() {
+            self.update_sudoers_file().await?;
+        }
+
+        Ok(revoked)
+    }
+
+    /// Live grants on this host left unused for longer than their command's
+    /// `idle_timeout_minutes`
+    pub async fn idle_grants(&self) -> Result<Vec<PermissionGrant>> {
+        let grants = self.db.list_permissions(&ListOptions {
+            host: Some(self.hostname.clone()),
+            ..ListOptions::default()
+        }).await?;
+
+        let now = Utc::now();
+        Ok(grants.into_iter().filter(|grant| self.is_idle(grant, now)).collect())
+    }
+
+    /// Revoke the grants `idle_grants` reports, regenerating the sudoers
+    /// file once afterwards. Returns the revoked grants.
+    pub async fn revoke_idle(&self, revoked_by: &str) -> Result<Vec<PermissionGrant>> {
+        self.ensure_writable("revoke permissions")?;
+
+        let idle: Vec<(i64, i64)> = self.idle_grants().await?
+            .into_iter()
+            .filter_map(|grant| {
+                let minutes = self.config.command_config(&grant.command)?.idle_timeout_minutes?;
+                Some((grant.id, minutes))
+            })
+            .collect();
+        if idle.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let revoked = self.db.revoke_idle(&idle, revoked_by).await?;
+        for grant in &revoked {
+            self.publish(EventKind::Revoked, grant);
+        }
+        if !revoked.is_empty() {
+            self.update_sudoers_file().await?;
+        }
+
+        Ok(revoked)
+    }
+
+    /// Users and commands holding more than one live or scheduled grant,
+    /// with the number of grants. Revoking or extending such a pair only
+    /// touches one of its grants.
+    pub async fn duplicate_grants(&self) -> Result<Vec<(String, String, i64)>> {
+        self.db.duplicate_grants().await
+    }
+
+    /// Revoke all but the latest-expiring grant of every pair that
+    /// `duplicate_grants` reports, regenerating the sudoers file once
+    /// afterwards. Returns the revoked grants.
+    pub async fn revoke_duplicates(&self, revoked_by: &str) -> Result<Vec<PermissionGrant>> {
+        self.ensure_writable("revoke permissions")?;
+
+        let revoked = self.db.revoke_duplicates(revoked_by).await?;
+        for grant in &revoked {
+            self.publish(EventKind::Revoked, grant);
+        }
+        if !revoked.is_empty() {
+            self.update_sudoers_file().await?;
+        }
+
+        Ok(revoked)
+    }
+
+    /// Check the database connection and schema
+    pub async fn database_health(&self) -> Result<DatabaseHealth> {
+        self.db.health_check().await
+    }
+
+    /// Run the full database integrity check, which opening the database
+    /// leaves out; see `Database::integrity_check`
+    pub async fn integrity_check(&self) -> Result<()> {
+        self.db.integrity_check().await
+    }
+
+    /// Reclaim the space left in the database file by deleted rows and the
+    /// write-ahead log
+    pub async fn compact_database(&self) -> Result<Compaction> {
+        self.ensure_writable("compact the database")?;
+        let compaction = self.db.compact().await?;
+        info!("Compacted database from {} to {} bytes", compaction.size_before, compaction.size_after);
+        Ok(compaction)
+    }
+
+    /// Read audit log entries matching `query`, newest first
+    pub async fn audit_entries(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+        self.db.audit_entries(query).await
+    }
+
+    /// Recompute the audit log hash chain to detect edited or removed entries
+    pub async fn verify_audit_chain(&self) -> Result<AuditChainReport> {
+        self.db.verify_audit_chain().await
+    }
+
+    /// Clean up expired permissions
+    pub async fn cleanup_expired(&self) -> Result<u64> {
+        self.ensure_writable("clean up expired permissions")?;
+        let expired = self.db.expire_grants().await?;
+        for grant in &expired {
+            self.publish(EventKind::Expired, grant);
+        }
+        let count = expired.len() as u64;
+        if count > 0 {
+            self.update_sudoers_file().await?;
+            info!("Cleaned up {} expired permission(s)", count);
+        }
+        Ok(count)
+    }
+
+    /// Count the expired grants `cleanup_expired` would revoke
+    pub async fn count_expired(&self) -> Result<u64> {
+        self.db.count_expired().await
+    }
+
+    /// Delete grants revoked longer ago than `hard_delete_after_days`.
+    /// Returns `None` when hard deletion is not configured.
+    pub async fn purge_revoked(&self) -> Result<Option<u64>> {
+        self.ensure_writable("purge revoked permissions")?;
+        match self.purge_cutoff() {
+            Some(older_than) => self.db.purge_revoked(older_than).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Count the grants `purge_revoked` would delete. Returns `None` when
+    /// hard deletion is not configured.
+    pub async fn count_purgeable(&self) -> Result<Option<u64>> {
+        match self.purge_cutoff() {
+            Some(older_than) => self.db.count_purgeable(older_than).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn purge_cutoff(&self) -> Option<DateTime<Utc>> {
+        self.config
+            .hard_delete_after_days
+            .map(|days| Utc::now() - Duration::days(days as i64))
+    }
+
+    /// When the sudoers file next needs rewriting because a grant expires,
+    /// a scheduled grant starts or a grant goes idle. Idle timeouts are
+    /// looked up per granted command, so commands rendered from a template
+    /// use the template's.
+    pub async fn next_sudoers_change(&self) -> Result<Option<DateTime<Utc>>> {
+        let transition = self.db.next_transition().await?;
+        let grants = self.db.list_permissions(&ListOptions {
+            host: Some(self.hostname.clone()),
+            ..ListOptions::default()
+        }).await?;
+        let next_idle = grants.iter().filter_map(|grant| self.idle_deadline(grant)).min();
+        Ok(transition.into_iter().chain(next_idle).min())
+    }
+
+    /// The unrevoked grant that expires next, if any
+    pub async fn next_expiry(&self) -> Result<Option<PermissionGrant>> {
+        self.db.next_expiry().await
+    }
+
+    /// Rewrite the sudoers file from the currently active grants, returning
+    /// the number of rules written. The database is not changed.
+    pub async fn refresh_sudoers(&self) -> Result<usize> {
+        self.ensure_writable("refresh the sudoers file")?;
+        self.update_sudoers_file().await
+    }
+
+    /// Update the sudoers file with current permissions, returning the
+    /// number of rules written
+    async fn update_sudoers_file(&self) -> Result<usize> {
+        if !self.config.sudoers_enabled {
+            debug!("Sudoers management disabled, not writing {:?}", self.config.sudoers_path);
+            return Ok(0);
+        }
+
+        let mut content = self.config.sudoers_header.clone()
+            .unwrap_or_else(|| DEFAULT_SUDOERS_HEADER.to_string());
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&format!("# Generated at {}\n\n", Utc::now().to_rfc3339()));
+
+        let rules = self.sudoers_rules().await?;
+        for rule in &rules {
+            content.push_str(rule);
+            content.push('\n');
+        }
+
+        // Write to temporary file first
+        let temp_path = self.config.sudoers_path.with_extension("tmp");
+        fs::write(&temp_path, content.as_bytes())
+            .map_err(|e| sudoers_write_error(e, &temp_path))?;
+
+        // Set correct permissions (0440)
+        let mut perms = fs::metadata(&temp_path)
+            .map_err(|e| PermissionError::io_error(e, temp_path.clone()))?
+            .permissions();
+        perms.set_mode(0o440);
+        fs::set_permissions(&temp_path, perms)
+            .map_err(|e| sudoers_write_error(e, &temp_path))?;
+
+        // Move temporary file to final location
+        fs::rename(&temp_path, &self.config.sudoers_path)
+            .map_err(|e| sudoers_write_error(e, &self.config.sudoers_path))?;
+
+        Ok(rules.len())
+    }
+
+    /// The sudoers rules for every active grant that applies on this host,
+    /// none while maintenance mode is on. Rules are sorted by user and then
+    /// command so that regenerating the file for the same grants gives the
+    /// same rules in the same order.
+    async fn sudoers_rules(&self) -> Result<Vec<String>> {
+        if self.db.maintenance().await?.is_some() {
+            return Ok(Vec::new());
+        }
+
+        let now = Utc::now();
+        let mut grants = self.db.list_permissions(&ListOptions {
+            host: Some(self.hostname.clone()),
+            ..ListOptions::default()
+        }).await?;
+        grants.retain(|grant| !self.is_idle(grant, now));
+        grants.sort_by(|a, b| (&a.username, &a.command, a.id).cmp(&(&b.username, &b.command, b.id)));
+
+        Ok(grants
+            .into_iter()
+            .map(|grant| {
+                let spec = match self.config.command_config(&grant.command) {
+                    Some(cmd_config) => cmd_config.grant_sudoers_spec(grant.require_password),
+                    None if grant.require_password => "(ALL)".to_string(),
+                    None => "(ALL) NOPASSWD:".to_string(),
+                };
+                format!("{} ALL={} {}", grant.username, spec, self.config.sudoers_command(&grant.command))
+            })
+            .collect())
+    }
+
+    /// Whether the sudoers file holds exactly the rules the active grants
+    /// call for, ignoring comments and rule order. A file that was edited by
+    /// hand or missed a refresh is reported as out of sync.
+    pub async fn sudoers_in_sync(&self) -> Result<bool> {
+        let path = &self.config.sudoers_path;
+        let content = fs::read_to_string(path)
+            .map_err(|e| PermissionError::io_error(e, path.clone()))?;
+
+        let mut actual: Vec<&str> = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        let mut expected = self.sudoers_rules().await?;
+        actual.sort_unstable();
+        expected.sort_unstable();
+
+        Ok(actual == expected)
+    }
+
+    /// Reject usernames that do not match the configured pattern, before
+    /// they can reach the database or the sudoers file. Whitespace and
+    /// control characters are refused whatever the pattern allows, since
+    /// they would split a sudoers rule.
+    fn validate_username(&self, username: &str) -> Result<()> {
+        let invalid = username.chars().any(|c| c.is_whitespace() || c.is_control())
+            || !self.config.username_regex()?.is_match(username);
+        if invalid {
+            return Err(PermissionError::InvalidUsername(username.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Check that `username` may run operations that change grants or the
+    /// configuration under `config`: it must belong to one of
+    /// `admin_groups`, unless none are configured or it is root and
+    /// `admin_root_bypass` is set.
+    pub fn authorize_admin(config: &Config, username: &str) -> Result<()> {
+        if config.admin_groups.is_empty() {
+            return Ok(());
+        }
+
+        let is_root = matches!(User::from_name(username), Ok(Some(user)) if user.uid.is_root());
+        if is_root && config.admin_root_bypass {
+            return Ok(());
+        }
+
+        for group in &config.admin_groups {
+            if SystemIdentity.user_in_group(username, group)? {
+                return Ok(());
+            }
+        }
+
+        Err(PermissionError::PermissionDenied(format!(
+            "{} is not a member of any admin group ({})",
+            username,
+            config.admin_groups.join(", ")
+        )))
+    }
+
+    /// Set up required directories with appropriate permissions
+    fn setup_directories(config: &Config) -> Result<()> {
+        for dir in config.required_directories() {
+            fs::create_dir_all(dir)
+                .map_err(|e| PermissionError::io_error(e, dir.to_path_buf()))?;
+            
+            let mut perms = fs::metadata(dir)
+                .map_err(|e| PermissionError::io_error(e, dir.to_path_buf()))?
+                .permissions();
+            perms.set_mode(0o755);
+            fs::set_permissions(dir, perms)
+                .map_err(|e| PermissionError::io_error(e, dir.to_path_buf()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Builder for a [`PermissionManager`] embedded in another program.
+///
+/// Without options it behaves like [`PermissionManager::new`] with the
+/// default configuration: directories are created, the database is opened
+/// at `db_path` and the sudoers file is regenerated.
+#[derive(Default)]
+pub struct PermissionManagerBuilder {
+    config: Option<Config>,
+    manage_sudoers: Option<bool>,
+    identity: Option<Box<dyn IdentityProvider>>,
+    store: Option<Database>,
+}
+
+impl PermissionManagerBuilder {
+    /// Configuration to use instead of `Config::detect()`
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Whether the manager writes the sudoers file, overriding
+    /// `sudoers_enabled`. Without it grants are only recorded, for
+    /// programs that enforce them some other way.
+    pub fn manage_sudoers(mut self, manage: bool) -> Self {
+        self.manage_sudoers = Some(manage);
+        self
+    }
+
+    /// Look up users and groups through `identity` instead of the host's
+    /// account databases
+    pub fn identity_provider(mut self, identity: impl IdentityProvider + 'static) -> Self {
+        self.identity = Some(Box::new(identity));
+        self
+    }
+
+    /// Keep grants in an already opened database instead of opening
+    /// `db_path`. No directories are created, and audit exporters are the
+    /// ones attached to `store`.
+    pub fn store(mut self, store: Database) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Validate the configuration and open the manager
+    pub async fn build(self) -> Result<PermissionManager> {
+        let mut config = self.config.unwrap_or_else(Config::detect);
+        if let Some(manage) = self.manage_sudoers {
+            config.sudoers_enabled = manage;
+        }
+        config.validate()?;
+
+        let warnings = PermissionManager::warning_deduplicator(&config);
+        let db = match self.store {
+            Some(store) => store,
+            None => {
+                PermissionManager::setup_directories(&config)?;
+                PermissionManager::open_database(&config, &warnings).await?
+            }
+        };
+
+        let mut manager = PermissionManager::from_parts(config, db, warnings, false)?;
+        if let Some(identity) = self.identity {
+            manager.identity = identity;
+        }
+        manager.initialize().await?;
+
+        match manager.check_clock().await {
+            Err(e @ PermissionError::ClockAnomaly { .. }) => {
+                warn!("{}. Expiry decisions may be wrong until the clock is corrected", e);
+            }
+            other => other?,
+        }
+
+        Ok(manager)
+    }
+}
+
+/// Map a failed sudoers write to an error, calling out missing privileges
+/// separately from other IO failures
+fn sudoers_write_error(e: std::io::Error, path: &Path) -> PermissionError {
+    if e.kind() == std::io::ErrorKind::PermissionDenied {
+        PermissionError::PermissionDenied(format!(
+            "cannot write {}; run permctl as root or set sudoers_enabled: false",
+            path.display()
+        ))
+    } else {
+        PermissionError::io_error(e, path)
+    }
+}
+
+/// Sender for a manager's events; nothing is buffered until a subscriber
+/// exists
+fn event_channel() -> broadcast::Sender<PermissionEvent> {
+    broadcast::channel(EVENT_CHANNEL_CAPACITY).0
+}
+
+/// The local machine's host name. Grants default to it and sudoers rules are
+/// rendered for it, so a host without one cannot be managed.
+fn local_hostname() -> Result<String> {
+    let name = hostname::get()
+        .map_err(|source| PermissionError::SystemCommand { source, cmd: "gethostname".to_string() })?
+        .to_string_lossy()
+        .into_owned();
+    if name.is_empty() {
+        return Err(PermissionError::Config("the host name is empty; set one before running permctl".to_string()));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    async fn create_test_manager() -> (PermissionManager, TempDir) {
+        let temp_dir = TempDir::new().unwrap();
+        
+        let mut config = Config {
+            allowed_commands: HashMap::new(),
+            sudoers_path: temp_dir.path().join("sudoers"),
+            db_path: temp_dir.path().join("test.db"),
+            db_busy_timeout_ms: 10_000,
+            db_wal_autocheckpoint: 1000,
+            db_encryption_key_path: None,
+            log_path: temp_dir.path().join("test.log"),
+            log_max_bytes: 0,
             debug: false,
             log_retention_days: 30,
+            syslog: None,
+            check_cache: None,
+            hard_delete_after_days: None,
+            approval_request_ttl_minutes: None,
+            require_reason: false,
+            strict_clock: false,
+            reject_writable_commands: false,
+            api_group: None,
+            api_token_path: None,
+            sudoers_enabled: true,
+            sudoers_header: None,
+            username_pattern: None,
+            log_dedup_secs: 300,
+            confirmation_timeout_secs: 120,
+            total_duration_window_hours: 24,
+            templates: HashMap::new(),
+            admin_groups: Vec::new(),
+            admin_root_bypass: true,
+            delegation_mode: false,
+            admin_commands: HashMap::new(),
+            auto_sudo: false,
+            user_notifications: false,
+            revalidate_groups: false,
+            command_defaults: crate::config::CommandConfigDefaults::default(),
+            regrant_policy: crate::config::RegrantPolicy::default(),
+            audit_detail: crate::config::AuditDetail::default(),
+            async_audit: false,
+            notify_webhook: None,
+        };
+
+        config.allowed_commands.insert(
+            "/test/command".to_string(),
+            crate::config::CommandConfig {
+                description: "Test command".to_string(),
+                max_duration: 60,
+                required_groups: vec!["users".to_string()],
+                audit_usage: true,
+                max_concurrent_users: 5,
+                ..Default::default()
+            },
+        );
+
+        let manager = PermissionManager::new(config).await.unwrap();
+        (manager, temp_dir)
+    }
+
+    /// Fixed accounts, so group policy can be tested without system users
+    struct MockIdentity(HashMap<&'static str, Vec<&'static str>>);
+
+    impl IdentityProvider for MockIdentity {
+        fn user_exists(&self, username: &str) -> Result<bool> {
+            Ok(self.0.contains_key(username))
+        }
+
+        fn user_groups(&self, username: &str) -> Result<Vec<String>> {
+            let groups = self.0.get(username).into_iter().flatten();
+            Ok(groups.map(|g| g.to_string()).collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grant_policy_uses_identity_provider() {
+        let (manager, _temp) = create_test_manager().await;
+        let manager = manager.with_identity_provider(MockIdentity(HashMap::from([
+            ("ldapuser", vec!["users"]),
+            ("contractor", vec!["guests"]),
+        ])));
+
+        manager.grant_permission("ldapuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+
+        let err = manager.grant_permission("contractor", "/test/command", Duration::minutes(30), "admin").await.unwrap_err();
+        assert!(matches!(err, PermissionError::GroupRequirementNotMet { ref group, .. } if group == "users"));
+
+        // Real accounts are unknown to the provider
+        let err = manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap_err();
+        assert!(matches!(err, PermissionError::UserNotFound(_)));
+        manager.db.grant_permission("testuser", "/test/command", Utc::now() + Duration::hours(1), "admin").await.unwrap();
+        assert_eq!(manager.orphaned_users().await.unwrap(), vec!["testuser".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_validate_grant_reports_every_failure() {
+        let (manager, _temp) = create_test_manager().await;
+        let manager = manager.with_identity_provider(MockIdentity(HashMap::from([
+            ("member", vec!["users"]),
+            ("guest", vec!["guests"]),
+        ])));
+
+        let checks = manager.validate_grant("member", "/test/command", Duration::minutes(30), "admin", GrantOptions::default()).await;
+        assert!(checks.iter().all(GrantCheck::passed));
+
+        // Failures do not stop the checks after them
+        let checks = manager.validate_grant("guest", "/test/command", Duration::minutes(120), "admin", GrantOptions::default()).await;
+        let failed: Vec<_> = checks.iter().filter(|check| !check.passed()).map(|check| check.name).collect();
+        assert_eq!(failed, vec!["duration limits", "required groups"]);
+        assert!(checks.iter().any(|check| check.name == "user exists" && check.passed()));
+
+        // Nothing is granted, and a grant fails on the first of them
+        assert!(!manager.check_permission("member", "/test/command").await.unwrap());
+        let err = manager.grant_permission("guest", "/test/command", Duration::minutes(120), "admin").await.unwrap_err();
+        assert!(matches!(err, PermissionError::InvalidDuration(_)));
+    }
+
+    #[tokio::test]
+    async fn test_revalidate_groups_revokes_departed_members() {
+        let (manager, _temp) = create_test_manager().await;
+        let manager = manager.with_identity_provider(MockIdentity(HashMap::from([
+            ("stays", vec!["users"]),
+            ("leaves", vec!["guests"]),
+            ("away", vec!["guests"]),
+        ])));
+        for username in ["stays", "leaves"] {
+            manager.db.grant_permission(username, "/test/command", Utc::now() + Duration::hours(1), "admin").await.unwrap();
+        }
+        // Membership on another host is not known here
+        let away = manager.db.grant_permission_with_limit(
+            "away", "/test/command", Some("elsewhere"), None, Utc::now() + Duration::hours(1), "admin", None, None, false, None, None, None,
+        ).await.unwrap().0;
+
+        let failing = manager.grants_failing_groups().await.unwrap();
+        assert_eq!(failing.len(), 1);
+        assert_eq!((failing[0].0.username.as_str(), failing[0].1.as_str()), ("leaves", "users"));
+
+        let revoked = manager.revalidate_groups("permctl").await.unwrap();
+        assert_eq!(revoked.len(), 1);
+        assert!(manager.db.revoke_for_group_loss(&[(away.id, "users".to_string())], &manager.hostname, "permctl").await.unwrap().is_empty());
+        assert_eq!(revoked[0].revoke_reason.as_deref(), Some("No longer a member of required group users"));
+        assert!(!manager.check_permission("leaves", "/test/command").await.unwrap());
+        assert!(manager.check_permission("stays", "/test/command").await.unwrap());
+
+        let audit = manager.audit_entries(&AuditQuery { action: Some("group_revalidation".to_string()), ..AuditQuery::default() }).await.unwrap();
+        assert_eq!(audit.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_idle_grants_are_refused_and_revoked() {
+        let (mut manager, _temp) = create_test_manager().await;
+        manager.config.allowed_commands.get_mut("/test/command").unwrap().idle_timeout_minutes = Some(30);
+        for username in ["idle", "busy"] {
+            manager.db.grant_permission(username, "/test/command", Utc::now() + Duration::hours(8), "admin").await.unwrap();
+        }
+        sqlx::query("UPDATE permission_grants SET granted_at = ?")
+            .bind(Utc::now() - Duration::hours(1))
+            .execute(manager.db.get_pool())
+            .await
+            .unwrap();
+        manager.db.update_last_used("busy", "/test/command").await.unwrap();
+
+        assert!(!manager.check_permission("idle", "/test/command").await.unwrap());
+        assert!(manager.check_permission("busy", "/test/command").await.unwrap());
+        let batch = manager.check_permissions("idle", &["/test/command".to_string()]).await.unwrap();
+        assert_eq!(batch.get("/test/command"), Some(&false));
+
+        let revoked = manager.revoke_idle("permctl").await.unwrap();
+        assert_eq!(revoked.len(), 1);
+        assert_eq!(revoked[0].username, "idle");
+        assert_eq!(revoked[0].revoke_reason.as_deref(), Some("Unused for 30 minutes"));
+        assert!(manager.idle_grants().await.unwrap().is_empty());
+
+        let audit = manager.audit_entries(&AuditQuery { action: Some("idle_timeout".to_string()), ..AuditQuery::default() }).await.unwrap();
+        assert_eq!(audit.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_grant_normalizes_command() {
+        let (manager, _temp) = create_test_manager().await;
+
+        let grant = manager.grant_permission("testuser", "/test/command ", Duration::minutes(30), "admin")
+            .await.unwrap().granted().unwrap();
+        assert_eq!(grant.command, "/test/command");
+        let grant = manager.grant_permission("testuser", "/test//command", Duration::minutes(30), "admin")
+            .await.unwrap().granted().unwrap();
+        assert_eq!(grant.command, "/test/command");
+
+        // Checks and recorded uses find the grant the same way
+        assert!(manager.check_permission("testuser", "/test/command ").await.unwrap());
+        assert!(manager.check_permission("testuser", "/test//command").await.unwrap());
+        let commands = ["/test/command ".to_string(), "/test//command".to_string()];
+        let checked = manager.check_permissions("testuser", &commands).await.unwrap();
+        assert!(commands.iter().all(|command| checked[command]));
+        manager.record_usage("testuser", "/test//command ").await.unwrap();
+        let held = manager.list_user_permissions("testuser").await.unwrap();
+        assert_eq!(held[0].use_count, 1);
+
+        assert!(manager.revoke_permission("testuser", " /test/command", "admin").await.unwrap());
+
+        let err = manager.grant_permission("testuser", "/test//other ", Duration::minutes(30), "admin").await.unwrap_err();
+        assert_eq!(err.to_string(), "Command not allowed: \"/test/other\"");
+    }
+
+    #[tokio::test]
+    async fn test_grant_outcome_reports_replacement() {
+        let (manager, _temp) = create_test_manager().await;
+
+        let first = manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+        let GrantOutcome::Granted(first) = first else { panic!("expected an immediate grant") };
+        assert!(!first.replaced_existing);
+        assert_eq!(first.jitter_secs, 0);
+
+        let second = manager.grant_permission("testuser", "/test/command", Duration::minutes(45), "admin").await.unwrap();
+        let json = serde_json::to_value(&second).unwrap();
+        let GrantOutcome::Granted(second) = second else { panic!("expected an immediate grant") };
+        assert!(second.replaced_existing);
+
+        // Scripts keep finding the grant's fields at the top level
+        assert_eq!(json["outcome"], "granted");
+        assert_eq!(json["id"], second.id());
+        assert_eq!(json["replaced_existing"], true);
+    }
+
+    #[tokio::test]
+    async fn test_grant_records_on_behalf_of() {
+        let (manager, _temp) = create_test_manager().await;
+        let manager = manager.with_identity_provider(MockIdentity(HashMap::from([
+            ("member", vec!["users"]),
+            ("alice", vec![]),
+        ])));
+
+        let err = manager
+            .grant_permission_on_host("member", "/test/command", Duration::minutes(30), "deploy-bot", GrantOptions { on_behalf_of: Some("ghost"), ..Default::default() })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, PermissionError::UserNotFound(user) if user == "ghost"));
+
+        let grant = manager
+            .grant_permission_on_host("member", "/test/command", Duration::minutes(30), "deploy-bot", GrantOptions { on_behalf_of: Some("alice"), ..Default::default() })
+            .await.unwrap().granted().unwrap();
+        assert_eq!(grant.granted_by, "deploy-bot");
+        assert_eq!(grant.on_behalf_of.as_deref(), Some("alice"));
+
+        let audit = manager.audit_entries(&AuditQuery { action: Some("grant".to_string()), ..AuditQuery::default() }).await.unwrap();
+        let details = crate::audit_details::AuditDetails::parse(audit[0].details.as_deref().unwrap()).unwrap();
+        assert_eq!(details.on_behalf_of.as_deref(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_drop_own_grants() {
+        let (mut manager, _temp) = create_test_manager().await;
+        let base = manager.config.allowed_commands["/test/command"].clone();
+        manager.config.allowed_commands.insert("/test/other".to_string(), base);
+        let manager = manager.with_identity_provider(MockIdentity(HashMap::from([
+            ("testuser", vec!["users"]),
+            ("otheruser", vec!["users"]),
+        ])));
+        for command in ["/test/command", "/test/other"] {
+            manager.grant_permission("testuser", command, Duration::minutes(30), "admin").await.unwrap();
+        }
+        manager.grant_permission("otheruser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+
+        let dropped = manager.drop_own("testuser", Some("/test/command")).await.unwrap();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].revoked_by.as_deref(), Some("testuser"));
+        assert!(!manager.check_permission("testuser", "/test/command").await.unwrap());
+        assert!(manager.check_permission("otheruser", "/test/command").await.unwrap());
+
+        let dropped = manager.drop_own("testuser", None).await.unwrap();
+        assert_eq!(dropped.iter().map(|g| g.command.as_str()).collect::<Vec<_>>(), ["/test/other"]);
+        assert!(manager.drop_own("testuser", None).await.unwrap().is_empty());
+        assert!(manager.check_permission("otheruser", "/test/command").await.unwrap());
+
+        let audit = manager.audit_entries(&AuditQuery { action: Some("self_revoke".to_string()), ..AuditQuery::default() }).await.unwrap();
+        assert_eq!(audit.len(), 2);
+        assert!(audit.iter().all(|entry| entry.username == "testuser"));
+    }
+
+    #[tokio::test]
+    async fn test_grant_and_revoke_permission() {
+        let (manager, _temp) = create_test_manager().await;
+        
+        let grant = manager.grant_permission(
+            "testuser",
+            "/test/command",
+            Duration::minutes(30),
+            "admin"
+        ).await.unwrap().granted().unwrap();
+
+        assert!(grant.id > 0);
+
+        let revoked = manager.revoke_permission(
+            "testuser",
+            "/test/command",
+            "admin"
+        ).await.unwrap();
+
+        assert!(revoked);
+    }
+
+    #[tokio::test]
+    async fn test_regrant_policy_applies_per_host() {
+        async fn grant(manager: &PermissionManager, host: Option<&str>) -> Result<GrantOutcome> {
+            let options = GrantOptions { host, ..Default::default() };
+            manager.grant_permission_on_host("testuser", "/test/command", Duration::minutes(30), "admin", options).await
+        }
+        let (mut manager, _temp) = create_test_manager().await;
+
+        manager.config.regrant_policy = RegrantPolicy::Reject;
+        grant(&manager, None).await.unwrap();
+        let err = grant(&manager, None).await.unwrap_err();
+        assert!(matches!(err, PermissionError::GrantExists { .. }));
+        // Holding the command on this host does not stand in the way elsewhere
+        grant(&manager, Some("db1")).await.unwrap();
+
+        manager.config.regrant_policy = RegrantPolicy::Extend;
+        let held = manager.db.held_grant("testuser", "/test/command", Some(manager.hostname())).await.unwrap().unwrap();
+        let extended = grant(&manager, None).await.unwrap().granted().unwrap();
+        assert_eq!(extended.id, held.id);
+        assert_eq!(manager.list_user_permissions("testuser").await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_total_duration_cap() {
+        let (mut manager, _temp) = create_test_manager().await;
+        manager.config.allowed_commands.get_mut("/test/command").unwrap().max_total_duration = Some(45);
+
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin")
+            .await.unwrap();
+
+        let err = manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin")
+            .await.unwrap_err();
+        assert!(matches!(err, PermissionError::TotalDurationExceeded { limit: 45, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_total_duration_cap_bounds_jitter_and_rounding() {
+        let (mut manager, _temp) = create_test_manager().await;
+        let cmd_config = manager.config.allowed_commands.get_mut("/test/command").unwrap();
+        cmd_config.max_total_duration = Some(45);
+        cmd_config.expiry_round_to = Some(crate::config::ExpiryRounding::Hour);
+
+        // Forty minutes from five past the hour would round up to fifty-five,
+        // past the cap, so the expiry is left unrounded
+        let start = chrono::DurationRound::duration_trunc(Utc::now(), Duration::hours(1)).unwrap() + Duration::minutes(65);
+        let grant = manager.grant_permission_from("testuser", "/test/command", Some(start), Duration::minutes(40), "admin")
+            .await
+            .unwrap()
+            .granted()
+            .unwrap();
+        assert_eq!(grant.expires_at, start + Duration::minutes(40));
+        manager.revoke_permission("testuser", "/test/command", "admin").await.unwrap();
+
+        // Jitter may not lengthen a grant past what is left of the cap,
+        // which revoked grants still count towards
+        let mut used = 40;
+        for _ in 0..5 {
+            let cmd_config = manager.config.allowed_commands.get_mut("/test/command").unwrap();
+            cmd_config.expiry_round_to = None;
+            cmd_config.expiry_jitter_minutes = Some(30);
+            cmd_config.max_total_duration = Some(used + 50);
+            let grant = manager.grant_permission_from("testuser", "/test/command", Some(start), Duration::minutes(45), "admin")
+                .await
+                .unwrap()
+                .granted()
+                .unwrap();
+            let minutes = (grant.expires_at - start).num_minutes();
+            assert!(minutes <= 50, "{}", minutes);
+            used += minutes;
+            manager.revoke_permission("testuser", "/test/command", "admin").await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_cache_is_evicted_on_revoke() {
+        let (manager, _temp) = create_test_manager().await;
+        let manager = PermissionManager {
+            check_cache: Some(CheckCache::new(std::time::Duration::from_secs(60), 16)),
+            ..manager
+        };
+
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+        assert!(manager.check_permission("testuser", "/test/command").await.unwrap());
+
+        // The hit is served from the cache without reading the grant
+        sqlx::query("UPDATE permission_grants SET username = 'moved'")
+            .execute(manager.db.get_pool())
+            .await
+            .unwrap();
+        assert!(manager.check_permission("testuser", "/test/command").await.unwrap());
+        sqlx::query("UPDATE permission_grants SET username = 'testuser'")
+            .execute(manager.db.get_pool())
+            .await
+            .unwrap();
+
+        manager.revoke_permission("testuser", "/test/command", "admin").await.unwrap();
+        assert!(!manager.check_permission("testuser", "/test/command").await.unwrap());
+
+        // Changes made by another process, such as the CLI, evict it too
+        let other = Database::new(&manager.config.db_path).await.unwrap();
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+        assert!(manager.check_permission("testuser", "/test/command").await.unwrap());
+        other.revoke_permission("testuser", "/test/command", "admin").await.unwrap();
+        assert!(!manager.check_permission("testuser", "/test/command").await.unwrap());
+
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+        assert!(manager.check_permission("testuser", "/test/command").await.unwrap());
+        other.set_maintenance(true, "admin").await.unwrap();
+        assert!(!manager.check_permission("testuser", "/test/command").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_delegation_mode_limits_admins_to_their_scope() {
+        let (mut manager, _temp) = create_test_manager().await;
+        manager.config.delegation_mode = true;
+
+        let err = manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "lead").await.unwrap_err();
+        assert!(err.is_permission_denied());
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "root").await.unwrap();
+
+        // Listed in admin_commands
+        manager.config.admin_commands.insert("lead".to_string(), vec!["/test/command".to_string()]);
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "lead").await.unwrap();
+
+        // Held by the admin
+        manager.config.admin_commands.clear();
+        manager.db.grant_permission("lead", "/test/command", Utc::now() + Duration::hours(1), "root").await.unwrap();
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "lead").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_grant_several_commands_at_once() {
+        let (mut manager, _temp) = create_test_manager().await;
+        let base = manager.config.allowed_commands["/test/command"].clone();
+        manager.config.allowed_commands.insert("/test/other".to_string(), crate::config::CommandConfig {
+            max_concurrent_users: 1,
+            ..base.clone()
+        });
+        manager.config.allowed_commands.insert("/test/approved".to_string(), crate::config::CommandConfig {
+            requires_approval: true,
+            ..base
+        });
+        let commands = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        let grants = manager.grant_permissions_on_host(
+            "testuser", &commands(&["/test/command", "/test/other", "/test/command"]), Duration::minutes(30), "admin", GrantOptions::default(),
+        ).await.unwrap();
+        assert_eq!(grants.iter().map(|g| g.command.as_str()).collect::<Vec<_>>(), ["/test/command", "/test/other"]);
+        let sudoers = fs::read_to_string(&manager.config.sudoers_path).unwrap();
+        assert!(sudoers.contains("/test/command") && sudoers.contains("/test/other"));
+        manager.revoke_permission("testuser", "/test/command", "admin").await.unwrap();
+        manager.revoke_permission("testuser", "/test/other", "admin").await.unwrap();
+
+        // Every invalid command is reported and nothing is granted
+        let err = manager.grant_permissions_on_host(
+            "testuser", &commands(&["/test/command", "/bin/unknown", "/test/approved"]), Duration::minutes(30), "admin", GrantOptions::default(),
+        ).await.unwrap_err();
+        let PermissionError::BatchRejected { failures } = err else { panic!("unexpected error {:?}", err) };
+        assert!(matches!(failures.as_slice(), [
+            (unknown, PermissionError::CommandNotAllowed(_)),
+            (approved, PermissionError::NotBatchable(_)),
+        ] if unknown == "/bin/unknown" && approved == "/test/approved"));
+        assert!(!manager.check_permission("testuser", "/test/command").await.unwrap());
+
+        // A failure inside the transaction rolls back the grants before it
+        manager.db.grant_permission("root", "/test/other", Utc::now() + Duration::hours(1), "admin").await.unwrap();
+        let err = manager.grant_permissions_on_host(
+            "testuser", &commands(&["/test/command", "/test/other"]), Duration::minutes(30), "admin", GrantOptions::default(),
+        ).await.unwrap_err();
+        assert!(matches!(err, PermissionError::ConcurrentUserLimit { .. }));
+        assert!(!manager.check_permission("testuser", "/test/command").await.unwrap());
+
+        // A confirmation token cannot be spread over a batch
+        let err = manager.grant_permissions_on_host(
+            "testuser", &commands(&["/test/command", "/test/other"]), Duration::minutes(30), "admin",
+            GrantOptions { confirmation: Some("123456"), ..Default::default() },
+        ).await.unwrap_err();
+        assert!(matches!(err, PermissionError::User(_)));
+    }
+
+    #[tokio::test]
+    async fn test_conflicting_commands_cannot_be_held_together() {
+        let (mut manager, _temp) = create_test_manager().await;
+        let base = manager.config.allowed_commands["/test/command"].clone();
+        manager.config.allowed_commands.insert("/test/deploy-prod".to_string(), crate::config::CommandConfig {
+            conflicts_with: vec!["/test/command".to_string()],
+            ..base
+        });
+
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin")
+            .await.unwrap();
+
+        let err = manager.grant_permission("testuser", "/test/deploy-prod", Duration::minutes(30), "admin")
+            .await.unwrap_err();
+        assert!(matches!(
+            err,
+            PermissionError::ConflictingGrant { ref conflict, .. } if conflict == "/test/command"
+        ));
+
+        // Re-granting the held command is not a conflict, and revoking it
+        // frees the user to take the other one
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(10), "admin")
+            .await.unwrap();
+        manager.revoke_permission("testuser", "/test/command", "admin").await.unwrap();
+        manager.grant_permission("testuser", "/test/deploy-prod", Duration::minutes(30), "admin")
+            .await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_revoke_requires_reason_when_configured() {
+        let (mut manager, _temp) = create_test_manager().await;
+        manager.config.require_reason = true;
+
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin")
+            .await.unwrap();
+
+        let err = manager.revoke_permission("testuser", "/test/command", "admin")
+            .await.unwrap_err();
+        assert!(matches!(err, PermissionError::ReasonRequired(_)));
+
+        assert!(manager.revoke_permission_with_reason("testuser", "/test/command", "admin", Some("done"))
+            .await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_confirmation_token_completes_grant() {
+        let (mut manager, _temp) = create_test_manager().await;
+        manager.config.allowed_commands.get_mut("/test/command").unwrap().require_confirmation = true;
+        let grant = |confirm: Option<String>, duration: i64| {
+            let manager = &manager;
+            async move {
+                manager.grant_permission_on_host(
+                    "testuser", "/test/command", Duration::minutes(duration), "admin", GrantOptions { confirmation: confirm.as_deref(), ..Default::default() },
+                ).await
+            }
+        };
+
+        let token = match grant(None, 30).await.unwrap() {
+            GrantOutcome::ConfirmationRequired(confirmation) => confirmation.token,
+            other => panic!("expected a confirmation token, got {:?}", other),
+        };
+        assert_eq!(token.len(), 6);
+        assert!(!manager.check_permission("testuser", "/test/command").await.unwrap());
+
+        // A wrong token, or the right one for a different request, is refused
+        let wrong = if token == "000000" { "000001" } else { "000000" };
+        assert!(matches!(grant(Some(wrong.to_string()), 30).await, Err(PermissionError::InvalidConfirmation(_))));
+        assert!(matches!(grant(Some(token.clone()), 45).await, Err(PermissionError::InvalidConfirmation(_))));
+
+        assert!(grant(Some(token.clone()), 30).await.unwrap().granted().is_some());
+        assert!(manager.check_permission("testuser", "/test/command").await.unwrap());
+
+        // Tokens are single use
+        assert!(matches!(grant(Some(token), 30).await, Err(PermissionError::InvalidConfirmation(_))));
+
+        // Too many wrong tokens discard the confirmation, so the right one
+        // no longer works either
+        manager.revoke_permission("testuser", "/test/command", "admin").await.unwrap();
+        let token = match grant(None, 30).await.unwrap() {
+            GrantOutcome::ConfirmationRequired(confirmation) => confirmation.token,
+            other => panic!("expected a confirmation token, got {:?}", other),
+        };
+        let wrong = if token == "000000" { "000001" } else { "000000" };
+        for _ in 0..crate::db::MAX_CONFIRMATION_ATTEMPTS {
+            assert!(matches!(grant(Some(wrong.to_string()), 30).await, Err(PermissionError::InvalidConfirmation(_))));
+        }
+        assert!(matches!(grant(Some(token), 30).await, Err(PermissionError::InvalidConfirmation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_strict_clock_refuses_grants_after_backward_jump() {
+        let (mut manager, _temp) = create_test_manager().await;
+        manager.config.strict_clock = true;
+
+        // Simulate a mutation recorded while the clock was an hour ahead
+        sqlx::query("INSERT OR REPLACE INTO clock_state (id, last_seen) VALUES (1, ?)")
+            .bind(Utc::now() + Duration::hours(1))
+            .execute(manager.db.get_pool())
+            .await
+            .unwrap();
+
+        assert!(matches!(manager.check_clock().await, Err(PermissionError::ClockAnomaly { .. })));
+        let err = manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin")
+            .await.unwrap_err();
+        assert!(matches!(err, PermissionError::ClockAnomaly { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_approval_required_commands_wait_for_approve() {
+        let (mut manager, _temp) = create_test_manager().await;
+
+        manager.config.allowed_commands.get_mut("/test/command").unwrap().requires_approval = true;
+
+        let outcome = manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "testuser")
+            .await
+            .unwrap();
+        let GrantOutcome::Pending(request) = outcome else {
+            panic!("expected a pending request");
+        };
+        assert!(!manager.check_permission("testuser", "/test/command").await.unwrap());
+
+        let grant = manager.approve_request(request.id, "admin").await.unwrap().granted().unwrap();
+        assert_eq!(grant.username, "testuser");
+        assert!(manager.check_permission("testuser", "/test/command").await.unwrap());
+
+        // Once the flag is removed, leftover requests cannot be approved
+        let other = manager.grant_permission("testuser", "/test/command", Duration::minutes(10), "testuser")
+            .await
+            .unwrap();
+        let GrantOutcome::Pending(other) = other else {
+            panic!("expected a pending request");
+        };
+        manager.config.allowed_commands.get_mut("/test/command").unwrap().requires_approval = false;
+        let err = manager.approve_request(other.id, "admin").await.unwrap_err();
+        assert!(matches!(err, PermissionError::ApprovalNotRequired(_)));
+    }
+
+    #[tokio::test]
+    async fn test_approval_quorum() {
+        let (mut manager, _temp) = create_test_manager().await;
+        let cmd_config = manager.config.allowed_commands.get_mut("/test/command").unwrap();
+        cmd_config.requires_approval = true;
+        cmd_config.required_approvals = 2;
+
+        let GrantOutcome::Pending(request) = manager
+            .grant_permission("testuser", "/test/command", Duration::minutes(30), "requester")
+            .await
+            .unwrap()
+        else {
+            panic!("expected a pending request");
+        };
+        assert_eq!(manager.required_approvals(&request), 2);
+
+        for user in ["requester", "testuser"] {
+            let err = manager.approve_request(request.id, user).await.unwrap_err();
+            assert!(matches!(err, PermissionError::SelfApproval { .. }));
+        }
+
+        let outcome = manager.approve_request(request.id, "admin").await.unwrap();
+        assert!(matches!(&outcome, ApprovalOutcome::Recorded { request, required: 2 } if request.approvals == 1));
+        assert!(!manager.check_permission("testuser", "/test/command").await.unwrap());
+        assert_eq!(manager.list_pending_requests().await.unwrap()[0].approvals, 1);
+
+        let err = manager.approve_request(request.id, "admin").await.unwrap_err();
+        assert!(matches!(err, PermissionError::AlreadyApproved { .. }));
+
+        let grant = manager.approve_request(request.id, "root").await.unwrap().granted().unwrap();
+        assert_eq!(grant.granted_by, "root");
+        assert!(manager.check_permission("testuser", "/test/command").await.unwrap());
+        assert!(manager.list_pending_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stale_requests_expire_unapproved() {
+        let (mut manager, _temp) = create_test_manager().await;
+        manager.config.allowed_commands.get_mut("/test/command").unwrap().requires_approval = true;
+        manager.config.approval_request_ttl_minutes = Some(60);
+
+        let GrantOutcome::Pending(request) = manager
+            .grant_permission("testuser", "/test/command", Duration::minutes(30), "testuser")
+            .await
+            .unwrap()
+        else {
+            panic!("expected a pending request");
+        };
+        assert!(manager.expire_stale_requests().await.unwrap().is_empty());
+
+        sqlx::query("UPDATE grant_requests SET requested_at = ? WHERE id = ?")
+            .bind(Utc::now() - Duration::minutes(90))
+            .bind(request.id)
+            .execute(manager.db.get_pool())
+            .await
+            .unwrap();
+
+        // Past its deadline the request cannot be approved, even before cleanup
+        let err = manager.approve_request(request.id, "admin").await.unwrap_err();
+        assert!(matches!(err, PermissionError::AccessExpired));
+
+        let expired = manager.expire_stale_requests().await.unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].status, RequestStatus::ExpiredUnapproved);
+        assert!(manager.list_pending_requests().await.unwrap().is_empty());
+
+        let err = manager.approve_request(request.id, "admin").await.unwrap_err();
+        assert!(matches!(err, PermissionError::AccessExpired));
+        assert!(!manager.check_permission("testuser", "/test/command").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_grants_for_other_hosts_are_not_rendered() {
+        let (mut manager, _temp) = create_test_manager().await;
+        manager.hostname = "web1".to_string();
+
+        assert_eq!(manager.grant_scope(None).as_deref(), Some("web1"));
+        assert_eq!(manager.grant_scope(Some(ALL_HOSTS)), None);
+
+        manager.grant_permission_on_host("testuser", "/test/command", Duration::minutes(30), "admin", GrantOptions { host: Some("db1"), ..Default::default() })
+            .await
+            .unwrap();
+        assert!(!manager.check_permission("testuser", "/test/command").await.unwrap());
+        let sudoers = fs::read_to_string(&manager.config.sudoers_path).unwrap();
+        assert!(!sudoers.contains("testuser"));
+
+        // Grants default to the local host and sit alongside other hosts' ones
+        let grant = manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin")
+            .await
+            .unwrap()
+            .granted()
+            .unwrap();
+        assert_eq!(grant.host.as_deref(), Some("web1"));
+        assert!(manager.check_permission("testuser", "/test/command").await.unwrap());
+        let sudoers = fs::read_to_string(&manager.config.sudoers_path).unwrap();
+        assert!(sudoers.contains("testuser ALL="));
+        assert_eq!(manager.list_user_permissions("testuser").await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_freezes_grants() {
+        let (manager, _temp) = create_test_manager().await;
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+
+        assert!(manager.set_maintenance(true, "admin").await.unwrap());
+        assert!(!manager.set_maintenance(true, "admin").await.unwrap());
+        assert_eq!(manager.maintenance().await.unwrap().unwrap().enabled_by, "admin");
+        assert!(!manager.check_permission("testuser", "/test/command").await.unwrap());
+        let checked = manager.check_permissions("testuser", &["/test/command".to_string()]).await.unwrap();
+        assert!(!checked["/test/command"]);
+        let sudoers = fs::read_to_string(&manager.config.sudoers_path).unwrap();
+        assert!(!sudoers.contains("testuser"));
+        assert!(manager.sudoers_in_sync().await.unwrap());
+
+        assert!(manager.set_maintenance(false, "admin").await.unwrap());
+        assert!(manager.maintenance().await.unwrap().is_none());
+        assert!(manager.check_permission("testuser", "/test/command").await.unwrap());
+        let sudoers = fs::read_to_string(&manager.config.sudoers_path).unwrap();
+        assert!(sudoers.contains("testuser ALL="));
+    }
+
+    #[tokio::test]
+    async fn test_active_user_count() {
+        let (mut manager, _temp) = create_test_manager().await;
+        manager.config.allowed_commands.get_mut("/test/command").unwrap().max_concurrent_users = 2;
+        assert_eq!(manager.active_user_count("/test/command").await.unwrap(), 0);
+        assert_eq!(manager.active_user_count("/not/configured").await.unwrap(), 0);
+
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+        assert_eq!(manager.active_user_count("/test/command").await.unwrap(), 1);
+
+        // A re-grant replaces the user's grant rather than taking a second slot
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(45), "admin").await.unwrap();
+        assert_eq!(manager.active_user_count("/test/command").await.unwrap(), 1);
+
+        manager.db.grant_permission("otheruser", "/test/command", Utc::now() + Duration::hours(1), "admin").await.unwrap();
+        assert_eq!(manager.active_user_count("/test/command").await.unwrap(), 2);
+        let report = manager.quota_report().await.unwrap();
+        assert!(report.commands.iter().any(|quota| quota.command == "/test/command" && quota.at_limit()));
+
+        // Revoked and expired grants free their slot
+        manager.revoke_permission("testuser", "/test/command", "admin").await.unwrap();
+        manager.db.grant_permission("expired", "/test/command", Utc::now() - Duration::minutes(1), "admin").await.unwrap();
+        assert_eq!(manager.active_user_count("/test/command").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_quota_report() {
+        let (mut manager, _temp) = create_test_manager().await;
+        let cmd_config = manager.config.allowed_commands.get_mut("/test/command").unwrap();
+        cmd_config.max_concurrent_users = 1;
+        cmd_config.max_total_duration = Some(120);
+
+        let report = manager.quota_report().await.unwrap();
+        assert!(!report.commands[0].at_limit());
+        assert!(report.users.is_empty());
+
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+        let report = manager.quota_report().await.unwrap();
+        let command = report.commands.iter().find(|q| q.command == "/test/command").unwrap();
+        assert_eq!((command.active_users, command.max_concurrent_users), (1, Some(1)));
+        assert!(command.at_limit());
+
+        let [user] = report.users.as_slice() else { panic!("expected one user, got {:?}", report.users) };
+        assert_eq!((user.username.as_str(), user.active_grants, user.recent_grants), ("testuser", 1, 1));
+        assert_eq!(user.total_duration[0].limit_minutes, 120);
+        assert!((29..=30).contains(&user.total_duration[0].used_minutes));
+    }
+
+    #[tokio::test]
+    async fn test_grant_can_require_password() {
+        let (manager, _temp) = create_test_manager().await;
+
+        let rule = |manager: &PermissionManager| {
+            let sudoers = fs::read_to_string(&manager.config.sudoers_path).unwrap();
+            sudoers.lines().find(|line| line.starts_with("testuser ")).map(str::to_string)
+        };
+
+        manager.grant_permission_on_host("testuser", "/test/command", Duration::minutes(30), "admin", GrantOptions { require_password: true, ..Default::default() })
+            .await
+            .unwrap();
+        // The flag is read back from the database whenever sudoers is rebuilt
+        manager.cleanup_expired().await.unwrap();
+        assert_eq!(rule(&manager).as_deref(), Some("testuser ALL=(ALL) /test/command"));
+
+        manager.revoke_permission("testuser", "/test/command", "admin").await.unwrap();
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+        assert_eq!(rule(&manager).as_deref(), Some("testuser ALL=(ALL) NOPASSWD: /test/command"));
+    }
+
+    #[tokio::test]
+    async fn test_templated_commands_are_granted() {
+        let (mut manager, _temp) = create_test_manager().await;
+        manager.config.templates.insert("restart".to_string(), crate::config::CommandTemplate {
+            command: "/usr/bin/systemctl restart {service}".to_string(),
+            variables: HashMap::from([("service".to_string(), vec!["nginx".to_string()])]),
+            policy: manager.config.allowed_commands["/test/command"].clone(),
+        });
+
+        let vars = HashMap::from([("service".to_string(), "nginx".to_string())]);
+        let command = manager.config.render_template("restart", &vars).unwrap();
+        manager.grant_permission("testuser", &command, Duration::minutes(30), "admin").await.unwrap();
+
+        let sudoers = fs::read_to_string(&manager.config.sudoers_path).unwrap();
+        assert!(sudoers.contains("testuser ALL=(ALL) NOPASSWD: /usr/bin/systemctl restart nginx"));
+
+        // The template's idle timeout applies to the rendered command
+        manager.config.templates.get_mut("restart").unwrap().policy.idle_timeout_minutes = Some(10);
+        let next = manager.next_sudoers_change().await.unwrap().unwrap();
+        assert!(next <= Utc::now() + Duration::minutes(10));
+
+        // Rendered commands outside the template are not allowed
+        let err = manager.grant_permission("testuser", "/usr/bin/systemctl restart sshd", Duration::minutes(30), "admin")
+            .await.unwrap_err();
+        assert!(matches!(err, PermissionError::CommandNotAllowed(_)));
+    }
+
+    #[tokio::test]
+    async fn test_read_only_manager_refuses_changes() {
+        let (manager, _temp) = create_test_manager().await;
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+        let sudoers = fs::read_to_string(&manager.config.sudoers_path).unwrap();
+
+        let config = Config {
+            allowed_commands: manager.config.allowed_commands.clone(),
+            sudoers_path: manager.config.sudoers_path.clone(),
+            db_path: manager.config.db_path.clone(),
+            log_path: manager.config.log_path.clone(),
+            ..Config::default()
+        };
+        let reader = PermissionManager::new_readonly(config).await.unwrap();
+        assert!(reader.is_read_only());
+        assert!(reader.check_permission("testuser", "/test/command").await.unwrap());
+        assert_eq!(reader.list_user_permissions("testuser").await.unwrap().len(), 1);
+
+        let denied = [
+            reader.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.err(),
+            reader.revoke_permission("testuser", "/test/command", "admin").await.err(),
+            reader.cleanup_expired().await.err(),
+            reader.refresh_sudoers().await.err(),
+        ];
+        assert!(denied.iter().all(|e| matches!(e, Some(PermissionError::PermissionDenied(_)))));
+        assert!(manager.check_permission("testuser", "/test/command").await.unwrap());
+        assert_eq!(fs::read_to_string(&manager.config.sudoers_path).unwrap(), sudoers);
+    }
+
+    #[tokio::test]
+    async fn test_command_paths_are_escaped_in_sudoers() {
+        let (mut manager, _temp) = create_test_manager().await;
+        let policy = manager.config.allowed_commands["/test/command"].clone();
+        manager.config.allowed_commands.insert("/opt/my app/bin/tool".to_string(), policy);
+
+        manager.grant_permission("testuser", "/opt/my app/bin/tool", Duration::minutes(30), "admin").await.unwrap();
+
+        let sudoers = fs::read_to_string(&manager.config.sudoers_path).unwrap();
+        assert!(sudoers.contains(r"testuser ALL=(ALL) NOPASSWD: /opt/my\ app/bin/tool"));
+        assert!(manager.sudoers_in_sync().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sudoers_rules_are_sorted() {
+        let (manager, _temp) = create_test_manager().await;
+        let expires_at = Utc::now() + Duration::hours(1);
+        for (username, command) in [
+            ("zoe", "/usr/bin/b"),
+            ("adam", "/usr/bin/z"),
+            ("zoe", "/usr/bin/a"),
+            ("adam", "/usr/bin/c"),
+            ("mia", "/usr/bin/m"),
+        ] {
+            manager.db.grant_permission(username, command, expires_at, "admin").await.unwrap();
+        }
+        assert_eq!(manager.refresh_sudoers().await.unwrap(), 5);
+
+        let sudoers = fs::read_to_string(&manager.config.sudoers_path).unwrap();
+        let rules: Vec<&str> = sudoers.lines().filter(|line| !line.is_empty() && !line.starts_with('#')).collect();
+        assert_eq!(rules, [
+            "adam ALL=(ALL) NOPASSWD: /usr/bin/c",
+            "adam ALL=(ALL) NOPASSWD: /usr/bin/z",
+            "mia ALL=(ALL) NOPASSWD: /usr/bin/m",
+            "zoe ALL=(ALL) NOPASSWD: /usr/bin/a",
+            "zoe ALL=(ALL) NOPASSWD: /usr/bin/b",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_sudoers_in_sync() {
+        let (manager, _temp) = create_test_manager().await;
+        assert!(manager.sudoers_in_sync().await.unwrap());
+
+        let mut sudoers = fs::read_to_string(&manager.config.sudoers_path).unwrap();
+        sudoers.push_str("mallory ALL=(ALL) NOPASSWD: ALL\n");
+        fs::set_permissions(&manager.config.sudoers_path, fs::Permissions::from_mode(0o640)).unwrap();
+        fs::write(&manager.config.sudoers_path, sudoers).unwrap();
+        assert!(!manager.sudoers_in_sync().await.unwrap());
+
+        assert_eq!(manager.refresh_sudoers().await.unwrap(), 0);
+        assert!(manager.sudoers_in_sync().await.unwrap());
+
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin")
+            .await.unwrap();
+        assert_eq!(manager.refresh_sudoers().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_custom_sudoers_header() {
+        let (mut manager, _temp) = create_test_manager().await;
+        let sudoers = fs::read_to_string(&manager.config.sudoers_path).unwrap();
+        assert!(sudoers.starts_with(DEFAULT_SUDOERS_HEADER));
+        assert!(sudoers.contains("# Generated at "));
+
+        manager.config.sudoers_header = Some("# ACME Corp: access is audited".to_string());
+        manager.refresh_sudoers().await.unwrap();
+        let sudoers = fs::read_to_string(&manager.config.sudoers_path).unwrap();
+        let mut lines = sudoers.lines();
+        assert_eq!(lines.next(), Some("# ACME Corp: access is audited"));
+        assert!(lines.next().unwrap().starts_with("# Generated at "));
+    }
+
+    #[tokio::test]
+    async fn test_disabled_sudoers_is_never_written() {
+        let temp_dir = TempDir::new().unwrap();
+        let sudoers_path = temp_dir.path().join("sudoers.d").join("permctl");
+        let config = Config {
+            sudoers_path: sudoers_path.clone(),
+            sudoers_enabled: false,
+            db_path: temp_dir.path().join("test.db"),
+            log_path: temp_dir.path().join("test.log"),
+            ..Config::default()
         };
 
+        let manager = PermissionManager::new(config).await.unwrap();
+        manager.refresh_sudoers().await.unwrap();
+        assert!(!sudoers_path.exists());
+        assert!(!sudoers_path.parent().unwrap().exists());
+    }
+
+    #[tokio::test]
+    async fn test_builder_with_injected_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let sudoers_path = temp_dir.path().join("sudoers.d").join("permctl");
+        let mut config = Config {
+            sudoers_path: sudoers_path.clone(),
+            db_path: temp_dir.path().join("unused").join("test.db"),
+            log_path: temp_dir.path().join("unused").join("test.log"),
+            ..Config::default()
+        };
         config.allowed_commands.insert(
             "/test/command".to_string(),
-            crate::config::CommandConfig {
-                description: "Test command".to_string(),
-                max_duration: 60,
-                required_groups: vec!["users".to_string()],
-                audit_usage: true,
-                max_concurrent_users: 5,
-            },
+            serde_yaml::from_str("{description: Test command, max_duration: 60, required_groups: [users]}").unwrap(),
         );
 
-        let manager = PermissionManager::new(config).await.unwrap();
-        (manager, temp_dir)
+        let store = Database::new(temp_dir.path().join("embedded.db")).await.unwrap();
+        let manager = PermissionManager::builder()
+            .config(config)
+            .manage_sudoers(false)
+            .identity_provider(MockIdentity(HashMap::from([("svcuser", vec!["users"])])))
+            .store(store)
+            .build()
+            .await
+            .unwrap();
+
+        manager.grant_permission("svcuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+        assert!(manager.check_permission("svcuser", "/test/command").await.unwrap());
+        assert!(!sudoers_path.exists());
+        assert!(!temp_dir.path().join("unused").exists());
     }
 
     #[tokio::test]
-    async fn test_grant_and_revoke_permission() {
+    async fn test_writable_command_targets_are_rejected() {
+        let (mut manager, temp) = create_test_manager().await;
+        let script = temp.path().join("deploy.sh");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o777)).unwrap();
+        let command = script.to_str().unwrap().to_string();
+        let policy = manager.config.allowed_commands["/test/command"].clone();
+        manager.config.allowed_commands.insert(command.clone(), policy);
+
+        // Only warned about by default
+        manager.grant_permission("testuser", &command, Duration::minutes(30), "admin").await.unwrap();
+        manager.revoke_permission("testuser", &command, "admin").await.unwrap();
+
+        manager.config.reject_writable_commands = true;
+        let err = manager.grant_permission("testuser", &command, Duration::minutes(30), "admin").await.unwrap_err();
+        assert!(matches!(err, PermissionError::UnsafeCommandTarget { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_grant_reason_must_match_pattern() {
+        let (mut manager, _temp) = create_test_manager().await;
+        manager.config.allowed_commands.get_mut("/test/command").unwrap().reason_pattern = Some(crate::config::ReasonPattern::new(r"^(JIRA|INC)-\d+").unwrap());
+        let grant = |reason: Option<&'static str>| {
+            let manager = &manager;
+            async move {
+                manager.grant_permission_on_host(
+                    "testuser", "/test/command", Duration::minutes(30), "admin", GrantOptions { reason, ..Default::default() },
+                ).await
+            }
+        };
+
+        for reason in [None, Some("  "), Some("restarting nginx")] {
+            let err = grant(reason).await.unwrap_err();
+            assert!(matches!(err, PermissionError::Config(ref msg) if msg.starts_with("reason must match pattern")));
+        }
+
+        grant(Some("INC-4711 nginx is down")).await.unwrap();
+        let audit = manager.audit_entries(&AuditQuery { action: Some("grant".to_string()), ..AuditQuery::default() }).await.unwrap();
+        assert!(audit[0].details.as_deref().unwrap().contains("INC-4711 nginx is down"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_usernames_are_rejected() {
+        let (mut manager, _temp) = create_test_manager().await;
+
+        for username in ["root\nattacker", "bob ALL=(ALL) ALL", "Alice", "-rf", "a,b", ""] {
+            let err = manager.grant_permission(username, "/test/command", Duration::minutes(30), "admin")
+                .await.unwrap_err();
+            assert!(matches!(err, PermissionError::InvalidUsername(_)), "{:?} was not rejected", username);
+        }
+        assert!(manager.list_active_permissions(&ListOptions::all()).await.unwrap().is_empty());
+
+        // A custom pattern still cannot let a newline through
+        manager.config.username_pattern = Some("(?s).+".to_string());
+        assert!(manager.validate_username("ad.user@corp").is_ok());
+        assert!(manager.validate_username("root\nattacker").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_grant_from_past_is_rejected() {
         let (manager, _temp) = create_test_manager().await;
-        
-        let id = manager.grant_permission(
+
+        let err = manager.grant_permission_from(
             "testuser",
             "/test/command",
+            Some(Utc::now() - Duration::minutes(5)),
             Duration::minutes(30),
-            "admin"
-        ).await.unwrap();
+            "admin",
+        ).await.unwrap_err();
+        assert!(matches!(err, PermissionError::InvalidDuration(_)));
+    }
 
-        assert!(id > 0);
+    #[tokio::test]
+    async fn test_grant_until_expiry_time() {
+        let (manager, _temp) = create_test_manager().await;
 
-        let revoked = manager.revoke_permission(
-            "testuser",
-            "/test/command",
-            "admin"
+        for expires_at in [Utc::now() - Duration::minutes(5), Utc::now() + Duration::minutes(90)] {
+            let err = manager.grant_permission_until("testuser", "/test/command", expires_at, "admin").await.unwrap_err();
+            assert!(matches!(err, PermissionError::InvalidDuration(_)));
+        }
+
+        let expires_at = Utc::now() + Duration::minutes(30);
+        let grant = manager.grant_permission_until("testuser", "/test/command", expires_at, "admin")
+            .await
+            .unwrap()
+            .granted()
+            .unwrap();
+        assert_eq!(grant.expires_at.timestamp(), expires_at.timestamp());
+        assert!(manager.check_permission("testuser", "/test/command").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_grant_duration_out_of_range_is_rejected() {
+        let (mut manager, _temp) = create_test_manager().await;
+
+        for duration in [Duration::zero(), Duration::minutes(-30)] {
+            let err = manager.grant_permission("testuser", "/test/command", duration, "admin").await.unwrap_err();
+            assert!(matches!(err, PermissionError::InvalidDuration(ref msg) if msg.contains("positive")));
+        }
+
+        // Even when policy allows it, an unrepresentable expiry is an error
+        manager.config.allowed_commands.get_mut("/test/command").unwrap().max_duration = i64::MAX;
+        let huge = Duration::try_minutes(i64::MAX).unwrap_or(Duration::MAX);
+        let err = manager.grant_permission("testuser", "/test/command", huge, "admin").await.unwrap_err();
+        assert!(matches!(err, PermissionError::InvalidDuration(ref msg) if msg == "duration too large"));
+        assert!(!manager.check_permission("testuser", "/test/command").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_grant_below_minimum_duration_is_rejected() {
+        let (mut manager, _temp) = create_test_manager().await;
+        manager.config.allowed_commands.get_mut("/test/command").unwrap().min_duration = Some(30);
+
+        let err = manager.grant_permission("testuser", "/test/command", Duration::minutes(29), "admin").await.unwrap_err();
+        assert!(matches!(err, PermissionError::InvalidDuration(ref msg) if msg.contains("30 minutes")));
+
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_changes_are_published_to_subscribers() {
+        let (manager, _temp) = create_test_manager().await;
+        let mut events = manager.subscribe();
+
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+        manager.revoke_permission("testuser", "/test/command", "admin").await.unwrap();
+        manager.db.grant_permission("olduser", "/test/command", Utc::now() - Duration::minutes(1), "admin").await.unwrap();
+        manager.cleanup_expired().await.unwrap();
+
+        let received: Vec<(EventKind, String)> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| (event.kind, event.grant.username))
+            .collect();
+        assert_eq!(received, vec![
+            (EventKind::Granted, "testuser".to_string()),
+            (EventKind::Revoked, "testuser".to_string()),
+            (EventKind::Expired, "olduser".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_admin_groups_gate_operators() {
+        let (mut manager, _temp) = create_test_manager().await;
+        assert!(PermissionManager::authorize_admin(&manager.config, "nobody").is_ok());
+
+        manager.config.admin_groups = vec!["no-such-group-permctl".to_string()];
+        assert!(PermissionManager::authorize_admin(&manager.config, "root").is_ok());
+        let err = PermissionManager::authorize_admin(&manager.config, "nobody").unwrap_err();
+        assert!(err.is_permission_denied());
+
+        manager.config.admin_root_bypass = false;
+        assert!(PermissionManager::authorize_admin(&manager.config, "root").is_err());
+
+        manager.config.admin_groups.push("root".to_string());
+        assert!(PermissionManager::authorize_admin(&manager.config, "root").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_grants_of_deleted_users_are_orphans() {
+        let (manager, _temp) = create_test_manager().await;
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+        let expires_at = Utc::now() + Duration::minutes(30);
+        manager.db.grant_permission("deleted-user-permctl", "/test/command", expires_at, "admin").await.unwrap();
+        // The account may well exist on the host this grant is for
+        manager.db.grant_permission_with_limit(
+            "deleted-user-permctl", "/test/other", Some("elsewhere"), None, expires_at, "admin", None, None, false, None, None, None,
         ).await.unwrap();
 
-        assert!(revoked);
+        assert_eq!(manager.orphaned_users().await.unwrap(), vec!["deleted-user-permctl"]);
+
+        let revoked = manager.revoke_orphaned("admin").await.unwrap();
+        assert_eq!(revoked.len(), 1);
+        assert_eq!(revoked[0].username, "deleted-user-permctl");
+        assert_eq!(revoked[0].command, "/test/command");
+        assert!(manager.orphaned_users().await.unwrap().is_empty());
+        assert!(manager.check_permission("testuser", "/test/command").await.unwrap());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_active_during_requires_ordered_range() {
+        let (manager, _temp) = create_test_manager().await;
+        manager.grant_permission("testuser", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+
+        let now = Utc::now();
+        let err = manager.list_active_during(now, now - Duration::hours(1), None).await.unwrap_err();
+        assert!(matches!(err, PermissionError::InvalidDuration(_)));
+
+        let grants = manager.list_active_during(now - Duration::hours(1), now, None).await.unwrap();
+        assert_eq!(grants.len(), 1);
+        assert!(manager.list_active_during(now - Duration::hours(1), now, Some("other")).await.unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_grants_respect_user_limit() {
+        let (mut manager, _temp) = create_test_manager().await;
+        manager.config.allowed_commands.insert(
+            "/test/exclusive".to_string(),
+            crate::config::CommandConfig {
+                required_groups: vec![],
+                max_concurrent_users: 1,
+                ..manager.config.allowed_commands["/test/command"].clone()
+            },
+        );
+        let manager = std::sync::Arc::new(manager);
+
+        let handles: Vec<_> = ["root", "daemon", "bin", "nobody"]
+            .into_iter()
+            .map(|user| {
+                let manager = manager.clone();
+                tokio::spawn(async move {
+                    manager.grant_permission(user, "/test/exclusive", Duration::minutes(10), "admin").await
+                })
+            })
+            .collect();
+
+        let mut granted = 0;
+        for handle in handles {
+            match handle.await.unwrap() {
+                Ok(_) => granted += 1,
+                Err(e) => assert!(matches!(e, PermissionError::ConcurrentUserLimit { limit: 1, .. }), "{:?}", e),
+            }
+        }
+        assert_eq!(granted, 1);
+    }
+}
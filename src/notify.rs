@@ -0,0 +1,155 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::db::PermissionGrant;
+use crate::error::{PermissionError, Result};
+use crate::manager::PermissionEvent;
+
+/// Seconds curl may spend delivering one notification
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+
+/// Whether `url` is an absolute http(s) URL that can be handed to curl
+pub fn is_valid_webhook_url(url: &str) -> bool {
+    let Some(rest) = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://")) else {
+        return false;
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    !host.is_empty() && !url.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// POST `event` as JSON to `url` with curl. The URL and body are handed
+/// over on curl's standard input as a config file, so a token in the URL
+/// never shows up in the process list, before returning; curl finishes in
+/// the background, so a slow endpoint never holds up the change that
+/// triggered it, and the runtime waits for pending deliveries before the
+/// process exits. Delivery is best effort: only failing to start curl is
+/// reported.
+pub fn send_webhook(url: &str, event: &PermissionEvent) -> Result<()> {
+    let body = serde_json::to_string(event)
+        .map_err(|e| PermissionError::Config(format!("Failed to serialize notification: {}", e)))?;
+
+    let mut child = Command::new("curl")
+        .args(["--silent", "--fail", "--max-time", &WEBHOOK_TIMEOUT_SECS.to_string()])
+        .args(["--header", "Content-Type: application/json", "--config", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| PermissionError::system_command(e, "curl"))?;
+
+    // The body is far smaller than a pipe buffer, so this does not block
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(curl_config(url, &body).as_bytes()).map_err(|e| PermissionError::system_command(e, "curl"))?;
+    }
+    tokio::task::spawn_blocking(move || child.wait());
+
+    Ok(())
+}
+
+/// curl config file posting `body` to `url`
+fn curl_config(url: &str, body: &str) -> String {
+    format!("url = {}\ndata-binary = {}\n", curl_quote(url), curl_quote(body))
+}
+
+/// `value` as a double-quoted curl config string
+fn curl_quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Message telling the holder of `grant` what they may now run and until
+/// when
+pub fn grant_message(grant: &PermissionGrant) -> String {
+    let mut message = format!("permctl: {} granted you {}", grant.granted_by, grant.command);
+    if let Some(from) = grant.active_from {
+        message.push_str(&format!(" from {}", from.format("%Y-%m-%d %H:%M UTC")));
+    }
+    let minutes = (grant.expires_at - grant.effective_start()).num_minutes();
+    message.push_str(&format!(
+        " until {} ({} minutes).\nRun it with: sudo {}\n",
+        grant.expires_at.format("%Y-%m-%d %H:%M UTC"),
+        minutes,
+        grant.command
+    ));
+    message
+}
+
+/// Show `message` on the terminals `username` is logged in on with
+/// write(1). Fails if write cannot be started or reports an error, such as
+/// the user not being logged in or refusing messages.
+pub fn write_to_user(username: &str, message: &str) -> Result<()> {
+    let mut child = Command::new("write")
+        .arg(username)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| PermissionError::system_command(e, "write"))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(message.as_bytes()).map_err(|e| PermissionError::system_command(e, "write"))?;
+    }
+    let output = child.wait_with_output().map_err(|e| PermissionError::system_command(e, "write"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(PermissionError::system_command(std::io::Error::other(stderr.trim().to_string()), "write"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_webhook_url_validation() {
+        assert!(is_valid_webhook_url("https://hooks.example.com/dba"));
+        assert!(is_valid_webhook_url("http://localhost:8080"));
+        assert!(!is_valid_webhook_url("hooks.example.com/dba"));
+        assert!(!is_valid_webhook_url("ftp://example.com"));
+        assert!(!is_valid_webhook_url("https:///path"));
+        assert!(!is_valid_webhook_url("https://example.com/a b"));
+    }
+
+    #[test]
+    fn test_curl_config_quotes_values() {
+        let config = curl_config("https://hooks.example.com/T0K3N", r#"{"command":"/opt/a \"b\""}"#);
+        assert_eq!(config, r#"url = "https://hooks.example.com/T0K3N"
+data-binary = "{\"command\":\"/opt/a \\\"b\\\"\"}"
+"#);
+    }
+
+    #[test]
+    fn test_grant_message() {
+        let grant = PermissionGrant {
+            id: 1,
+            username: "alice".to_string(),
+            command: "/usr/bin/docker".to_string(),
+            granted_at: "2024-06-01T09:00:00Z".parse().unwrap(),
+            expires_at: "2024-06-01T10:30:00Z".parse().unwrap(),
+            granted_by: "admin".to_string(),
+            active_from: Some("2024-06-01T10:00:00Z".parse().unwrap()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            grant_message(&grant),
+            "permctl: admin granted you /usr/bin/docker from 2024-06-01 10:00 UTC until 2024-06-01 10:30 UTC (30 minutes).\n\
+             Run it with: sudo /usr/bin/docker\n"
+        );
+    }
+}
@@ -0,0 +1,576 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use chrono::{Duration, Utc};
+use nix::unistd::{Gid, Group, Uid, User};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+use crate::db::ListOptions;
+use crate::error::{PermissionError, Result};
+use crate::manager::{GrantOptions, PermissionEvent, PermissionManager};
+
+/// Default location of the API socket
+pub const DEFAULT_SOCKET_PATH: &str = "/run/permctl/permctl.sock";
+/// Number of grants returned by `list` when no limit is given
+const DEFAULT_PAGE_SIZE: i64 = 100;
+/// Longest the daemon waits between sudoers refreshes, which bounds how long
+/// it can miss an expiry for a grant created by another process
+const MAX_REFRESH_INTERVAL_SECS: u64 = 60;
+/// Recorded as revoking the grants the daemon's group revalidation ends
+const REVALIDATION_ACTOR: &str = "permctl";
+/// Longest request line accepted, in bytes, so a client cannot make the
+/// daemon buffer without bound
+const MAX_REQUEST_BYTES: u64 = 64 * 1024;
+
+/// A request sent as a single line of JSON
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum ApiRequest {
+    Grant {
+        username: String,
+        command: String,
+        duration_minutes: i64,
+        /// Defaults to the server's host; `*` applies on all hosts
+        #[serde(default)]
+        host: Option<String>,
+        /// Token from an earlier attempt, for commands that require
+        /// confirmation
+        #[serde(default)]
+        confirm: Option<String>,
+        /// Make sudo ask for the user's own password even if the command
+        /// does not
+        #[serde(default)]
+        require_password: bool,
+        /// Why the grant is needed; required by commands with a
+        /// `reason_pattern`
+        #[serde(default)]
+        reason: Option<String>,
+        /// User the caller is granting for, such as the person whose request
+        /// an automation is acting on
+        #[serde(default)]
+        on_behalf_of: Option<String>,
+    },
+    Revoke {
+        username: String,
+        command: String,
+        #[serde(default)]
+        reason: Option<String>,
+    },
+    Approve {
+        request_id: i64,
+    },
+    Check {
+        username: String,
+        command: String,
+    },
+    List {
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default = "default_page_size")]
+        limit: i64,
+        #[serde(default)]
+        offset: i64,
+    },
+    /// Switch the connection to a stream of events, one per line, for every
+    /// grant, revocation and expiry made by the daemon
+    Subscribe,
+}
+
+fn default_page_size() -> i64 {
+    DEFAULT_PAGE_SIZE
+}
+
+/// The response written back for each request, as a single line of JSON
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ApiResponse {
+    Ok { result: serde_json::Value },
+    Error {
+        message: String,
+        /// Correlation ID of the grant the error concerns, for finding its
+        /// history in the logs and audit table
+        #[serde(skip_serializing_if = "Option::is_none")]
+        correlation_id: Option<String>,
+    },
+    /// A change to a grant, sent to subscribed connections
+    Event { event: Box<PermissionEvent> },
+    /// A subscriber fell behind and this many events were dropped
+    Lagged { skipped: u64 },
+}
+
+impl ApiRequest {
+    /// Whether the request can change which grants are active
+    fn changes_grants(&self) -> bool {
+        matches!(self, Self::Grant { .. } | Self::Approve { .. } | Self::Revoke { .. })
+    }
+}
+
+impl From<Result<serde_json::Value>> for ApiResponse {
+    fn from(result: Result<serde_json::Value>) -> Self {
+        match result {
+            Ok(result) => Self::Ok { result },
+            Err(e) => Self::Error {
+                message: e.to_string(),
+                correlation_id: e.correlation_id().map(str::to_string),
+            },
+        }
+    }
+}
+
+/// Credentials of a connected client
+#[derive(Debug, Clone)]
+struct Peer {
+    uid: Uid,
+    gid: Gid,
+    name: String,
+}
+
+/// Serve the JSON API on a Unix domain socket until SIGINT or SIGTERM, then
+/// flush queued audit entries and remove the socket. The database gets a
+/// full integrity check before serving starts.
+///
+/// While serving, the sudoers file is also rewritten at each grant expiry.
+/// Only root, the user the daemon runs as and, if configured, members of
+/// `api_group` may connect; this is enforced from the peer credentials
+/// (`SO_PEERCRED`) of each connection. Requests longer than
+/// `MAX_REQUEST_BYTES` are refused and the connection closed.
+pub async fn serve(manager: Arc<PermissionManager>, socket_path: &Path) -> Result<()> {
+    manager.integrity_check().await?;
+
+    if let Some(parent) = socket_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| PermissionError::io_error(e, parent.to_path_buf()))?;
+    }
+
+    // Remove a stale socket left behind by a previous run
+    if socket_path.exists() {
+        fs::remove_file(socket_path)
+            .map_err(|e| PermissionError::io_error(e, socket_path.to_path_buf()))?;
+    }
+
+    let listener = UnixListener::bind(socket_path)
+        .map_err(|e| PermissionError::io_error(e, socket_path.to_path_buf()))?;
+
+    let api_group = match &manager.config().api_group {
+        Some(name) => {
+            let group = Group::from_name(name)
+                .map_err(|e| PermissionError::Config(format!("Failed to look up api_group {}: {}", name, e)))?
+                .ok_or_else(|| PermissionError::Config(format!("api_group {} does not exist", name)))?;
+
+            std::os::unix::fs::chown(socket_path, None, Some(group.gid.as_raw()))
+                .map_err(|e| PermissionError::io_error(e, socket_path.to_path_buf()))?;
+            Some(group)
+        }
+        None => None,
+    };
+
+    let mode = if api_group.is_some() { 0o660 } else { 0o600 };
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(mode))
+        .map_err(|e| PermissionError::io_error(e, socket_path.to_path_buf()))?;
+
+    info!("Listening on {}", socket_path.display());
+    let api_group = Arc::new(api_group);
+
+    let grants_changed = Arc::new(Notify::new());
+    tokio::spawn(refresh_sudoers_loop(manager.clone(), grants_changed.clone()));
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = &mut shutdown => break,
+        };
+        let (stream, _) = accepted
+            .map_err(|e| PermissionError::io_error(e, socket_path.to_path_buf()))?;
+
+        let manager = manager.clone();
+        let api_group = api_group.clone();
+        let grants_changed = grants_changed.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(manager, stream, api_group.as_ref().as_ref(), &grants_changed).await {
+                warn!("API connection failed: {}", e);
+            }
+        });
+    }
+
+    info!("Shutting down");
+    manager.flush_audit().await;
+    let _ = fs::remove_file(socket_path);
+    Ok(())
+}
+
+/// Resolve on SIGINT or SIGTERM
+pub(crate) async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            warn!("Failed to listen for SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+/// Rewrite the sudoers file whenever a grant expires or a scheduled grant
+/// starts, so the file never keeps honoring a grant past its `expires_at`
+/// while waiting for the next cleanup run. Expired grants are cleaned up at
+/// the same time, which publishes their expiry to event subscribers, and with
+/// `revalidate_groups` grants whose holder left a required group are revoked.
+/// Grants left unused past their command's idle timeout are revoked as well.
+/// Grant requests pending past `approval_request_ttl_minutes` are expired.
+/// Requests that change grants wake the loop early so it can pick up a
+/// sooner deadline.
+pub(crate) async fn refresh_sudoers_loop(manager: Arc<PermissionManager>, grants_changed: Arc<Notify>) {
+    let max_wait = std::time::Duration::from_secs(MAX_REFRESH_INTERVAL_SECS);
+
+    loop {
+        let wait = match manager.next_sudoers_change().await {
+            Ok(Some(at)) => (at - Utc::now()).to_std().unwrap_or_default().min(max_wait),
+            Ok(None) => max_wait,
+            Err(e) => {
+                manager.warn_deduplicated(&format!("Failed to find next grant expiry: {}", e));
+                max_wait
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {
+                debug!("Refreshing sudoers file");
+                if let Err(e) = manager.cleanup_expired().await {
+                    manager.warn_deduplicated(&format!("Failed to clean up expired permissions: {}", e));
+                }
+                if let Err(e) = manager.expire_stale_requests().await {
+                    manager.warn_deduplicated(&format!("Failed to expire stale grant requests: {}", e));
+                }
+                if let Err(e) = manager.revoke_idle(REVALIDATION_ACTOR).await {
+                    manager.warn_deduplicated(&format!("Failed to revoke idle permissions: {}", e));
+                }
+                if manager.config().revalidate_groups {
+                    if let Err(e) = manager.revalidate_groups(REVALIDATION_ACTOR).await {
+                        manager.warn_deduplicated(&format!("Failed to revalidate group membership: {}", e));
+                    }
+                }
+                if let Err(e) = manager.refresh_sudoers().await {
+                    manager.warn_deduplicated(&format!("Failed to refresh sudoers file: {}", e));
+                }
+            }
+            _ = grants_changed.notified() => {}
+        }
+    }
+}
+
+async fn handle_connection(
+    manager: Arc<PermissionManager>,
+    stream: UnixStream,
+    api_group: Option<&Group>,
+    grants_changed: &Notify,
+) -> std::io::Result<()> {
+    let cred = stream.peer_cred()?;
+    let uid = Uid::from_raw(cred.uid());
+    let gid = Gid::from_raw(cred.gid());
+    let name = User::from_uid(uid)
+        .ok()
+        .flatten()
+        .map(|user| user.name)
+        .unwrap_or_else(|| uid.to_string());
+    let peer = Peer { uid, gid, name };
+
+    let (reader, mut writer) = stream.into_split();
+
+    if !is_authorized(&peer, api_group) {
+        warn!("Rejected API connection from {} (uid {})", peer.name, peer.uid);
+        let response = ApiResponse::Error {
+            message: PermissionError::PermissionDenied(format!("{} may not use the permctl API", peer.name)).to_string(),
+            correlation_id: None,
+        };
+        write_response(&mut writer, &response).await?;
+        return Ok(());
+    }
+
+    debug!("Accepted API connection from {} (uid {})", peer.name, peer.uid);
+
+    let mut reader = BufReader::new(reader);
+    loop {
+        let line = match next_request(&mut reader).await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                warn!("Closing API connection from {}: {}", peer.name, e);
+                let response = ApiResponse::Error { message: format!("Invalid request: {}", e), correlation_id: None };
+                return write_response(&mut writer, &response).await;
+            }
+            Err(e) => return Err(e),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<ApiRequest>(&line) {
+            Ok(ApiRequest::Subscribe) => {
+                // Subscribe before acknowledging so no event is missed
+                let events = manager.subscribe();
+                write_response(&mut writer, &ApiResponse::Ok { result: json!({ "subscribed": true }) }).await?;
+                debug!("{} subscribed to events", peer.name);
+                return stream_events(events, &mut reader, &mut writer).await;
+            }
+            Ok(request) => {
+                let changes_grants = request.changes_grants();
+                let response = handle_request(&manager, request, &peer.name).await;
+                if changes_grants {
+                    grants_changed.notify_one();
+                }
+                response
+            }
+            Err(e) => ApiResponse::Error {
+                message: format!("Invalid request: {}", e),
+                correlation_id: None,
+            },
+        };
+        write_response(&mut writer, &response).await?;
+    }
+
+    Ok(())
+}
+
+/// Read the next request line from `reader`, without its newline, or
+/// `None` once the client has closed the connection. A line longer than
+/// `MAX_REQUEST_BYTES` or not valid UTF-8 is an `InvalidData` error.
+async fn next_request<R>(reader: &mut R) -> std::io::Result<Option<String>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut line = Vec::new();
+    if reader.take(MAX_REQUEST_BYTES + 1).read_until(b'\n', &mut line).await? == 0 {
+        return Ok(None);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    } else if line.len() as u64 > MAX_REQUEST_BYTES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("request is longer than {} bytes", MAX_REQUEST_BYTES),
+        ));
+    }
+    String::from_utf8(line).map(Some).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Write each event to a subscribed client until it disconnects. Anything
+/// else the client sends is ignored. A client that reads too slowly loses
+/// the oldest events rather than holding up the daemon, and is sent a
+/// `lagged` line with how many it missed.
+async fn stream_events<R, W>(
+    mut events: broadcast::Receiver<PermissionEvent>,
+    reader: &mut R,
+    writer: &mut W,
+) -> std::io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => write_response(writer, &ApiResponse::Event { event: Box::new(event) }).await?,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Event subscriber fell behind; dropped {} event(s)", skipped);
+                    write_response(writer, &ApiResponse::Lagged { skipped }).await?;
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            line = next_request(reader) => {
+                if line?.is_none() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn is_authorized(peer: &Peer, api_group: Option<&Group>) -> bool {
+    if peer.uid.is_root() || peer.uid == Uid::effective() {
+        return true;
+    }
+
+    match api_group {
+        Some(group) => peer.gid == group.gid || group.mem.contains(&peer.name),
+        None => false,
+    }
+}
+
+/// Run a single API request against the manager on behalf of `caller`
+pub async fn handle_request(manager: &PermissionManager, request: ApiRequest, caller: &str) -> ApiResponse {
+    let result = match request {
+        ApiRequest::Grant { username, command, duration_minutes, host, confirm, require_password, reason, on_behalf_of } => match Duration::try_minutes(duration_minutes) {
+            Some(duration) => manager
+                .grant_permission_on_host(&username, &command, duration, caller, GrantOptions {
+                    host: host.as_deref(),
+                    require_password,
+                    confirmation: confirm.as_deref(),
+                    reason: reason.as_deref(),
+                    on_behalf_of: on_behalf_of.as_deref(),
+                    ..Default::default()
+                })
+                .await
+                .and_then(|outcome| to_json(&outcome)),
+            None => Err(PermissionError::InvalidDuration("duration too large".to_string())),
+        },
+        ApiRequest::Approve { request_id } => manager
+            .approve_request(request_id, caller)
+            .await
+            .and_then(|grant| to_json(&grant)),
+        ApiRequest::Revoke { username, command, reason } => manager
+            .revoke_permission_with_reason(&username, &command, caller, reason.as_deref())
+            .await
+            .map(|revoked| json!({ "revoked": revoked })),
+        ApiRequest::Check { username, command } => manager
+            .check_permission(&username, &command)
+            .await
+            .map(|allowed| json!({ "allowed": allowed })),
+        ApiRequest::List { username, limit, offset } => manager
+            .list_permissions_page(&ListOptions {
+                username,
+                limit: Some(limit),
+                offset,
+                ..ListOptions::default()
+            })
+            .await
+            .and_then(|page| to_json(&page)),
+        ApiRequest::Subscribe => Err(PermissionError::PermissionDenied(
+            "events can only be subscribed to over the API socket".to_string(),
+        )),
+    };
+
+    result.into()
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<serde_json::Value> {
+    serde_json::to_value(value)
+        .map_err(|e| PermissionError::Config(format!("Failed to serialize response: {}", e)))
+}
+
+async fn write_response<W>(writer: &mut W, response: &ApiResponse) -> std::io::Result<()>
+where
+    W: AsyncWriteExt + Unpin,
+{
+    let mut line = serde_json::to_string(response)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CommandConfig, Config};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_socket_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = Config {
+            sudoers_path: temp_dir.path().join("sudoers"),
+            db_path: temp_dir.path().join("test.db"),
+            log_path: temp_dir.path().join("test.log"),
+            ..Config::default()
+        };
+        let manager = Arc::new(PermissionManager::new(config).await.unwrap());
+        let socket_path = temp_dir.path().join("permctl.sock");
+
+        let server = tokio::spawn({
+            let socket_path = socket_path.clone();
+            async move { serve(manager, &socket_path).await }
+        });
+        while !socket_path.exists() {
+            tokio::task::yield_now().await;
+        }
+
+        let stream = UnixStream::connect(&socket_path).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        writer.write_all(b"{\"method\":\"check\",\"username\":\"nobody\",\"command\":\"/usr/bin/docker\"}\n").await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(response, json!({ "status": "ok", "result": { "allowed": false } }));
+
+        writer.write_all(b"{\"method\":\"unknown\"}\n").await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(response["status"], "error");
+
+        let request = format!(
+            "{{\"method\":\"grant\",\"username\":\"root\",\"command\":\"/usr/bin/docker\",\"duration_minutes\":{}}}\n",
+            i64::MAX
+        );
+        writer.write_all(request.as_bytes()).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(response["status"], "error");
+        assert!(response["message"].as_str().unwrap().contains("duration too large"));
+
+        // An oversized request is refused and the connection closed
+        let oversized = vec![b' '; MAX_REQUEST_BYTES as usize + 1];
+        writer.write_all(&oversized).await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(response["status"], "error");
+        assert!(response["message"].as_str().unwrap().contains("longer than"));
+        assert!(lines.next_line().await.unwrap().is_none());
+
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_streams_events() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = Config {
+            sudoers_path: temp_dir.path().join("sudoers"),
+            db_path: temp_dir.path().join("test.db"),
+            log_path: temp_dir.path().join("test.log"),
+            ..Config::default()
+        };
+        config.allowed_commands.insert("/test/command".to_string(), CommandConfig {
+            description: "Test command".to_string(),
+            max_duration: 60,
+            required_groups: Vec::new(),
+            max_concurrent_users: 5,
+            ..Default::default()
+        });
+        let manager = Arc::new(PermissionManager::new(config).await.unwrap());
+        let socket_path = temp_dir.path().join("permctl.sock");
+
+        let server = tokio::spawn({
+            let manager = manager.clone();
+            let socket_path = socket_path.clone();
+            async move { serve(manager, &socket_path).await }
+        });
+        while !socket_path.exists() {
+            tokio::task::yield_now().await;
+        }
+
+        let stream = UnixStream::connect(&socket_path).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        writer.write_all(b"{\"method\":\"subscribe\"}\n").await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(response, json!({ "status": "ok", "result": { "subscribed": true } }));
+
+        manager.grant_permission("root", "/test/command", Duration::minutes(30), "admin").await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(response["status"], "event");
+        assert_eq!(response["event"]["kind"], "granted");
+        assert_eq!(response["event"]["grant"]["username"], "root");
+
+        server.abort();
+    }
+}